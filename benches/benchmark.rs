@@ -1,4 +1,3 @@
-use blink_alloc::Blink;
 use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
 use std::hint::black_box;
 use std::time::Duration;
@@ -19,10 +18,8 @@ fn benchmark(c: &mut Criterion) {
     let data = data();
     let pts = || data.pts.iter().copied().enumerate();
 
-    static mut BLINK: Blink = Blink::new();
-
-    unsafe {
-        let mut my_tr = rtree::RTree::new(&BLINK);
+    {
+        let mut my_tr = rtree::RTree::default();
         pts().for_each(|(i, p)| my_tr.insert(rtree::Rect::point(p[0], p[1]), i));
 
         let mut tr = rtree_rs::RTree::new();
@@ -60,19 +57,15 @@ fn benchmark(c: &mut Criterion) {
 
     c.bench_function("rtree insert", |b| {
         b.iter_batched_ref(
-            || unsafe {
-                BLINK.reset();
-                rtree::RTree::new(&BLINK)
-            },
+            || rtree::RTree::default(),
             |tr| pts().for_each(|(i, [x, y])| tr.insert(rtree::Rect::point(x, y), i)),
             BatchSize::LargeInput,
         );
     });
     c.bench_function("rtree search-item", |b| {
         b.iter_batched_ref(
-            || unsafe {
-                BLINK.reset();
-                let mut tr = rtree::RTree::new(&BLINK);
+            || {
+                let mut tr = rtree::RTree::default();
                 pts().for_each(|(i, [x, y])| tr.insert(rtree::Rect::point(x, y), i));
                 tr
             },
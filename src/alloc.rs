@@ -0,0 +1,228 @@
+//! Allocation of the child arrays of nodes.
+
+use crate::geom::Circle;
+use crate::node::Node;
+use crate::{Scalar, MAX_ITEMS};
+use arrayvec::ArrayVec;
+use blink_alloc::Blink;
+use std::ops::{Deref, DerefMut};
+use std::ptr::NonNull;
+use std::rc::Rc;
+use std::sync::atomic::{self, AtomicU32};
+use std::sync::Arc;
+
+/// The child array of a node, along with what the node keeps about the
+/// items below it.
+///
+/// Keeping those here rather than in [`Parent`](crate::Parent) leaves a node
+/// no larger than its rect and a pointer, so that the arrays holding nodes
+/// and items stay compact. Derefs to the children.
+pub struct NodeVec<T, A: Alloc<T, S>, S = f32> {
+    pub(crate) children: ArrayVec<Node<T, A, S>, MAX_ITEMS>,
+    /// Bounding circle, kept only while [`Config::circles`] is set.
+    ///
+    /// [`Config::circles`]: crate::Config::circles
+    pub(crate) circle: Option<Circle>,
+    /// Number of items below the node.
+    pub(crate) count: usize,
+    /// Not less than the sequence number of any item below the node, see
+    /// [`RTree::search_recent`](crate::RTree::search_recent). Removals leave
+    /// it as it is.
+    pub(crate) newest: u64,
+    /// Not less than the range of the attributes of the items below the
+    /// node, see [`RTree::set_attribute`](crate::RTree::set_attribute).
+    /// Removals leave it as it is.
+    pub(crate) attr: [f32; 2],
+    /// Not less than the margin of any item below the node, see
+    /// [`RTree::set_margin`](crate::RTree::set_margin). Removals leave it as
+    /// it is.
+    pub(crate) margin: f32,
+    /// Number of searches that descended into the node, see
+    /// [`Config::profile`](crate::Config::profile).
+    pub(crate) hits: AtomicU32,
+}
+
+impl<T, A: Alloc<T, S>, S> NodeVec<T, A, S> {
+    pub(crate) fn new() -> Self {
+        Self {
+            children: ArrayVec::new(),
+            circle: None,
+            count: 0,
+            newest: 0,
+            attr: [f32::INFINITY, f32::NEG_INFINITY],
+            margin: 0.0,
+            hits: AtomicU32::new(0),
+        }
+    }
+
+    /// A copy of `self` holding `children`.
+    pub(crate) fn copy_with(&self, children: ArrayVec<Node<T, A, S>, MAX_ITEMS>) -> Self {
+        Self {
+            children,
+            circle: self.circle,
+            count: self.count,
+            newest: self.newest,
+            attr: self.attr,
+            margin: self.margin,
+            hits: AtomicU32::new(self.hits.load(atomic::Ordering::Relaxed)),
+        }
+    }
+}
+
+impl<T, A: Alloc<T, S>, S> Deref for NodeVec<T, A, S> {
+    type Target = ArrayVec<Node<T, A, S>, MAX_ITEMS>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.children
+    }
+}
+
+impl<T, A: Alloc<T, S>, S> DerefMut for NodeVec<T, A, S> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.children
+    }
+}
+
+/// Allocates the child arrays of the nodes of a tree, chosen per tree by the
+/// value passed to [`RTree::new`].
+///
+/// [`BoxAlloc`] puts each array on the heap and frees it with its node, and
+/// `&Blink` bumps them out of an arena, which is faster but frees nothing
+/// until the arena is reset and borrows it for the life of the tree.
+/// [`ArenaAlloc`] owns its arena instead, and resets it when the tree is
+/// [cleared](crate::RTree::clear).
+///
+/// [`RTree::new`]: crate::RTree::new
+pub trait Alloc<T, S = f32>: Sized {
+    type Output: DerefMut<Target = NodeVec<T, Self, S>>;
+
+    fn make(&self) -> Self::Output;
+
+    /// Reclaims what can be reclaimed once the tree has dropped all of its
+    /// nodes, see [`RTree::clear`](crate::RTree::clear). Does nothing by
+    /// default.
+    fn reset(&mut self) {}
+}
+
+/// Allocates each node on the heap, so that the tree owns its nodes and can
+/// be stored and returned like any other value. The default for [`RTree`].
+///
+/// Nodes can be shared with [frozen views](crate::RTree::freeze) of the
+/// tree, and the tree copies a shared node before changing it, along with
+/// the path above it.
+///
+/// [`RTree`]: crate::RTree
+#[derive(Clone, Copy, Default, Debug)]
+pub struct BoxAlloc;
+
+impl<T, S: Scalar> Alloc<T, S> for BoxAlloc {
+    type Output = SharedVec<T, S>;
+
+    fn make(&self) -> Self::Output {
+        SharedVec(Arc::new(NodeVec::new()))
+    }
+}
+
+/// A child array of a [`BoxAlloc`], copied on write once it's shared.
+pub struct SharedVec<T, S: Scalar = f32>(Arc<NodeVec<T, BoxAlloc, S>>);
+
+/// Shares the array, and with it the nodes below it.
+impl<T, S: Scalar> Clone for SharedVec<T, S> {
+    fn clone(&self) -> Self {
+        SharedVec(self.0.clone())
+    }
+}
+
+impl<T, S: Scalar> Deref for SharedVec<T, S> {
+    type Target = NodeVec<T, BoxAlloc, S>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T, S: Scalar> DerefMut for SharedVec<T, S> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        if Arc::get_mut(&mut self.0).is_none() {
+            let children = self.0.iter().map(Node::share).collect();
+            self.0 = Arc::new(self.0.copy_with(children));
+        }
+        Arc::get_mut(&mut self.0).unwrap()
+    }
+}
+
+/// Bumps the nodes out of an arena shared by the allocator and every node,
+/// so that the tree doesn't borrow it, and resets the arena when the tree is
+/// [cleared](crate::RTree::clear).
+///
+/// Clones share the arena, which is reset only once nothing else holds it:
+/// clearing one of two trees sharing an arena reclaims nothing until the
+/// other one is gone too.
+#[derive(Clone, Default)]
+pub struct ArenaAlloc {
+    pub(crate) blink: Rc<Blink>,
+}
+
+impl ArenaAlloc {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// A child array in an [`ArenaAlloc`], keeping the arena alive.
+pub struct ArenaVec<T, S = f32> {
+    vec: NonNull<NodeVec<T, ArenaAlloc, S>>,
+    _arena: Rc<Blink>,
+}
+
+impl<T, S> Deref for ArenaVec<T, S> {
+    type Target = NodeVec<T, ArenaAlloc, S>;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: the arena is only reset when no array holds it, and
+        // every array is its own allocation
+        unsafe { self.vec.as_ref() }
+    }
+}
+
+impl<T, S> DerefMut for ArenaVec<T, S> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // SAFETY: see deref
+        unsafe { self.vec.as_mut() }
+    }
+}
+
+/// The arena frees no memory, but the children are dropped in place, so
+/// that the arrays below them let go of the arena too.
+impl<T, S> Drop for ArenaVec<T, S> {
+    fn drop(&mut self) {
+        // SAFETY: the arena is kept alive by `_arena` until after this, and
+        // the array isn't used again
+        unsafe { std::ptr::drop_in_place(self.vec.as_ptr()) }
+    }
+}
+
+impl<T, S> Alloc<T, S> for ArenaAlloc {
+    type Output = ArenaVec<T, S>;
+
+    fn make(&self) -> Self::Output {
+        ArenaVec {
+            vec: NonNull::from(self.blink.put_no_drop(NodeVec::new())),
+            _arena: self.blink.clone(),
+        }
+    }
+
+    fn reset(&mut self) {
+        if let Some(blink) = Rc::get_mut(&mut self.blink) {
+            blink.reset();
+        }
+    }
+}
+
+impl<'a, T: 'a, S: 'a> Alloc<T, S> for &'a Blink {
+    type Output = &'a mut NodeVec<T, Self, S>;
+
+    fn make(&self) -> Self::Output {
+        self.put_no_drop(NodeVec::new())
+    }
+}
@@ -0,0 +1,141 @@
+//! Indexing Bevy entities.
+//!
+//! [`SpatialIndexPlugin`] keeps a [`SpatialIndex`] resource in sync with the
+//! entities that have a `GlobalTransform` and an `Aabb`, projected onto the
+//! xy plane. Entities are reinserted when either component changes and
+//! removed along with them, so systems can query the index through
+//! [`SpatialQuery`] without maintaining it themselves.
+
+use crate::{BoxAlloc, Point, RTree, Rect};
+use ::bevy::app::{App, Plugin, PostUpdate};
+use ::bevy::ecs::prelude::*;
+use ::bevy::ecs::system::SystemParam;
+use ::bevy::render::primitives::Aabb;
+use ::bevy::transform::components::GlobalTransform;
+use ::bevy::transform::TransformSystem;
+use std::collections::HashMap;
+
+#[derive(Resource)]
+pub struct SpatialIndex {
+    tree: RTree<Entity, BoxAlloc>,
+    rects: HashMap<Entity, Rect>,
+}
+
+impl Default for SpatialIndex {
+    fn default() -> Self {
+        SpatialIndex {
+            tree: RTree::new(BoxAlloc),
+            rects: HashMap::new(),
+        }
+    }
+}
+
+impl SpatialIndex {
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.len() == 0
+    }
+
+    /// The indexed bounds of `entity`.
+    pub fn get(&self, entity: Entity) -> Option<Rect> {
+        self.rects.get(&entity).copied()
+    }
+
+    /// Entities whose bounds intersect `rect`.
+    pub fn search(&self, rect: Rect) -> impl Iterator<Item = Entity> + '_ {
+        self.tree.search(rect).map(|item| *item.data)
+    }
+
+    /// The `k` entities nearest to `point`, closest first.
+    pub fn nearest(&self, point: Point, k: usize) -> Vec<Entity> {
+        let mut found = self.tree.knn_batch(&[point], k);
+        found
+            .pop()
+            .unwrap_or_default()
+            .iter()
+            .map(|item| *item.data)
+            .collect()
+    }
+
+    fn update(&mut self, entity: Entity, rect: Rect) {
+        if let Some(old) = self.rects.insert(entity, rect) {
+            if old == rect {
+                return;
+            }
+            self.tree.remove(old, &entity);
+        }
+        self.tree.insert(rect, entity);
+    }
+
+    fn remove(&mut self, entity: Entity) {
+        if let Some(old) = self.rects.remove(&entity) {
+            self.tree.remove(old, &entity);
+        }
+    }
+}
+
+/// Read access to the [`SpatialIndex`] from a system.
+#[derive(SystemParam)]
+pub struct SpatialQuery<'w> {
+    index: Res<'w, SpatialIndex>,
+}
+
+impl SpatialQuery<'_> {
+    pub fn search(&self, rect: Rect) -> impl Iterator<Item = Entity> + '_ {
+        self.index.search(rect)
+    }
+
+    pub fn nearest(&self, point: Point, k: usize) -> Vec<Entity> {
+        self.index.nearest(point, k)
+    }
+
+    pub fn get(&self, entity: Entity) -> Option<Rect> {
+        self.index.get(entity)
+    }
+}
+
+/// Adds the [`SpatialIndex`] and updates it after transform propagation.
+pub struct SpatialIndexPlugin;
+
+impl Plugin for SpatialIndexPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SpatialIndex>().add_systems(
+            PostUpdate,
+            sync_index.after(TransformSystem::TransformPropagate),
+        );
+    }
+}
+
+type Moved = Or<(Changed<GlobalTransform>, Changed<Aabb>)>;
+
+fn sync_index(
+    mut index: ResMut<SpatialIndex>,
+    moved: Query<(Entity, &GlobalTransform, &Aabb), Moved>,
+    mut lost_aabb: RemovedComponents<Aabb>,
+    mut lost_transform: RemovedComponents<GlobalTransform>,
+) {
+    // removals first, entities that got the components back are in `moved`
+    for entity in lost_aabb.read().chain(lost_transform.read()) {
+        index.remove(entity);
+    }
+    for (entity, transform, aabb) in &moved {
+        index.update(entity, bounds(transform, aabb));
+    }
+}
+
+/// The xy extent of `aabb` once transformed to world space.
+fn bounds(transform: &GlobalTransform, aabb: &Aabb) -> Rect {
+    let affine = transform.affine();
+    let center = affine.transform_point3a(aabb.center);
+    let m = affine.matrix3;
+    let half = aabb.half_extents;
+    let x = m.x_axis.x.abs() * half.x + m.y_axis.x.abs() * half.y + m.z_axis.x.abs() * half.z;
+    let y = m.x_axis.y.abs() * half.x + m.y_axis.y.abs() * half.y + m.z_axis.y.abs() * half.z;
+    Rect::new(
+        Point::new(center.x - x, center.y - y),
+        Point::new(center.x + x, center.y + y),
+    )
+}
@@ -0,0 +1,173 @@
+//! Querying by S2 or H3 cell coverings.
+//!
+//! Cells are converted to rects of longitude (x) and latitude (y) in
+//! degrees, covering the cell's boundary with a small margin. Cells that
+//! cross the antimeridian become two rects and cells around a pole span
+//! every longitude, so the items of a covering are found at the cost of a
+//! few extra items near its edges.
+
+use crate::{Alloc, IterItem, Point, RTree, Rect};
+use std::collections::HashSet;
+use std::io::{self, Error, ErrorKind};
+
+impl<T, A: Alloc<T>> RTree<T, A> {
+    /// Finds the items intersecting any of the S2 `cells`, each once.
+    #[cfg(feature = "s2")]
+    pub fn search_s2_cells(&self, cells: &[u64]) -> io::Result<Vec<IterItem<'_, T>>> {
+        let mut rects = Vec::new();
+        for &cell in cells {
+            rects.extend(s2_cell_rects(cell)?);
+        }
+        Ok(self.search_rects(&rects))
+    }
+
+    /// Finds the items intersecting any of the H3 `cells`, each once.
+    #[cfg(feature = "h3")]
+    pub fn search_h3_cells(&self, cells: &[u64]) -> io::Result<Vec<IterItem<'_, T>>> {
+        let mut rects = Vec::new();
+        for &cell in cells {
+            rects.extend(h3_cell_rects(cell)?);
+        }
+        Ok(self.search_rects(&rects))
+    }
+
+    fn search_rects(&self, rects: &[Rect]) -> Vec<IterItem<'_, T>> {
+        let mut seen = HashSet::new();
+        let mut found = Vec::new();
+        for rect in rects {
+            found.extend(self.search(*rect).filter(|item| seen.insert(item.key)));
+        }
+        found
+    }
+}
+
+/// The rects covering an S2 cell, given by its 64-bit id.
+#[cfg(feature = "s2")]
+pub fn s2_cell_rects(id: u64) -> io::Result<Vec<Rect>> {
+    const POS_TO_IJ: [[u64; 4]; 4] = [[0, 1, 3, 2], [0, 2, 3, 1], [3, 2, 0, 1], [3, 1, 0, 2]];
+    const POS_TO_ORIENTATION: [usize; 4] = [1, 0, 0, 3];
+
+    let face = (id >> 61) as usize;
+    let zeros = id.trailing_zeros();
+    if face > 5 || zeros > 60 || zeros & 1 != 0 {
+        return Err(Error::new(ErrorKind::InvalidInput, "invalid S2 cell id"));
+    }
+    let level = 30 - zeros / 2;
+    // walk down the Hilbert curve of the face
+    let (mut i, mut j) = (0, 0);
+    let mut orientation = face & 1;
+    for k in 1..=level {
+        let pos = (id >> (61 - 2 * k)) as usize & 3;
+        let ij = POS_TO_IJ[orientation][pos];
+        i = i << 1 | ij >> 1;
+        j = j << 1 | ij & 1;
+        orientation ^= POS_TO_ORIENTATION[pos];
+    }
+    let size = 1 << (30 - level);
+    let (i, j) = (i << (30 - level), j << (30 - level));
+    let uv = |v: u64| {
+        let s = v as f64 / (1u64 << 30) as f64;
+        if s >= 0.5 {
+            (4.0 * s * s - 1.0) / 3.0
+        } else {
+            (1.0 - 4.0 * (1.0 - s) * (1.0 - s)) / 3.0
+        }
+    };
+    let (u0, u1, v0, v1) = (uv(i), uv(i + size), uv(j), uv(j + size));
+    let xyz = |u: f64, v: f64| match face {
+        0 => [1.0, u, v],
+        1 => [-u, 1.0, v],
+        2 => [-u, -v, 1.0],
+        3 => [-1.0, -v, -u],
+        4 => [v, -1.0, -u],
+        _ => [v, u, -1.0],
+    };
+    // the poles are at the centers of faces 2 and 5
+    let pole = u0 <= 0.0 && 0.0 <= u1 && v0 <= 0.0 && 0.0 <= v1;
+    let corners = [xyz(u0, v0), xyz(u1, v0), xyz(u1, v1), xyz(u0, v1)];
+    Ok(polygon_rects(
+        &corners,
+        pole && face == 2,
+        pole && face == 5,
+    ))
+}
+
+/// The rects covering an H3 cell, given by its 64-bit index.
+#[cfg(feature = "h3")]
+pub fn h3_cell_rects(index: u64) -> io::Result<Vec<Rect>> {
+    use h3o::{CellIndex, LatLng};
+
+    let cell = CellIndex::try_from(index)
+        .map_err(|_| Error::new(ErrorKind::InvalidInput, "invalid H3 cell index"))?;
+    let vertices: Vec<[f64; 3]> = cell
+        .boundary()
+        .iter()
+        .map(|ll| {
+            let (lat, lng) = (ll.lat_radians(), ll.lng_radians());
+            [lat.cos() * lng.cos(), lat.cos() * lng.sin(), lat.sin()]
+        })
+        .collect();
+    let contains = |lat| {
+        let pole = LatLng::new(lat, 0.0).expect("valid pole");
+        pole.to_cell(cell.resolution()) == cell
+    };
+    Ok(polygon_rects(&vertices, contains(90.0), contains(-90.0)))
+}
+
+/// Bounds of a spherical polygon with great circle edges between
+/// `vertices`, which are unit vectors. `north` and `south` tell whether it
+/// contains the poles.
+fn polygon_rects(vertices: &[[f64; 3]], north: bool, south: bool) -> Vec<Rect> {
+    // Edges are sampled, which may cut off the bulge of an edge between
+    // samples by about the square of their distance over 8, padded twice.
+    const SAMPLES: usize = 16;
+    let (mut lat0, mut lat1) = (f64::INFINITY, f64::NEG_INFINITY);
+    let (mut east0, mut west1) = (f64::INFINITY, f64::NEG_INFINITY);
+    let (mut lng0, mut lng1) = (f64::INFINITY, f64::NEG_INFINITY);
+    let mut pad: f64 = 1e-5;
+    for (k, a) in vertices.iter().enumerate() {
+        let b = &vertices[(k + 1) % vertices.len()];
+        let angle = (a[0] * b[0] + a[1] * b[1] + a[2] * b[2])
+            .clamp(-1.0, 1.0)
+            .acos();
+        let step = angle / SAMPLES as f64;
+        pad = pad.max((step * step / 4.0).to_degrees());
+        for s in 0..SAMPLES {
+            let t = s as f64 / SAMPLES as f64;
+            let p: [f64; 3] = std::array::from_fn(|d| a[d] * (1.0 - t) + b[d] * t);
+            let lat = p[2].atan2(p[0].hypot(p[1])).to_degrees();
+            let lng = p[1].atan2(p[0]).to_degrees();
+            lat0 = lat0.min(lat);
+            lat1 = lat1.max(lat);
+            lng0 = lng0.min(lng);
+            lng1 = lng1.max(lng);
+            if lng >= 0.0 {
+                east0 = east0.min(lng);
+            } else {
+                west1 = west1.max(lng);
+            }
+        }
+    }
+    let rect = |lng0: f64, lat0: f64, lng1: f64, lat1: f64| {
+        Rect::new(
+            Point::new(lng0.max(-180.0) as f32, lat0.max(-90.0) as f32),
+            Point::new(lng1.min(180.0) as f32, lat1.min(90.0) as f32),
+        )
+    };
+    let (lat0, lat1) = (lat0 - pad, lat1 + pad);
+    if north || south {
+        let lat0 = if south { -90.0 } else { lat0 };
+        let lat1 = if north { 90.0 } else { lat1 };
+        return vec![rect(-180.0, lat0, 180.0, lat1)];
+    }
+    // longitude is stretched by the cosine of the latitude
+    let lng_pad = pad / lat0.abs().max(lat1.abs()).to_radians().cos().max(1e-3);
+    if lng1 - lng0 > 180.0 {
+        // across the antimeridian
+        return vec![
+            rect(east0 - lng_pad, lat0, 180.0, lat1),
+            rect(-180.0, lat0, west1 + lng_pad, lat1),
+        ];
+    }
+    vec![rect(lng0 - lng_pad, lat0, lng1 + lng_pad, lat1)]
+}
@@ -0,0 +1,277 @@
+//! Batch operations split into chunks of work for an executor of choice.
+//!
+//! Packing a tree and joining two trees are divided into independent chunks,
+//! which can be sent to a thread pool or spawned as blocking tasks of an
+//! async runtime, and whose results are put together at the end. The crate
+//! runs none of them itself.
+
+use crate::node::{join, pack_height, pack_node, tile, Entry, Slab};
+use crate::{Alloc, IterItem, Node, Parent, RTree, Rect};
+use std::marker::PhantomData;
+use std::sync::atomic::AtomicBool;
+
+/// A tree being packed by [`RTree::bulk_load_chunked`] or
+/// [`RTree::rebuild_chunked`].
+///
+/// The chunks taken with [`take_chunks`](Self::take_chunks) may be run in
+/// any order and on any thread, and their results handed to
+/// [`finish`](Self::finish) in any order.
+pub struct PackJob<T, A: Alloc<T>> {
+    tree: RTree<T, A>,
+    chunks: Vec<PackChunk<T, A>>,
+    /// Number of chunks to be finished.
+    count: usize,
+    height: usize,
+}
+
+/// A subtree of a [`PackJob`] to be packed.
+pub struct PackChunk<T, A: Alloc<T>> {
+    index: usize,
+    entries: Vec<Entry>,
+    fill: usize,
+    height: usize,
+    alloc: A,
+    data: PhantomData<fn() -> T>,
+}
+
+/// A subtree packed by [`PackChunk::run`].
+pub struct Packed<T, A: Alloc<T>> {
+    index: usize,
+    node: Parent<T, A>,
+    nodes: usize,
+}
+
+impl<T, A: Alloc<T>> PackChunk<T, A> {
+    /// Number of items in the chunk.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn run(self) -> Packed<T, A> {
+        let mut nodes = 0;
+        let never = AtomicBool::new(false);
+        let node = pack_node(
+            self.entries,
+            &self.alloc,
+            self.fill,
+            self.height,
+            &never,
+            &mut nodes,
+        )
+        .unwrap();
+        Packed {
+            index: self.index,
+            node,
+            nodes,
+        }
+    }
+}
+
+impl<T, A: Alloc<T> + Clone> PackJob<T, A> {
+    fn new(mut tree: RTree<T, A>, entries: Vec<Entry>) -> Self {
+        let fill = tree.config.max_items - 1;
+        let height = pack_height(entries.len(), fill);
+        let groups = match height {
+            _ if entries.is_empty() => vec![],
+            0 => vec![entries],
+            _ => tile(entries, fill, height),
+        };
+        let chunks: Vec<_> = groups
+            .into_iter()
+            .enumerate()
+            .map(|(index, entries)| PackChunk {
+                index,
+                entries,
+                fill,
+                height: height.saturating_sub(1),
+                alloc: tree.alloc.clone(),
+                data: PhantomData,
+            })
+            .collect();
+        tree.root = None;
+        tree.nodes = 0;
+        tree.height = 0;
+        PackJob {
+            tree,
+            count: chunks.len(),
+            chunks,
+            height,
+        }
+    }
+}
+
+impl<T, A: Alloc<T>> PackJob<T, A> {
+    /// Takes the chunks left to run, at most one per child of the root.
+    pub fn take_chunks(&mut self) -> Vec<PackChunk<T, A>> {
+        std::mem::take(&mut self.chunks)
+    }
+
+    /// Puts the results of all chunks together into the tree.
+    ///
+    /// Panics if a chunk is missing.
+    pub fn finish<I>(self, packed: I) -> RTree<T, A>
+    where
+        I: IntoIterator<Item = Packed<T, A>>,
+    {
+        let mut tree = self.tree;
+        let mut packed: Vec<_> = packed.into_iter().collect();
+        assert_eq!(packed.len(), self.count, "missing packed chunks");
+        if packed.is_empty() {
+            return tree;
+        }
+        packed.sort_by_key(|p| p.index);
+        let nodes: usize = packed.iter().map(|p| p.nodes).sum();
+        let mut root = if self.height == 0 {
+            tree.nodes = nodes;
+            packed.pop().unwrap().node
+        } else {
+            tree.nodes = nodes + 1;
+            let children = packed.into_iter().map(|p| Node::Parent(p.node)).collect();
+            Parent::of(children, &tree.alloc)
+        };
+        root.renew(&tree.values);
+        if let Some(attr) = tree.attr {
+            root.reattr(&tree.values, attr);
+        }
+        if let Some(margin) = tree.margin {
+            root.remargin(&tree.values, margin);
+        }
+        if tree.config.circles {
+            root.set_circles(true);
+        }
+        tree.root = Some(Node::Parent(root));
+        tree.height = self.height;
+        tree
+    }
+}
+
+impl<T, A: Alloc<T> + Clone> RTree<T, A> {
+    /// Like [`bulk_load`](Self::bulk_load), leaving the packing to chunks
+    /// run by the caller, see [`PackJob`].
+    pub fn bulk_load_chunked<I>(alloc: A, items: I) -> PackJob<T, A>
+    where
+        I: IntoIterator<Item = (Rect, T)>,
+    {
+        let mut tree = RTree::new(alloc);
+        let entries: Vec<Entry> = items
+            .into_iter()
+            .map(|(rect, data)| Entry {
+                rect,
+                index: tree.values.insert(data),
+            })
+            .collect();
+        tree.length = entries.len();
+        PackJob::new(tree, entries)
+    }
+
+    /// Like [`rebuild`](Self::rebuild), leaving the packing to chunks run by
+    /// the caller, see [`PackJob`]. The tree is handed back by
+    /// [`PackJob::finish`].
+    pub fn rebuild_chunked(mut self) -> PackJob<T, A> {
+        let mut entries = Vec::with_capacity(self.length);
+        if let Some(mut root) = self.root.take() {
+            root.nodes().flatten_into(&mut entries);
+        }
+        entries.sort_by_key(|e| self.values.seq(e.index));
+        PackJob::new(self, entries)
+    }
+}
+
+/// The node if it has child nodes rather than items.
+fn inner<T, A: Alloc<T>>(node: &Node<T, A>) -> Option<&Parent<T, A>> {
+    match node {
+        Node::Parent(p) if !p.is_leaf() => Some(p),
+        _ => None,
+    }
+}
+
+/// A pair of subtrees to join, see [`RTree::pairs_within_chunked`].
+pub struct JoinChunk<'a, 'b, T, A: Alloc<T>, U, B: Alloc<U>> {
+    a: &'a Node<T, A>,
+    b: &'b Node<U, B>,
+    values_a: &'a Slab<T>,
+    values_b: &'b Slab<U>,
+}
+
+impl<'a, 'b, T, A: Alloc<T>, U, B: Alloc<U>> JoinChunk<'a, 'b, T, A, U, B> {
+    /// Finds the pairs of items of the two subtrees whose rects pass `test`.
+    pub fn run<P>(&self, mut test: P) -> Vec<(IterItem<'a, T>, IterItem<'b, U>)>
+    where
+        P: FnMut(&Rect, &Rect) -> bool,
+    {
+        let mut pairs = Vec::new();
+        let never = AtomicBool::new(false);
+        let _ = join(self.a, self.b, &mut test, None, &never, &mut |a, b| {
+            pairs.push((
+                a.iter_item(self.values_a, 0.0),
+                b.iter_item(self.values_b, 0.0),
+            ));
+        });
+        pairs
+    }
+}
+
+impl<T, A: Alloc<T>> RTree<T, A> {
+    /// Splits [`pairs_within`](Self::pairs_within) into at least `chunks`
+    /// pairs of subtrees, unless the trees are too shallow, which together
+    /// find every pair once.
+    ///
+    /// Pairs of nodes failing `test` are dropped while splitting, with the
+    /// same requirements on `test` as `pairs_within`. The chunks borrow the
+    /// trees, so they can be run on scoped threads.
+    pub fn pairs_within_chunked<'b, U, B, P>(
+        &self,
+        other: &'b RTree<U, B>,
+        chunks: usize,
+        mut test: P,
+    ) -> Vec<JoinChunk<'_, 'b, T, A, U, B>>
+    where
+        B: Alloc<U>,
+        P: FnMut(&Rect, &Rect) -> bool,
+    {
+        let (Some(ra), Some(rb)) = (&self.root, &other.root) else {
+            return Vec::new();
+        };
+        let mut pairs = vec![(ra, rb)];
+        if !test(ra.rect(), rb.rect()) {
+            pairs.clear();
+        }
+        // split the larger inner node of every pair until there are enough
+        while pairs.len() < chunks {
+            let mut split = false;
+            let mut next = Vec::with_capacity(pairs.len() * 2);
+            for (a, b) in pairs {
+                match (inner(a), inner(b)) {
+                    (Some(x), y) if y.is_none() || x.rect.area() >= b.rect().area() => {
+                        let children = x.nodes.iter().filter(|n| test(n.rect(), b.rect()));
+                        next.extend(children.map(|n| (n, b)));
+                        split = true;
+                    }
+                    (_, Some(y)) => {
+                        let children = y.nodes.iter().filter(|n| test(a.rect(), n.rect()));
+                        next.extend(children.map(|n| (a, n)));
+                        split = true;
+                    }
+                    _ => next.push((a, b)),
+                }
+            }
+            pairs = next;
+            if !split {
+                break;
+            }
+        }
+        pairs
+            .into_iter()
+            .map(|(a, b)| JoinChunk {
+                a,
+                b,
+                values_a: &self.values,
+                values_b: &other.values,
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,49 @@
+//! Counting the node and value allocations of each kind of operation, to
+//! attribute the memory growth of a tree to a workload.
+//!
+//! Sizes are those of [`RTree::memory_usage`]. Splits happen during inserts
+//! and the reinsertions of removals, so their allocations are counted both
+//! on their own and as part of the operation that caused them.
+
+use crate::{Alloc, RTree};
+
+/// Allocations of one kind of operation.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AllocCount {
+    /// Number of operations.
+    pub ops: u64,
+    /// Nodes allocated by these operations.
+    pub nodes: u64,
+    /// Bytes allocated by these operations, for nodes and values.
+    pub bytes: u64,
+}
+
+impl AllocCount {
+    pub(crate) fn record(&mut self, ops: usize, nodes: usize, bytes: usize) {
+        self.ops += ops as u64;
+        self.nodes += nodes as u64;
+        self.bytes += bytes as u64;
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AllocCounters {
+    pub insert: AllocCount,
+    pub split: AllocCount,
+    /// Removals, including [`RTree::retain_in_rect`], which allocate when
+    /// reinserting the items of underfull nodes.
+    pub remove: AllocCount,
+    /// Nodes ever allocated, for attributing them to operations.
+    pub(crate) allocated: usize,
+}
+
+impl<T, A: Alloc<T, S>, S> RTree<T, A, S> {
+    /// The allocations since the tree was created or the counters reset.
+    pub fn alloc_counters(&self) -> AllocCounters {
+        self.allocs
+    }
+
+    pub fn reset_alloc_counters(&mut self) {
+        self.allocs = AllocCounters::default();
+    }
+}
@@ -0,0 +1,105 @@
+//! Walking the nodes of a tree by hand, for algorithms the built-in queries
+//! don't cover.
+
+use crate::node::Slab;
+use crate::{Alloc, IterItem, Node, RTree, Rect, Scalar};
+
+/// A position in a tree, from which to move down to a child or back up.
+///
+/// The cursor starts at the root and ends at the items, which are the
+/// leaves of the tree.
+pub struct Cursor<'a, T, A: Alloc<T, S>, S = f32> {
+    /// The nodes from the root to the current one.
+    path: Vec<&'a Node<T, A, S>>,
+    values: &'a Slab<T>,
+}
+
+impl<T, A: Alloc<T, S>, S> Clone for Cursor<'_, T, A, S> {
+    fn clone(&self) -> Self {
+        Cursor {
+            path: self.path.clone(),
+            values: self.values,
+        }
+    }
+}
+
+impl<'a, T, A: Alloc<T, S>, S: Scalar> Cursor<'a, T, A, S> {
+    fn node(&self) -> &'a Node<T, A, S> {
+        self.path[self.path.len() - 1]
+    }
+
+    fn children(&self) -> &'a [Node<T, A, S>] {
+        match self.node() {
+            Node::Item(_) => &[],
+            Node::Parent(parent) => &parent.nodes,
+        }
+    }
+
+    /// The bounding rect of the current node.
+    pub fn rect(&self) -> Rect<S> {
+        *self.node().rect()
+    }
+
+    /// Number of items at or below the current node.
+    pub fn count(&self) -> usize {
+        self.node().count()
+    }
+
+    /// Number of children of the current node, zero at an item.
+    pub fn len(&self) -> usize {
+        self.children().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of moves down from the root.
+    pub fn depth(&self) -> usize {
+        self.path.len() - 1
+    }
+
+    /// The item at the cursor, if it's at a leaf.
+    pub fn item(&self) -> Option<IterItem<'a, T, S>> {
+        match self.node() {
+            Node::Item(entry) => Some(entry.iter_item(self.values, 0.0)),
+            Node::Parent(_) => None,
+        }
+    }
+
+    /// The rect of child `i`, to decide where to go without moving.
+    pub fn child_rect(&self, i: usize) -> Option<Rect<S>> {
+        self.children().get(i).map(|node| *node.rect())
+    }
+
+    /// Moves down to child `i`, returning whether it exists.
+    pub fn down(&mut self, i: usize) -> bool {
+        match self.children().get(i) {
+            Some(child) => {
+                self.path.push(child);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Moves up to the parent, returning whether there is one.
+    pub fn up(&mut self) -> bool {
+        if self.path.len() == 1 {
+            return false;
+        }
+        self.path.pop();
+        true
+    }
+}
+
+impl<T, A: Alloc<T, S>, S: Scalar> RTree<T, A, S> {
+    /// A cursor at the root, or `None` if the tree is empty.
+    pub fn cursor(&self) -> Option<Cursor<'_, T, A, S>> {
+        let root = self.root.as_ref()?;
+        Some(Cursor {
+            path: vec![root],
+            values: &self.values,
+        })
+    }
+}
@@ -0,0 +1,119 @@
+//! A bloom filter over the grid cells covered by items, which answers most
+//! point membership misses without walking the tree.
+
+use crate::{Alloc, Point, RTree, Rect, Scalar};
+
+const HASHES: u64 = 3;
+
+/// Rects covering more cells than this saturate the filter.
+const MAX_CELLS: i64 = 64;
+
+#[derive(Clone)]
+pub(crate) struct Filter {
+    cell: f32,
+    bits: Vec<u64>,
+    /// Set once an item covered too many cells to be recorded, after which
+    /// every point may be covered.
+    saturated: bool,
+}
+
+impl Filter {
+    fn new(cell: f32, bits: usize) -> Self {
+        Filter {
+            cell,
+            bits: vec![0; bits.div_ceil(64).max(1)],
+            saturated: false,
+        }
+    }
+
+    fn cell<S: Scalar>(&self, p: Point<S>) -> (i64, i64) {
+        let cell = self.cell as f64;
+        (
+            (p.x.to_f64() / cell).floor() as i64,
+            (p.y.to_f64() / cell).floor() as i64,
+        )
+    }
+
+    /// Bit positions of a cell, by double hashing.
+    fn positions(&self, (x, y): (i64, i64)) -> impl Iterator<Item = usize> {
+        let h = mix((x as u64) ^ mix(y as u64));
+        let (h1, h2) = (h & 0xffff_ffff, (h >> 32) | 1);
+        let m = self.bits.len() as u64 * 64;
+        (0..HASHES).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % m) as usize)
+    }
+
+    pub(crate) fn insert<S: Scalar>(&mut self, rect: &Rect<S>) {
+        if self.saturated {
+            return;
+        }
+        let (x0, y0) = self.cell(rect.min);
+        let (x1, y1) = self.cell(rect.max);
+        // non-finite coordinates end up as saturated casts
+        let w = x1.saturating_sub(x0).saturating_add(1);
+        let h = y1.saturating_sub(y0).saturating_add(1);
+        let cells = w.saturating_mul(h);
+        if !(1..=MAX_CELLS).contains(&cells) {
+            self.saturated = true;
+            return;
+        }
+        for x in x0..=x1 {
+            for y in y0..=y1 {
+                for i in self.positions((x, y)) {
+                    self.bits[i / 64] |= 1 << (i % 64);
+                }
+            }
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.bits.fill(0);
+        self.saturated = false;
+    }
+
+    pub(crate) fn may_contain<S: Scalar>(&self, p: Point<S>) -> bool {
+        self.saturated
+            || self
+                .positions(self.cell(p))
+                .all(|i| self.bits[i / 64] & (1 << (i % 64)) != 0)
+    }
+}
+
+/// The splitmix64 finalizer.
+fn mix(mut z: u64) -> u64 {
+    z = z.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+impl<T, A: Alloc<T, S>, S: Scalar> RTree<T, A, S> {
+    /// Starts keeping a filter of `bits` bits over square cells of size
+    /// `cell`, built from the current items and updated on insertion.
+    ///
+    /// Removals don't clear the filter, so after many of them it should be
+    /// enabled again to rebuild it. Items covering more than 64 cells make
+    /// the filter useless.
+    pub fn enable_filter(&mut self, cell: f32, bits: usize) {
+        assert!(cell > 0.0, "cell must be positive");
+        let mut filter = Filter::new(cell, bits);
+        for item in self.iter() {
+            filter.insert(&item.rect);
+        }
+        self.filter = Some(filter);
+    }
+
+    pub fn disable_filter(&mut self) {
+        self.filter = None;
+    }
+
+    /// Determines whether any item's rect contains `point`, consulting the
+    /// filter before the tree.
+    pub fn contains_point(&self, point: Point<S>) -> bool {
+        if let Some(filter) = &self.filter {
+            if !filter.may_contain(point) {
+                return false;
+            }
+        }
+        self.search(Rect::point(point.x, point.y)).next().is_some()
+    }
+}
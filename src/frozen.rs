@@ -0,0 +1,740 @@
+//! Immutable snapshots of a tree that can be shared between threads.
+//!
+//! With the `f16` feature, snapshots can store their coordinates as half
+//! precision floats, which halves the size of the rects at the cost of
+//! precision.
+//!
+//! Views can also be built as priority R-trees with
+//! [`RTree::freeze_pr`], which bounds the number of nodes a window query
+//! visits even for inputs like long skinny rects that defeat the heuristics
+//! of an incrementally built tree.
+//!
+//! For indexes too large to hold their payloads, an [`EnvelopeIndex`] stores
+//! only the rects and a `u32` id per item, leaving the payloads to another
+//! store.
+
+use crate::geom::Ordered;
+use crate::journal::Encode;
+use crate::node::Entry;
+use crate::{Alloc, BoxAlloc, ItemKey, IterItem, Node, Parent, Point, RTree, Rect, Slab};
+use std::ops::Range;
+use std::sync::Arc;
+
+#[cfg(feature = "f16")]
+pub use half::f16;
+
+/// The storage format of the coordinates in a [`FrozenView`].
+pub trait Coord: Copy {
+    /// The largest value not greater than `v`.
+    fn down(v: f32) -> Self;
+    /// The smallest value not less than `v`.
+    fn up(v: f32) -> Self;
+    fn get(self) -> f32;
+}
+
+impl Coord for f32 {
+    fn down(v: f32) -> Self {
+        v
+    }
+
+    fn up(v: f32) -> Self {
+        v
+    }
+
+    fn get(self) -> f32 {
+        self
+    }
+}
+
+#[cfg(feature = "f16")]
+impl Coord for f16 {
+    fn down(v: f32) -> Self {
+        let h = f16::from_f32(v);
+        if h.to_f32() > v {
+            step(h, -1)
+        } else {
+            h
+        }
+    }
+
+    fn up(v: f32) -> Self {
+        let h = f16::from_f32(v);
+        if h.to_f32() < v {
+            step(h, 1)
+        } else {
+            h
+        }
+    }
+
+    fn get(self) -> f32 {
+        self.to_f32()
+    }
+}
+
+/// The next representable value towards positive (`dir = 1`) or negative
+/// (`dir = -1`) infinity.
+#[cfg(feature = "f16")]
+fn step(h: f16, dir: i16) -> f16 {
+    let bits = h.to_bits();
+    if bits & 0x7fff == 0 {
+        // the smallest subnormal of the right sign
+        return f16::from_bits(if dir > 0 { 0x0001 } else { 0x8001 });
+    }
+    let away = (bits & 0x8000 == 0) == (dir > 0);
+    f16::from_bits(if away { bits + 1 } else { bits - 1 })
+}
+
+/// A rect with its min rounded down and its max rounded up, so that it
+/// always covers the original.
+#[derive(Clone, Copy)]
+struct FrozenRect<C> {
+    min: [C; 2],
+    max: [C; 2],
+}
+
+impl<C: Coord> FrozenRect<C> {
+    fn new(rect: &Rect) -> Self {
+        FrozenRect {
+            min: [C::down(rect.min.x), C::down(rect.min.y)],
+            max: [C::up(rect.max.x), C::up(rect.max.y)],
+        }
+    }
+
+    fn get(&self) -> Rect {
+        Rect::new(
+            Point::new(self.min[0].get(), self.min[1].get()),
+            Point::new(self.max[0].get(), self.max[1].get()),
+        )
+    }
+}
+
+struct FrozenNode<C> {
+    rect: FrozenRect<C>,
+    /// Range of the children in `nodes`, or in `items` for leaves.
+    children: Range<u32>,
+}
+
+struct FrozenItem<T, C> {
+    rect: FrozenRect<C>,
+    key: ItemKey,
+    seq: u64,
+    data: T,
+}
+
+/// A read-only snapshot of a tree.
+///
+/// Views made by [`RTree::freeze`] share the nodes and values of the tree.
+/// The others hold copies laid out in flat arrays, where the children of a
+/// node are stored next to each other and referred to by index. Either way
+/// the view holds no pointers into the tree that it could change, and is
+/// `Send` and `Sync` whenever `T` is.
+///
+/// Rects of copies are stored as `C` and rounded outwards, so items and
+/// nodes are never missed by searches but may be reported with slightly
+/// larger rects.
+pub struct FrozenView<T, C = f32> {
+    layout: Layout<T, C>,
+    height: usize,
+}
+
+enum Layout<T, C> {
+    Flat {
+        nodes: Vec<FrozenNode<C>>,
+        items: Vec<FrozenItem<T, C>>,
+    },
+    /// The root of the tree, sharing the nodes below it, and its values.
+    Shared {
+        root: Option<Parent<T, BoxAlloc>>,
+        values: Slab<T>,
+        len: usize,
+    },
+}
+
+impl<T> RTree<T> {
+    /// Makes an immutable view of the tree, which query threads can hold on
+    /// to while the tree keeps changing.
+    ///
+    /// The view shares the nodes and values of the tree instead of copying
+    /// them. The tree then copies what it changes while a view still holds
+    /// it, a path of nodes and a chunk of 64 values at a time. Keys of the
+    /// returned items are the keys in `self` at the time of freezing.
+    ///
+    /// Trees with other allocators are copied by
+    /// [`freeze_with`](Self::freeze_with).
+    pub fn freeze(&self) -> Arc<FrozenView<T>>
+    where
+        T: Clone,
+    {
+        let root = match &self.root {
+            Some(Node::Parent(root)) => Some(root.share()),
+            _ => None,
+        };
+        Arc::new(FrozenView {
+            layout: Layout::Shared {
+                root,
+                values: self.values.share(T::clone),
+                len: self.length,
+            },
+            height: self.height,
+        })
+    }
+}
+
+impl<T, A: Alloc<T>> RTree<T, A> {
+    /// Copies the tree into a view like [`freeze`](RTree::freeze), storing
+    /// the coordinates as `C`.
+    ///
+    /// Takes time linear in the number of items.
+    pub fn freeze_with<C: Coord>(&self) -> Arc<FrozenView<T, C>>
+    where
+        T: Clone,
+    {
+        let values = &self.values;
+        let (nodes, items) = match &self.root {
+            Some(Node::Parent(root)) => flatten(root, self.height, &mut |entry| FrozenItem {
+                rect: FrozenRect::new(&entry.rect),
+                key: values.key(entry.index),
+                seq: values.seq(entry.index),
+                data: values[entry.index].clone(),
+            }),
+            _ => (Vec::new(), Vec::new()),
+        };
+        Arc::new(FrozenView {
+            layout: Layout::Flat { nodes, items },
+            height: self.height,
+        })
+    }
+
+    /// Like [`freeze_with`](Self::freeze_with), laying out the view as a
+    /// priority R-tree with nodes of at most
+    /// [`Config::max_items`](crate::Config) children instead of copying the
+    /// nodes of the tree.
+    ///
+    /// A window query then visits O(√(n/B) + k/B) nodes in the worst case, for
+    /// `n` items, `k` results and nodes of `B` children. Building takes
+    /// O(n log n) time.
+    ///
+    /// The layout depends only on the rects and the order they were inserted
+    /// in, so the same inserts always give the same
+    /// [bytes](FrozenView::to_bytes).
+    pub fn freeze_pr(&self) -> Arc<FrozenView<T>>
+    where
+        T: Clone,
+    {
+        self.freeze_pr_with()
+    }
+
+    /// Like [`freeze_pr`](Self::freeze_pr), storing the coordinates as `C`.
+    pub fn freeze_pr_with<C: Coord>(&self) -> Arc<FrozenView<T, C>>
+    where
+        T: Clone,
+    {
+        let fill = self.config.max_items;
+        // in insertion order, which breaks the ties between equal rects
+        let mut all: Vec<_> = self.iter().collect();
+        all.sort_by_key(|item| item.seq);
+        let rects = all.iter().map(|item| item.rect).collect();
+        let (items, nodes, height) = layout(rects, fill, |rects| {
+            let mut groups = Vec::new();
+            pseudo_pr_tree((0..rects.len()).collect(), rects, fill, 0, &mut groups);
+            groups
+        });
+        let items = items
+            .iter()
+            .map(|&i| &all[i])
+            .map(|item| FrozenItem {
+                rect: FrozenRect::new(&item.rect),
+                key: item.key,
+                seq: item.seq,
+                data: item.data.clone(),
+            })
+            .collect();
+        Arc::new(FrozenView {
+            layout: Layout::Flat { nodes, items },
+            height,
+        })
+    }
+}
+
+/// Lays out `root` and the nodes below it in flat arrays, with the root
+/// first and the children of each node next to each other, making the items
+/// of the leaves with `item`.
+fn flatten<T, A: Alloc<T>, C: Coord, I, F>(
+    root: &Parent<T, A>,
+    height: usize,
+    item: &mut F,
+) -> (Vec<FrozenNode<C>>, Vec<I>)
+where
+    F: FnMut(&Entry) -> I,
+{
+    let mut nodes = vec![FrozenNode {
+        rect: FrozenRect::new(&root.rect),
+        children: 0..0,
+    }];
+    let mut items = Vec::new();
+    push_children(root, height, 0, &mut nodes, &mut items, item);
+    (nodes, items)
+}
+
+/// Pushes the children of `parent`, stored at `at`, and the nodes below
+/// them.
+fn push_children<T, A: Alloc<T>, C: Coord, I, F>(
+    parent: &Parent<T, A>,
+    height: usize,
+    at: usize,
+    nodes: &mut Vec<FrozenNode<C>>,
+    items: &mut Vec<I>,
+    item: &mut F,
+) where
+    F: FnMut(&Entry) -> I,
+{
+    if height == 0 {
+        let start = items.len() as u32;
+        for node in parent.nodes.iter() {
+            if let Node::Item(entry) = node {
+                items.push(item(entry));
+            }
+        }
+        nodes[at].children = start..items.len() as u32;
+        return;
+    }
+    let start = nodes.len();
+    for node in parent.nodes.iter() {
+        nodes.push(FrozenNode {
+            rect: FrozenRect::new(node.rect()),
+            children: 0..0,
+        });
+    }
+    nodes[at].children = start as u32..nodes.len() as u32;
+    for (i, node) in parent.nodes.iter().enumerate() {
+        if let Node::Parent(child) = node {
+            push_children(child, height - 1, start + i, nodes, items, item);
+        }
+    }
+}
+
+/// Lays out a tree over `rects` bottom up, grouping the rects of each level
+/// into nodes of at most `fill` children with `group`. Returns the order of
+/// the rects in the leaves, the nodes with the root first, and the height.
+fn layout<C: Coord>(
+    mut rects: Vec<Rect>,
+    fill: usize,
+    mut group: impl FnMut(&[Rect]) -> Vec<Vec<usize>>,
+) -> (Vec<usize>, Vec<FrozenNode<C>>, usize) {
+    let mut items: Vec<usize> = (0..rects.len()).collect();
+    // nodes of each level, bottom up, with children indexed within the
+    // level below
+    let mut levels: Vec<Vec<(Rect, Range<u32>)>> = Vec::new();
+    while rects.len() > fill {
+        let groups = group(&rects);
+        // lay out the level below in the order of the groups
+        let order: Vec<usize> = groups.iter().flatten().copied().collect();
+        match levels.last_mut() {
+            Some(below) => *below = order.iter().map(|&i| below[i].clone()).collect(),
+            None => items = order.iter().map(|&i| items[i]).collect(),
+        }
+        let mut level = Vec::with_capacity(groups.len());
+        let mut start = 0;
+        for group in &groups {
+            let mut rect = rects[group[0]];
+            for &i in group {
+                rect.expand(&rects[i]);
+            }
+            let end = start + group.len() as u32;
+            level.push((rect, start..end));
+            start = end;
+        }
+        rects = level.iter().map(|n| n.0).collect();
+        levels.push(level);
+    }
+    let mut nodes = Vec::new();
+    let Some(&first) = rects.first() else {
+        return (items, nodes, levels.len());
+    };
+    let mut root = first;
+    for rect in &rects {
+        root.expand(rect);
+    }
+    nodes.push(FrozenNode {
+        rect: FrozenRect::new(&root),
+        children: 1..1 + rects.len() as u32,
+    });
+    if levels.is_empty() {
+        nodes[0].children = 0..items.len() as u32;
+    }
+    // top down, offsetting children by the start of the level below
+    for (i, level) in levels.iter().enumerate().rev() {
+        let offset = if i == 0 {
+            0
+        } else {
+            (nodes.len() + level.len()) as u32
+        };
+        for (rect, children) in level {
+            nodes.push(FrozenNode {
+                rect: FrozenRect::new(rect),
+                children: children.start + offset..children.end + offset,
+            });
+        }
+    }
+    (items, nodes, levels.len())
+}
+
+/// Groups `indexes` into the leaves of a pseudo priority R-tree over
+/// `rects`: the `fill` rects extending furthest towards each side become
+/// leaves of their own, and the rest are split in half by the side of
+/// `depth`, in turn. Ties go to the lower index.
+fn pseudo_pr_tree(
+    mut indexes: Vec<usize>,
+    rects: &[Rect],
+    fill: usize,
+    depth: usize,
+    out: &mut Vec<Vec<usize>>,
+) {
+    let key = |side: usize, i: usize| {
+        let r = &rects[i];
+        [r.min.x, r.min.y, -r.max.x, -r.max.y][side]
+    };
+    let cmp = |side: usize| {
+        move |&a: &usize, &b: &usize| key(side, a).total_cmp(&key(side, b)).then(a.cmp(&b))
+    };
+    for side in 0..5 {
+        if indexes.len() <= fill {
+            out.push(indexes);
+            return;
+        }
+        if side == 4 {
+            break;
+        }
+        indexes.select_nth_unstable_by(fill - 1, cmp(side));
+        let rest = indexes.split_off(fill);
+        out.push(indexes);
+        indexes = rest;
+    }
+    let mid = indexes.len() / 2;
+    indexes.select_nth_unstable_by(mid, cmp(depth % 4));
+    let right = indexes.split_off(mid);
+    pseudo_pr_tree(indexes, rects, fill, depth + 1, out);
+    pseudo_pr_tree(right, rects, fill, depth + 1, out);
+}
+
+impl<T, C: Coord> FrozenView<T, C> {
+    pub fn len(&self) -> usize {
+        match &self.layout {
+            Layout::Flat { items, .. } => items.len(),
+            Layout::Shared { len, .. } => *len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn rect(&self) -> Option<Rect> {
+        match &self.layout {
+            Layout::Flat { nodes, .. } => nodes.first().map(|root| root.rect.get()),
+            Layout::Shared { root, .. } => root.as_ref().map(|root| root.rect),
+        }
+    }
+
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = IterItem<'_, T>> {
+        let (items, mut stack, values) = match &self.layout {
+            Layout::Flat { items, .. } => (&items[..], Vec::new(), None),
+            Layout::Shared { root, values, .. } => {
+                let stack = root.iter().map(|root| root.nodes.iter()).collect();
+                (&[][..], stack, Some(values))
+            }
+        };
+        let shared = std::iter::from_fn(move || {
+            while let Some(children) = stack.last_mut() {
+                match children.next() {
+                    Some(Node::Item(entry)) => return Some(entry.iter_item(values?, 0.0)),
+                    Some(Node::Parent(child)) => stack.push(child.nodes.iter()),
+                    None => {
+                        stack.pop();
+                    }
+                }
+            }
+            None
+        });
+        Counted {
+            iter: items.iter().map(FrozenItem::iter_item).chain(shared),
+            len: self.len(),
+        }
+    }
+
+    /// Writes out the layout of the view: its nodes and items in the order
+    /// they are stored, with the coordinates as stored. Shared views are
+    /// written as their copies would be.
+    ///
+    /// Freezing is deterministic, so trees built by the same inserts give
+    /// the same bytes, which makes this a check that a shipped index is
+    /// reproducible.
+    pub fn to_bytes(&self) -> Vec<u8>
+    where
+        T: Encode,
+    {
+        let mut out = Vec::new();
+        let rect = |out: &mut Vec<u8>, r: &FrozenRect<C>| {
+            for c in r.min.iter().chain(&r.max) {
+                c.get().encode(out);
+            }
+        };
+        let nodes = |out: &mut Vec<u8>, nodes: &[FrozenNode<C>]| {
+            nodes.len().encode(out);
+            for node in nodes {
+                rect(out, &node.rect);
+                node.children.start.encode(out);
+                node.children.end.encode(out);
+            }
+        };
+        let item = |out: &mut Vec<u8>, r: &FrozenRect<C>, key: ItemKey, seq: u64, data: &T| {
+            rect(out, r);
+            key.index.encode(out);
+            key.gen.encode(out);
+            seq.encode(out);
+            data.encode(out);
+        };
+        self.height.encode(&mut out);
+        match &self.layout {
+            Layout::Flat { nodes: flat, items } => {
+                nodes(&mut out, flat);
+                items.len().encode(&mut out);
+                for it in items {
+                    item(&mut out, &it.rect, it.key, it.seq, &it.data);
+                }
+            }
+            Layout::Shared { root, values, .. } => {
+                let (flat, entries) = match root {
+                    Some(root) => flatten(root, self.height, &mut |entry| {
+                        (FrozenRect::new(&entry.rect), entry.index)
+                    }),
+                    None => (Vec::new(), Vec::new()),
+                };
+                nodes(&mut out, &flat);
+                entries.len().encode(&mut out);
+                for (r, index) in entries {
+                    let (key, seq) = (values.key(index), values.seq(index));
+                    item(&mut out, &r, key, seq, &values[index]);
+                }
+            }
+        }
+        out
+    }
+
+    pub fn search(&self, rect: Rect) -> FrozenSearchIterator<'_, T, C> {
+        let mut stack = Vec::new();
+        let mut shared = Vec::new();
+        match &self.layout {
+            Layout::Flat { nodes, .. } => {
+                if let Some(root) = nodes.first() {
+                    stack.reserve(self.height + 1);
+                    stack.push((self.height, range(&root.children)));
+                }
+            }
+            Layout::Shared { root, .. } => {
+                if let Some(root) = root {
+                    shared.reserve(self.height + 1);
+                    shared.push(root.nodes.iter());
+                }
+            }
+        }
+        FrozenSearchIterator {
+            view: self,
+            stack,
+            shared,
+            rect,
+        }
+    }
+}
+
+impl<T, C: Coord> FrozenItem<T, C> {
+    fn iter_item(&self) -> IterItem<'_, T> {
+        IterItem {
+            rect: self.rect.get(),
+            data: &self.data,
+            key: self.key,
+            seq: self.seq,
+            dist: Default::default(),
+        }
+    }
+}
+
+fn range(r: &Range<u32>) -> Range<usize> {
+    r.start as usize..r.end as usize
+}
+
+/// An iterator known to yield `len` more items.
+struct Counted<I> {
+    iter: I,
+    len: usize,
+}
+
+impl<I: Iterator> Iterator for Counted<I> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<I::Item> {
+        let item = self.iter.next()?;
+        self.len -= 1;
+        Some(item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<I: Iterator> ExactSizeIterator for Counted<I> {}
+
+pub struct FrozenSearchIterator<'a, T, C = f32> {
+    view: &'a FrozenView<T, C>,
+    /// Height of a node along with the children left to visit.
+    stack: Vec<(usize, Range<usize>)>,
+    /// Children left to visit of the nodes of a shared view.
+    shared: Vec<std::slice::Iter<'a, Node<T, BoxAlloc>>>,
+    rect: Rect,
+}
+
+impl<'a, T, C: Coord> Iterator for FrozenSearchIterator<'a, T, C> {
+    type Item = IterItem<'a, T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (nodes, items) = match &self.view.layout {
+            Layout::Flat { nodes, items } => (nodes, items),
+            Layout::Shared { values, .. } => {
+                while let Some(children) = self.shared.last_mut() {
+                    let Some(node) = children.next() else {
+                        self.shared.pop();
+                        continue;
+                    };
+                    if !node.rect().intersects(&self.rect) {
+                        continue;
+                    }
+                    match node {
+                        Node::Item(entry) => return Some(entry.iter_item(values, 0.0)),
+                        Node::Parent(child) => self.shared.push(child.nodes.iter()),
+                    }
+                }
+                return None;
+            }
+        };
+        while let Some((height, children)) = self.stack.last_mut() {
+            let Some(i) = children.next() else {
+                self.stack.pop();
+                continue;
+            };
+            if *height == 0 {
+                let item = &items[i];
+                if item.rect.get().intersects(&self.rect) {
+                    return Some(item.iter_item());
+                }
+            } else {
+                let node = &nodes[i];
+                if node.rect.get().intersects(&self.rect) {
+                    let height = *height - 1;
+                    self.stack.push((height, range(&node.children)));
+                }
+            }
+        }
+        None
+    }
+}
+
+/// A read-only index of rects, each with a `u32` id instead of a value.
+///
+/// Items take only the size of their rect and id, with no keys or sequence
+/// numbers, so billions of them fit where a [`FrozenView`] wouldn't. The
+/// payloads live elsewhere and are looked up by id, see
+/// [`search_with`](Self::search_with).
+pub struct EnvelopeIndex<C = f32> {
+    nodes: Vec<FrozenNode<C>>,
+    items: Vec<(FrozenRect<C>, u32)>,
+    height: usize,
+}
+
+impl<C: Coord> EnvelopeIndex<C> {
+    /// Packs `items` by Sort-Tile-Recursive into nodes of at most `fill`
+    /// children. Items with equal centers keep the order they are given in.
+    pub fn build(items: Vec<(Rect, u32)>, fill: usize) -> Self {
+        assert!(fill >= 2, "nodes need room for two children");
+        let rects = items.iter().map(|item| item.0).collect();
+        let (order, nodes, height) = layout(rects, fill, |rects| str_groups(rects, fill));
+        EnvelopeIndex {
+            nodes,
+            items: order
+                .iter()
+                .map(|&i| (FrozenRect::new(&items[i].0), items[i].1))
+                .collect(),
+            height,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn rect(&self) -> Option<Rect> {
+        self.nodes.first().map(|root| root.rect.get())
+    }
+
+    /// Searches for the ids of the items intersecting `rect`, along with
+    /// their rects.
+    pub fn search(&self, rect: Rect) -> impl Iterator<Item = (Rect, u32)> + '_ {
+        let mut stack = Vec::with_capacity(self.height + 1);
+        if let Some(root) = self.nodes.first() {
+            stack.push((self.height, range(&root.children)));
+        }
+        std::iter::from_fn(move || {
+            while let Some((height, children)) = stack.last_mut() {
+                let Some(i) = children.next() else {
+                    stack.pop();
+                    continue;
+                };
+                if *height == 0 {
+                    let (r, id) = &self.items[i];
+                    let r = r.get();
+                    if r.intersects(&rect) {
+                        return Some((r, *id));
+                    }
+                } else {
+                    let node = &self.nodes[i];
+                    if node.rect.get().intersects(&rect) {
+                        let height = *height - 1;
+                        stack.push((height, range(&node.children)));
+                    }
+                }
+            }
+            None
+        })
+    }
+
+    /// Like [`search`](Self::search), resolving the ids with `lookup`.
+    pub fn search_with<'a, T, F>(
+        &'a self,
+        rect: Rect,
+        mut lookup: F,
+    ) -> impl Iterator<Item = (Rect, T)> + 'a
+    where
+        F: FnMut(u32) -> T + 'a,
+    {
+        self.search(rect).map(move |(r, id)| (r, lookup(id)))
+    }
+}
+
+/// Groups `rects` into runs of `fill` by Sort-Tile-Recursive: sorted into
+/// vertical slices by x, and each slice cut into runs by y.
+fn str_groups(rects: &[Rect], fill: usize) -> Vec<Vec<usize>> {
+    let groups = rects.len().div_ceil(fill);
+    let slices = (groups as f64).sqrt().ceil() as usize;
+    let mut order: Vec<usize> = (0..rects.len()).collect();
+    order.sort_by_key(|&i| Ordered(rects[i].center().x));
+    let mut out = Vec::with_capacity(groups);
+    for slice in order.chunks_mut(groups.div_ceil(slices) * fill) {
+        slice.sort_by_key(|&i| Ordered(rects[i].center().y));
+        out.extend(slice.chunks(fill).map(|group| group.to_vec()));
+    }
+    out
+}
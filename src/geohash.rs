@@ -0,0 +1,92 @@
+//! Geohashes of items, for interop with systems that shard or cache by
+//! geohash.
+//!
+//! Points are longitude (x) and latitude (y) in degrees. Cells are
+//! half-open, a point on the edge of two cells belongs to the one to its
+//! east or north.
+
+use crate::{Alloc, IterItem, Point, RTree, Rect};
+use std::io::{self, Error, ErrorKind};
+
+const BASE32: &[u8; 32] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+/// The geohash of `point` with `precision` characters.
+pub fn encode(point: Point, precision: usize) -> String {
+    let mut lng = (-180.0, 180.0);
+    let mut lat = (-90.0, 90.0);
+    let mut hash = String::with_capacity(precision);
+    let mut even = true;
+    for _ in 0..precision {
+        let mut c = 0;
+        for _ in 0..5 {
+            // bits alternate between longitude and latitude
+            let (range, v) = if even {
+                (&mut lng, point.x as f64)
+            } else {
+                (&mut lat, point.y as f64)
+            };
+            let mid = (range.0 + range.1) / 2.0;
+            c <<= 1;
+            if v >= mid {
+                c |= 1;
+                range.0 = mid;
+            } else {
+                range.1 = mid;
+            }
+            even = !even;
+        }
+        hash.push(BASE32[c] as char);
+    }
+    hash
+}
+
+/// The cell of a geohash.
+pub fn decode(hash: &str) -> io::Result<Rect> {
+    let mut lng = (-180.0, 180.0);
+    let mut lat = (-90.0, 90.0);
+    let mut even = true;
+    for b in hash.bytes() {
+        let c = BASE32
+            .iter()
+            .position(|&x| x == b.to_ascii_lowercase())
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "invalid geohash"))?;
+        for bit in (0..5).rev() {
+            let range = if even { &mut lng } else { &mut lat };
+            let mid = (range.0 + range.1) / 2.0;
+            if c >> bit & 1 == 1 {
+                range.0 = mid;
+            } else {
+                range.1 = mid;
+            }
+            even = !even;
+        }
+    }
+    Ok(Rect::new(
+        Point::new(lng.0 as f32, lat.0 as f32),
+        Point::new(lng.1 as f32, lat.1 as f32),
+    ))
+}
+
+impl<T, A: Alloc<T>> RTree<T, A> {
+    /// Returns every item along with the geohash of the center of its rect.
+    pub fn geohashes(
+        &self,
+        precision: usize,
+    ) -> impl Iterator<Item = (String, IterItem<'_, T>)> + '_ {
+        self.iter()
+            .map(move |item| (encode(item.rect.center(), precision), item))
+    }
+
+    /// Finds the items whose rect centers have a geohash starting with
+    /// `prefix`.
+    pub fn search_geohash<'a>(
+        &'a self,
+        prefix: &'a str,
+    ) -> io::Result<impl Iterator<Item = IterItem<'a, T>> + 'a> {
+        let cell = decode(prefix)?;
+        let prefix = prefix.to_ascii_lowercase();
+        Ok(self
+            .search(cell)
+            .filter(move |item| encode(item.rect.center(), prefix.len()) == prefix))
+    }
+}
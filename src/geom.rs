@@ -0,0 +1,614 @@
+//! Points, rects and the distance metrics between them.
+
+use crate::journal::Encode;
+use std::cmp::Ordering;
+use std::fmt::Debug;
+use std::ops::{Add, Mul, Sub};
+
+/// A coordinate type, `f32` by default.
+///
+/// Nodes are chosen and split with the arithmetic of the coordinates
+/// themselves, and fall back to f64 where that could overflow or lose the
+/// order of nearly equal values.
+pub trait Scalar:
+    Copy
+    + PartialOrd
+    + Default
+    + Debug
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + Encode
+    + 'static
+{
+    /// Relative rounding error of the arithmetic, zero when it's exact.
+    const EPSILON: f64;
+
+    /// The lowest and highest values, infinite for floats, which bound
+    /// [`Rect::EVERYTHING`].
+    const MIN: Self;
+    const MAX: Self;
+
+    /// Areas, wide enough to hold the product of two coordinates.
+    type Area: Copy + PartialOrd + Default + Sub<Output = Self::Area>;
+
+    /// The area of the rect from `min` to `max`, zero if it has no width or
+    /// height, even an infinite one, or is empty. Saturates where the area
+    /// doesn't fit.
+    fn area(min: Point<Self>, max: Point<Self>) -> Self::Area;
+
+    fn to_f64(self) -> f64;
+
+    /// Rounds `v` to the nearest value, saturating at the bounds.
+    fn from_f64(v: f64) -> Self;
+}
+
+macro_rules! scalar {
+    ($($t:ty, $area:ty, $eps:expr, $min:expr, $max:expr, $mul:ident, $round:expr);*) => {$(
+        impl Scalar for $t {
+            const EPSILON: f64 = $eps;
+            const MIN: Self = $min;
+            const MAX: Self = $max;
+
+            type Area = $area;
+
+            fn area(min: Point<Self>, max: Point<Self>) -> $area {
+                let w = max.x as $area - min.x as $area;
+                let h = max.y as $area - min.y as $area;
+                if w > 0 as $area && h > 0 as $area {
+                    w.$mul(h)
+                } else {
+                    0 as $area
+                }
+            }
+
+            fn to_f64(self) -> f64 {
+                self as f64
+            }
+
+            fn from_f64(v: f64) -> Self {
+                // casts saturate, and round floats but truncate integers
+                $round(v) as $t
+            }
+        }
+    )*};
+}
+
+scalar!(
+    f32, f32, f32::EPSILON as f64, f32::NEG_INFINITY, f32::INFINITY, mul, f64::from;
+    f64, f64, f64::EPSILON, f64::NEG_INFINITY, f64::INFINITY, mul, f64::from;
+    i32, i64, 0.0, i32::MIN, i32::MAX, saturating_mul, f64::round;
+    i64, i128, 0.0, i64::MIN, i64::MAX, saturating_mul, f64::round
+);
+
+#[derive(Copy, Clone)]
+pub(crate) enum Axis {
+    X,
+    Y,
+}
+
+#[derive(Clone, Copy, PartialEq, Default, Debug)]
+pub struct Point<S = f32> {
+    pub x: S,
+    pub y: S,
+}
+
+impl<S: Scalar> Point<S> {
+    pub(crate) const fn new(x: S, y: S) -> Self {
+        Self { x, y }
+    }
+
+    pub(crate) fn on(self, axis: Axis) -> S {
+        match axis {
+            Axis::X => self.x,
+            Axis::Y => self.y,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Default, Debug)]
+pub struct Rect<S = f32> {
+    pub min: Point<S>,
+    pub max: Point<S>,
+}
+
+impl<S: Scalar> Rect<S> {
+    /// A rect covering every other, to search for all items.
+    pub const EVERYTHING: Self = Self::new(Point::new(S::MIN, S::MIN), Point::new(S::MAX, S::MAX));
+
+    /// A rect covering nothing, with its min above its max. It intersects
+    /// no rect, has no area, and leaves a rect unchanged when expanded by
+    /// it, which makes it the start of a union of rects.
+    pub const EMPTY: Self = Self::new(Point::new(S::MAX, S::MAX), Point::new(S::MIN, S::MIN));
+
+    pub const fn new(min: Point<S>, max: Point<S>) -> Self {
+        Self { min, max }
+    }
+
+    /// Whether `self` covers no point, having its min above its max on an
+    /// axis, like [`Rect::EMPTY`].
+    pub fn is_empty(&self) -> bool {
+        !(self.min.x <= self.max.x && self.min.y <= self.max.y)
+    }
+
+    pub const fn point(x: S, y: S) -> Self {
+        Self {
+            min: Point { x, y },
+            max: Point { x, y },
+        }
+    }
+
+    pub(crate) fn expand(&mut self, rect: &Self) {
+        if rect.min.x < self.min.x {
+            self.min.x = rect.min.x;
+        }
+        if rect.max.x > self.max.x {
+            self.max.x = rect.max.x;
+        }
+        if rect.min.y < self.min.y {
+            self.min.y = rect.min.y;
+        }
+        if rect.max.y > self.max.y {
+            self.max.y = rect.max.y;
+        }
+    }
+
+    pub(crate) fn larger_axis(&self) -> Axis {
+        let (x, y) = self.extent64();
+        if y > x {
+            Axis::Y
+        } else {
+            Axis::X
+        }
+    }
+
+    /// Determines whether `rect` is intersecting `self`, comparing the
+    /// bounds of their overlap so that empty rects intersect nothing.
+    pub(crate) fn intersects(&self, rect: &Self) -> bool {
+        max(self.min.x, rect.min.x) <= min(self.max.x, rect.max.x)
+            && max(self.min.y, rect.min.y) <= min(self.max.y, rect.max.y)
+    }
+
+    /// Like `intersects`, with `self` excluding its upper and right edges.
+    pub(crate) fn intersects_half_open(&self, rect: &Self) -> bool {
+        self.min.x <= rect.max.x
+            && rect.min.x < self.max.x
+            && self.min.y <= rect.max.y
+            && rect.min.y < self.max.y
+    }
+
+    /// Determines whether `rect` is inside `self`, edges included.
+    pub(crate) fn contains(&self, rect: &Self) -> bool {
+        self.min.x <= rect.min.x
+            && rect.max.x <= self.max.x
+            && self.min.y <= rect.min.y
+            && rect.max.y <= self.max.y
+    }
+
+    /// Determines whether `rect` is on the lower/upper/left/right edge of `self`.
+    ///
+    /// Assumes `rect` is intersecting.
+    pub(crate) fn on_edge(&self, rect: &Self) -> bool {
+        // NaN coordinates count as on the edge
+        let above = |a: S, b: S| a.partial_cmp(&b).is_some_and(Ordering::is_gt);
+        if !above(rect.min.x, self.min.x) || !above(self.max.x, rect.max.x) {
+            return true;
+        }
+        if !above(rect.min.y, self.min.y) || !above(self.max.y, rect.max.y) {
+            return true;
+        }
+        false
+    }
+
+    pub(crate) fn area(&self) -> S::Area {
+        S::area(self.min, self.max)
+    }
+
+    pub(crate) fn unioned_area(&self, rect: &Self) -> S::Area {
+        let mut union = *self;
+        union.expand(rect);
+        union.area()
+    }
+
+    /// Width and height in f64, where the difference of two f32 is exact.
+    pub(crate) fn extent64(&self) -> (f64, f64) {
+        (
+            self.max.x.to_f64() - self.min.x.to_f64(),
+            self.max.y.to_f64() - self.min.y.to_f64(),
+        )
+    }
+
+    /// The area in f64, zero if `self` has no width or height.
+    pub(crate) fn area64(&self) -> f64 {
+        self.overlap64(self)
+    }
+
+    /// The center in f64, which can't overflow.
+    pub(crate) fn center64(&self) -> (f64, f64) {
+        (
+            (self.min.x.to_f64() + self.max.x.to_f64()) / 2.0,
+            (self.min.y.to_f64() + self.max.y.to_f64()) / 2.0,
+        )
+    }
+
+    /// The area of the intersection of `self` and `rect`, in f64.
+    pub(crate) fn overlap64(&self, rect: &Self) -> f64 {
+        let w = min(self.max.x, rect.max.x).to_f64() - max(self.min.x, rect.min.x).to_f64();
+        let h = min(self.max.y, rect.max.y).to_f64() - max(self.min.y, rect.min.y).to_f64();
+        if w > 0.0 && h > 0.0 {
+            w * h
+        } else {
+            0.0
+        }
+    }
+
+    /// The fraction of `self` covered by `rect`, along each axis of non-zero
+    /// extent.
+    pub(crate) fn overlap_fraction(&self, rect: &Self) -> f64 {
+        let axis = |lo0: S, hi0: S, lo: S, hi: S| {
+            let len = hi0.to_f64() - lo0.to_f64();
+            if len > 0.0 {
+                let overlap = min(hi0, hi).to_f64() - max(lo0, lo).to_f64();
+                overlap.max(0.0) / len
+            } else if lo <= lo0 && lo0 <= hi {
+                1.0
+            } else {
+                0.0
+            }
+        };
+        axis(self.min.x, self.max.x, rect.min.x, rect.max.x)
+            * axis(self.min.y, self.max.y, rect.min.y, rect.max.y)
+    }
+
+    /// Rounds the coordinates of `self` to the nearest multiples of `cell`.
+    pub(crate) fn snapped(&self, cell: f32) -> Self {
+        let cell = cell as f64;
+        let snap = |v: S| S::from_f64((v.to_f64() / cell).round() * cell);
+        Rect::new(
+            Point::new(snap(self.min.x), snap(self.min.y)),
+            Point::new(snap(self.max.x), snap(self.max.y)),
+        )
+    }
+}
+
+impl Rect {
+    /// A rect covering the whole plane, the same as [`Rect::EVERYTHING`].
+    pub const INFINITY: Self = Self::EVERYTHING;
+
+    pub(crate) fn center(&self) -> Point {
+        Point::new(
+            (self.min.x + self.max.x) / 2.0,
+            (self.min.y + self.max.y) / 2.0,
+        )
+    }
+
+    /// Squared distance between the closest points of `self` and `rect`,
+    /// zero if they intersect.
+    pub fn box_dist(&self, rect: &Rect) -> f32 {
+        let x = max(
+            0.0,
+            max(self.min.x, rect.min.x) - min(self.max.x, rect.max.x),
+        );
+        let y = max(
+            0.0,
+            max(self.min.y, rect.min.y) - min(self.max.y, rect.max.y),
+        );
+        x * x + y * y
+    }
+
+    /// An upper bound of the squared distance between `rect` and the
+    /// closest of the items covered by `self`, assuming each edge of `self`
+    /// touches one of them.
+    ///
+    /// The distance to a segment is largest at one of its ends, so the item
+    /// on an edge is no farther than the farther corner of that edge.
+    pub(crate) fn minmax_dist(&self, rect: &Rect) -> f32 {
+        let corners = [
+            Rect::point(self.min.x, self.min.y),
+            Rect::point(self.max.x, self.min.y),
+            Rect::point(self.max.x, self.max.y),
+            Rect::point(self.min.x, self.max.y),
+        ];
+        let dists = corners.map(|c| c.box_dist(rect));
+        (0..4).fold(f32::INFINITY, |d, i| {
+            min(d, max(dists[i], dists[(i + 1) % 4]))
+        })
+    }
+
+    /// Returns the earliest time in `[0, 1]` at which `self`, moving by
+    /// `motion` over the unit of time, intersects `rect`.
+    pub fn time_of_impact(&self, motion: Point, rect: &Rect) -> Option<f32> {
+        let mut enter = 0.0;
+        let mut exit = 1.0;
+        for axis in [Axis::X, Axis::Y] {
+            let d = motion.on(axis);
+            if d == 0.0 {
+                if self.min.on(axis) > rect.max.on(axis) || self.max.on(axis) < rect.min.on(axis) {
+                    return None;
+                }
+                continue;
+            }
+            let t0 = (rect.min.on(axis) - self.max.on(axis)) / d;
+            let t1 = (rect.max.on(axis) - self.min.on(axis)) / d;
+            enter = max(enter, min(t0, t1));
+            exit = min(exit, max(t0, t1));
+            if enter > exit {
+                return None;
+            }
+        }
+        Some(enter)
+    }
+
+    /// Returns the shortest translation that moves `self` out of `rect`, or
+    /// `None` if they don't intersect.
+    pub fn mtv(&self, rect: &Rect) -> Option<Point> {
+        if !self.intersects(rect) {
+            return None;
+        }
+        let push = |axis| {
+            let a = self.min.on(axis) + self.max.on(axis);
+            let b = rect.min.on(axis) + rect.max.on(axis);
+            if a < b {
+                rect.min.on(axis) - self.max.on(axis)
+            } else {
+                rect.max.on(axis) - self.min.on(axis)
+            }
+        };
+        let x = push(Axis::X);
+        let y = push(Axis::Y);
+        if x.abs() <= y.abs() {
+            Some(Point::new(x, 0.0))
+        } else {
+            Some(Point::new(0.0, y))
+        }
+    }
+
+    /// Grows `self` by `pad` on every side.
+    pub(crate) fn padded(&self, pad: Point) -> Rect {
+        Rect::new(
+            Point::new(self.min.x - pad.x, self.min.y - pad.y),
+            Point::new(self.max.x + pad.x, self.max.y + pad.y),
+        )
+    }
+}
+
+/// A circle enclosing the items below a node.
+///
+/// For items spread along a diagonal, it bounds their distance from a point
+/// more tightly than the node's rect, which lets nearest-neighbor searches
+/// skip more nodes.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Circle {
+    pub(crate) center: Point,
+    pub(crate) radius: f32,
+}
+
+impl Circle {
+    /// The circle through the corners of `rect`.
+    pub(crate) fn of<S: Scalar>(rect: &Rect<S>) -> Self {
+        let (w, h) = rect.extent64();
+        Circle::fit(
+            (rect.min.x.to_f64() + w / 2.0, rect.min.y.to_f64() + h / 2.0),
+            (w * w + h * h).sqrt() / 2.0,
+        )
+    }
+
+    /// Rounds a circle to f32, keeping it enclosing the original.
+    pub(crate) fn fit((x, y): (f64, f64), radius: f64) -> Self {
+        let center = Point::new(x as f32, y as f32);
+        let (dx, dy) = (center.x as f64 - x, center.y as f64 - y);
+        let radius = radius + (dx * dx + dy * dy).sqrt();
+        Circle {
+            center,
+            radius: (radius * (1.0 + f32::EPSILON as f64)) as f32,
+        }
+    }
+
+    /// Grows `self` into the smallest circle enclosing both circles.
+    pub(crate) fn expand(&mut self, other: &Circle) {
+        let (x, y) = (self.center.x as f64, self.center.y as f64);
+        let (dx, dy) = (other.center.x as f64 - x, other.center.y as f64 - y);
+        let d = (dx * dx + dy * dy).sqrt();
+        let (r0, r1) = (self.radius as f64, other.radius as f64);
+        if d + r1 <= r0 {
+            return;
+        }
+        if d + r0 <= r1 {
+            *self = *other;
+            return;
+        }
+        let r = (d + r0 + r1) / 2.0;
+        let t = (r - r0) / d;
+        *self = Circle::fit((x + dx * t, y + dy * t), r);
+    }
+
+    /// Squared distance from `point` to the circle, zero inside of it, which
+    /// is never more than the distance of what the circle encloses.
+    pub(crate) fn dist(&self, point: Point) -> f32 {
+        let d = self.gap(point);
+        (d * d) as f32
+    }
+
+    /// Distance from `point` to the circle, zero inside of it.
+    pub(crate) fn gap(&self, point: Point) -> f64 {
+        let dx = point.x as f64 - self.center.x as f64;
+        let dy = point.y as f64 - self.center.y as f64;
+        let d = (dx * dx + dy * dy).sqrt() - self.radius as f64;
+        // leave room for the rounding of the distances of the items
+        max(0.0, d as f32) as f64 * (1.0 - 1e-6)
+    }
+}
+
+/// A distance for nearest-neighbor searches, see
+/// [`RTree::knn_batch_with`].
+pub trait Metric {
+    /// Distance from `point` to an item's rect.
+    fn dist(&self, point: Point, rect: &Rect) -> f32;
+
+    /// A lower bound of the distance from `point` to the items covered by a
+    /// node's rect. Defaults to `dist`, which is right for any metric where
+    /// the closest point of a rect is no farther than the rects it covers.
+    fn lower_bound(&self, point: Point, rect: &Rect) -> f32 {
+        self.dist(point, rect)
+    }
+
+    /// A lower bound of the distance from `point` to the items within the
+    /// circle at `center` of `radius`, see [`Config::circles`]. Defaults to
+    /// zero, which never prunes anything.
+    fn circle_bound(&self, point: Point, center: Point, radius: f32) -> f32 {
+        let _ = (point, center, radius);
+        0.0
+    }
+}
+
+/// The squared euclidean distance, as in [`Rect::box_dist`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Euclidean;
+
+/// The sum of the distances along the axes.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Manhattan;
+
+/// The largest of the distances along the axes.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Chebyshev;
+
+/// Distances from `point` to `rect` along the axes, zero within.
+pub(crate) fn gaps(point: Point, rect: &Rect) -> (f32, f32) {
+    let x = max(0.0, max(rect.min.x - point.x, point.x - rect.max.x));
+    let y = max(0.0, max(rect.min.y - point.y, point.y - rect.max.y));
+    (x, y)
+}
+
+impl Metric for Euclidean {
+    fn dist(&self, point: Point, rect: &Rect) -> f32 {
+        rect.box_dist(&Rect::point(point.x, point.y))
+    }
+
+    fn circle_bound(&self, point: Point, center: Point, radius: f32) -> f32 {
+        Circle { center, radius }.dist(point)
+    }
+}
+
+impl Metric for Manhattan {
+    fn dist(&self, point: Point, rect: &Rect) -> f32 {
+        let (x, y) = gaps(point, rect);
+        x + y
+    }
+
+    fn circle_bound(&self, point: Point, center: Point, radius: f32) -> f32 {
+        // never shorter than the euclidean distance
+        Circle { center, radius }.gap(point) as f32
+    }
+}
+
+impl Metric for Chebyshev {
+    fn dist(&self, point: Point, rect: &Rect) -> f32 {
+        let (x, y) = gaps(point, rect);
+        max(x, y)
+    }
+
+    fn circle_bound(&self, point: Point, center: Point, radius: f32) -> f32 {
+        // never shorter than the euclidean distance over the square root of 2
+        (Circle { center, radius }.gap(point) * std::f64::consts::FRAC_1_SQRT_2) as f32
+    }
+}
+
+/// Geometry that is indexed by its envelope but can answer exact point
+/// containment, such as polygons.
+pub trait Polygon {
+    fn contains_point(&self, point: Point) -> bool;
+}
+
+/// A closed ring of vertices, tested with the even-odd rule.
+impl Polygon for [Point] {
+    fn contains_point(&self, point: Point) -> bool {
+        let mut inside = false;
+        let mut j = self.len().wrapping_sub(1);
+        for (i, a) in self.iter().enumerate() {
+            let b = self[j];
+            if (a.y > point.y) != (b.y > point.y)
+                && point.x < (b.x - a.x) * (point.y - a.y) / (b.y - a.y) + a.x
+            {
+                inside = !inside;
+            }
+            j = i;
+        }
+        inside
+    }
+}
+
+impl Polygon for Vec<Point> {
+    fn contains_point(&self, point: Point) -> bool {
+        self.as_slice().contains_point(point)
+    }
+}
+
+#[derive(PartialEq)]
+pub(crate) struct Ordered(pub(crate) f32);
+
+impl Eq for Ordered {}
+
+impl PartialOrd for Ordered {
+    fn partial_cmp(&self, other: &Ordered) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Ordered {
+    fn cmp(&self, other: &Ordered) -> Ordering {
+        if self.0 < other.0 {
+            Ordering::Less
+        } else if self.0 > other.0 {
+            Ordering::Greater
+        } else {
+            Ordering::Equal
+        }
+    }
+}
+
+/// Position of `p` along a Hilbert curve laid over `extent` on a 2^16 grid.
+pub(crate) fn hilbert(extent: &Rect, p: Point) -> u32 {
+    const N: u32 = 1 << 16;
+    let scale = |v: f32, lo: f32, hi: f32| {
+        if hi > lo {
+            ((v - lo) / (hi - lo) * (N - 1) as f32) as u32
+        } else {
+            0
+        }
+    };
+    let mut x = scale(p.x, extent.min.x, extent.max.x);
+    let mut y = scale(p.y, extent.min.y, extent.max.y);
+    let mut d = 0;
+    let mut s = N / 2;
+    while s > 0 {
+        let rx = (x & s > 0) as u32;
+        let ry = (y & s > 0) as u32;
+        d += s * s * ((3 * rx) ^ ry);
+        if ry == 0 {
+            if rx == 1 {
+                x = N - 1 - x;
+                y = N - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    d
+}
+
+pub(crate) fn min<S: PartialOrd>(a: S, b: S) -> S {
+    if a < b {
+        a
+    } else {
+        b
+    }
+}
+
+pub(crate) fn max<S: PartialOrd>(a: S, b: S) -> S {
+    if a > b {
+        a
+    } else {
+        b
+    }
+}
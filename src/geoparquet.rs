@@ -0,0 +1,72 @@
+//! Loading GeoParquet files.
+//!
+//! The primary geometry column is found through the `geo` file metadata and
+//! must use the WKB encoding. Envelopes are computed from the WKB values,
+//! rows with a null or empty geometry are skipped.
+
+use crate::{wkb, Alloc, RTree};
+use arrow_array::cast::AsArray;
+use arrow_array::{Array, RecordBatch};
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::errors::{ParquetError, Result};
+use parquet::file::reader::ChunkReader;
+
+impl<T, A: Alloc<T>> RTree<T, A> {
+    /// Inserts every row of a GeoParquet file.
+    ///
+    /// `f` receives each record batch along with the row index and returns
+    /// the item for that row.
+    pub fn load_geoparquet<R, F>(&mut self, reader: R, mut f: F) -> Result<()>
+    where
+        R: ChunkReader + 'static,
+        F: FnMut(&RecordBatch, usize) -> T,
+    {
+        let builder = ParquetRecordBatchReaderBuilder::try_new(reader)?;
+        let column = builder
+            .metadata()
+            .file_metadata()
+            .key_value_metadata()
+            .and_then(|kv| kv.iter().find(|kv| kv.key == "geo"))
+            .and_then(|kv| kv.value.as_deref())
+            .and_then(primary_column)
+            .unwrap_or("geometry")
+            .to_string();
+        for batch in builder.build()? {
+            let batch = batch?;
+            let geometry = batch
+                .column_by_name(&column)
+                .ok_or_else(|| general(format!("missing geometry column {column:?}")))?;
+            for row in 0..batch.num_rows() {
+                if geometry.is_null(row) {
+                    continue;
+                }
+                let value = if let Some(array) = geometry.as_binary_opt::<i32>() {
+                    array.value(row)
+                } else if let Some(array) = geometry.as_binary_opt::<i64>() {
+                    array.value(row)
+                } else {
+                    return Err(general("geometry column is not WKB encoded".into()));
+                };
+                let rect = wkb::envelope(value).map_err(|err| general(err.to_string()))?;
+                if let Some(rect) = rect {
+                    self.insert(rect, f(&batch, row));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Extracts `primary_column` from the `geo` metadata JSON without a full
+/// JSON parser; column names with escapes fall back to the default.
+fn primary_column(geo: &str) -> Option<&str> {
+    let rest = &geo[geo.find("\"primary_column\"")? + 16..];
+    let rest = rest.trim_start().strip_prefix(':')?.trim_start();
+    let rest = rest.strip_prefix('"')?;
+    let name = &rest[..rest.find('"')?];
+    (!name.contains('\\')).then_some(name)
+}
+
+fn general(msg: String) -> ParquetError {
+    ParquetError::General(msg)
+}
@@ -0,0 +1,144 @@
+//! An index storing each distinct rect once along with all of its values.
+//!
+//! When many values share an identical rect, such as events binned by grid
+//! cell, a plain tree holds an entry per value and compares the same rect
+//! for each of them while searching. Here the tree holds an entry per rect
+//! and a search yields the values of a rect together.
+//!
+//! Removing a value scans the values of its rect, unless they're kept in
+//! order with [`Grouped::insert_sorted`], which lets
+//! [`Grouped::remove_sorted`] find it by binary search.
+
+use crate::{Alloc, ItemKey, RTree, Rect};
+use std::collections::HashMap;
+
+pub struct Grouped<T, A: Alloc<Vec<T>>> {
+    tree: RTree<Vec<T>, A>,
+    /// The tree item of each rect, by the bits of its coordinates.
+    keys: HashMap<[u32; 4], ItemKey>,
+    length: usize,
+}
+
+/// Hashable bits of a rect, with negative zeros made positive so that equal
+/// rects have equal bits.
+fn bits(rect: &Rect) -> [u32; 4] {
+    [rect.min.x, rect.min.y, rect.max.x, rect.max.y].map(|v| (v + 0.0).to_bits())
+}
+
+impl<T, A: Alloc<Vec<T>>> Grouped<T, A> {
+    pub fn new(alloc: A) -> Self {
+        Grouped {
+            tree: RTree::new(alloc),
+            keys: HashMap::new(),
+            length: 0,
+        }
+    }
+
+    /// Number of values.
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    /// Number of distinct rects.
+    pub fn rects(&self) -> usize {
+        self.tree.len()
+    }
+
+    pub fn insert(&mut self, rect: Rect, data: T) {
+        self.values_mut(rect).push(data);
+    }
+
+    /// Inserts a value into `rect` after the values not greater than it, so
+    /// that the values of a rect inserted only this way stay in order.
+    pub fn insert_sorted(&mut self, rect: Rect, data: T)
+    where
+        T: Ord,
+    {
+        let values = self.values_mut(rect);
+        let i = values.partition_point(|v| *v <= data);
+        values.insert(i, data);
+    }
+
+    /// The values of `rect`, adding the rect if it's new and counting the
+    /// value about to be added.
+    fn values_mut(&mut self, rect: Rect) -> &mut Vec<T> {
+        self.length += 1;
+        let key = match self.keys.get(&bits(&rect)) {
+            Some(&key) => key,
+            None => {
+                self.tree.insert(rect, Vec::new());
+                let key = self
+                    .tree
+                    .search(rect)
+                    .find(|item| item.rect == rect)
+                    .unwrap()
+                    .key;
+                self.keys.insert(bits(&rect), key);
+                key
+            }
+        };
+        self.tree.get_mut(key).unwrap()
+    }
+
+    /// Removes a value from `rect`, dropping the rect once it has none left.
+    ///
+    /// The other values of `rect` keep their order.
+    pub fn remove(&mut self, rect: Rect, data: &T) -> Option<T>
+    where
+        T: PartialEq,
+    {
+        self.remove_with(rect, |values| values.iter().position(|v| v == data))
+    }
+
+    /// Like [`remove`](Self::remove), finding the value by binary search,
+    /// which requires the values of `rect` to be in order, see
+    /// [`insert_sorted`](Self::insert_sorted).
+    pub fn remove_sorted(&mut self, rect: Rect, data: &T) -> Option<T>
+    where
+        T: Ord,
+    {
+        self.remove_with(rect, |values| values.binary_search(data).ok())
+    }
+
+    fn remove_with<F>(&mut self, rect: Rect, find: F) -> Option<T>
+    where
+        F: FnOnce(&[T]) -> Option<usize>,
+    {
+        let key = *self.keys.get(&bits(&rect))?;
+        let values = self.tree.get_mut(key).unwrap();
+        let i = find(values)?;
+        let data = values.remove(i);
+        if values.is_empty() {
+            // the only empty group, so it's the one removed
+            self.tree.remove_where(rect, |values| values.is_empty());
+            self.keys.remove(&bits(&rect));
+        }
+        self.length -= 1;
+        Some(data)
+    }
+
+    /// The values of exactly `rect`.
+    pub fn get(&self, rect: Rect) -> &[T] {
+        match self.keys.get(&bits(&rect)) {
+            Some(&key) => self.tree.get(key).unwrap(),
+            None => &[],
+        }
+    }
+
+    /// Searches for the rects intersecting `rect`, with their values.
+    pub fn search(&self, rect: Rect) -> impl Iterator<Item = (Rect, &[T])> + '_ {
+        self.tree
+            .search(rect)
+            .map(|item| (item.rect, item.data.as_slice()))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Rect, &[T])> + '_ {
+        self.tree
+            .iter()
+            .map(|item| (item.rect, item.data.as_slice()))
+    }
+}
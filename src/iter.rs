@@ -0,0 +1,655 @@
+//! Iterators over the items of a tree.
+
+use crate::geom::Ordered;
+use crate::node::Slab;
+use crate::{Alloc, ItemKey, Node, Parent, Point, Rect, Scalar};
+use std::borrow::BorrowMut;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::iter::FusedIterator;
+use std::marker::PhantomData;
+use std::slice::Iter;
+use std::sync::atomic;
+
+pub struct IterItem<'n, T, S = f32> {
+    pub rect: Rect<S>,
+    pub data: &'n T,
+    pub key: ItemKey,
+    /// Sequence number of the item, increasing with every insert into the
+    /// tree, so newer items have larger numbers.
+    pub seq: u64,
+    pub dist: f32,
+}
+
+pub(crate) struct StackNode<'a, T, A: Alloc<T, S>, S = f32> {
+    nodes: Iter<'a, Node<T, A, S>>,
+}
+
+impl<'a, T, A: Alloc<T, S>, S: Scalar> StackNode<'a, T, A, S> {
+    pub(crate) fn new_stack(
+        root: &'a Option<Node<T, A, S>>,
+        height: usize,
+    ) -> Vec<StackNode<'a, T, A, S>> {
+        let mut stack = Vec::with_capacity(height + 1);
+        if let Some(Node::Parent(parent)) = root {
+            stack.push(StackNode {
+                nodes: parent.nodes.iter(),
+            });
+        }
+        stack
+    }
+}
+
+// scan iterator -- visits every item, so the remaining count is known.
+
+pub struct ScanIterator<'a, T, A: Alloc<T, S>, S = f32> {
+    stack: Vec<StackNode<'a, T, A, S>>,
+    /// Walks the tree from the other end, for `next_back`. Both ends stop
+    /// once `remaining` is zero, so they never yield the same item.
+    back: Vec<StackNode<'a, T, A, S>>,
+    values: &'a Slab<T>,
+    remaining: usize,
+}
+
+impl<'a, T, A: Alloc<T, S>, S: Scalar> ScanIterator<'a, T, A, S> {
+    pub(crate) fn new(
+        root: &'a Option<Node<T, A, S>>,
+        values: &'a Slab<T>,
+        height: usize,
+        length: usize,
+    ) -> Self {
+        Self {
+            stack: StackNode::new_stack(root, height),
+            back: StackNode::new_stack(root, height),
+            values,
+            remaining: length,
+        }
+    }
+}
+
+impl<'a, T, A: Alloc<T, S>, S: Scalar> Iterator for ScanIterator<'a, T, A, S> {
+    type Item = IterItem<'a, T, S>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        while let Some(stack) = self.stack.last_mut() {
+            match stack.nodes.next() {
+                Some(Node::Item(data)) => {
+                    self.remaining -= 1;
+                    return Some(data.iter_item(self.values, Default::default()));
+                }
+                Some(Node::Parent(nodes)) => {
+                    self.stack.push(StackNode {
+                        nodes: nodes.nodes.iter(),
+                    });
+                }
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+        None
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, T, A: Alloc<T, S>, S: Scalar> DoubleEndedIterator for ScanIterator<'a, T, A, S> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        while let Some(stack) = self.back.last_mut() {
+            match stack.nodes.next_back() {
+                Some(Node::Item(data)) => {
+                    self.remaining -= 1;
+                    return Some(data.iter_item(self.values, Default::default()));
+                }
+                Some(Node::Parent(nodes)) => {
+                    self.back.push(StackNode {
+                        nodes: nodes.nodes.iter(),
+                    });
+                }
+                None => {
+                    self.back.pop();
+                }
+            }
+        }
+        None
+    }
+}
+
+impl<'a, T, A: Alloc<T, S>, S: Scalar> ExactSizeIterator for ScanIterator<'a, T, A, S> {}
+
+impl<'a, T, A: Alloc<T, S>, S: Scalar> FusedIterator for ScanIterator<'a, T, A, S> {}
+
+// search iterator -- much like the scan iterator but with a intersects guard.
+
+pub struct SearchIterator<'a, T, A: Alloc<T, S>, S = f32> {
+    stack: Vec<StackNode<'a, T, A, S>>,
+    values: &'a Slab<T>,
+    rect: Rect<S>,
+    half_open: bool,
+    profile: bool,
+    /// Skips items with a sequence number up to this, see
+    /// [`RTree::search_recent`].
+    pub(crate) since: Option<u64>,
+}
+
+impl<'a, T, A: Alloc<T, S>, S: Scalar> SearchIterator<'a, T, A, S> {
+    pub(crate) fn new(
+        root: &'a Option<Node<T, A, S>>,
+        values: &'a Slab<T>,
+        height: usize,
+        rect: Rect<S>,
+        half_open: bool,
+        profile: bool,
+    ) -> Self {
+        let stack = StackNode::new_stack(root, height);
+        #[cfg(feature = "stats")]
+        crate::stats::count(|c| c.visited += stack.len() as u64);
+        Self {
+            stack,
+            values,
+            rect,
+            half_open,
+            profile,
+            since: None,
+        }
+    }
+}
+
+impl<'a, T, A: Alloc<T, S>, S: Scalar> Iterator for SearchIterator<'a, T, A, S> {
+    type Item = IterItem<'a, T, S>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        'outer: while let Some(stack) = self.stack.last_mut() {
+            for node in stack.nodes.by_ref() {
+                if !node.rect().intersects(&self.rect) {
+                    continue;
+                }
+                match node {
+                    Node::Item(data) => {
+                        if self.half_open && !data.rect.intersects_half_open(&self.rect) {
+                            continue;
+                        }
+                        let item = data.iter_item(self.values, Default::default());
+                        if self.since.is_some_and(|since| item.seq <= since) {
+                            continue;
+                        }
+                        return Some(item);
+                    }
+                    Node::Parent(nodes) => {
+                        if self.since.is_some_and(|since| nodes.nodes.newest <= since) {
+                            continue;
+                        }
+                        if self.profile {
+                            nodes.nodes.hits.fetch_add(1, atomic::Ordering::Relaxed);
+                        }
+                        #[cfg(feature = "stats")]
+                        crate::stats::count(|c| c.visited += 1);
+                        self.stack.push(StackNode {
+                            nodes: nodes.nodes.iter(),
+                        });
+                        continue 'outer;
+                    }
+                }
+            }
+            self.stack.pop();
+        }
+        None
+    }
+}
+
+/// A part of a result of [`RTree::refine`](crate::RTree::refine).
+pub enum Refinement<'a, T, S = f32> {
+    /// A node intersecting the query with `count` items below it, some of
+    /// which may lie outside the query.
+    Node {
+        rect: Rect<S>,
+        count: usize,
+    },
+    Item(IterItem<'a, T, S>),
+}
+
+/// Yields the result of a search a level of the tree at a time, from the
+/// nodes below the root down to the items.
+pub struct RefineIterator<'a, T, A: Alloc<T, S>, S = f32> {
+    level: Vec<&'a Node<T, A, S>>,
+    values: &'a Slab<T>,
+    rect: Rect<S>,
+    half_open: bool,
+}
+
+impl<'a, T, A: Alloc<T, S>, S: Scalar> RefineIterator<'a, T, A, S> {
+    pub(crate) fn new(
+        root: &'a Option<Node<T, A, S>>,
+        values: &'a Slab<T>,
+        rect: Rect<S>,
+        half_open: bool,
+    ) -> Self {
+        let mut iter = Self {
+            level: Vec::new(),
+            values,
+            rect,
+            half_open,
+        };
+        if let Some(Node::Parent(root)) = root {
+            if root.rect.intersects(&rect) {
+                iter.level = iter.below(&[root]);
+            }
+        }
+        iter
+    }
+
+    /// The children of `parents` intersecting the query.
+    fn below(&self, parents: &[&'a Parent<T, A, S>]) -> Vec<&'a Node<T, A, S>> {
+        parents
+            .iter()
+            .flat_map(|parent| parent.nodes.iter())
+            .filter(|node| node.rect().intersects(&self.rect))
+            .collect()
+    }
+}
+
+impl<'a, T, A: Alloc<T, S>, S: Scalar> Iterator for RefineIterator<'a, T, A, S> {
+    /// The whole result at the next level, replacing the previous one.
+    type Item = Vec<Refinement<'a, T, S>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.level.is_empty() {
+            return None;
+        }
+        let mut out = Vec::with_capacity(self.level.len());
+        let mut parents = Vec::new();
+        for node in self.level.drain(..) {
+            match node {
+                Node::Item(entry) => {
+                    if !self.half_open || entry.rect.intersects_half_open(&self.rect) {
+                        out.push(Refinement::Item(entry.iter_item(self.values, 0.0)));
+                    }
+                }
+                Node::Parent(parent) => {
+                    out.push(Refinement::Node {
+                        rect: parent.rect,
+                        count: parent.nodes.count,
+                    });
+                    parents.push(parent);
+                }
+            }
+        }
+        self.level = self.below(&parents);
+        Some(out)
+    }
+}
+
+impl<'a, T, A: Alloc<T, S>, S: Scalar> FusedIterator for RefineIterator<'a, T, A, S> {}
+
+/// The order of the results of [`RTree::search_ordered`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Order {
+    /// By the min x of the rects.
+    MinX,
+    /// By the distance from a point, nearest first.
+    Near(Point),
+    /// By the order items were inserted in, which is only known for items,
+    /// so every matching item is queued before the first one is returned.
+    Insertion,
+}
+
+impl Order {
+    /// A lower bound of the keys of the items below a node, or an item's key.
+    pub(crate) fn key(&self, rect: &Rect) -> f32 {
+        match self {
+            Order::MinX => rect.min.x,
+            Order::Near(p) => Rect::point(p.x, p.y).box_dist(rect),
+            Order::Insertion => 0.0,
+        }
+    }
+}
+
+pub(crate) struct Pending<'a, T, A: Alloc<T>> {
+    key: f32,
+    node: &'a Node<T, A>,
+    /// Insertion order of an item, zero for parents.
+    seq: u64,
+}
+
+impl<'a, T, A: Alloc<T>> PartialEq for Pending<'a, T, A> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<'a, T, A: Alloc<T>> Eq for Pending<'a, T, A> {}
+
+impl<'a, T, A: Alloc<T>> PartialOrd for Pending<'a, T, A> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<'a, T, A: Alloc<T>> Ord for Pending<'a, T, A> {
+    /// Reversed, so that the heap pops the smallest key first, parents
+    /// before items and then items in insertion order.
+    fn cmp(&self, other: &Self) -> Ordering {
+        let leaf = |n: &Node<T, A>| matches!(n, Node::Item(_));
+        self.key
+            .total_cmp(&other.key)
+            .then_with(|| leaf(self.node).cmp(&leaf(other.node)))
+            .then_with(|| self.seq.cmp(&other.seq))
+            .reverse()
+    }
+}
+
+pub struct OrderedSearchIterator<'a, T, A: Alloc<T>> {
+    heap: BinaryHeap<Pending<'a, T, A>>,
+    values: &'a Slab<T>,
+    rect: Rect,
+    order: Order,
+    half_open: bool,
+}
+
+impl<'a, T, A: Alloc<T>> OrderedSearchIterator<'a, T, A> {
+    pub(crate) fn new(
+        root: &'a Option<Node<T, A>>,
+        values: &'a Slab<T>,
+        rect: Rect,
+        order: Order,
+        half_open: bool,
+    ) -> Self {
+        let mut iter = OrderedSearchIterator {
+            heap: BinaryHeap::new(),
+            values,
+            rect,
+            order,
+            half_open,
+        };
+        if let Some(root) = root {
+            if root.rect().intersects(&rect) {
+                iter.push(root);
+            }
+        }
+        iter
+    }
+
+    fn push(&mut self, node: &'a Node<T, A>) {
+        let seq = match node {
+            Node::Item(entry) => {
+                if self.half_open && !entry.rect.intersects_half_open(&self.rect) {
+                    return;
+                }
+                self.values.seq(entry.index)
+            }
+            Node::Parent(_) => 0,
+        };
+        let key = self.order.key(node.rect());
+        self.heap.push(Pending { key, node, seq });
+    }
+}
+
+impl<'a, T, A: Alloc<T>> Iterator for OrderedSearchIterator<'a, T, A> {
+    type Item = IterItem<'a, T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(pending) = self.heap.pop() {
+            match pending.node {
+                Node::Item(entry) => {
+                    let dist = match self.order {
+                        Order::Near(_) => pending.key,
+                        _ => Default::default(),
+                    };
+                    return Some(entry.iter_item(self.values, dist));
+                }
+                Node::Parent(nodes) => {
+                    #[cfg(feature = "stats")]
+                    crate::stats::count(|c| c.visited += 1);
+                    for node in nodes.nodes.iter() {
+                        if node.rect().intersects(&self.rect) {
+                            self.push(node);
+                        }
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Merges iterators whose items are each in `order`, such as the results of
+/// [`RTree::search_ordered`] on several trees, into one iterator in `order`.
+///
+/// Items with the same key come from the earlier iterator first. Insertion
+/// order is only meaningful within each tree, so it merges by seq as if the
+/// trees shared a sequence.
+pub fn merge_ordered<'a, T, I>(
+    iters: impl IntoIterator<Item = I>,
+    order: Order,
+) -> MergeIterator<'a, T, I>
+where
+    I: Iterator<Item = IterItem<'a, T>>,
+{
+    let mut merge = MergeIterator {
+        iters: iters.into_iter().collect(),
+        heads: BinaryHeap::new(),
+        order,
+    };
+    for source in 0..merge.iters.len() {
+        merge.advance(source);
+    }
+    merge
+}
+
+pub(crate) struct Head<'a, T> {
+    key: f32,
+    seq: u64,
+    /// Index of the iterator the item came from.
+    source: usize,
+    item: IterItem<'a, T>,
+}
+
+impl<'a, T> Head<'a, T> {
+    pub(crate) fn rank(&self) -> (Ordered, u64, usize) {
+        (Ordered(self.key), self.seq, self.source)
+    }
+}
+
+impl<'a, T> PartialEq for Head<'a, T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<'a, T> Eq for Head<'a, T> {}
+
+impl<'a, T> PartialOrd for Head<'a, T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<'a, T> Ord for Head<'a, T> {
+    /// Reversed, so that the heap pops the smallest key first.
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.rank().cmp(&other.rank()).reverse()
+    }
+}
+
+pub struct MergeIterator<'a, T, I> {
+    iters: Vec<I>,
+    /// The next item of each iterator that has one.
+    heads: BinaryHeap<Head<'a, T>>,
+    order: Order,
+}
+
+impl<'a, T, I: Iterator<Item = IterItem<'a, T>>> MergeIterator<'a, T, I> {
+    pub(crate) fn advance(&mut self, source: usize) {
+        if let Some(item) = self.iters[source].next() {
+            let (key, seq) = match self.order {
+                Order::Insertion => (0.0, item.seq),
+                order => (order.key(&item.rect), 0),
+            };
+            self.heads.push(Head {
+                key,
+                seq,
+                source,
+                item,
+            });
+        }
+    }
+}
+
+impl<'a, T, I: Iterator<Item = IterItem<'a, T>>> Iterator for MergeIterator<'a, T, I> {
+    type Item = IterItem<'a, T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let head = self.heads.pop()?;
+        self.advance(head.source);
+        Some(head.item)
+    }
+}
+
+pub struct NearbyItem<'a, T, A: Alloc<T>> {
+    pub(crate) dist: f32,
+    pub(crate) node: &'a Node<T, A>,
+    /// Insertion order of an item, zero for parents.
+    seq: u64,
+}
+
+impl<'a, T, A: Alloc<T>> NearbyItem<'a, T, A> {
+    pub(crate) fn new(dist: f32, node: &'a Node<T, A>, values: &Slab<T>) -> Self {
+        let seq = match node {
+            Node::Item(entry) => values.seq(entry.index),
+            Node::Parent(_) => 0,
+        };
+        NearbyItem { dist, node, seq }
+    }
+}
+
+impl<'a, T, A: Alloc<T>> PartialEq for NearbyItem<'a, T, A> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<'a, T, A: Alloc<T>> Eq for NearbyItem<'a, T, A> {}
+
+impl<'a, T, A: Alloc<T>> PartialOrd for NearbyItem<'a, T, A> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<'a, T, A: Alloc<T>> Ord for NearbyItem<'a, T, A> {
+    /// Reversed, so that the heap pops the nearest first.
+    ///
+    /// Ties go to parents before items, so that every item at a distance is
+    /// queued before the first one is returned. Items at the same distance
+    /// are then ordered by their rects and by their insertion order.
+    fn cmp(&self, other: &Self) -> Ordering {
+        let (a, b) = (self.node.rect(), other.node.rect());
+        let leaf = |n: &Node<T, A>| matches!(n, Node::Item(_));
+        self.dist
+            .total_cmp(&other.dist)
+            .then_with(|| leaf(self.node).cmp(&leaf(other.node)))
+            .then_with(|| a.min.x.total_cmp(&b.min.x))
+            .then_with(|| a.min.y.total_cmp(&b.min.y))
+            .then_with(|| a.max.x.total_cmp(&b.max.x))
+            .then_with(|| a.max.y.total_cmp(&b.max.y))
+            .then_with(|| self.seq.cmp(&other.seq))
+            .reverse()
+    }
+}
+
+/// A queue of pending nodes for nearest neighbor iteration.
+///
+/// Passing the same queue to [`RTree::nearby_with`] for many queries reuses
+/// its allocation. The queue borrows the tree, so it can be reused for as
+/// long as the tree isn't modified.
+pub struct NearbyQueue<'a, T, A: Alloc<T>> {
+    heap: BinaryHeap<NearbyItem<'a, T, A>>,
+}
+
+impl<'a, T, A: Alloc<T>> NearbyQueue<'a, T, A> {
+    pub fn new() -> Self {
+        NearbyQueue {
+            heap: BinaryHeap::new(),
+        }
+    }
+}
+
+impl<'a, T, A: Alloc<T>> Default for NearbyQueue<'a, T, A> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct NearbyIterator<'a, T, A: Alloc<T>, F, Q = NearbyQueue<'a, T, A>> {
+    queue: Q,
+    values: &'a Slab<T>,
+    dist: F,
+    marker: PhantomData<&'a Node<T, A>>,
+}
+
+impl<'a, T, A, F, Q> NearbyIterator<'a, T, A, F, Q>
+where
+    A: Alloc<T>,
+    F: FnMut(&Rect, Option<&'a T>) -> f32,
+    Q: BorrowMut<NearbyQueue<'a, T, A>>,
+{
+    pub(crate) fn new(
+        root: &'a Option<Node<T, A>>,
+        values: &'a Slab<T>,
+        dist: F,
+        mut queue: Q,
+    ) -> Self {
+        let heap = &mut queue.borrow_mut().heap;
+        heap.clear();
+        if let Some(root) = root {
+            heap.push(NearbyItem::new(Default::default(), root, values));
+        }
+        NearbyIterator {
+            queue,
+            values,
+            dist,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<'a, T, A, F, Q> Iterator for NearbyIterator<'a, T, A, F, Q>
+where
+    A: Alloc<T>,
+    F: FnMut(&Rect, Option<&'a T>) -> f32,
+    Q: BorrowMut<NearbyQueue<'a, T, A>>,
+{
+    type Item = IterItem<'a, T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let heap = &mut self.queue.borrow_mut().heap;
+        while let Some(item) = heap.pop() {
+            match &item.node {
+                Node::Item(data) => {
+                    return Some(data.iter_item(self.values, item.dist));
+                }
+                Node::Parent(nodes) => {
+                    #[cfg(feature = "stats")]
+                    crate::stats::count(|c| c.visited += 1);
+                    heap.extend(nodes.nodes.iter().map(|node| {
+                        let (rect, item) = match node {
+                            Node::Item(item) => (&item.rect, Some(&self.values[item.index])),
+                            Node::Parent(nodes) => (&nodes.rect, None),
+                        };
+                        let dist = (self.dist)(rect, item);
+                        NearbyItem::new(dist, node, self.values)
+                    }));
+                }
+            }
+        }
+        None
+    }
+}
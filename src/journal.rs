@@ -0,0 +1,238 @@
+//! Recording the mutations of a tree to replay them on another.
+//!
+//! A journal is a compact binary log of inserts, removals and updates. It is
+//! enabled per tree with [`RTree::enable_journal`], collected with
+//! [`RTree::take_journal`] and replayed with [`RTree::apply_journal`], which
+//! keeps a replica in sync without shipping whole snapshots.
+
+use crate::{Alloc, Point, RTree, Rect, Scalar};
+use std::io::{self, Error, ErrorKind};
+use std::marker::PhantomData;
+
+/// Values that can be written to a journal.
+///
+/// Encodings must be self-delimiting, `decode` consumes exactly the bytes
+/// written by `encode`.
+pub trait Encode: Sized {
+    fn encode(&self, out: &mut Vec<u8>);
+    fn decode(buf: &mut &[u8]) -> io::Result<Self>;
+}
+
+macro_rules! encode_num {
+    ($($t:ty),*) => {$(
+        impl Encode for $t {
+            fn encode(&self, out: &mut Vec<u8>) {
+                out.extend(self.to_le_bytes());
+            }
+
+            fn decode(buf: &mut &[u8]) -> io::Result<Self> {
+                Ok(<$t>::from_le_bytes(take(buf)?))
+            }
+        }
+    )*};
+}
+
+encode_num!(u8, u16, u32, u64, i8, i16, i32, i64, f32, f64);
+
+impl Encode for usize {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (*self as u64).encode(out);
+    }
+
+    fn decode(buf: &mut &[u8]) -> io::Result<Self> {
+        usize::try_from(u64::decode(buf)?).map_err(|_| invalid("value out of range"))
+    }
+}
+
+impl Encode for bool {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(*self as u8);
+    }
+
+    fn decode(buf: &mut &[u8]) -> io::Result<Self> {
+        Ok(u8::decode(buf)? != 0)
+    }
+}
+
+impl Encode for String {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (self.len() as u32).encode(out);
+        out.extend(self.as_bytes());
+    }
+
+    fn decode(buf: &mut &[u8]) -> io::Result<Self> {
+        let len = u32::decode(buf)? as usize;
+        if buf.len() < len {
+            return Err(invalid("truncated journal"));
+        }
+        let (bytes, rest) = buf.split_at(len);
+        *buf = rest;
+        String::from_utf8(bytes.to_vec()).map_err(|_| invalid("invalid utf-8"))
+    }
+}
+
+const INSERT: u8 = 0;
+const REMOVE: u8 = 1;
+const UPDATE: u8 = 2;
+const CLEAR: u8 = 3;
+
+#[derive(Clone)]
+pub(crate) struct Journal<T, S = f32> {
+    buf: Vec<u8>,
+    encode: fn(&T, &mut Vec<u8>),
+    scratch: Vec<u8>,
+    scalar: PhantomData<S>,
+}
+
+impl<T, S: Scalar> Journal<T, S> {
+    fn new() -> Self
+    where
+        T: Encode,
+    {
+        Journal {
+            buf: Vec::new(),
+            encode: T::encode,
+            scratch: Vec::new(),
+            scalar: PhantomData,
+        }
+    }
+
+    fn op(&mut self, op: u8, rect: &Rect<S>) {
+        self.buf.push(op);
+        for v in [rect.min.x, rect.min.y, rect.max.x, rect.max.y] {
+            v.encode(&mut self.buf);
+        }
+    }
+
+    pub(crate) fn insert(&mut self, rect: &Rect<S>, value: &T) {
+        self.op(INSERT, rect);
+        (self.encode)(value, &mut self.buf);
+    }
+
+    pub(crate) fn remove(&mut self, rect: &Rect<S>, value: &T) {
+        self.op(REMOVE, rect);
+        (self.encode)(value, &mut self.buf);
+    }
+
+    fn update(&mut self, rect: &Rect<S>, old: &T, new: &T) {
+        self.op(UPDATE, rect);
+        (self.encode)(old, &mut self.buf);
+        (self.encode)(new, &mut self.buf);
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.buf.push(CLEAR);
+    }
+
+    /// Calls `f` on an item, recording its removal if `f` returns false or
+    /// its update if `f` changed the encoded value.
+    pub(crate) fn visit<F>(&mut self, rect: &Rect<S>, value: &mut T, f: F) -> bool
+    where
+        F: FnOnce(&Rect<S>, &mut T) -> bool,
+    {
+        let mut old = std::mem::take(&mut self.scratch);
+        old.clear();
+        (self.encode)(value, &mut old);
+        let keep = f(rect, value);
+        if keep {
+            let start = self.buf.len();
+            self.op(UPDATE, rect);
+            self.buf.extend(&old);
+            let at = self.buf.len();
+            (self.encode)(value, &mut self.buf);
+            if self.buf[at..] == old[..] {
+                self.buf.truncate(start);
+            }
+        } else {
+            self.op(REMOVE, rect);
+            self.buf.extend(&old);
+        }
+        self.scratch = old;
+        keep
+    }
+}
+
+impl<T, A: Alloc<T, S>, S: Scalar> RTree<T, A, S> {
+    /// Starts recording inserts, removals and updates of items.
+    ///
+    /// Values changed through [`get_mut`](Self::get_mut) or
+    /// [`for_each_in_rect_mut`](Self::for_each_in_rect_mut) aren't recorded,
+    /// those changed by [`retain_in_rect`](Self::retain_in_rect) are.
+    pub fn enable_journal(&mut self)
+    where
+        T: Encode,
+    {
+        self.journal.get_or_insert_with(Journal::new);
+    }
+
+    /// Stops recording, discarding the operations not taken yet.
+    pub fn disable_journal(&mut self) {
+        self.journal = None;
+    }
+
+    /// Returns the operations recorded since the last call, leaving the
+    /// journal empty but enabled.
+    pub fn take_journal(&mut self) -> Vec<u8> {
+        match &mut self.journal {
+            Some(journal) => std::mem::take(&mut journal.buf),
+            None => Vec::new(),
+        }
+    }
+
+    /// Replays the operations recorded by another tree's journal.
+    ///
+    /// Fails if the journal is malformed or removes or updates an item that
+    /// isn't in `self`, leaving the operations before the failing one
+    /// applied.
+    pub fn apply_journal(&mut self, mut journal: &[u8]) -> io::Result<()>
+    where
+        T: Encode + PartialEq,
+    {
+        let buf = &mut journal;
+        while !buf.is_empty() {
+            let op = u8::decode(buf)?;
+            if op == CLEAR {
+                self.drain_all();
+                continue;
+            }
+            let min = Point::new(S::decode(buf)?, S::decode(buf)?);
+            let max = Point::new(S::decode(buf)?, S::decode(buf)?);
+            let rect = Rect::new(min, max);
+            let value = T::decode(buf)?;
+            match op {
+                INSERT => self.insert(rect, value),
+                REMOVE => {
+                    self.remove(rect, &value)
+                        .ok_or_else(|| invalid("removed item not found"))?;
+                }
+                UPDATE => {
+                    let new = T::decode(buf)?;
+                    let key = self
+                        .search(rect)
+                        .find(|item| item.rect == rect && *item.data == value)
+                        .map(|item| item.key)
+                        .ok_or_else(|| invalid("updated item not found"))?;
+                    if let Some(journal) = &mut self.journal {
+                        journal.update(&rect, &value, &new);
+                    }
+                    *self.values.get_mut(key).unwrap() = new;
+                }
+                _ => return Err(invalid("unknown journal operation")),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn take<const N: usize>(buf: &mut &[u8]) -> io::Result<[u8; N]> {
+    if buf.len() < N {
+        return Err(invalid("truncated journal"));
+    }
+    let (bytes, rest) = buf.split_at(N);
+    *buf = rest;
+    Ok(bytes.try_into().unwrap())
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
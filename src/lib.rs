@@ -1,674 +1,1254 @@
 #[cfg(test)]
 mod test;
 
-use arrayvec::ArrayVec;
-use blink_alloc::Blink;
-use std::cmp::Ordering;
-use std::collections::BinaryHeap;
-use std::ops::DerefMut;
-use std::slice::Iter;
+pub mod alloc;
+#[cfg(feature = "bevy")]
+pub mod bevy;
+#[cfg(any(feature = "s2", feature = "h3"))]
+pub mod cells;
+pub mod chunked;
+#[cfg(feature = "alloc-counters")]
+pub mod counters;
+pub mod cursor;
+mod filter;
+pub mod frozen;
+pub mod geohash;
+pub mod geom;
+#[cfg(feature = "geoparquet")]
+pub mod geoparquet;
+pub mod grouped;
+pub mod iter;
+pub mod journal;
+#[cfg(any(feature = "csv", feature = "ndjson"))]
+pub mod loader;
+pub mod lsm;
+pub mod nd;
+pub mod node;
+#[cfg(feature = "osmpbf")]
+pub mod osmpbf;
+pub mod overlay;
+pub mod prelude;
+pub mod query;
+#[cfg(feature = "shapefile")]
+pub mod shapefile;
+#[cfg(feature = "stats")]
+pub mod stats;
+#[cfg(feature = "proptest")]
+pub mod strategies;
+#[cfg(feature = "stream")]
+pub mod stream;
+pub mod topology;
+#[cfg(feature = "wkb")]
+pub mod wkb;
+
+pub use alloc::{Alloc, ArenaAlloc, BoxAlloc, NodeVec};
+pub use cursor::Cursor;
+use geom::Circle;
+pub use geom::{Chebyshev, Euclidean, Manhattan, Metric, Point, Polygon, Rect, Scalar};
+pub use iter::{
+    merge_ordered, IterItem, MergeIterator, NearbyItem, NearbyIterator, NearbyQueue, Order,
+    OrderedSearchIterator, RefineIterator, Refinement, ScanIterator, SearchIterator,
+};
+pub use node::{Entry, Item, ItemKey, Node, Parent, Slab};
+pub use query::{Budgeted, Collision, Diff, InvalidRectError, Overlap};
+
+use filter::Filter;
+use journal::Journal;
+use node::{pack, pack_node, tile, Slot, CHUNK};
+use std::sync::atomic::{self, AtomicBool};
 
 const MAX_ITEMS: usize = 32;
 const MIN_ITEMS: usize = 2;
-
-#[derive(Copy, Clone)]
-enum Axis {
-    X,
-    Y,
+/// Number of items removed between checks for cancellation, see
+/// [`RTree::try_drain_in_rect`].
+const DRAIN_CHUNK: usize = 1024;
+
+/// The error of [`RTree::try_insert`], holding the item that wasn't
+/// inserted.
+#[derive(Debug)]
+pub struct MemoryLimitError<T, S = f32> {
+    pub rect: Rect<S>,
+    pub data: T,
+    /// Estimated memory usage at the time of the insert.
+    pub usage: usize,
+    pub limit: usize,
 }
 
-#[derive(Clone, Copy, PartialEq, Default, Debug)]
-pub struct Point {
-    pub x: f32,
-    pub y: f32,
+impl<T, S> std::fmt::Display for MemoryLimitError<T, S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "memory limit of {} bytes reached, {} bytes in use",
+            self.limit, self.usage
+        )
+    }
 }
 
-impl Point {
-    const fn new(x: f32, y: f32) -> Self {
-        Self { x, y }
-    }
+impl<T: std::fmt::Debug, S: std::fmt::Debug> std::error::Error for MemoryLimitError<T, S> {}
 
-    fn on(self, axis: Axis) -> f32 {
-        match axis {
-            Axis::X => self.x,
-            Axis::Y => self.y,
-        }
-    }
+/// The error of [`RTree::insert_unique`], holding the item that wasn't
+/// inserted.
+#[derive(Debug)]
+pub struct DuplicateError<T, S = f32> {
+    pub rect: Rect<S>,
+    pub data: T,
 }
 
-#[derive(Clone, Copy, PartialEq, Default, Debug)]
-pub struct Rect {
-    pub min: Point,
-    pub max: Point,
+impl<T, S> std::fmt::Display for DuplicateError<T, S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "item already in the tree")
+    }
 }
 
-impl Rect {
-    const INFINITY: Self = Self::new(
-        Point::new(f32::NEG_INFINITY, f32::NEG_INFINITY),
-        Point::new(f32::INFINITY, f32::INFINITY),
-    );
+impl<T: std::fmt::Debug, S: std::fmt::Debug> std::error::Error for DuplicateError<T, S> {}
 
-    pub const fn new(min: Point, max: Point) -> Self {
-        Self { min, max }
-    }
+/// The error of the batch operations taking a cancellation token, such as
+/// [`RTree::try_rebuild`], returned once the token is set.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cancelled;
 
-    pub const fn point(x: f32, y: f32) -> Self {
-        Self {
-            min: Point { x, y },
-            max: Point { x, y },
-        }
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "operation cancelled")
     }
+}
 
-    fn expand(&mut self, rect: &Self) {
-        if rect.min.x < self.min.x {
-            self.min.x = rect.min.x;
-        }
-        if rect.max.x > self.max.x {
-            self.max.x = rect.max.x;
-        }
-        if rect.min.y < self.min.y {
-            self.min.y = rect.min.y;
-        }
-        if rect.max.y > self.max.y {
-            self.max.y = rect.max.y;
-        }
-    }
+impl std::error::Error for Cancelled {}
 
-    fn larger_axis(&self) -> Axis {
-        let x = self.max.x - self.min.x;
-        let y = self.max.y - self.min.y;
-        if y > x {
-            Axis::Y
-        } else {
-            Axis::X
-        }
-    }
+/// Distribution of the number of children per node.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Occupancy {
+    /// `leaves[n]` is the number of leaf nodes holding `n` items.
+    pub leaves: Vec<usize>,
+    /// `branches[n]` is the number of internal nodes holding `n` child nodes.
+    pub branches: Vec<usize>,
+}
 
-    /// Determines whether `rect` is intersecting `self`.
-    fn intersects(&self, rect: &Self) -> bool {
-        if rect.min.x > self.max.x || rect.max.x < self.min.x {
-            return false;
-        }
-        if rect.min.y > self.max.y || rect.max.y < self.min.y {
-            return false;
-        }
-        true
-    }
+/// Measures of the arrangement of a tree, see [`RTree::quality`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quality {
+    pub height: usize,
+    pub nodes: usize,
+    /// Total area of the nodes below the root, which searches are more
+    /// likely to descend into the larger it is.
+    pub area: f64,
+    /// Total area shared by sibling nodes, which searches may have to
+    /// descend into more than once.
+    pub overlap: f64,
+}
 
-    /// Determines whether `rect` is on the lower/upper/left/right edge of `self`.
-    ///
-    /// Assumes `rect` is intersecting.
-    fn on_edge(&self, rect: &Self) -> bool {
-        if !(rect.min.x > self.min.x) || !(rect.max.x < self.max.x) {
-            return true;
-        }
-        if !(rect.min.y > self.min.y) || !(rect.max.y < self.max.y) {
-            return true;
-        }
-        false
-    }
+/// Sizes and fill of the nodes of a tree, see [`RTree::stats`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Stats {
+    pub height: usize,
+    /// Number of nodes, leaves included.
+    pub nodes: usize,
+    pub leaves: usize,
+    /// The least, mean and largest share of [`Config::max_items`] that the
+    /// nodes hold, counting the root, which may hold as few as two.
+    pub min_fill: f64,
+    pub mean_fill: f64,
+    pub max_fill: f64,
+    /// Total area of the leaves, which the items are found in.
+    pub area: f64,
+}
 
-    fn area(&self) -> f32 {
-        let x = self.max.x - self.min.x;
-        let y = self.max.y - self.min.y;
-        x * y
-    }
+/// A removal of the items in a rect spread over several calls, see
+/// [`RTree::remove_in_rect_incremental`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Removal<S = f32> {
+    rect: Rect<S>,
+    removed: usize,
+    done: bool,
+}
 
-    fn unioned_area(&self, rect: &Rect) -> f32 {
-        let x = max(self.max.x, rect.max.x) - min(self.min.x, rect.min.x);
-        let y = max(self.max.y, rect.max.y) - min(self.min.y, rect.min.y);
-        x * y
+impl<S: Scalar> Removal<S> {
+    /// Number of items removed so far.
+    pub fn removed(&self) -> usize {
+        self.removed
     }
 
-    pub fn box_dist(&self, rect: &Rect) -> f32 {
-        let x = max(self.min.x, rect.min.x) - min(self.max.x, rect.max.x);
-        let y = max(self.min.y, rect.min.y) - min(self.max.y, rect.max.y);
-        x * x + y * y
+    /// Whether no items are left in the rect.
+    pub fn is_done(&self) -> bool {
+        self.done
     }
 }
 
-enum I {
-    P(List),
+/// Tunable parameters of a tree, see [`RTree::tune`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Config {
+    /// Number of children at which a node is split, from 4 to 32.
+    pub max_items: usize,
+    /// See [`RTree::set_robust`].
+    pub robust: bool,
+    /// Prunes nodes by their bounding circles as well as their rects in
+    /// [`RTree::knn_batch`], see [`Metric::circle_bound`]. Circles are
+    /// tighter for clusters spread along a diagonal, but cost a few more
+    /// operations per visited node, and are only kept up to date while this
+    /// is set, which costs inserts and removals a few square roots.
+    pub circles: bool,
+    /// Makes searches treat the rects of items as half-open, `[min, max)`,
+    /// so that a point on the edge shared by two tiles matches only one of
+    /// them. Items with zero width or height never match.
+    pub half_open: bool,
+    /// Counts how often searches descend into each node, so that
+    /// [`RTree::reorder`] can move the busiest nodes first.
+    pub profile: bool,
+    /// Rounds the rects of inserted and removed items to a grid of cells of
+    /// this size, which makes rects differing by rounding errors equal, so
+    /// that an item can be removed with a recomputed rect. Searches return
+    /// the rounded rects.
+    pub snap: Option<f32>,
 }
 
-struct N {
-    rect: Rect,
-    n: I,
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            max_items: MAX_ITEMS,
+            robust: false,
+            circles: false,
+            half_open: false,
+            profile: false,
+            snap: None,
+        }
+    }
 }
 
-fn choose_least_enlargement(nodes: &mut [N], rect: &Rect) -> &mut N {
-    let mut ret = None;
-    let mut min_delta = 0.0;
-    let mut min_area = 0.0;
-    for n in nodes {
-        let uarea = n.rect.unioned_area(rect);
-        let area = n.rect.area();
-        let delta = uarea - area;
-        if ret.is_none() || delta < min_delta || (delta == min_delta && area < min_area) {
-            ret = Some(n);
-            min_delta = delta;
-            min_area = area;
-        }
-    }
-    ret.expect("empty parent")
+/// An R-tree of items of `T` with coordinates of `S`, allocating its nodes
+/// with `A`.
+///
+/// With the default [`BoxAlloc`], `RTree<T>` owns all of its memory. An arena
+/// like [`Blink`](blink_alloc::Blink) is faster, but ties the tree to the
+/// lifetime of the arena.
+pub struct RTree<T, A: Alloc<T, S> = BoxAlloc, S = f32> {
+    root: Option<Node<T, A, S>>,
+    values: Slab<T>,
+    length: usize,
+    height: usize,
+    alloc: A,
+    journal: Option<Journal<T, S>>,
+    filter: Option<Filter>,
+    config: Config,
+    /// Number of nodes, for estimating memory usage.
+    nodes: usize,
+    memory_limit: Option<usize>,
+    /// See [`set_attribute`](Self::set_attribute).
+    attr: Option<fn(&T) -> f32>,
+    /// See [`set_margin`](Self::set_margin).
+    margin: Option<fn(&T) -> f32>,
+    #[cfg(feature = "alloc-counters")]
+    allocs: counters::AllocCounters,
 }
 
-pub type NodeVec<T, A> = ArrayVec<Node<T, A>, MAX_ITEMS>;
-
-pub trait Alloc<T>: Sized {
-    type Output: DerefMut<Target = NodeVec<T, Self>>;
-
-    fn make(&self) -> Self::Output;
+impl<T, A: Alloc<T>> RTree<T, A> {
+    /// Creates a tree of f32 coordinates, see
+    /// [`with_scalar`](Self::with_scalar) for others.
+    pub fn new(alloc: A) -> Self {
+        Self::with_scalar(alloc)
+    }
 }
 
-struct BoxAlloc;
-
-impl<T: 'static> Alloc<T> for BoxAlloc {
-    type Output = Box<NodeVec<T, Self>>;
-
-    fn make(&self) -> Self::Output {
-        Box::new(NodeVec::new())
+impl<T> Default for RTree<T> {
+    fn default() -> Self {
+        Self::new(BoxAlloc)
     }
 }
 
-impl<'a, T: 'a> Alloc<T> for &'a Blink {
-    type Output = &'a mut NodeVec<T, Self>;
+/// Builds the tree with [`RTree::bulk_load`].
+impl<T> FromIterator<(Rect, T)> for RTree<T> {
+    fn from_iter<I: IntoIterator<Item = (Rect, T)>>(items: I) -> Self {
+        Self::bulk_load(BoxAlloc, items)
+    }
+}
 
-    fn make(&self) -> Self::Output {
-        self.put_no_drop(NodeVec::new())
+impl<T, A: Alloc<T, S>, S: Scalar> Extend<(Rect<S>, T)> for RTree<T, A, S> {
+    fn extend<I: IntoIterator<Item = (Rect<S>, T)>>(&mut self, items: I) {
+        for (rect, data) in items {
+            self.insert(rect, data);
+        }
     }
 }
 
-pub struct Parent<T, A: Alloc<T>> {
-    nodes: A::Output,
-    rect: Rect,
+/// Shows the number of items, the height and the rect of the tree, see
+/// [`RTree::format_tree`] for its nodes.
+impl<T, A: Alloc<T, S>, S: Scalar> std::fmt::Debug for RTree<T, A, S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("RTree")
+            .field("len", &self.length)
+            .field("height", &self.height)
+            .field("rect", &self.rect())
+            .finish_non_exhaustive()
+    }
 }
 
-impl<T, A: Alloc<T>> Parent<T, A> {
-    fn new(rect: Rect, alloc: &A) -> Self {
-        Self {
-            nodes: alloc.make(),
-            rect,
+/// Copies the nodes into the allocator of `self`, so that the copy can be
+/// changed without affecting the original. Keys of the original's items
+/// are valid in the copy as well.
+impl<T: Clone, A: Alloc<T, S> + Clone, S: Scalar> Clone for RTree<T, A, S> {
+    fn clone(&self) -> Self {
+        let root = self.root.as_ref().map(|root| match root {
+            Node::Parent(root) => Node::Parent(root.clone_in(&self.alloc)),
+            Node::Item(_) => unreachable!(),
+        });
+        RTree {
+            root,
+            values: self.values.clone(),
+            length: self.length,
+            height: self.height,
+            alloc: self.alloc.clone(),
+            journal: self.journal.clone(),
+            filter: self.filter.clone(),
+            config: self.config,
+            nodes: self.nodes,
+            memory_limit: self.memory_limit,
+            attr: self.attr,
+            margin: self.margin,
+            #[cfg(feature = "alloc-counters")]
+            allocs: self.allocs,
         }
     }
+}
 
-    fn len(&self) -> usize {
-        self.nodes.len()
+impl<T, A: Alloc<T, S>, S: Scalar> RTree<T, A, S> {
+    /// Creates a tree of coordinates of type `S`, such as
+    /// `RTree::<_, _, f64>::with_scalar(alloc)`.
+    pub fn with_scalar(alloc: A) -> Self {
+        RTree {
+            root: None,
+            values: Slab::new(0),
+            length: 0,
+            height: 0,
+            alloc,
+            journal: None,
+            filter: None,
+            config: Config::default(),
+            nodes: 0,
+            memory_limit: None,
+            attr: None,
+            margin: None,
+            #[cfg(feature = "alloc-counters")]
+            allocs: counters::AllocCounters::default(),
+        }
     }
 
-    fn is_full(&self) -> bool {
-        self.nodes.is_full()
+    pub fn len(&self) -> usize {
+        self.length
     }
 
-    fn choose_least_enlargement(&mut self, rect: &Rect) -> &mut Node<T, A> {
-        let mut n = None;
-        let mut min_delta = 0.0;
-        let mut min_area = 0.0;
-        for node in self.nodes.iter_mut() {
-            let uarea = node.rect().unioned_area(rect);
-            let area = node.rect().area();
-            let delta = uarea - area;
-            if n.is_none() || delta < min_delta || (delta == min_delta && area < min_area) {
-                n = Some(node);
-                min_delta = delta;
-                min_area = area;
-            }
-        }
-        n.expect("empty parent")
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
     }
 
-    fn insert(&mut self, rect: Rect, item: T, height: usize, alloc: &A) {
-        if height > 0 {
-            // branch node
-            let Node::Parent(child) = self.choose_least_enlargement(&rect) else {
-                return;
-            };
-            child.insert(rect, item, height - 1, alloc);
-            if child.is_full() {
-                let right = child.split_largest_axis_edge_snap(alloc);
-                self.nodes.push(right);
-            }
-        } else {
-            // leaf node
-            self.nodes.push(Node::Item(Item { rect, item }));
-        }
-        self.rect.expand(&rect);
+    /// Makes insertion robust to duplicate, zero-area and nearly identical
+    /// rects, which otherwise lead to lopsided splits driven by rounding.
+    ///
+    /// Slows down insertion somewhat. Off by default.
+    pub fn set_robust(&mut self, robust: bool) {
+        self.config.robust = robust;
     }
 
-    fn recalc(&mut self) {
-        if self.nodes.len() == 0 {
-            return;
-        }
-        let mut rect = self.nodes[0].rect().clone();
-        for i in 1..self.nodes.len() {
-            rect.expand(&self.nodes[i].rect());
-        }
-        self.rect = rect;
+    pub fn config(&self) -> Config {
+        self.config
     }
 
-    fn split_largest_axis_edge_snap(&mut self, alloc: &A) -> Node<T, A> {
-        let rect = self.rect;
-        let axis = rect.larger_axis();
-        let mut right = Parent::new(rect, alloc);
-        let lchilds = &mut self.nodes;
-        let rchilds = &mut right.nodes;
-        let mut i = 0;
-        while i < lchilds.len() {
-            let min = lchilds[i].rect().min.on(axis) - rect.min.on(axis);
-            let max = rect.max.on(axis) - lchilds[i].rect().max.on(axis);
-            if min < max {
-                // stay left
-                i += 1;
-            } else {
-                // move right
-                rchilds.push(lchilds.swap_remove(i));
+    /// Changes the parameters used by later inserts and removals. Existing
+    /// nodes are left as they are, see [`tune`](Self::tune) for rebuilding.
+    pub fn set_config(&mut self, config: Config) {
+        assert!(
+            (2 * MIN_ITEMS..=MAX_ITEMS).contains(&config.max_items),
+            "max_items out of range"
+        );
+        assert!(
+            config.snap.is_none_or(|cell| cell > 0.0),
+            "snap must be positive"
+        );
+        if config.circles != self.config.circles {
+            if let Some(Node::Parent(root)) = &mut self.root {
+                root.set_circles(config.circles);
             }
         }
-        // Make sure that both left and right nodes have at least
-        // MIN_ITEMS by moving items into under-flowed nodes.
-        if lchilds.len() < MIN_ITEMS {
-            // reverse sort by min axis
-            rchilds.sort_unstable_by_key(|n| Ordered(n.rect().min.on(axis)));
-            while lchilds.len() < MIN_ITEMS {
-                lchilds.push(rchilds.pop().unwrap());
-            }
-        } else if rchilds.len() < MIN_ITEMS {
-            // reverse sort by max axis
-            lchilds.sort_unstable_by_key(|n| Ordered(n.rect().max.on(axis)));
-            while rchilds.len() < MIN_ITEMS {
-                rchilds.push(lchilds.pop().unwrap());
-            }
-        }
-        // recalculate and sort the nodes
-        self.recalc();
-        right.recalc();
-        self.sort_by_x();
-        right.sort_by_x();
-        Node::Parent(right)
+        self.config = config;
     }
 
-    fn push(&mut self, child: Node<T, A>) {
-        self.nodes.push(child);
+    pub fn rect(&self) -> Option<Rect<S>> {
+        self.root.as_ref().map(|root| *root.rect())
     }
 
-    fn sort_by_x(&mut self) {
-        self.nodes.sort_unstable_by_key(|n| Ordered(n.rect().min.x));
+    /// Panics if the insertion would exceed the memory limit, see
+    /// [`try_insert`](Self::try_insert).
+    pub fn insert(&mut self, rect: Rect<S>, data: T) {
+        if let Err(err) = self.try_insert(rect, data) {
+            panic!("{}", err);
+        }
     }
 
-    fn flatten_into(&mut self, reinsert: &mut Vec<Item<T>>) {
-        while let Some(node) = self.nodes.pop() {
-            match node {
-                Node::Item(item) => reinsert.push(item),
-                Node::Parent(mut nodes) => nodes.flatten_into(reinsert),
+    /// Inserts an item unless the tree could then use more memory than
+    /// allowed by [`set_memory_limit`](Self::set_memory_limit), in which case
+    /// the item is handed back in the error.
+    pub fn try_insert(&mut self, rect: Rect<S>, data: T) -> Result<(), MemoryLimitError<T, S>> {
+        let rect = self.snap(rect);
+        if let Some(limit) = self.memory_limit {
+            let usage = self.memory_usage();
+            if usage.saturating_add(self.insert_growth()) > limit {
+                return Err(MemoryLimitError {
+                    rect,
+                    data,
+                    usage,
+                    limit,
+                });
             }
         }
+        if let Some(journal) = &mut self.journal {
+            journal.insert(&rect, &data);
+        }
+        if let Some(filter) = &mut self.filter {
+            filter.insert(&rect);
+        }
+        #[cfg(feature = "alloc-counters")]
+        let (allocated, slots) = (self.allocs.allocated, self.values.capacity());
+        let index = self.values.insert(data);
+        self.insert_entry(Entry { rect, index });
+        #[cfg(feature = "alloc-counters")]
+        {
+            let nodes = self.allocs.allocated - allocated;
+            let slots = self.values.capacity() - slots;
+            let bytes = nodes * std::mem::size_of::<NodeVec<T, A, S>>()
+                + slots * std::mem::size_of::<Slot<T>>();
+            self.allocs.insert.record(1, nodes, bytes);
+        }
+        Ok(())
     }
 
-    pub fn remove(
-        &mut self,
-        rect: &Rect,
-        data: &T,
-        reinsert: &mut Vec<Item<T>>,
-        height: usize,
-    ) -> (Option<Item<T>>, bool)
+    /// Inserts an item unless an item with the same rect and value is already
+    /// in the tree, in which case the item is handed back in the error.
+    pub fn insert_unique(&mut self, rect: Rect<S>, data: T) -> Result<(), DuplicateError<T, S>>
     where
         T: PartialEq,
     {
-        let nodes = &mut self.nodes;
-        if height == 0 {
-            // remove from leaf
-            for i in 0..nodes.len() {
-                if nodes[i].item() != data {
-                    continue;
-                }
-                let Node::Item(item) = nodes.swap_remove(i) else {
-                    continue;
-                };
-                let recalced = self.rect.on_edge(&item.rect);
-                if recalced {
-                    self.recalc();
-                }
-                return (Some(item), recalced);
-            }
-        } else {
-            for i in 0..nodes.len() {
-                let node = nodes[i].nodes();
-                if !node.rect.intersects(rect) {
-                    continue;
-                }
-                let (removed, mut recalced) = node.remove(rect, data, reinsert, height - 1);
-                if removed.is_none() {
-                    continue;
-                }
-                let underflow = node.len() < MIN_ITEMS;
-                if underflow {
-                    let nrect = node.rect;
-                    nodes.swap_remove(i).nodes().flatten_into(reinsert);
-                    if !recalced {
-                        recalced = self.rect.on_edge(&nrect);
-                    }
-                }
-                if recalced {
-                    self.recalc();
-                }
-                return (removed, recalced);
+        let rect = self.snap(rect);
+        if let Some(Node::Parent(root)) = &self.root {
+            if root.rect.contains(&rect) && root.contains_item(&rect, &data, &self.values) {
+                return Err(DuplicateError { rect, data });
             }
         }
-        (None, false)
+        self.insert(rect, data);
+        Ok(())
     }
-}
-
-pub struct Item<T> {
-    rect: Rect,
-    item: T,
-}
-
-pub enum Node<T, A: Alloc<T>> {
-    Item(Item<T>),
-    Parent(Parent<T, A>),
-}
 
-impl<T, A: Alloc<T>> Node<T, A> {
-    fn rect(&self) -> &Rect {
-        match self {
-            Node::Item(n) => &n.rect,
-            Node::Parent(n) => &n.rect,
+    /// Rounds `rect` to the grid of [`Config::snap`], if any.
+    fn snap(&self, rect: Rect<S>) -> Rect<S> {
+        match self.config.snap {
+            Some(cell) => rect.snapped(cell),
+            None => rect,
         }
     }
 
-    fn item(&self) -> &T {
-        match self {
-            Node::Item(n) => &n.item,
-            Node::Parent(_) => panic!("not a leaf node"),
+    /// Sets a numeric attribute of the values, whose range is kept by every
+    /// node so that [`search_where`](Self::search_where) can skip the
+    /// subtrees with no values in the range it's after.
+    ///
+    /// The ranges are computed for the current items, and kept up to date
+    /// as items are inserted. Values changed in place, such as through
+    /// [`get_mut`](Self::get_mut), need the attribute to be set again.
+    pub fn set_attribute(&mut self, attr: fn(&T) -> f32) {
+        self.attr = Some(attr);
+        if let Some(Node::Parent(root)) = &mut self.root {
+            root.reattr(&self.values, attr);
         }
     }
 
-    fn nodes(&mut self) -> &mut Parent<T, A> {
-        match self {
-            Node::Item(_) => panic!("not a parent node"),
-            Node::Parent(n) => n,
+    /// Sets the margin of the values, the distance within which
+    /// [`search_padded`](Self::search_padded) finds them. Every node keeps
+    /// the largest margin below it, so that searches only descend where
+    /// some item could reach.
+    ///
+    /// The margins are computed for the current items, and kept up to date
+    /// as items are inserted. Values changed in place, such as through
+    /// [`get_mut`](Self::get_mut), need the margin to be set again.
+    pub fn set_margin(&mut self, margin: fn(&T) -> f32) {
+        self.margin = Some(margin);
+        if let Some(Node::Parent(root)) = &mut self.root {
+            root.remargin(&self.values, margin);
         }
     }
-}
 
-pub struct RTree<T, A: Alloc<T>> {
-    root: Option<Node<T, A>>,
-    length: usize,
-    height: usize,
-    alloc: A,
-}
-
-impl<T, A: Alloc<T>> RTree<T, A> {
-    pub fn new(alloc: A) -> Self {
-        RTree {
-            root: None,
-            length: 0,
-            height: 0,
-            alloc,
-        }
+    /// Caps the estimated memory usage of the tree, in bytes, which makes
+    /// inserts that could exceed it fail. `None` removes the limit.
+    ///
+    /// Removals never fail, even when reinserting items creates nodes.
+    pub fn set_memory_limit(&mut self, limit: Option<usize>) {
+        self.memory_limit = limit;
     }
 
-    pub fn len(&self) -> usize {
-        self.length
+    /// Estimates the memory used by the nodes and values of the tree, in
+    /// bytes.
+    ///
+    /// Nodes freed by removals are counted off even though an arena
+    /// allocator like [`Blink`](blink_alloc::Blink) only reclaims them when it's reset.
+    pub fn memory_usage(&self) -> usize {
+        let values = &self.values;
+        self.nodes * std::mem::size_of::<NodeVec<T, A, S>>()
+            + values.capacity() * std::mem::size_of::<Slot<T>>()
+            + values.free.capacity() * std::mem::size_of::<u32>()
     }
 
-    pub fn rect(&self) -> Option<Rect> {
-        self.root.as_ref().map(|root| root.rect().clone())
+    /// The most an insert can add to [`memory_usage`](Self::memory_usage): a
+    /// split at every level and a new root, and the growth of the values.
+    fn insert_growth(&self) -> usize {
+        let nodes = (self.height + 2) * std::mem::size_of::<NodeVec<T, A, S>>();
+        if !self.values.is_full() {
+            return nodes;
+        }
+        nodes + CHUNK * std::mem::size_of::<Slot<T>>()
     }
 
-    pub fn insert(&mut self, rect: Rect, data: T) {
+    fn insert_entry(&mut self, entry: Entry<S>) {
+        if self.root.is_none() {
+            self.nodes += 1;
+            #[cfg(feature = "alloc-counters")]
+            {
+                self.allocs.allocated += 1;
+            }
+        }
+        #[cfg(feature = "alloc-counters")]
+        let nodes = self.nodes;
         let root = self
             .root
-            .get_or_insert_with(|| Node::Parent(Parent::new(rect, &self.alloc)))
+            .get_or_insert_with(|| {
+                let mut root = Parent::new(entry.rect, &self.alloc);
+                root.nodes.circle = self.config.circles.then(|| Circle::of(&entry.rect));
+                Node::Parent(root)
+            })
             .nodes();
-        root.insert(rect, data, self.height, &self.alloc);
-        if root.is_full() {
+        let seq = self.values.seq(entry.index);
+        let attr = self.attr.map(|attr| attr(&self.values[entry.index]));
+        let margin = self
+            .margin
+            .map_or(0.0, |margin| margin(&self.values[entry.index]));
+        root.insert(
+            entry,
+            seq,
+            attr,
+            margin,
+            self.height,
+            &self.alloc,
+            &self.config,
+            &mut self.nodes,
+        );
+        let full = root.is_full(&self.config);
+        #[cfg(feature = "alloc-counters")]
+        {
+            // each split allocates its right half, and a root split a new root
+            let splits = self.nodes - nodes + full as usize;
+            let size = std::mem::size_of::<NodeVec<T, A, S>>();
+            self.allocs.split.record(splits, splits, splits * size);
+            self.allocs.allocated += splits + full as usize;
+        }
+        if full {
+            self.nodes += 2;
             let mut new_root = Parent::new(root.rect, &self.alloc);
-            let right = root.split_largest_axis_edge_snap(&self.alloc);
+            new_root.nodes.newest = root.nodes.newest;
+            new_root.nodes.attr = root.nodes.attr;
+            new_root.nodes.margin = root.nodes.margin;
+            new_root.nodes.circle = root.nodes.circle;
+            let right = root.split_largest_axis_edge_snap(&self.alloc, self.config.robust);
+            #[cfg(feature = "stats")]
+            stats::count(|c| c.splits += 1);
             let left = self.root.take().unwrap();
             new_root.push(left);
             new_root.push(right);
+            new_root.recalc();
             self.root = Some(Node::Parent(new_root));
             self.height += 1;
         }
         self.length += 1;
     }
 
-    pub fn remove(&mut self, rect: Rect, data: &T) -> Option<Item<T>>
+    pub fn remove(&mut self, rect: Rect<S>, data: &T) -> Option<Item<T, S>>
+    where
+        T: PartialEq,
+    {
+        self.remove_where(rect, |value| value == data)
+    }
+
+    /// Removes an item intersecting `rect` for whose value `matches` returns
+    /// true, for values that can't be compared for equality.
+    ///
+    /// Only the first match found is removed, see
+    /// [`retain_in_rect`](Self::retain_in_rect) for removing all of them.
+    pub fn remove_where<F>(&mut self, rect: Rect<S>, mut matches: F) -> Option<Item<T, S>>
+    where
+        F: FnMut(&T) -> bool,
+    {
+        let removed = self.remove_entry(rect, |_, value| matches(value))?;
+        let item = self.values.remove(removed.index);
+        if let Some(journal) = &mut self.journal {
+            journal.remove(&removed.rect, &item);
+        }
+        Some(Item {
+            rect: removed.rect,
+            item,
+        })
+    }
+
+    /// Moves the item at `old` with value `data` to `new`, returning whether
+    /// it was found.
+    ///
+    /// When `new` still fits in the item's leaf, the item is moved in place
+    /// with a single descent, and otherwise it's removed and inserted again.
+    /// Either way the item keeps its key.
+    pub fn update(&mut self, old: Rect<S>, new: Rect<S>, data: &T) -> bool
     where
         T: PartialEq,
     {
+        let (old, new) = (self.snap(old), self.snap(new));
+        let Some(root) = &mut self.root else {
+            return false;
+        };
+        let mut matches = |value: &T| value == data;
+        let moved = root
+            .nodes()
+            .relocate(&old, &new, &mut matches, &self.values, self.height);
+        let index = match moved {
+            Some(index) => index,
+            // only the item at exactly `old`, like the move in place
+            None => match self.remove_entry(old, |rect, value| *rect == old && value == data) {
+                Some(entry) => {
+                    self.insert_entry(Entry {
+                        rect: new,
+                        index: entry.index,
+                    });
+                    entry.index
+                }
+                None => return false,
+            },
+        };
+        if let Some(journal) = &mut self.journal {
+            journal.remove(&old, &self.values[index]);
+            journal.insert(&new, &self.values[index]);
+        }
+        if let Some(filter) = &mut self.filter {
+            filter.insert(&new);
+        }
+        true
+    }
+
+    /// Removes the entry of an item intersecting `rect` whose rect and value
+    /// match, leaving its value in the slab.
+    fn remove_entry<F>(&mut self, rect: Rect<S>, mut matches: F) -> Option<Entry<S>>
+    where
+        F: FnMut(&Rect<S>, &T) -> bool,
+    {
+        let rect = self.snap(rect);
         if let Some(root) = &mut self.root {
             let root = root.nodes();
             let mut reinsert = Vec::new();
-            let (removed, recalced) = root.remove(&rect, data, &mut reinsert, self.height);
-            if removed.is_none() {
-                return None;
-            }
+            let (removed, recalced) = root.remove(
+                &rect,
+                &mut matches,
+                &self.values,
+                &mut reinsert,
+                self.height,
+                &self.config,
+                &mut self.nodes,
+            );
+            let removed = removed?;
             self.length -= reinsert.len() + 1;
             if self.length == 0 {
                 self.root = None;
+                self.height = 0;
+                self.nodes = 0;
             } else if self.height > 0 && root.len() == 1 {
                 let mut n = root.nodes.pop().unwrap();
                 n.nodes().recalc();
                 self.height -= 1;
                 self.root = Some(n);
+                self.nodes -= 1;
             } else if recalced {
                 if let Some(root) = &mut self.root {
                     root.nodes().recalc();
                 }
             }
-            while let Some(item) = reinsert.pop() {
-                self.insert(item.rect, item.item);
+            #[cfg(feature = "stats")]
+            stats::count(|c| c.reinserts += reinsert.len() as u64);
+            #[cfg(feature = "alloc-counters")]
+            let allocated = self.allocs.allocated;
+            while let Some(entry) = reinsert.pop() {
+                self.insert_entry(entry);
             }
-            removed
+            #[cfg(feature = "alloc-counters")]
+            self.count_remove(allocated);
+            Some(removed)
         } else {
             None
         }
     }
 
-    pub fn iter(&self) -> SearchIterator<'_, T, A> {
-        SearchIterator::new(&self.root, self.height, Rect::INFINITY)
+    /// Calls `f` on every item intersecting `rect`, keeping only those for
+    /// which it returns true.
+    ///
+    /// Items can be modified in place. The tree is rebalanced once, after all
+    /// items have been visited.
+    pub fn retain_in_rect<F>(&mut self, rect: Rect<S>, f: F)
+    where
+        F: FnMut(&Rect<S>, &mut T) -> bool,
+    {
+        for entry in self.retain_budgeted(rect, usize::MAX, f) {
+            self.values.remove(entry.index);
+        }
     }
 
-    pub fn search(&self, rect: Rect) -> SearchIterator<'_, T, A> {
-        SearchIterator::new(&self.root, self.height, rect)
+    /// Calls `f` on every item, keeping only those for which it returns true,
+    /// in a single pass over the tree.
+    ///
+    /// Like [`retain_in_rect`](Self::retain_in_rect), the tree is rebalanced
+    /// once, after all items have been visited.
+    pub fn retain<F>(&mut self, f: F)
+    where
+        F: FnMut(&Rect<S>, &mut T) -> bool,
+    {
+        if let Some(rect) = self.rect() {
+            self.retain_in_rect(rect, f);
+        }
+    }
+
+    /// Starts removing the items intersecting `rect`, removing at most
+    /// `budget` of them now and the rest in calls to
+    /// [`resume_removal`](Self::resume_removal), so that clearing a large
+    /// region can be spread over many frames.
+    ///
+    /// Items inserted into `rect` before the removal is done are removed as
+    /// well.
+    pub fn remove_in_rect_incremental(&mut self, rect: Rect<S>, budget: usize) -> Removal<S> {
+        let mut removal = Removal {
+            rect,
+            removed: 0,
+            done: false,
+        };
+        self.resume_removal(&mut removal, budget);
+        removal
+    }
+
+    /// Removes at most `budget` more items of `removal`, returning the number
+    /// removed.
+    pub fn resume_removal(&mut self, removal: &mut Removal<S>, budget: usize) -> usize {
+        if removal.done {
+            return 0;
+        }
+        let removed = self.retain_budgeted(removal.rect, budget, |_, _| false);
+        for entry in &removed {
+            self.values.remove(entry.index);
+        }
+        removal.removed += removed.len();
+        removal.done = removed.len() < budget;
+        removed.len()
+    }
+
+    /// Removes the items intersecting `rect` in a single traversal,
+    /// returning them in no particular order.
+    ///
+    /// Nodes left with too few children are fixed up once, after all items
+    /// have been removed.
+    pub fn drain_in_rect(&mut self, rect: Rect<S>) -> std::vec::IntoIter<(Rect<S>, T)> {
+        let removed = self.retain_budgeted(rect, usize::MAX, |_, _| false);
+        let items: Vec<_> = removed
+            .into_iter()
+            .map(|e| (e.rect, self.values.remove(e.index)))
+            .collect();
+        items.into_iter()
     }
 
-    pub fn nearby<F>(&self, dist: F) -> NearbyIterator<T, A, F>
+    /// Like [`drain_in_rect`](Self::drain_in_rect), giving up once `cancel`
+    /// is set, such as when the client waiting for the items disconnects.
+    ///
+    /// The items are removed in chunks, checking `cancel` in between. When
+    /// cancelled, the items removed so far are inserted again, keeping their
+    /// keys, so the tree holds the same items as before.
+    pub fn try_drain_in_rect(
+        &mut self,
+        rect: Rect<S>,
+        cancel: &AtomicBool,
+    ) -> Result<std::vec::IntoIter<(Rect<S>, T)>, Cancelled> {
+        let mut removed: Vec<Entry<S>> = Vec::new();
+        loop {
+            if cancel.load(atomic::Ordering::Relaxed) {
+                for entry in removed {
+                    if let Some(journal) = &mut self.journal {
+                        journal.insert(&entry.rect, &self.values[entry.index]);
+                    }
+                    self.insert_entry(entry);
+                }
+                return Err(Cancelled);
+            }
+            let chunk = self.retain_budgeted(rect, DRAIN_CHUNK, |_, _| false);
+            let done = chunk.len() < DRAIN_CHUNK;
+            removed.extend(chunk);
+            if done {
+                break;
+            }
+        }
+        let items: Vec<_> = removed
+            .into_iter()
+            .map(|e| (e.rect, self.values.remove(e.index)))
+            .collect();
+        Ok(items.into_iter())
+    }
+
+    /// Like [`retain_in_rect`](Self::retain_in_rect), stopping once `budget`
+    /// items are removed. Returns the entries of the removed items, whose
+    /// values are still in the slab.
+    fn retain_budgeted<F>(&mut self, rect: Rect<S>, budget: usize, mut f: F) -> Vec<Entry<S>>
     where
-        F: FnMut(&Rect, Option<&'_ T>) -> f32,
+        F: FnMut(&Rect<S>, &mut T) -> bool,
     {
-        NearbyIterator::new(&self.root, dist)
+        let mut removed = Vec::new();
+        let Some(root) = &mut self.root else {
+            return removed;
+        };
+        let root = root.nodes();
+        let mut reinsert = Vec::new();
+        let journal = &mut self.journal;
+        let mut f = |rect: &Rect<S>, value: &mut T| match journal {
+            Some(journal) => journal.visit(rect, value, &mut f),
+            None => f(rect, value),
+        };
+        root.retain(
+            &rect,
+            &mut self.values,
+            &mut f,
+            budget,
+            &mut removed,
+            &mut reinsert,
+            self.height,
+            &mut self.nodes,
+        );
+        self.length -= removed.len() + reinsert.len();
+        if self.length == 0 {
+            self.root = None;
+            self.height = 0;
+            self.nodes = 0;
+        } else {
+            // shorten the tree while the root has a single child
+            while self.height > 0 && root.len() == 1 {
+                let Some(Node::Parent(child)) = root.nodes.pop() else {
+                    unreachable!()
+                };
+                *root = child;
+                self.height -= 1;
+                self.nodes -= 1;
+            }
+        }
+        #[cfg(feature = "stats")]
+        stats::count(|c| c.reinserts += reinsert.len() as u64);
+        #[cfg(feature = "alloc-counters")]
+        let allocated = self.allocs.allocated;
+        for entry in reinsert {
+            self.insert_entry(entry);
+        }
+        #[cfg(feature = "alloc-counters")]
+        self.count_remove(allocated);
+        removed
     }
-}
 
-// iterators, ScanIterator, SearchIterator, NearbyIterator
-
-pub struct IterItem<'n, T> {
-    pub rect: Rect,
-    pub data: &'n T,
-    pub dist: f32,
-}
+    #[cfg(feature = "alloc-counters")]
+    fn count_remove(&mut self, allocated: usize) {
+        let nodes = self.allocs.allocated - allocated;
+        let bytes = nodes * std::mem::size_of::<NodeVec<T, A, S>>();
+        self.allocs.remove.record(1, nodes, bytes);
+    }
 
-struct StackNode<'a, T, A: Alloc<T>> {
-    nodes: Iter<'a, Node<T, A>>,
-}
+    /// Rebuilds the tree by inserting all items again in their original
+    /// order.
+    fn reinsert_all(&mut self) {
+        let mut entries = Vec::with_capacity(self.length);
+        if let Some(mut root) = self.root.take() {
+            root.nodes().flatten_into(&mut entries);
+        }
+        entries.sort_by_key(|e| self.values.seq(e.index));
+        self.length = 0;
+        self.height = 0;
+        self.nodes = 0;
+        for entry in entries {
+            self.insert_entry(entry);
+        }
+    }
 
-impl<'a, T, A: Alloc<T>> StackNode<'a, T, A> {
-    fn new_stack(root: &'a Option<Node<T, A>>, height: usize) -> Vec<StackNode<'a, T, A>> {
-        let mut stack = Vec::with_capacity(height + 1);
-        if let Some(Node::Parent(parent)) = root {
-            stack.push(StackNode {
-                nodes: parent.nodes.iter(),
-            });
+    /// Measures how well the nodes of the tree are arranged for searching.
+    pub fn quality(&self) -> Quality {
+        let mut out = Quality {
+            height: self.height,
+            nodes: 0,
+            area: 0.0,
+            overlap: 0.0,
+        };
+        if let Some(Node::Parent(root)) = &self.root {
+            root.quality(self.height, &mut out);
         }
-        stack
+        out
     }
-}
 
-// search iterator -- much like the scan iterator but with a intersects guard.
+    /// Describes the nodes of the tree, one per line and indented by depth,
+    /// with their rects and the number of their children and items.
+    ///
+    /// Meant for looking into trees that search slowly: nodes with few
+    /// children or rects much larger than their siblings stand out.
+    pub fn format_tree(&self) -> String {
+        let mut out = String::new();
+        if let Some(Node::Parent(root)) = &self.root {
+            root.format(self.height, 0, &mut out);
+        }
+        out
+    }
 
-pub struct SearchIterator<'a, T, A: Alloc<T>> {
-    stack: Vec<StackNode<'a, T, A>>,
-    rect: Rect,
-}
+    /// Counts the nodes and leaves of the tree and measures how full they
+    /// are, such as to compare a tree built by
+    /// [`bulk_load`](Self::bulk_load) to one built by inserts, or different
+    /// [`Config::max_items`].
+    pub fn stats(&self) -> Stats {
+        let mut out = Stats {
+            height: self.height,
+            nodes: 0,
+            leaves: 0,
+            min_fill: 0.0,
+            mean_fill: 0.0,
+            max_fill: 0.0,
+            area: 0.0,
+        };
+        if let Some(Node::Parent(root)) = &self.root {
+            out.min_fill = f64::INFINITY;
+            root.stats(self.height, self.config.max_items, &mut out);
+            out.mean_fill /= out.nodes as f64;
+        }
+        out
+    }
 
-impl<'a, T, A: Alloc<T>> SearchIterator<'a, T, A> {
-    fn new(root: &'a Option<Node<T, A>>, height: usize, rect: Rect) -> Self {
-        Self {
-            stack: StackNode::new_stack(root, height),
-            rect,
+    /// Returns a histogram of the children per leaf and per internal node.
+    pub fn occupancy(&self) -> Occupancy {
+        let mut out = Occupancy {
+            leaves: vec![0; MAX_ITEMS + 1],
+            branches: vec![0; MAX_ITEMS + 1],
+        };
+        if let Some(Node::Parent(root)) = &self.root {
+            root.occupancy(self.height, &mut out);
         }
+        out
     }
-}
 
-impl<'a, T, A: Alloc<T>> Iterator for SearchIterator<'a, T, A> {
-    type Item = IterItem<'a, T>;
+    /// Consumes the tree, returning all items in no particular order.
+    pub fn into_vec(mut self) -> Vec<(Rect<S>, T)> {
+        self.drain_all().collect()
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        'outer: while let Some(stack) = self.stack.last_mut() {
-            while let Some(node) = stack.nodes.next() {
-                if !node.rect().intersects(&self.rect) {
-                    continue;
-                }
-                match node {
-                    Node::Item(data) => {
-                        return Some(IterItem {
-                            rect: data.rect,
-                            data: &data.item,
-                            dist: Default::default(),
-                        });
-                    }
-                    Node::Parent(nodes) => {
-                        self.stack.push(StackNode {
-                            nodes: nodes.nodes.iter(),
-                        });
-                        continue 'outer;
-                    }
-                }
-            }
-            self.stack.pop();
+    /// Removes all items from the tree, returning them in no particular order.
+    ///
+    /// The tree itself stays usable and keeps its allocator.
+    pub fn drain_all(&mut self) -> std::vec::IntoIter<(Rect<S>, T)> {
+        if let Some(journal) = &mut self.journal {
+            journal.clear();
+        }
+        let mut items = Vec::with_capacity(self.length);
+        if let Some(mut root) = self.root.take() {
+            root.nodes().flatten_into(&mut items);
         }
-        None
+        self.length = 0;
+        self.height = 0;
+        self.nodes = 0;
+        let items: Vec<_> = items
+            .into_iter()
+            .map(|n| (n.rect, self.values.remove(n.index)))
+            .collect();
+        items.into_iter()
     }
-}
 
-struct NearbyItem<'a, T, A: Alloc<T>> {
-    dist: f32,
-    node: &'a Node<T, A>,
-}
+    /// Removes all items from the tree and reclaims the memory of its nodes,
+    /// keeping its configuration, journal and filter, which is cleared too.
+    ///
+    /// The nodes of an [`ArenaAlloc`] are reclaimed by resetting its arena,
+    /// unless another tree shares it. Keys of the removed items are no
+    /// longer valid.
+    pub fn clear(&mut self) {
+        if let Some(journal) = &mut self.journal {
+            journal.clear();
+        }
+        if let Some(filter) = &mut self.filter {
+            filter.clear();
+        }
+        self.root = None;
+        self.values = Slab::new(self.values.next_gen());
+        self.length = 0;
+        self.height = 0;
+        self.nodes = 0;
+        self.alloc.reset();
+    }
 
-impl<'a, T, A: Alloc<T>> PartialEq for NearbyItem<'a, T, A> {
-    fn eq(&self, other: &Self) -> bool {
-        self.dist.eq(&other.dist)
+    /// Returns the value of the item with `key`, if it's still in the tree.
+    pub fn get(&self, key: ItemKey) -> Option<&T> {
+        self.values.get(key)
     }
-}
 
-impl<'a, T, A: Alloc<T>> Eq for NearbyItem<'a, T, A> {}
+    /// Changes made through the returned reference aren't recorded in the
+    /// [journal](Self::enable_journal).
+    pub fn get_mut(&mut self, key: ItemKey) -> Option<&mut T> {
+        self.values.get_mut(key)
+    }
 
-impl<'a, T, A: Alloc<T>> PartialOrd for NearbyItem<'a, T, A> {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.dist.partial_cmp(&other.dist).map(Ordering::reverse)
+    /// Moves the values of the items together in tree order, releasing the
+    /// storage left behind by removed items.
+    ///
+    /// Invalidates all previously returned [`ItemKey`]s.
+    pub fn compact(&mut self) {
+        let mut values = Slab::new(self.values.next_gen());
+        values.chunks.reserve_exact(self.length.div_ceil(CHUNK));
+        values.seq = self.values.seq;
+        if let Some(Node::Parent(root)) = &mut self.root {
+            root.compact(&mut self.values, &mut values);
+        }
+        self.values = values;
     }
-}
 
-impl<'a, T, A: Alloc<T>> Ord for NearbyItem<'a, T, A> {
-    fn cmp(&self, other: &Self) -> Ordering {
-        self.dist.total_cmp(&other.dist)
+    pub fn iter(&self) -> ScanIterator<'_, T, A, S> {
+        ScanIterator::new(&self.root, &self.values, self.height, self.length)
     }
-}
 
-pub struct NearbyIterator<'a, T, A: Alloc<T>, F> {
-    queue: BinaryHeap<NearbyItem<'a, T, A>>,
-    dist: F,
+    /// Moves the children of every node that searches descended into most
+    /// often first, which speeds up searches stopping at the first match
+    /// when queries favor some areas. Counting requires
+    /// [`Config::profile`], the counts are cleared afterwards.
+    pub fn reorder(&mut self) {
+        if let Some(Node::Parent(root)) = &mut self.root {
+            root.reorder();
+        }
+    }
 }
 
-impl<'a, T, A, F> NearbyIterator<'a, T, A, F>
-where
-    A: Alloc<T>,
-    F: FnMut(&Rect, Option<&'a T>) -> f32,
-{
-    fn new(root: &'a Option<Node<T, A>>, dist: F) -> Self {
-        let mut queue = BinaryHeap::new();
-        if let Some(root) = root {
-            queue.push(NearbyItem {
-                dist: Default::default(),
-                node: root,
-            });
-        }
-        NearbyIterator { queue, dist }
+impl<T, A: Alloc<T>> RTree<T, A> {
+    /// Builds a tree of `items` by Sort-Tile-Recursive packing, which takes
+    /// O(n log n) time and is much faster than inserting them one by one.
+    ///
+    /// Nodes are filled as far as [`Config::max_items`] allows and tile the
+    /// space with little overlap, which suits static data best: inserts
+    /// afterwards split the nodes they land in.
+    pub fn bulk_load<I>(alloc: A, items: I) -> Self
+    where
+        I: IntoIterator<Item = (Rect, T)>,
+    {
+        Self::try_bulk_load(alloc, items, &AtomicBool::new(false)).unwrap()
     }
-}
 
-impl<'a, T, A, F> Iterator for NearbyIterator<'a, T, A, F>
-where
-    A: Alloc<T>,
-    F: FnMut(&Rect, Option<&'a T>) -> f32,
-{
-    type Item = IterItem<'a, T>;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        while let Some(item) = self.queue.pop() {
-            match &item.node {
-                Node::Item(data) => {
-                    return Some(IterItem {
-                        rect: data.rect,
-                        data: &data.item,
-                        dist: item.dist,
-                    });
-                }
-                Node::Parent(nodes) => {
-                    self.queue.extend(nodes.nodes.iter().map(|node| {
-                        let (rect, item) = match node {
-                            Node::Item(item) => (&item.rect, Some(&item.item)),
-                            Node::Parent(nodes) => (&nodes.rect, None),
-                        };
-                        let dist = (self.dist)(rect, item);
-                        NearbyItem { dist, node }
-                    }));
+    /// Like [`bulk_load`](Self::bulk_load), giving up once `cancel` is set,
+    /// which is checked for every item taken from `items` and before packing
+    /// every node.
+    pub fn try_bulk_load<I>(alloc: A, items: I, cancel: &AtomicBool) -> Result<Self, Cancelled>
+    where
+        I: IntoIterator<Item = (Rect, T)>,
+    {
+        let mut tr = RTree::new(alloc);
+        let entries = items
+            .into_iter()
+            .map(
+                |(rect, data)| match cancel.load(atomic::Ordering::Relaxed) {
+                    true => Err(Cancelled),
+                    false => Ok(Entry {
+                        rect,
+                        index: tr.values.insert(data),
+                    }),
+                },
+            )
+            .collect::<Result<Vec<Entry>, _>>()?;
+        tr.length = entries.len();
+        if !entries.is_empty() {
+            let fill = tr.config.max_items - 1;
+            let (mut root, height) = pack(entries, &tr.alloc, fill, cancel, &mut tr.nodes)?;
+            root.renew(&tr.values);
+            tr.root = Some(Node::Parent(root));
+            tr.height = height;
+        }
+        Ok(tr)
+    }
+
+    /// Compares node capacities and split strategies on the current items,
+    /// returning each configuration with its cost for `queries`, cheapest
+    /// first.
+    ///
+    /// The cost is the number of rects tested when searching for all of
+    /// `queries` in a tree built by inserting the current rects in their
+    /// original order. With `apply`, `self` is rebuilt the same way using
+    /// the cheapest configuration.
+    pub fn tune(&mut self, queries: &[Rect], apply: bool) -> Vec<(Config, usize)> {
+        let mut entries = Vec::with_capacity(self.length);
+        if let Some(root) = &self.root {
+            root.entries(&mut entries);
+        }
+        entries.sort_by_key(|e| self.values.seq(e.index));
+        let mut report = Vec::new();
+        for max_items in [8, 16, 24, 32] {
+            for robust in [false, true] {
+                let mut tr = RTree::new(BoxAlloc);
+                tr.config = Config {
+                    max_items,
+                    robust,
+                    ..self.config
+                };
+                for e in &entries {
+                    tr.insert(e.rect, ());
                 }
+                let cost = queries.iter().map(|q| tr.search_cost(q)).sum();
+                report.push((tr.config, cost));
             }
         }
-        None
+        report.sort_by_key(|x| x.1);
+        if apply {
+            self.config = report[0].0;
+            self.reinsert_all();
+        }
+        report
     }
-}
 
-#[derive(PartialEq)]
-struct Ordered(f32);
+    fn search_cost(&self, rect: &Rect) -> usize {
+        match &self.root {
+            Some(Node::Parent(root)) if root.rect.intersects(rect) => {
+                root.search_cost(rect, self.height)
+            }
+            _ => 0,
+        }
+    }
 
-impl Eq for Ordered {}
+    /// Rebuilds the tree from its items by Sort-Tile-Recursive packing, which
+    /// undoes the degradation left by many inserts and removals. Returns the
+    /// quality of the tree before and after.
+    ///
+    /// Nodes are filled one short of [`Config::max_items`], so the next
+    /// insert into a node doesn't split it right away. The nodes of an arena
+    /// allocator like [`Blink`](blink_alloc::Blink) are only reclaimed when it's reset.
+    ///
+    /// The packing is deterministic: it depends only on the rects and the
+    /// order they were inserted in, not on the shape of the tree before, so
+    /// trees built from the same inserts are rebuilt identically.
+    pub fn rebuild(&mut self) -> (Quality, Quality) {
+        let before = self.quality();
+        let mut entries = Vec::with_capacity(self.length);
+        if let Some(mut root) = self.root.take() {
+            root.nodes().flatten_into(&mut entries);
+        }
+        self.repack(entries, &AtomicBool::new(false)).unwrap();
+        (before, self.quality())
+    }
 
-impl PartialOrd for Ordered {
-    fn partial_cmp(&self, other: &Ordered) -> Option<Ordering> {
-        Some(self.cmp(other))
+    /// Like [`rebuild`](Self::rebuild), giving up once `cancel` is set,
+    /// which is checked before packing every node. The new nodes are packed
+    /// beside the old ones, which a cancelled rebuild leaves as they were.
+    pub fn try_rebuild(&mut self, cancel: &AtomicBool) -> Result<(Quality, Quality), Cancelled> {
+        let before = self.quality();
+        let Some(root) = &self.root else {
+            return Ok((before, before));
+        };
+        let mut entries = Vec::with_capacity(self.length);
+        root.copy_entries(&mut entries);
+        self.repack(entries, cancel)?;
+        Ok((before, self.quality()))
     }
-}
 
-impl Ord for Ordered {
-    fn cmp(&self, other: &Ordered) -> Ordering {
-        if self.0 < other.0 {
-            Ordering::Less
-        } else if self.0 > other.0 {
-            Ordering::Greater
-        } else {
-            Ordering::Equal
+    /// Replaces the nodes with `entries` packed by STR, leaving the tree as
+    /// it was when cancelled.
+    fn repack(&mut self, mut entries: Vec<Entry>, cancel: &AtomicBool) -> Result<(), Cancelled> {
+        if entries.is_empty() {
+            self.root = None;
+            self.height = 0;
+            self.nodes = 0;
+            return Ok(());
         }
+        entries.sort_by_key(|e| self.values.seq(e.index));
+        let mut nodes = 0;
+        let fill = self.config.max_items - 1;
+        let (mut root, height) = pack(entries, &self.alloc, fill, cancel, &mut nodes)?;
+        root.renew(&self.values);
+        if let Some(attr) = self.attr {
+            root.reattr(&self.values, attr);
+        }
+        if let Some(margin) = self.margin {
+            root.remargin(&self.values, margin);
+        }
+        if self.config.circles {
+            root.set_circles(true);
+        }
+        self.root = Some(Node::Parent(root));
+        self.height = height;
+        self.nodes = nodes;
+        Ok(())
     }
-}
 
-fn min(a: f32, b: f32) -> f32 {
-    if a < b {
-        a
-    } else {
-        b
-    }
-}
+    /// Repacks the children of the root intersecting `rect` like
+    /// [`rebuild`](Self::rebuild), leaving the others as they are, which
+    /// undoes the degradation left by changes concentrated in a region at a
+    /// fraction of the cost. Returns the number of items repacked.
+    ///
+    /// Too few items for subtrees of the height of the others are inserted
+    /// again instead. The whole tree is rebuilt when `rect` covers all but
+    /// one of the children, or when the repacked ones wouldn't fit in the
+    /// root.
+    pub fn rebuild_in_rect(&mut self, rect: Rect) -> usize {
+        let Some(Node::Parent(root)) = &mut self.root else {
+            return 0;
+        };
+        let fill = self.config.max_items - 1;
+        let height = self.height;
+        let hit = |n: &Node<T, A>| n.rect().intersects(&rect);
+        let count: usize = root.nodes.iter().filter(|n| hit(n)).map(Node::count).sum();
+        let kept = root.nodes.iter().filter(|n| !hit(n)).count();
+        if count == 0 {
+            return 0;
+        }
+        let subtrees = count.div_ceil(fill.pow(height as u32));
+        if height == 0 || kept < 2 || kept + subtrees >= self.config.max_items {
+            self.rebuild();
+            return self.length;
+        }
 
-fn max(a: f32, b: f32) -> f32 {
-    if a > b {
-        a
-    } else {
-        b
+        let mut entries = Vec::with_capacity(count);
+        let mut i = 0;
+        while i < root.nodes.len() {
+            if hit(&root.nodes[i]) {
+                let mut child = root.nodes.swap_remove(i);
+                self.nodes -= 1 + child.nodes().flatten_into(&mut entries);
+            } else {
+                i += 1;
+            }
+        }
+        entries.sort_by_key(|e| self.values.seq(e.index));
+        if count <= fill.pow(height as u32 - 1) {
+            root.recalc();
+            self.length -= count;
+            #[cfg(feature = "stats")]
+            stats::count(|c| c.reinserts += count as u64);
+            for entry in entries {
+                self.insert_entry(entry);
+            }
+            return count;
+        }
+        let never = AtomicBool::new(false);
+        for group in tile(entries, fill, height) {
+            let mut child = pack_node(
+                group,
+                &self.alloc,
+                fill,
+                height - 1,
+                &never,
+                &mut self.nodes,
+            )
+            .unwrap();
+            child.renew(&self.values);
+            if let Some(attr) = self.attr {
+                child.reattr(&self.values, attr);
+            }
+            if let Some(margin) = self.margin {
+                child.remargin(&self.values, margin);
+            }
+            if self.config.circles {
+                child.set_circles(true);
+            }
+            root.push(Node::Parent(child));
+        }
+        root.recalc();
+        count
     }
 }
@@ -0,0 +1,86 @@
+//! Loading points from CSV and newline-delimited JSON.
+
+use crate::{Alloc, RTree, Rect};
+use std::io::{self, Error, ErrorKind};
+
+/// Names of the columns or keys holding the point coordinates.
+#[derive(Clone, Copy, Debug)]
+pub struct Columns<'a> {
+    pub x: &'a str,
+    pub y: &'a str,
+}
+
+impl Default for Columns<'_> {
+    fn default() -> Self {
+        Columns { x: "lon", y: "lat" }
+    }
+}
+
+impl<T, A: Alloc<T>> RTree<T, A> {
+    /// Inserts a point for every row of a CSV file with a header row.
+    ///
+    /// `f` converts each row to an item.
+    #[cfg(feature = "csv")]
+    pub fn load_csv<R, F>(&mut self, reader: R, columns: Columns, mut f: F) -> io::Result<()>
+    where
+        R: io::Read,
+        F: FnMut(&csv::StringRecord) -> T,
+    {
+        let mut reader = csv::Reader::from_reader(reader);
+        let headers = reader.headers()?;
+        let position = |name| {
+            headers
+                .iter()
+                .position(|h| h == name)
+                .ok_or_else(|| invalid(format!("missing column {name:?}")))
+        };
+        let (x, y) = (position(columns.x)?, position(columns.y)?);
+        let mut record = csv::StringRecord::new();
+        while reader.read_record(&mut record)? {
+            let coord = |i: usize| {
+                record
+                    .get(i)
+                    .and_then(|v| v.trim().parse().ok())
+                    .ok_or_else(|| invalid(format!("bad coordinate in {record:?}")))
+            };
+            let rect = Rect::point(coord(x)?, coord(y)?);
+            self.insert(rect, f(&record));
+        }
+        Ok(())
+    }
+
+    /// Inserts a point for every line of newline-delimited JSON objects.
+    ///
+    /// Coordinates may be numbers or numeric strings. `f` converts each
+    /// object to an item, empty lines are skipped.
+    #[cfg(feature = "ndjson")]
+    pub fn load_ndjson<R, F>(&mut self, reader: R, columns: Columns, mut f: F) -> io::Result<()>
+    where
+        R: io::BufRead,
+        F: FnMut(serde_json::Map<String, serde_json::Value>) -> T,
+    {
+        use serde_json::Value;
+
+        for line in reader.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let object: serde_json::Map<String, Value> = serde_json::from_str(&line)?;
+            let coord = |name| match object.get(name) {
+                Some(Value::Number(n)) => n.as_f64().map(|v| v as f32),
+                Some(Value::String(s)) => s.trim().parse().ok(),
+                _ => None,
+            };
+            let (Some(x), Some(y)) = (coord(columns.x), coord(columns.y)) else {
+                return Err(invalid(format!("bad coordinates in {line:?}")));
+            };
+            self.insert(Rect::point(x, y), f(object));
+        }
+        Ok(())
+    }
+}
+
+fn invalid(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
@@ -0,0 +1,280 @@
+//! A write-optimized index built from a mutable tree and immutable levels.
+//!
+//! New items go to a small memtable tree. Once it's full, its items are
+//! packed into a run of their own, which takes time bounded by the size of
+//! the memtable. Merging runs into a series of packed levels, each a few
+//! times larger than the previous one, is left to the caller: a
+//! [`MergeJob`] can be run on another thread while the index keeps taking
+//! writes, or [`Lsm::flush`] merges on the spot. Queries fan out over the
+//! memtable, the runs and every level, so merging often enough keeps their
+//! cost growing with the logarithm of the number of items rather than with
+//! the rate of writes.
+
+use crate::frozen::FrozenView;
+use crate::{Alloc, BoxAlloc, ItemKey, IterItem, RTree, Rect};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+/// Growth factor between consecutive levels.
+const FANOUT: usize = 4;
+
+#[derive(Clone)]
+struct Level<T> {
+    /// Unique among all levels ever built, so that tombstones of merged
+    /// levels never apply to new ones.
+    id: u64,
+    view: Arc<FrozenView<T>>,
+}
+
+pub struct Lsm<T, A: Alloc<T>> {
+    memtable: RTree<T, A>,
+    memtable_size: usize,
+    /// Packed memtables waiting to be merged into the levels.
+    runs: Vec<Level<T>>,
+    levels: Vec<Level<T>>,
+    /// Items removed from a run or level but still stored in it.
+    tombstones: HashSet<(u64, ItemKey)>,
+    next_id: u64,
+}
+
+/// A merge of the runs of an [`Lsm`] into its levels, taken by
+/// [`Lsm::merge_job`].
+///
+/// The job holds on to the runs and levels it merges and can be run on any
+/// thread, while the index keeps taking inserts, removals and queries. Its
+/// result is then handed back to [`Lsm::finish_merge`].
+pub struct MergeJob<T> {
+    inputs: Vec<Level<T>>,
+    /// Items of the inputs removed before the job was taken.
+    removed: HashSet<(u64, ItemKey)>,
+    /// The level the merged items go to.
+    level: usize,
+    id: u64,
+}
+
+/// The levels merged by [`MergeJob::run`].
+pub struct Merged<T> {
+    inputs: Vec<u64>,
+    level: usize,
+    output: Level<T>,
+    /// Keys of the merged items by their keys in the inputs, to carry over
+    /// removals made while the job ran.
+    keys: HashMap<(u64, ItemKey), ItemKey>,
+}
+
+impl<T: Clone> MergeJob<T> {
+    /// Packs the items of the inputs that weren't removed into a new level.
+    pub fn run(self) -> Merged<T> {
+        let mut sources = Vec::new();
+        let mut items = Vec::new();
+        for input in &self.inputs {
+            for item in input.view.iter() {
+                if !self.removed.contains(&(input.id, item.key)) {
+                    sources.push((input.id, item.key));
+                    items.push((item.rect, item.data.clone()));
+                }
+            }
+        }
+        let tr = RTree::bulk_load(BoxAlloc, items);
+        // packing keeps the values in the order they're given
+        let keys = (0..)
+            .zip(sources)
+            .map(|(i, source)| (source, tr.values.key(i)))
+            .collect();
+        Merged {
+            inputs: self.inputs.iter().map(|input| input.id).collect(),
+            level: self.level,
+            output: Level {
+                id: self.id,
+                view: tr.freeze(),
+            },
+            keys,
+        }
+    }
+}
+
+impl<T: Clone + 'static, A: Alloc<T>> Lsm<T, A> {
+    /// Creates an index whose memtable is packed into a run once it holds
+    /// `memtable_size` items.
+    pub fn new(alloc: A, memtable_size: usize) -> Self {
+        assert!(memtable_size > 0, "memtable_size must be positive");
+        Lsm {
+            memtable: RTree::new(alloc),
+            memtable_size,
+            runs: Vec::new(),
+            levels: Vec::new(),
+            tombstones: HashSet::new(),
+            next_id: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        let stored: usize = self.stored().map(|l| l.view.len()).sum();
+        self.memtable.len() + stored - self.tombstones.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of non-empty runs and levels, which bounds the number of
+    /// trees a query visits besides the memtable.
+    pub fn levels(&self) -> usize {
+        self.stored().filter(|l| !l.view.is_empty()).count()
+    }
+
+    fn stored(&self) -> impl Iterator<Item = &Level<T>> {
+        self.runs.iter().chain(&self.levels)
+    }
+
+    /// Inserts an item, packing the memtable into a run once it's full.
+    /// Runs are only merged by [`merge_job`](Self::merge_job) and
+    /// [`flush`](Self::flush).
+    pub fn insert(&mut self, rect: Rect, data: T) {
+        self.memtable.insert(rect, data);
+        if self.memtable.len() >= self.memtable_size {
+            self.seal();
+        }
+    }
+
+    fn seal(&mut self) {
+        if self.memtable.is_empty() {
+            return;
+        }
+        let items: Vec<(Rect, T)> = self.memtable.drain_all().collect();
+        let run = self.build(items);
+        self.runs.push(run);
+    }
+
+    /// Removes an item, returning whether it was found.
+    ///
+    /// Items in the runs and levels are only marked as removed, they are
+    /// dropped when they're next merged.
+    pub fn remove(&mut self, rect: Rect, data: &T) -> bool
+    where
+        T: PartialEq,
+    {
+        if self.memtable.remove(rect, data).is_some() {
+            return true;
+        }
+        for level in self.runs.iter().chain(&self.levels) {
+            let found = level.view.search(rect).find(|item| {
+                item.rect == rect
+                    && item.data == data
+                    && !self.tombstones.contains(&(level.id, item.key))
+            });
+            if let Some(item) = found {
+                self.tombstones.insert((level.id, item.key));
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Takes a merge of the runs into the first level with room for them
+    /// and the levels before it, or `None` if there are no runs.
+    pub fn merge_job(&mut self) -> Option<MergeJob<T>> {
+        if self.runs.is_empty() {
+            return None;
+        }
+        let live = |level: &Level<T>| {
+            let removed = self.tombstones.iter().filter(|t| t.0 == level.id).count();
+            level.view.len() - removed
+        };
+        let mut inputs = self.runs.clone();
+        let mut count: usize = inputs.iter().map(live).sum();
+        let mut level = 0;
+        while let Some(next) = self.levels.get(level) {
+            if !next.view.is_empty() {
+                inputs.push(next.clone());
+                count += live(next);
+            }
+            if count <= self.memtable_size * FANOUT.pow(level as u32 + 1) {
+                break;
+            }
+            level += 1;
+        }
+        let removed = self
+            .tombstones
+            .iter()
+            .filter(|t| inputs.iter().any(|input| input.id == t.0))
+            .copied()
+            .collect();
+        self.next_id += 1;
+        Some(MergeJob {
+            inputs,
+            removed,
+            level,
+            id: self.next_id,
+        })
+    }
+
+    /// Replaces the inputs of a merge with its result, returning whether it
+    /// was applied. Merges of runs or levels that were merged meanwhile by
+    /// another job are dropped.
+    pub fn finish_merge(&mut self, merged: Merged<T>) -> bool {
+        let input = |level: &Level<T>| merged.inputs.contains(&level.id);
+        let present = self.stored().filter(|l| input(l)).count();
+        let taken = self
+            .levels
+            .get(merged.level)
+            .is_some_and(|l| !l.view.is_empty() && !input(l));
+        if present < merged.inputs.len() || taken {
+            return false;
+        }
+        self.runs.retain(|l| !input(l));
+        while self.levels.len() <= merged.level {
+            let empty = self.build(Vec::new());
+            self.levels.push(empty);
+        }
+        for i in 0..self.levels.len() {
+            if input(&self.levels[i]) {
+                self.levels[i] = self.build(Vec::new());
+            }
+        }
+        // removals made while the job ran apply to the merged items
+        let stale: Vec<_> = self
+            .tombstones
+            .iter()
+            .filter(|t| merged.inputs.contains(&t.0))
+            .copied()
+            .collect();
+        for tombstone in stale {
+            self.tombstones.remove(&tombstone);
+            if let Some(&key) = merged.keys.get(&tombstone) {
+                self.tombstones.insert((merged.output.id, key));
+            }
+        }
+        self.levels[merged.level] = merged.output;
+        true
+    }
+
+    /// Packs the memtable into a run and merges all runs into the levels,
+    /// blocking until done.
+    pub fn flush(&mut self) {
+        self.seal();
+        while let Some(job) = self.merge_job() {
+            let merged = job.run();
+            self.finish_merge(merged);
+        }
+    }
+
+    fn build(&mut self, items: Vec<(Rect, T)>) -> Level<T> {
+        self.next_id += 1;
+        Level {
+            id: self.next_id,
+            view: RTree::bulk_load(BoxAlloc, items).freeze(),
+        }
+    }
+
+    /// Searches the memtable, the runs and every level.
+    pub fn search(&self, rect: Rect) -> impl Iterator<Item = IterItem<'_, T>> + '_ {
+        let levels = self.stored().flat_map(move |level| {
+            level
+                .view
+                .search(rect)
+                .filter(move |item| !self.tombstones.contains(&(level.id, item.key)))
+        });
+        self.memtable.search(rect).chain(levels)
+    }
+}
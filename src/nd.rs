@@ -0,0 +1,165 @@
+//! Indexing boxes of more than two dimensions.
+//!
+//! Nodes stay two-dimensional, so that the 2D trees keep their fast paths:
+//! boxes are indexed by their extent on two of their axes, and the other
+//! axes are checked against each item found there. Searches are as fast as
+//! in a 2D tree when the indexed axes are the most selective ones, such as x
+//! and y for boxes in a shallow 3D volume, and pick
+//! [which axes](RTreeNd::with_axes) those are for the data at hand. A search
+//! selective only on the other axes still visits every item within its
+//! extent on the indexed ones.
+//!
+//! Nearest neighbor queries are exact: the distance on the indexed axes
+//! bounds the distance in all of them from below, so nodes are visited in
+//! the same order as in the 2D tree and items are ranked by their distance
+//! in `D` dimensions.
+
+use crate::{Alloc, IterItem, Point, RTree, Rect, Scalar};
+
+/// A box in `D` dimensions, with its bounds on each axis.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct BoxN<const D: usize, S = f32> {
+    pub min: [S; D],
+    pub max: [S; D],
+}
+
+impl<const D: usize, S: Scalar> BoxN<D, S> {
+    pub const fn new(min: [S; D], max: [S; D]) -> Self {
+        BoxN { min, max }
+    }
+
+    pub const fn point(p: [S; D]) -> Self {
+        BoxN { min: p, max: p }
+    }
+
+    /// Determines whether `other` is intersecting `self`, edges included.
+    pub fn intersects(&self, other: &Self) -> bool {
+        (0..D).all(|i| self.min[i] <= other.max[i] && other.min[i] <= self.max[i])
+    }
+
+    /// The extent on axes `a` and `b`.
+    fn projected(&self, [a, b]: [usize; 2]) -> Rect<S> {
+        Rect::new(
+            Point::new(self.min[a], self.min[b]),
+            Point::new(self.max[a], self.max[b]),
+        )
+    }
+}
+
+impl<const D: usize> BoxN<D> {
+    /// The squared distance from `point` to the nearest point of `self`,
+    /// zero inside, like [`Rect::box_dist`].
+    pub fn point_dist(&self, point: &[f32; D]) -> f32 {
+        (0..D)
+            .map(|i| {
+                let d = (self.min[i] - point[i])
+                    .max(point[i] - self.max[i])
+                    .max(0.0);
+                d * d
+            })
+            .sum()
+    }
+}
+
+/// A tree of boxes in `D` dimensions, with `D` at least 2, indexed by two of
+/// their axes, see the [module](self) docs.
+pub struct RTreeNd<const D: usize, T, A: Alloc<(BoxN<D, S>, T), S>, S = f32> {
+    tree: RTree<(BoxN<D, S>, T), A, S>,
+    axes: [usize; 2],
+}
+
+impl<const D: usize, T, A: Alloc<(BoxN<D, f32>, T)>> RTreeNd<D, T, A> {
+    /// Creates a tree of f32 coordinates indexed by the first two axes, see
+    /// [`with_scalar`](Self::with_scalar) for others.
+    pub fn new(alloc: A) -> Self {
+        Self::with_scalar(alloc)
+    }
+
+    /// Iterates over the items by their distance from `point`, nearest
+    /// first, yielding the squared distance along with each item.
+    pub fn nearby(&self, point: [f32; D]) -> impl Iterator<Item = (f32, &BoxN<D>, &T)> + '_ {
+        let [a, b] = self.axes;
+        let target = Rect::point(point[a], point[b]);
+        // the distance on two axes bounds the distance on all of them
+        let dist = move |rect: &Rect, data: Option<&(BoxN<D>, T)>| match data {
+            Some((bbox, _)) => bbox.point_dist(&point),
+            None => rect.box_dist(&target),
+        };
+        self.tree
+            .nearby(dist)
+            .map(|item: IterItem<'_, _>| (item.dist, &item.data.0, &item.data.1))
+    }
+
+    /// Finds the item nearest to `point`, by [`BoxN::point_dist`].
+    pub fn nearest(&self, point: [f32; D]) -> Option<(&BoxN<D>, &T)> {
+        self.nearby(point)
+            .next()
+            .map(|(_, bbox, data)| (bbox, data))
+    }
+
+    /// Finds the `k` items nearest to `point`, ordered by
+    /// [`BoxN::point_dist`].
+    pub fn k_nearest(&self, point: [f32; D], k: usize) -> Vec<(&BoxN<D>, &T)> {
+        let found = self.nearby(point).take(k);
+        found.map(|(_, bbox, data)| (bbox, data)).collect()
+    }
+}
+
+impl<const D: usize, T, A: Alloc<(BoxN<D, S>, T), S>, S: Scalar> RTreeNd<D, T, A, S> {
+    pub fn with_scalar(alloc: A) -> Self {
+        Self::with_axes(alloc, [0, 1])
+    }
+
+    /// Creates a tree indexed by the two distinct `axes`, which are best the
+    /// ones the queries are the most selective on.
+    pub fn with_axes(alloc: A, axes: [usize; 2]) -> Self {
+        assert!(D >= 2, "boxes need at least two dimensions");
+        assert!(
+            axes[0] != axes[1] && axes.iter().all(|&axis| axis < D),
+            "axes must be two distinct axes of the boxes"
+        );
+        RTreeNd {
+            tree: RTree::with_scalar(alloc),
+            axes,
+        }
+    }
+
+    /// The two axes the tree is indexed by.
+    pub fn axes(&self) -> [usize; 2] {
+        self.axes
+    }
+
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.len() == 0
+    }
+
+    pub fn insert(&mut self, bbox: BoxN<D, S>, data: T) {
+        self.tree.insert(bbox.projected(self.axes), (bbox, data));
+    }
+
+    /// Removes an item with exactly `bbox` and `data`.
+    pub fn remove(&mut self, bbox: BoxN<D, S>, data: &T) -> Option<T>
+    where
+        T: PartialEq,
+    {
+        self.tree
+            .remove_where(bbox.projected(self.axes), |(b, d)| *b == bbox && d == data)
+            .map(|removed| removed.item.1)
+    }
+
+    /// Searches for the items intersecting `bbox`.
+    pub fn search(&self, bbox: BoxN<D, S>) -> impl Iterator<Item = (&BoxN<D, S>, &T)> + '_ {
+        self.tree
+            .search(bbox.projected(self.axes))
+            .map(|item| (&item.data.0, &item.data.1))
+            .filter(move |(b, _)| b.intersects(&bbox))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&BoxN<D, S>, &T)> + '_ {
+        self.tree.iter().map(|item| (&item.data.0, &item.data.1))
+    }
+}
@@ -0,0 +1,1519 @@
+//! The nodes of a tree and the slab holding the values of its items.
+
+use crate::geom::{max, Circle, Ordered};
+use crate::{
+    Alloc, BoxAlloc, Cancelled, Config, IterItem, Metric, Occupancy, Point, Quality, Rect, Scalar,
+    Stats, MAX_ITEMS, MIN_ITEMS,
+};
+use arrayvec::ArrayVec;
+use std::cmp::Ordering;
+use std::fmt::Write;
+use std::ops::{Index, IndexMut};
+use std::sync::atomic::{self, AtomicBool, AtomicU32};
+use std::sync::{Arc, OnceLock};
+
+/// A node above the items, holding its children and what it keeps about the
+/// items below it in a [`NodeVec`](crate::NodeVec).
+pub struct Parent<T, A: Alloc<T, S>, S = f32> {
+    pub(crate) nodes: A::Output,
+    pub(crate) rect: Rect<S>,
+}
+
+impl<T, A: Alloc<T, S>, S: Scalar> Parent<T, A, S> {
+    pub(crate) fn new(rect: Rect<S>, alloc: &A) -> Self {
+        Self {
+            nodes: alloc.make(),
+            rect,
+        }
+    }
+
+    /// A node holding `children`.
+    pub(crate) fn of(children: Vec<Node<T, A, S>>, alloc: &A) -> Self {
+        let mut parent = Parent::new(*children[0].rect(), alloc);
+        for child in children {
+            parent.push(child);
+        }
+        parent.recalc();
+        parent
+    }
+
+    /// A deep copy of the node, allocating the copies of it and all nodes
+    /// below it with `alloc`.
+    pub(crate) fn clone_in(&self, alloc: &A) -> Self {
+        let children = self.nodes.iter().map(|node| match node {
+            Node::Item(entry) => Node::Item(Entry {
+                rect: entry.rect,
+                index: entry.index,
+            }),
+            Node::Parent(parent) => Node::Parent(parent.clone_in(alloc)),
+        });
+        let mut nodes = alloc.make();
+        *nodes = self.nodes.copy_with(children.collect());
+        Self {
+            nodes,
+            rect: self.rect,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub(crate) fn is_leaf(&self) -> bool {
+        matches!(self.nodes.first(), Some(Node::Item(_)))
+    }
+
+    pub(crate) fn is_full(&self, config: &Config) -> bool {
+        self.nodes.len() >= config.max_items
+    }
+
+    pub(crate) fn choose_least_enlargement(&mut self, rect: &Rect<S>) -> &mut Node<T, A, S> {
+        let mut n = None;
+        let mut min_delta = S::Area::default();
+        let mut min_area = S::Area::default();
+        for node in self.nodes.iter_mut() {
+            let uarea = node.rect().unioned_area(rect);
+            let area = node.rect().area();
+            // infinite areas grow by nothing rather than by NaN
+            let delta = match uarea == area {
+                true => S::Area::default(),
+                false => uarea - area,
+            };
+            if n.is_none() || delta < min_delta || (delta == min_delta && area < min_area) {
+                n = Some(node);
+                min_delta = delta;
+                min_area = area;
+            }
+        }
+        n.expect("empty parent")
+    }
+
+    /// Like `choose_least_enlargement`, but computed in f64 and breaking ties
+    /// by the growth of the perimeter and then by the distance between the
+    /// centers, which tells apart zero-area and duplicate rects.
+    pub(crate) fn choose_least_enlargement_robust(&mut self, rect: &Rect<S>) -> &mut Node<T, A, S> {
+        let cost = |node: &Node<T, A, S>| {
+            let mut union = *node.rect();
+            union.expand(rect);
+            let (w, h) = node.rect().extent64();
+            let (uw, uh) = union.extent64();
+            let (a, b) = (node.rect().center64(), rect.center64());
+            let (dx, dy) = (a.0 - b.0, a.1 - b.1);
+            [uw * uh - w * h, w * h, uw + uh - w - h, dx * dx + dy * dy]
+        };
+        let i = (0..self.nodes.len())
+            .min_by(|&a, &b| {
+                let (a, b) = (cost(&self.nodes[a]), cost(&self.nodes[b]));
+                a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+            })
+            .expect("empty parent");
+        &mut self.nodes[i]
+    }
+
+    /// Adds the number of nodes created by splits to `nodes`. `attr` is the
+    /// attribute of the item, if the tree has one, and `margin` its margin.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn insert(
+        &mut self,
+        entry: Entry<S>,
+        seq: u64,
+        attr: Option<f32>,
+        margin: f32,
+        height: usize,
+        alloc: &A,
+        config: &Config,
+        nodes: &mut usize,
+    ) {
+        let rect = entry.rect;
+        if height > 0 {
+            // branch node
+            let child = if config.robust {
+                self.choose_least_enlargement_robust(&rect)
+            } else {
+                self.choose_least_enlargement(&rect)
+            };
+            let Node::Parent(child) = child else {
+                return;
+            };
+            child.insert(entry, seq, attr, margin, height - 1, alloc, config, nodes);
+            if child.is_full(config) {
+                let right = child.split_largest_axis_edge_snap(alloc, config.robust);
+                #[cfg(feature = "stats")]
+                crate::stats::count(|c| c.splits += 1);
+                self.nodes.push(right);
+                *nodes += 1;
+            }
+        } else {
+            // leaf node
+            self.nodes.push(Node::Item(entry));
+        }
+        self.nodes.count += 1;
+        self.nodes.newest = self.nodes.newest.max(seq);
+        if let Some(attr) = attr {
+            self.widen_attr([attr, attr]);
+        }
+        self.nodes.margin = self.nodes.margin.max(margin);
+        self.rect.expand(&rect);
+        if let Some(circle) = &mut self.nodes.circle {
+            circle.expand(&Circle::of(&rect));
+        }
+    }
+
+    pub(crate) fn recalc(&mut self) {
+        self.recount();
+        if self.nodes.len() == 0 {
+            return;
+        }
+        let mut rect = *self.nodes[0].rect();
+        for i in 1..self.nodes.len() {
+            rect.expand(self.nodes[i].rect());
+        }
+        self.rect = rect;
+        if self.nodes.circle.is_none() {
+            return;
+        }
+        let mut circle = self.nodes[0].circle();
+        for i in 1..self.nodes.len() {
+            circle.expand(&self.nodes[i].circle());
+        }
+        // merging circles can end up looser than the rect itself
+        let outer = Circle::of(&rect);
+        self.nodes.circle = Some(if outer.radius < circle.radius {
+            outer
+        } else {
+            circle
+        });
+    }
+
+    /// Starts or stops keeping the bounding circles of `self` and the nodes
+    /// below it, see [`Config::circles`].
+    pub(crate) fn set_circles(&mut self, on: bool) {
+        for node in self.nodes.iter_mut() {
+            if let Node::Parent(child) = node {
+                child.set_circles(on);
+            }
+        }
+        self.nodes.circle = on.then(|| Circle::of(&self.rect));
+        self.recalc();
+    }
+
+    /// Sets `newest` below `self` to the exact sequence numbers of the items.
+    pub(crate) fn renew(&mut self, values: &Slab<T>) {
+        let mut newest = 0;
+        for node in self.nodes.iter_mut() {
+            let seq = match node {
+                Node::Item(entry) => values.seq(entry.index),
+                Node::Parent(child) => {
+                    child.renew(values);
+                    child.nodes.newest
+                }
+            };
+            newest = newest.max(seq);
+        }
+        self.nodes.newest = newest;
+    }
+
+    /// Sets `attr` below `self` to the exact ranges of the attributes of the
+    /// items.
+    pub(crate) fn reattr(&mut self, values: &Slab<T>, attr: fn(&T) -> f32) {
+        self.nodes.attr = [f32::INFINITY, f32::NEG_INFINITY];
+        for i in 0..self.nodes.len() {
+            let range = match &mut self.nodes[i] {
+                Node::Item(entry) => {
+                    let v = attr(&values[entry.index]);
+                    [v, v]
+                }
+                Node::Parent(child) => {
+                    child.reattr(values, attr);
+                    child.nodes.attr
+                }
+            };
+            self.widen_attr(range);
+        }
+    }
+
+    /// Sets `margin` below `self` to the exact largest margins of the items.
+    pub(crate) fn remargin(&mut self, values: &Slab<T>, margin: fn(&T) -> f32) {
+        let mut largest = 0.0f32;
+        for node in self.nodes.iter_mut() {
+            let m = match node {
+                Node::Item(entry) => margin(&values[entry.index]),
+                Node::Parent(child) => {
+                    child.remargin(values, margin);
+                    child.nodes.margin
+                }
+            };
+            largest = largest.max(m);
+        }
+        self.nodes.margin = largest;
+    }
+
+    pub(crate) fn widen_attr(&mut self, [lo, hi]: [f32; 2]) {
+        self.nodes.attr = [self.nodes.attr[0].min(lo), self.nodes.attr[1].max(hi)];
+    }
+
+    pub(crate) fn recount(&mut self) {
+        self.nodes.count = self.nodes.iter().map(|n| n.count()).sum();
+    }
+
+    /// With `robust`, children about as close to both edges are distributed
+    /// evenly between the two nodes instead of all moving right.
+    pub(crate) fn split_largest_axis_edge_snap(
+        &mut self,
+        alloc: &A,
+        robust: bool,
+    ) -> Node<T, A, S> {
+        let rect = self.rect;
+        let axis = rect.larger_axis();
+        let mut right = Parent::new(rect, alloc);
+        right.nodes.newest = self.nodes.newest;
+        right.nodes.attr = self.nodes.attr;
+        right.nodes.margin = self.nodes.margin;
+        right.nodes.circle = self.nodes.circle;
+        let lchilds = &mut self.nodes;
+        let rchilds = &mut right.nodes;
+        // a few ulps at the magnitude of the coordinates
+        let mag = max(
+            rect.min.on(axis).to_f64().abs(),
+            rect.max.on(axis).to_f64().abs(),
+        );
+        let eps = mag * S::EPSILON * 4.0;
+        let mut ties = 0;
+        let mut i = 0;
+        while i < lchilds.len() {
+            // in f64, where integers at the bounds of their type can't
+            // overflow
+            let min = lchilds[i].rect().min.on(axis).to_f64() - rect.min.on(axis).to_f64();
+            let max = rect.max.on(axis).to_f64() - lchilds[i].rect().max.on(axis).to_f64();
+            let stay = if robust && (min - max).abs() <= eps {
+                ties += 1;
+                ties % 2 == 1
+            } else {
+                min < max
+            };
+            if stay {
+                // stay left
+                i += 1;
+            } else {
+                // move right
+                rchilds.push(lchilds.swap_remove(i));
+            }
+        }
+        // Make sure that both left and right nodes have at least
+        // MIN_ITEMS by moving items into under-flowed nodes.
+        if lchilds.len() < MIN_ITEMS {
+            // reverse sort by min axis
+            rchilds.sort_unstable_by(|a, b| cmp(a.rect().min.on(axis), b.rect().min.on(axis)));
+            while lchilds.len() < MIN_ITEMS {
+                lchilds.push(rchilds.pop().unwrap());
+            }
+        } else if rchilds.len() < MIN_ITEMS {
+            // reverse sort by max axis
+            lchilds.sort_unstable_by(|a, b| cmp(a.rect().max.on(axis), b.rect().max.on(axis)));
+            while rchilds.len() < MIN_ITEMS {
+                rchilds.push(lchilds.pop().unwrap());
+            }
+        }
+        // recalculate and sort the nodes
+        self.recalc();
+        right.recalc();
+        self.sort_by_x();
+        right.sort_by_x();
+        Node::Parent(right)
+    }
+
+    pub(crate) fn push(&mut self, child: Node<T, A, S>) {
+        self.nodes.push(child);
+    }
+
+    pub(crate) fn sort_by_x(&mut self) {
+        self.nodes
+            .sort_unstable_by(|a, b| cmp(a.rect().min.x, b.rect().min.x));
+    }
+
+    /// Returns the number of nodes below `self` that were dropped.
+    pub(crate) fn flatten_into(&mut self, reinsert: &mut Vec<Entry<S>>) -> usize {
+        let mut dropped = 0;
+        while let Some(node) = self.nodes.pop() {
+            match node {
+                Node::Item(item) => reinsert.push(item),
+                Node::Parent(mut nodes) => dropped += 1 + nodes.flatten_into(reinsert),
+            }
+        }
+        dropped
+    }
+
+    pub(crate) fn quality(&self, height: usize, out: &mut Quality) {
+        out.nodes += 1;
+        if height == 0 {
+            return;
+        }
+        for (i, a) in self.nodes.iter().enumerate() {
+            let (w, h) = a.rect().extent64();
+            out.area += w * h;
+            for b in &self.nodes[i + 1..] {
+                out.overlap += a.rect().overlap64(b.rect());
+            }
+            if let Node::Parent(child) = a {
+                child.quality(height - 1, out);
+            }
+        }
+    }
+
+    /// Adds `self` and the nodes below it to `out`, summing their fill into
+    /// `mean_fill`.
+    pub(crate) fn stats(&self, height: usize, max_items: usize, out: &mut Stats) {
+        let fill = self.len() as f64 / max_items as f64;
+        out.nodes += 1;
+        out.min_fill = out.min_fill.min(fill);
+        out.max_fill = out.max_fill.max(fill);
+        out.mean_fill += fill;
+        if height == 0 {
+            out.leaves += 1;
+            out.area += self.rect.area64();
+            return;
+        }
+        for node in self.nodes.iter() {
+            if let Node::Parent(child) = node {
+                child.stats(height - 1, max_items, out);
+            }
+        }
+    }
+
+    pub(crate) fn occupancy(&self, height: usize, out: &mut Occupancy) {
+        if height == 0 {
+            out.leaves[self.len()] += 1;
+            return;
+        }
+        out.branches[self.len()] += 1;
+        for node in self.nodes.iter() {
+            if let Node::Parent(child) = node {
+                child.occupancy(height - 1, out);
+            }
+        }
+    }
+
+    /// Writes a line for `self` and each node below it, indented by their
+    /// depth, see [`RTree::format_tree`].
+    pub(crate) fn format(&self, height: usize, depth: usize, out: &mut String) {
+        let Rect { min, max } = self.rect;
+        let _ = write!(
+            out,
+            "{:indent$}[{:?} {:?}, {:?} {:?}] ",
+            "",
+            min.x,
+            min.y,
+            max.x,
+            max.y,
+            indent = depth * 2
+        );
+        if height == 0 {
+            let _ = writeln!(out, "leaf of {} items", self.len());
+            return;
+        }
+        let _ = writeln!(out, "{} children, {} items", self.len(), self.nodes.count);
+        for node in self.nodes.iter() {
+            if let Node::Parent(child) = node {
+                child.format(height - 1, depth + 1, out);
+            }
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn remove<F>(
+        &mut self,
+        rect: &Rect<S>,
+        matches: &mut F,
+        values: &Slab<T>,
+        reinsert: &mut Vec<Entry<S>>,
+        height: usize,
+        config: &Config,
+        nodes: &mut usize,
+    ) -> (Option<Entry<S>>, bool)
+    where
+        F: FnMut(&Rect<S>, &T) -> bool,
+    {
+        let children = &mut self.nodes;
+        if height == 0 {
+            // remove from leaf
+            for i in 0..children.len() {
+                let r = children[i].rect();
+                if !r.intersects(rect) || !matches(r, &values[children[i].index()]) {
+                    continue;
+                }
+                let Node::Item(item) = children.swap_remove(i) else {
+                    continue;
+                };
+                self.nodes.count -= 1;
+                let recalced = self.rect.on_edge(&item.rect);
+                if recalced {
+                    self.recalc();
+                }
+                return (Some(item), recalced);
+            }
+        } else {
+            for i in 0..children.len() {
+                let node = children[i].nodes();
+                if !node.rect.intersects(rect) {
+                    continue;
+                }
+                let (removed, mut recalced) =
+                    node.remove(rect, matches, values, reinsert, height - 1, config, nodes);
+                if removed.is_none() {
+                    continue;
+                }
+                let underflow = node.len() < MIN_ITEMS;
+                if underflow {
+                    let nrect = node.rect;
+                    if !self.rebalance(i, reinsert, config, nodes) && !recalced {
+                        recalced = self.rect.on_edge(&nrect);
+                    }
+                }
+                if recalced {
+                    self.recalc();
+                } else {
+                    self.recount();
+                }
+                return (removed, recalced);
+            }
+        }
+        (None, false)
+    }
+
+    /// Fixes the underflowed child at `i` by merging it into its closest
+    /// sibling or by borrowing nodes from that sibling. Only when neither is
+    /// possible are its entries flattened into `reinsert`.
+    ///
+    /// Returns whether the child's nodes stayed below `self`. Dropped nodes
+    /// are subtracted from `nodes`.
+    pub(crate) fn rebalance(
+        &mut self,
+        i: usize,
+        reinsert: &mut Vec<Entry<S>>,
+        config: &Config,
+        nodes: &mut usize,
+    ) -> bool {
+        let Node::Parent(mut under) = self.nodes.swap_remove(i) else {
+            unreachable!()
+        };
+        if under.len() == 0 {
+            *nodes -= 1;
+            return true;
+        }
+        if self.nodes.is_empty() {
+            *nodes -= 1 + under.flatten_into(reinsert);
+            return false;
+        }
+        let Node::Parent(sibling) = self.choose_least_enlargement(&under.rect) else {
+            unreachable!()
+        };
+        if sibling.len() + under.len() < config.max_items {
+            // merge
+            while let Some(node) = under.nodes.pop() {
+                sibling.push(node);
+            }
+            sibling.nodes.newest = sibling.nodes.newest.max(under.nodes.newest);
+            sibling.widen_attr(under.nodes.attr);
+            sibling.nodes.margin = sibling.nodes.margin.max(under.nodes.margin);
+            sibling.recalc();
+            *nodes -= 1;
+            return true;
+        }
+        if sibling.len() - (MIN_ITEMS - under.len()) >= MIN_ITEMS {
+            // borrow the nodes of the sibling closest to the underflowed node
+            let (x, y) = under.rect.center64();
+            let dist = |rect: &Rect<S>| {
+                let dx = (rect.min.x.to_f64() - x)
+                    .max(x - rect.max.x.to_f64())
+                    .max(0.0);
+                let dy = (rect.min.y.to_f64() - y)
+                    .max(y - rect.max.y.to_f64())
+                    .max(0.0);
+                dx * dx + dy * dy
+            };
+            while under.len() < MIN_ITEMS {
+                let (j, _) = sibling
+                    .nodes
+                    .iter()
+                    .enumerate()
+                    .min_by(|(_, a), (_, b)| cmp(dist(a.rect()), dist(b.rect())))
+                    .unwrap();
+                under.push(sibling.nodes.swap_remove(j));
+            }
+            under.nodes.newest = under.nodes.newest.max(sibling.nodes.newest);
+            under.widen_attr(sibling.nodes.attr);
+            under.nodes.margin = under.nodes.margin.max(sibling.nodes.margin);
+            sibling.recalc();
+            under.recalc();
+            self.nodes.push(Node::Parent(under));
+            return true;
+        }
+        *nodes -= 1 + under.flatten_into(reinsert);
+        false
+    }
+
+    /// Calls `f` on the items intersecting `rect`, moving those for which it
+    /// returns false to `removed` until it holds `budget` entries. Their
+    /// values are left in `values`. Underflowed children are flattened into
+    /// `reinsert`.
+    ///
+    /// Dropped nodes are subtracted from `nodes`.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn retain<F>(
+        &mut self,
+        rect: &Rect<S>,
+        values: &mut Slab<T>,
+        f: &mut F,
+        budget: usize,
+        removed: &mut Vec<Entry<S>>,
+        reinsert: &mut Vec<Entry<S>>,
+        height: usize,
+        nodes: &mut usize,
+    ) where
+        F: FnMut(&Rect<S>, &mut T) -> bool,
+    {
+        let children = &mut self.nodes;
+        let before = removed.len();
+        let mut i = 0;
+        while i < children.len() && removed.len() < budget {
+            if !children[i].rect().intersects(rect) {
+                i += 1;
+                continue;
+            }
+            if height == 0 {
+                let Node::Item(entry) = &children[i] else {
+                    unreachable!()
+                };
+                if f(&entry.rect, &mut values[entry.index]) {
+                    i += 1;
+                    continue;
+                }
+                let Node::Item(entry) = children.swap_remove(i) else {
+                    unreachable!()
+                };
+                removed.push(entry);
+            } else {
+                let child = children[i].nodes();
+                child.retain(
+                    rect,
+                    values,
+                    f,
+                    budget,
+                    removed,
+                    reinsert,
+                    height - 1,
+                    nodes,
+                );
+                if child.len() < MIN_ITEMS {
+                    *nodes -= 1 + children.swap_remove(i).nodes().flatten_into(reinsert);
+                } else {
+                    i += 1;
+                }
+            }
+        }
+        if removed.len() > before {
+            self.recalc();
+        }
+    }
+
+    /// Moves the item at `old` whose value matches to `new` if `new` fits in
+    /// the rect of its leaf, returning its index. Returns `None` if no item
+    /// is found or it doesn't fit.
+    pub(crate) fn relocate<F>(
+        &mut self,
+        old: &Rect<S>,
+        new: &Rect<S>,
+        matches: &mut F,
+        values: &Slab<T>,
+        height: usize,
+    ) -> Option<u32>
+    where
+        F: FnMut(&T) -> bool,
+    {
+        if height == 0 {
+            if !self.rect.contains(new) {
+                return None;
+            }
+            let entry = self.nodes.iter_mut().find_map(|node| match node {
+                Node::Item(e) if e.rect == *old && matches(&values[e.index]) => Some(e),
+                _ => None,
+            })?;
+            entry.rect = *new;
+            let index = entry.index;
+            self.recalc();
+            return Some(index);
+        }
+        for node in self.nodes.iter_mut() {
+            let Node::Parent(child) = node else {
+                continue;
+            };
+            if !child.rect.contains(old) {
+                continue;
+            }
+            if let Some(index) = child.relocate(old, new, matches, values, height - 1) {
+                self.recalc();
+                return Some(index);
+            }
+        }
+        None
+    }
+
+    /// Estimates the number of items below `self` intersecting `rect`,
+    /// assuming the items of the nodes `levels` down are spread evenly.
+    pub(crate) fn estimate_count(&self, rect: &Rect<S>, height: usize, levels: usize) -> f64 {
+        if rect.contains(&self.rect) {
+            return self.nodes.count as f64;
+        }
+        if height == 0 {
+            return self
+                .nodes
+                .iter()
+                .filter(|n| n.rect().intersects(rect))
+                .count() as f64;
+        }
+        if levels == 0 {
+            return self.nodes.count as f64 * self.rect.overlap_fraction(rect);
+        }
+        let mut count = 0.0;
+        for node in self.nodes.iter() {
+            if let Node::Parent(child) = node {
+                if child.rect.intersects(rect) {
+                    count += child.estimate_count(rect, height - 1, levels - 1);
+                }
+            }
+        }
+        count
+    }
+
+    /// Counts the rects tested when searching for `rect` below `self`.
+    pub(crate) fn search_cost(&self, rect: &Rect<S>, height: usize) -> usize {
+        let mut cost = self.len();
+        if height > 0 {
+            for node in self.nodes.iter() {
+                if let Node::Parent(child) = node {
+                    if child.rect.intersects(rect) {
+                        cost += child.search_cost(rect, height - 1);
+                    }
+                }
+            }
+        }
+        cost
+    }
+
+    /// Orders the children by the number of searches that descended into
+    /// them, most first, and clears the counts.
+    pub(crate) fn reorder(&mut self) {
+        let hits = |node: &Node<T, A, S>| match node {
+            Node::Parent(n) => n.nodes.hits.load(atomic::Ordering::Relaxed),
+            Node::Item(_) => 0,
+        };
+        self.nodes.sort_by_key(|n| std::cmp::Reverse(hits(n)));
+        for node in self.nodes.iter_mut() {
+            if let Node::Parent(child) = node {
+                child.reorder();
+            }
+        }
+        self.nodes.hits = AtomicU32::new(0);
+    }
+
+    /// Determines whether an item below `self` has exactly `rect` and `data`,
+    /// only descending into nodes containing `rect`.
+    pub(crate) fn contains_item(&self, rect: &Rect<S>, data: &T, values: &Slab<T>) -> bool
+    where
+        T: PartialEq,
+    {
+        self.nodes.iter().any(|node| match node {
+            Node::Item(entry) => entry.rect == *rect && values[entry.index] == *data,
+            Node::Parent(child) => {
+                child.rect.contains(rect) && child.contains_item(rect, data, values)
+            }
+        })
+    }
+
+    /// Calls `f` on the entries below `self` that intersect `rect`.
+    pub(crate) fn for_each_in_rect<F: FnMut(&Entry<S>)>(
+        &self,
+        rect: &Rect<S>,
+        half_open: bool,
+        f: &mut F,
+    ) {
+        #[cfg(feature = "stats")]
+        crate::stats::count(|c| c.visited += 1);
+        for node in self.nodes.iter() {
+            if !node.rect().intersects(rect) {
+                continue;
+            }
+            match node {
+                Node::Item(entry) => {
+                    if !half_open || entry.rect.intersects_half_open(rect) {
+                        f(entry);
+                    }
+                }
+                Node::Parent(child) => child.for_each_in_rect(rect, half_open, f),
+            }
+        }
+    }
+
+    /// Like [`for_each_in_rect`](Self::for_each_in_rect), keeping the rects
+    /// of the nodes from the root down to the item's leaf in `path`.
+    pub(crate) fn for_each_with_path<F>(
+        &self,
+        rect: &Rect<S>,
+        half_open: bool,
+        path: &mut Vec<Rect<S>>,
+        f: &mut F,
+    ) where
+        F: FnMut(&[Rect<S>], &Entry<S>),
+    {
+        #[cfg(feature = "stats")]
+        crate::stats::count(|c| c.visited += 1);
+        path.push(self.rect);
+        for node in self.nodes.iter() {
+            if !node.rect().intersects(rect) {
+                continue;
+            }
+            match node {
+                Node::Item(entry) => {
+                    if !half_open || entry.rect.intersects_half_open(rect) {
+                        f(path, entry);
+                    }
+                }
+                Node::Parent(child) => child.for_each_with_path(rect, half_open, path, f),
+            }
+        }
+        path.pop();
+    }
+
+    /// Like [`for_each_in_rect`](Self::for_each_in_rect), skipping the
+    /// children whose range of attributes fails `test`.
+    pub(crate) fn for_each_where<P, F>(&self, rect: &Rect<S>, half_open: bool, test: &P, f: &mut F)
+    where
+        P: Fn([f32; 2]) -> bool,
+        F: FnMut(&Entry<S>),
+    {
+        #[cfg(feature = "stats")]
+        crate::stats::count(|c| c.visited += 1);
+        for node in self.nodes.iter() {
+            if !node.rect().intersects(rect) {
+                continue;
+            }
+            match node {
+                Node::Item(entry) => {
+                    if !half_open || entry.rect.intersects_half_open(rect) {
+                        f(entry);
+                    }
+                }
+                Node::Parent(child) => {
+                    if test(child.nodes.attr) {
+                        child.for_each_where(rect, half_open, test, f);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Moves the values of the items below `self` from `old` to `new`, in
+    /// tree order.
+    pub(crate) fn compact(&mut self, old: &mut Slab<T>, new: &mut Slab<T>) {
+        for node in self.nodes.iter_mut() {
+            match node {
+                Node::Item(entry) => {
+                    let seq = old.seq(entry.index);
+                    entry.index = new.insert(old.remove(entry.index));
+                    new.slot_mut(entry.index).seq = seq;
+                }
+                Node::Parent(child) => child.compact(old, new),
+            }
+        }
+    }
+}
+
+/// An item taken out of a tree, see [`RTree::remove`].
+pub struct Item<T, S = f32> {
+    pub(crate) rect: Rect<S>,
+    pub(crate) item: T,
+}
+
+impl<T, S: Scalar> Parent<T, BoxAlloc, S> {
+    /// A copy of the node sharing the nodes below it, see [`BoxAlloc`].
+    pub(crate) fn share(&self) -> Self {
+        Self {
+            nodes: self.nodes.clone(),
+            rect: self.rect,
+        }
+    }
+}
+
+impl<T, A: Alloc<T>> Parent<T, A> {
+    /// Visits the items whose rects intersect `rect` grown by their own
+    /// `margin`, skipping the children that no item below them could reach
+    /// with the largest margin kept by the child.
+    pub(crate) fn for_each_padded<M, F>(&self, rect: &Rect, half_open: bool, margin: &M, f: &mut F)
+    where
+        M: Fn(&Entry) -> f32,
+        F: FnMut(&Entry),
+    {
+        #[cfg(feature = "stats")]
+        crate::stats::count(|c| c.visited += 1);
+        for node in self.nodes.iter() {
+            match node {
+                Node::Item(entry) => {
+                    let m = margin(entry);
+                    let padded = rect.padded(Point::new(m, m));
+                    let hit = if half_open {
+                        entry.rect.intersects_half_open(&padded)
+                    } else {
+                        entry.rect.intersects(&padded)
+                    };
+                    if hit {
+                        f(entry);
+                    }
+                }
+                Node::Parent(child) => {
+                    let m = child.nodes.margin;
+                    if child.rect.intersects(&rect.padded(Point::new(m, m))) {
+                        child.for_each_padded(rect, half_open, margin, f);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<T, S: Scalar> Item<T, S> {
+    pub fn rect(&self) -> Rect<S> {
+        self.rect
+    }
+
+    pub fn data(&self) -> &T {
+        &self.item
+    }
+
+    pub fn into_inner(self) -> (Rect<S>, T) {
+        (self.rect, self.item)
+    }
+}
+
+/// A leaf entry, pointing at its value in the tree's [`Slab`].
+pub struct Entry<S = f32> {
+    pub(crate) rect: Rect<S>,
+    pub(crate) index: u32,
+}
+
+impl<S: Scalar> Entry<S> {
+    pub(crate) fn iter_item<'a, T>(&self, values: &'a Slab<T>, dist: f32) -> IterItem<'a, T, S> {
+        IterItem {
+            rect: self.rect,
+            data: &values[self.index],
+            key: values.key(self.index),
+            seq: values.seq(self.index),
+            dist,
+        }
+    }
+}
+
+/// A stable handle to a value stored in a tree.
+///
+/// Keys stay valid until their item is removed or the tree is
+/// [compacted](RTree::compact). A stale key never resolves to another value.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct ItemKey {
+    pub(crate) index: u32,
+    pub(crate) gen: u32,
+}
+
+pub(crate) struct Slot<T> {
+    pub(crate) gen: u32,
+    /// Insertion order of the value, used to break ties.
+    pub(crate) seq: u64,
+    pub(crate) value: Option<T>,
+}
+
+/// Number of slots in a chunk of a [`Slab`].
+pub(crate) const CHUNK: usize = 64;
+
+type Chunk<T> = ArrayVec<Slot<T>, CHUNK>;
+
+/// Storage for item values, so that nodes only move small indices around.
+///
+/// Values are kept in chunks of slots, separate from the nodes, and slots
+/// of removed values are reused by later inserts. Chunks can be shared, see
+/// [`share`](Self::share), and a shared chunk is copied before it's written
+/// to, so that only the chunks that change are copied.
+///
+/// A slot's generation is bumped whenever its value is removed, which tells
+/// apart keys to the old and to the new value of a reused slot.
+pub struct Slab<T> {
+    pub(crate) chunks: Vec<Arc<Chunk<T>>>,
+    pub(crate) free: Vec<u32>,
+    /// Generation of newly created slots.
+    pub(crate) gen: u32,
+    /// Sequence number of the next inserted value.
+    pub(crate) seq: u64,
+    /// Copies the values of shared chunks, set when they're first shared.
+    copy: OnceLock<fn(&T) -> T>,
+}
+
+impl<T> Slab<T> {
+    pub(crate) const fn new(gen: u32) -> Self {
+        Self {
+            chunks: Vec::new(),
+            free: Vec::new(),
+            gen,
+            seq: 0,
+            copy: OnceLock::new(),
+        }
+    }
+
+    /// Number of slots, including those of removed values.
+    pub(crate) fn len(&self) -> usize {
+        self.chunks
+            .last()
+            .map_or(0, |last| (self.chunks.len() - 1) * CHUNK + last.len())
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.chunks.len() * CHUNK
+    }
+
+    /// Whether the next insert needs a new chunk.
+    pub(crate) fn is_full(&self) -> bool {
+        self.free.is_empty() && self.len() == self.capacity()
+    }
+
+    pub(crate) fn slot(&self, index: u32) -> &Slot<T> {
+        let i = index as usize;
+        &self.chunks[i / CHUNK][i % CHUNK]
+    }
+
+    pub(crate) fn slot_mut(&mut self, index: u32) -> &mut Slot<T> {
+        let i = index as usize;
+        &mut self.chunk_mut(i / CHUNK)[i % CHUNK]
+    }
+
+    pub(crate) fn seq(&self, index: u32) -> u64 {
+        self.slot(index).seq
+    }
+
+    /// The chunk at `i`, copied first if it's shared.
+    fn chunk_mut(&mut self, i: usize) -> &mut Chunk<T> {
+        let chunk = &mut self.chunks[i];
+        if Arc::get_mut(chunk).is_none() {
+            let copy = self.copy.get().expect("shared chunk without a copy");
+            let slots = chunk.iter().map(|slot| Slot {
+                gen: slot.gen,
+                seq: slot.seq,
+                value: slot.value.as_ref().map(copy),
+            });
+            *chunk = Arc::new(slots.collect());
+        }
+        Arc::get_mut(chunk).unwrap()
+    }
+
+    /// A slab sharing the chunks of `self`. Both copy the values of a chunk
+    /// they share with `copy` before writing to it.
+    pub(crate) fn share(&self, copy: fn(&T) -> T) -> Self {
+        let copy = *self.copy.get_or_init(|| copy);
+        Self {
+            chunks: self.chunks.clone(),
+            free: self.free.clone(),
+            gen: self.gen,
+            seq: self.seq,
+            copy: OnceLock::from(copy),
+        }
+    }
+
+    pub(crate) fn insert(&mut self, value: T) -> u32 {
+        let seq = self.seq;
+        self.seq += 1;
+        if let Some(index) = self.free.pop() {
+            let slot = self.slot_mut(index);
+            slot.seq = seq;
+            slot.value = Some(value);
+            return index;
+        }
+        let index = self.len();
+        if index == self.capacity() {
+            self.chunks.push(Arc::new(Chunk::new()));
+        }
+        let gen = self.gen;
+        self.chunk_mut(index / CHUNK).push(Slot {
+            gen,
+            seq,
+            value: Some(value),
+        });
+        index as u32
+    }
+
+    pub(crate) fn remove(&mut self, index: u32) -> T {
+        let slot = self.slot_mut(index);
+        let value = slot.value.take().expect("vacant slot");
+        slot.gen = slot.gen.wrapping_add(1);
+        self.free.push(index);
+        value
+    }
+
+    pub(crate) fn key(&self, index: u32) -> ItemKey {
+        ItemKey {
+            index,
+            gen: self.slot(index).gen,
+        }
+    }
+
+    pub(crate) fn get(&self, key: ItemKey) -> Option<&T> {
+        if key.index as usize >= self.len() {
+            return None;
+        }
+        let slot = self.slot(key.index);
+        if slot.gen != key.gen {
+            return None;
+        }
+        slot.value.as_ref()
+    }
+
+    pub(crate) fn get_mut(&mut self, key: ItemKey) -> Option<&mut T> {
+        if key.index as usize >= self.len() || self.slot(key.index).gen != key.gen {
+            return None;
+        }
+        self.slot_mut(key.index).value.as_mut()
+    }
+
+    /// A generation newer than that of any slot, so that no existing key is
+    /// valid in a slab created with it.
+    pub(crate) fn next_gen(&self) -> u32 {
+        let slots = self.chunks.iter().flat_map(|chunk| chunk.iter());
+        let gen = slots.fold(self.gen, |g, s| g.max(s.gen));
+        gen.wrapping_add(1)
+    }
+}
+
+/// Shares the chunks, see [`Slab::share`].
+impl<T: Clone> Clone for Slab<T> {
+    fn clone(&self) -> Self {
+        self.share(T::clone)
+    }
+}
+
+impl<T> Index<u32> for Slab<T> {
+    type Output = T;
+
+    fn index(&self, index: u32) -> &T {
+        self.slot(index).value.as_ref().expect("vacant slot")
+    }
+}
+
+impl<T> IndexMut<u32> for Slab<T> {
+    fn index_mut(&mut self, index: u32) -> &mut T {
+        self.slot_mut(index).value.as_mut().expect("vacant slot")
+    }
+}
+
+/// A child of a node, an item in the leaves and a node above them.
+///
+/// Items are stored as an [`Entry`] of their rect and the index of their
+/// value in the [`Slab`], where the values of all items sit in one array,
+/// and nodes as their rect and a pointer to their [`NodeVec`](crate::NodeVec). Either is
+/// only a few words, which keeps the arrays of children, and the searches
+/// scanning them, compact whatever the size of `T`.
+pub enum Node<T, A: Alloc<T, S>, S = f32> {
+    Item(Entry<S>),
+    Parent(Parent<T, A, S>),
+}
+
+impl<T, A: Alloc<T, S>, S: Scalar> Node<T, A, S> {
+    pub(crate) fn rect(&self) -> &Rect<S> {
+        match self {
+            Node::Item(n) => &n.rect,
+            Node::Parent(n) => &n.rect,
+        }
+    }
+
+    /// Number of items at or below the node.
+    pub(crate) fn count(&self) -> usize {
+        match self {
+            Node::Item(_) => 1,
+            Node::Parent(n) => n.nodes.count,
+        }
+    }
+
+    pub(crate) fn circle(&self) -> Circle {
+        match self {
+            Node::Item(n) => Circle::of(&n.rect),
+            Node::Parent(n) => n.nodes.circle.unwrap_or_else(|| Circle::of(&n.rect)),
+        }
+    }
+
+    pub(crate) fn index(&self) -> u32 {
+        match self {
+            Node::Item(n) => n.index,
+            Node::Parent(_) => panic!("not a leaf node"),
+        }
+    }
+
+    pub(crate) fn nodes(&mut self) -> &mut Parent<T, A, S> {
+        match self {
+            Node::Item(_) => panic!("not a parent node"),
+            Node::Parent(n) => n,
+        }
+    }
+
+    /// Like [`entries`](Self::entries), copying the entries for packing
+    /// beside the node.
+    pub(crate) fn copy_entries(&self, out: &mut Vec<Entry<S>>) {
+        match self {
+            Node::Item(n) => out.push(Entry {
+                rect: n.rect,
+                index: n.index,
+            }),
+            Node::Parent(n) => n.nodes.iter().for_each(|n| n.copy_entries(out)),
+        }
+    }
+
+    pub(crate) fn entries<'a>(&'a self, out: &mut Vec<&'a Entry<S>>) {
+        match self {
+            Node::Item(n) => out.push(n),
+            Node::Parent(n) => n.nodes.iter().for_each(|n| n.entries(out)),
+        }
+    }
+}
+
+impl<T, S: Scalar> Node<T, BoxAlloc, S> {
+    /// See [`Parent::share`].
+    pub(crate) fn share(&self) -> Self {
+        match self {
+            Node::Item(entry) => Node::Item(Entry {
+                rect: entry.rect,
+                index: entry.index,
+            }),
+            Node::Parent(parent) => Node::Parent(parent.share()),
+        }
+    }
+}
+
+impl<T, A: Alloc<T>> Node<T, A> {
+    /// Distance from `point` in `metric`, bounding nodes by their circles
+    /// along with their rects if `circles` is set.
+    pub(crate) fn metric_dist<M: Metric>(&self, point: Point, metric: &M, circles: bool) -> f32 {
+        match self {
+            Node::Item(n) => metric.dist(point, &n.rect),
+            Node::Parent(n) => {
+                let dist = metric.lower_bound(point, &n.rect);
+                match n.nodes.circle {
+                    Some(c) if circles => max(dist, metric.circle_bound(point, c.center, c.radius)),
+                    _ => dist,
+                }
+            }
+        }
+    }
+}
+
+/// Orders coordinates, taking NaNs as equal to everything.
+fn cmp<S: PartialOrd>(a: S, b: S) -> Ordering {
+    a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+}
+
+/// Determines whether `a` and `b` have the same structure and items.
+pub(crate) fn same<T, A, B>(a: &Node<T, A>, va: &Slab<T>, b: &Node<T, B>, vb: &Slab<T>) -> bool
+where
+    T: PartialEq,
+    A: Alloc<T>,
+    B: Alloc<T>,
+{
+    match (a, b) {
+        (Node::Item(x), Node::Item(y)) => x.rect == y.rect && va[x.index] == vb[y.index],
+        (Node::Parent(x), Node::Parent(y)) => {
+            x.rect == y.rect
+                && x.len() == y.len()
+                && x.nodes
+                    .iter()
+                    .zip(y.nodes.iter())
+                    .all(|(x, y)| same(x, va, y, vb))
+        }
+        _ => false,
+    }
+}
+
+/// Collects the entries of `a` and `b`, skipping the subtrees they share.
+///
+/// Children are paired by their rects. Identical pairs are skipped and other
+/// pairs of parents are compared recursively.
+pub(crate) fn diff_nodes<'a, 'b, T, A, B>(
+    a: &'a Parent<T, A>,
+    va: &Slab<T>,
+    b: &'b Parent<T, B>,
+    vb: &Slab<T>,
+    out_a: &mut Vec<&'a Entry>,
+    out_b: &mut Vec<&'b Entry>,
+) where
+    T: PartialEq,
+    A: Alloc<T>,
+    B: Alloc<T>,
+{
+    let mut matched = [false; MAX_ITEMS];
+    'next: for x in a.nodes.iter() {
+        let mut pair = None;
+        for (j, y) in b.nodes.iter().enumerate() {
+            if matched[j] || x.rect() != y.rect() {
+                continue;
+            }
+            if same(x, va, y, vb) {
+                matched[j] = true;
+                continue 'next;
+            }
+            pair = pair.or(Some(j));
+        }
+        match (x, pair.map(|j| (j, &b.nodes[j]))) {
+            (Node::Parent(x), Some((j, Node::Parent(y)))) => {
+                matched[j] = true;
+                diff_nodes(x, va, y, vb, out_a, out_b);
+            }
+            _ => x.entries(out_a),
+        }
+    }
+    for (j, y) in b.nodes.iter().enumerate() {
+        if !matched[j] {
+            y.entries(out_b);
+        }
+    }
+}
+
+/// Calls `f` for every pair of items from `a` and `b` whose rects pass
+/// `test`. Pairs of nodes failing `test` are skipped, so it must pass for
+/// two nodes whenever it passes for any two rects within them.
+///
+/// With `sweep`, `test` may only pass for rects at most that far apart on
+/// x, and pairs of leaves are joined by a plane sweep instead of testing
+/// every pair of their items.
+///
+/// Stops once `cancel` is set, which is checked for every pair of nodes.
+pub(crate) fn join<'a, 'b, T, A, U, B, P, F>(
+    a: &'a Node<T, A>,
+    b: &'b Node<U, B>,
+    test: &mut P,
+    sweep: Option<f32>,
+    cancel: &AtomicBool,
+    f: &mut F,
+) -> Result<(), Cancelled>
+where
+    A: Alloc<T>,
+    B: Alloc<U>,
+    P: FnMut(&Rect, &Rect) -> bool,
+    F: FnMut(&'a Entry, &'b Entry),
+{
+    if !test(a.rect(), b.rect()) {
+        return Ok(());
+    }
+    match (a, b) {
+        (Node::Item(x), Node::Item(y)) => f(x, y),
+        (Node::Parent(x), Node::Item(_)) => {
+            for n in x.nodes.iter() {
+                join(n, b, test, sweep, cancel, f)?;
+            }
+        }
+        (Node::Item(_), Node::Parent(y)) => {
+            for n in y.nodes.iter() {
+                join(a, n, test, sweep, cancel, f)?;
+            }
+        }
+        (Node::Parent(x), Node::Parent(y)) => {
+            if cancel.load(atomic::Ordering::Relaxed) {
+                return Err(Cancelled);
+            }
+            match sweep {
+                Some(pad) if x.is_leaf() && y.is_leaf() => plane_sweep(x, y, test, pad, f),
+                // descend the larger of the two nodes first
+                _ if x.rect.area() >= y.rect.area() => {
+                    for n in x.nodes.iter() {
+                        join(n, b, test, sweep, cancel, f)?;
+                    }
+                }
+                _ => {
+                    for n in y.nodes.iter() {
+                        join(a, n, test, sweep, cancel, f)?;
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Joins the items of leaves `x` and `y` by sorting both by x and sweeping
+/// a line across them, so only items at most `pad` apart on x are tested.
+/// Dense leaves then take far fewer tests than all of their pairs.
+fn plane_sweep<'a, 'b, T, A, U, B, P, F>(
+    x: &'a Parent<T, A>,
+    y: &'b Parent<U, B>,
+    test: &mut P,
+    pad: f32,
+    f: &mut F,
+) where
+    A: Alloc<T>,
+    B: Alloc<U>,
+    P: FnMut(&Rect, &Rect) -> bool,
+    F: FnMut(&'a Entry, &'b Entry),
+{
+    let mut xs: ArrayVec<&'a Entry, MAX_ITEMS> = ArrayVec::new();
+    for node in x.nodes.iter() {
+        if let Node::Item(entry) = node {
+            if test(&entry.rect, &y.rect) {
+                xs.push(entry);
+            }
+        }
+    }
+    let mut ys: ArrayVec<&'b Entry, MAX_ITEMS> = ArrayVec::new();
+    for node in y.nodes.iter() {
+        if let Node::Item(entry) = node {
+            if test(&x.rect, &entry.rect) {
+                ys.push(entry);
+            }
+        }
+    }
+    xs.sort_unstable_by(|a, b| cmp(a.rect.min.x, b.rect.min.x));
+    ys.sort_unstable_by(|a, b| cmp(a.rect.min.x, b.rect.min.x));
+    let (mut i, mut j) = (0, 0);
+    while i < xs.len() && j < ys.len() {
+        if xs[i].rect.min.x <= ys[j].rect.min.x {
+            let a = xs[i];
+            for &b in ys[j..].iter() {
+                if b.rect.min.x > a.rect.max.x + pad {
+                    break;
+                }
+                if test(&a.rect, &b.rect) {
+                    f(a, b);
+                }
+            }
+            i += 1;
+        } else {
+            let b = ys[j];
+            for &a in xs[i..].iter() {
+                if a.rect.min.x > b.rect.max.x + pad {
+                    break;
+                }
+                if test(&a.rect, &b.rect) {
+                    f(a, b);
+                }
+            }
+            j += 1;
+        }
+    }
+}
+
+/// Calls `f` on every pair of distinct items below `node` whose rects pass
+/// `test`, once per pair: the pairs below each child are found by
+/// recursing into it, and those across two children by joining them.
+pub(crate) fn self_join<'a, T, A, P, F>(
+    node: &'a Node<T, A>,
+    test: &mut P,
+    sweep: Option<f32>,
+    cancel: &AtomicBool,
+    f: &mut F,
+) -> Result<(), Cancelled>
+where
+    A: Alloc<T>,
+    P: FnMut(&Rect, &Rect) -> bool,
+    F: FnMut(&'a Entry, &'a Entry),
+{
+    let Node::Parent(parent) = node else {
+        return Ok(());
+    };
+    for (i, a) in parent.nodes.iter().enumerate() {
+        self_join(a, test, sweep, cancel, f)?;
+        for b in &parent.nodes[i + 1..] {
+            join(a, b, test, sweep, cancel, f)?;
+        }
+    }
+    Ok(())
+}
+
+/// Packs `entries` into nodes of at most `fill` children by
+/// Sort-Tile-Recursive, adding the number of nodes to `nodes`. Returns the
+/// root and its height.
+///
+/// Entries with equal centers keep the order they are given in, which makes
+/// the result a function of `entries` alone. Stops once `cancel` is set,
+/// which is checked before packing every node.
+pub(crate) fn pack<T, A: Alloc<T>>(
+    entries: Vec<Entry>,
+    alloc: &A,
+    fill: usize,
+    cancel: &AtomicBool,
+    nodes: &mut usize,
+) -> Result<(Parent<T, A>, usize), Cancelled> {
+    let height = pack_height(entries.len(), fill);
+    Ok((
+        pack_node(entries, alloc, fill, height, cancel, nodes)?,
+        height,
+    ))
+}
+
+/// Height of the root of `len` entries packed into nodes of `fill`.
+pub(crate) fn pack_height(len: usize, fill: usize) -> usize {
+    let mut height = 0;
+    let mut capacity = fill;
+    while capacity < len {
+        capacity *= fill;
+        height += 1;
+    }
+    height
+}
+
+/// Packs `entries` into a node of `height`, top down so that the children
+/// of every node tile its rect, see [`tile`].
+pub(crate) fn pack_node<T, A: Alloc<T>>(
+    entries: Vec<Entry>,
+    alloc: &A,
+    fill: usize,
+    height: usize,
+    cancel: &AtomicBool,
+    nodes: &mut usize,
+) -> Result<Parent<T, A>, Cancelled> {
+    if cancel.load(atomic::Ordering::Relaxed) {
+        return Err(Cancelled);
+    }
+    *nodes += 1;
+    if height == 0 {
+        return Ok(Parent::of(
+            entries.into_iter().map(Node::Item).collect(),
+            alloc,
+        ));
+    }
+    let mut out = Vec::new();
+    for child in tile(entries, fill, height) {
+        out.push(Node::Parent(pack_node(
+            child,
+            alloc,
+            fill,
+            height - 1,
+            cancel,
+            nodes,
+        )?));
+    }
+    Ok(Parent::of(out, alloc))
+}
+
+/// Splits the entries of a node of `height` into the entries of its
+/// children: they are sorted into vertical slices by x, and each slice is
+/// cut into children by y.
+pub(crate) fn tile(mut entries: Vec<Entry>, fill: usize, height: usize) -> Vec<Vec<Entry>> {
+    // sizes of `parts` runs of `len`, differing by at most one
+    let even =
+        |len: usize, parts: usize| (0..parts).map(move |i| len * (i + 1) / parts - len * i / parts);
+    let children = entries.len().div_ceil(fill.pow(height as u32));
+    let sizes: Vec<usize> = even(entries.len(), children).collect();
+    let slices = (children as f64).sqrt().ceil() as usize;
+    entries.sort_by_key(|e| Ordered(e.rect.center().x));
+    let mut entries = entries.into_iter();
+    let mut sizes = sizes.into_iter();
+    let mut out = Vec::with_capacity(children);
+    for count in even(children, slices) {
+        let sizes: Vec<usize> = sizes.by_ref().take(count).collect();
+        let mut slice: Vec<Entry> = entries.by_ref().take(sizes.iter().sum()).collect();
+        slice.sort_by_key(|e| Ordered(e.rect.center().y));
+        let mut slice = slice.into_iter();
+        for size in sizes {
+            out.push(slice.by_ref().take(size).collect());
+        }
+    }
+    out
+}
@@ -0,0 +1,351 @@
+//! Loading OpenStreetMap `.pbf` files.
+//!
+//! Nodes are indexed as points and ways by the bounding box of their nodes,
+//! with x the longitude and y the latitude. Relations are skipped.
+
+use crate::{Alloc, Point, RTree, Rect};
+use flate2::read::ZlibDecoder;
+use std::collections::HashMap;
+use std::io::{self, Error, ErrorKind, Read};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Node,
+    Way,
+}
+
+/// A node or way, as passed to the filter of [`RTree::load_osm_pbf`].
+#[derive(Debug)]
+pub struct Element<'a> {
+    pub kind: Kind,
+    pub id: i64,
+    pub tags: &'a [(&'a str, &'a str)],
+}
+
+impl Element<'_> {
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        self.tags.iter().find(|(k, _)| *k == key).map(|(_, v)| *v)
+    }
+}
+
+impl<T, A: Alloc<T>> RTree<T, A> {
+    /// Inserts the nodes and ways of an OpenStreetMap `.pbf` file for which
+    /// `f` returns an item.
+    ///
+    /// Ways are only read with `ways`, which keeps the location of every
+    /// node in memory to compute their bounding boxes. Ways none of whose
+    /// nodes are in the file are skipped.
+    pub fn load_osm_pbf<R, F>(&mut self, mut reader: R, ways: bool, mut f: F) -> io::Result<()>
+    where
+        R: Read,
+        F: FnMut(&Element) -> Option<T>,
+    {
+        let mut nodes = HashMap::new();
+        let mut buf = Vec::new();
+        while let Some(kind) = read_blob(&mut reader, &mut buf)? {
+            if kind == "OSMData" {
+                let block = Block::new(&buf)?;
+                block.visit(|kind, id, tags, refs, point| {
+                    let rect = match kind {
+                        Kind::Node => {
+                            if ways {
+                                nodes.insert(id, point);
+                            }
+                            Rect::point(point.x, point.y)
+                        }
+                        Kind::Way if ways => {
+                            let mut points = refs.iter().filter_map(|id| nodes.get(id));
+                            let Some(first) = points.next() else {
+                                return;
+                            };
+                            let mut rect = Rect::point(first.x, first.y);
+                            for p in points {
+                                rect.expand(&Rect::point(p.x, p.y));
+                            }
+                            rect
+                        }
+                        Kind::Way => return,
+                    };
+                    if let Some(item) = f(&Element { kind, id, tags }) {
+                        self.insert(rect, item);
+                    }
+                })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads the next blob into `buf`, returning its type or `None` at the end
+/// of the file.
+fn read_blob(reader: &mut impl Read, buf: &mut Vec<u8>) -> io::Result<Option<String>> {
+    let mut len = [0; 4];
+    match reader.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err),
+    }
+    let header = read_vec(reader, u32::from_be_bytes(len) as usize)?;
+    let mut kind = String::new();
+    let mut size = 0;
+    let mut msg = &header[..];
+    while let Some((tag, field)) = next_field(&mut msg)? {
+        match (tag, field) {
+            (1, Field::Bytes(b)) => kind = utf8(b)?.to_string(),
+            (3, Field::Varint(v)) => size = v as usize,
+            _ => {}
+        }
+    }
+    let blob = read_vec(reader, size)?;
+    let mut msg = &blob[..];
+    buf.clear();
+    while let Some((tag, field)) = next_field(&mut msg)? {
+        match (tag, field) {
+            (1, Field::Bytes(b)) => buf.extend_from_slice(b),
+            (3, Field::Bytes(b)) => {
+                ZlibDecoder::new(b).read_to_end(buf)?;
+            }
+            (4..=7, Field::Bytes(_)) => return Err(invalid("unsupported blob compression")),
+            _ => {}
+        }
+    }
+    Ok(Some(kind))
+}
+
+fn read_vec(reader: &mut impl Read, len: usize) -> io::Result<Vec<u8>> {
+    // blobs are at most 32 MiB
+    if len > 32 << 20 {
+        return Err(invalid("blob too large"));
+    }
+    let mut buf = vec![0; len];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+/// A decoded `PrimitiveBlock`.
+struct Block<'a> {
+    strings: Vec<&'a str>,
+    groups: Vec<&'a [u8]>,
+    granularity: i64,
+    lat_offset: i64,
+    lon_offset: i64,
+}
+
+impl<'a> Block<'a> {
+    fn new(mut msg: &'a [u8]) -> io::Result<Self> {
+        let mut block = Block {
+            strings: Vec::new(),
+            groups: Vec::new(),
+            granularity: 100,
+            lat_offset: 0,
+            lon_offset: 0,
+        };
+        while let Some((tag, field)) = next_field(&mut msg)? {
+            match (tag, field) {
+                (1, Field::Bytes(mut table)) => {
+                    while let Some((tag, field)) = next_field(&mut table)? {
+                        if let (1, Field::Bytes(s)) = (tag, field) {
+                            block.strings.push(utf8(s)?);
+                        }
+                    }
+                }
+                (2, Field::Bytes(group)) => block.groups.push(group),
+                (17, Field::Varint(v)) => block.granularity = v as i64,
+                (19, Field::Varint(v)) => block.lat_offset = v as i64,
+                (20, Field::Varint(v)) => block.lon_offset = v as i64,
+                _ => {}
+            }
+        }
+        Ok(block)
+    }
+
+    fn point(&self, lat: i64, lon: i64) -> Point {
+        let deg = |offset: i64, v: i64| (1e-9 * (offset + self.granularity * v) as f64) as f32;
+        Point::new(deg(self.lon_offset, lon), deg(self.lat_offset, lat))
+    }
+
+    fn string(&self, i: u64) -> io::Result<&'a str> {
+        self.strings
+            .get(i as usize)
+            .copied()
+            .ok_or_else(|| invalid("string index out of range"))
+    }
+
+    /// Calls `f` with the kind, id, tags, node refs and location of every
+    /// node and way of the block.
+    fn visit<F>(&self, mut f: F) -> io::Result<()>
+    where
+        F: FnMut(Kind, i64, &[(&str, &str)], &[i64], Point),
+    {
+        let (mut tags, mut refs) = (Vec::new(), Vec::new());
+        let (mut keys, mut vals, mut deltas) = (Vec::new(), Vec::new(), Vec::new());
+        for &group in &self.groups {
+            let mut group = group;
+            while let Some((tag, field)) = next_field(&mut group)? {
+                let (kind, mut msg) = match (tag, field) {
+                    (1, Field::Bytes(msg)) => (Kind::Node, msg),
+                    (2, Field::Bytes(msg)) => {
+                        self.visit_dense(msg, &mut f)?;
+                        continue;
+                    }
+                    (3, Field::Bytes(msg)) => (Kind::Way, msg),
+                    _ => continue,
+                };
+                let (mut id, mut lat, mut lon) = (0, 0, 0);
+                keys.clear();
+                vals.clear();
+                deltas.clear();
+                while let Some((tag, field)) = next_field(&mut msg)? {
+                    match (kind, tag) {
+                        (Kind::Node, 1) => id = zigzag(field.varint()?),
+                        (Kind::Way, 1) => id = field.varint()? as i64,
+                        (_, 2) => field.repeated(&mut keys)?,
+                        (_, 3) => field.repeated(&mut vals)?,
+                        (Kind::Node, 8) => lat = zigzag(field.varint()?),
+                        (Kind::Node, 9) => lon = zigzag(field.varint()?),
+                        (Kind::Way, 8) => field.repeated(&mut deltas)?,
+                        _ => {}
+                    }
+                }
+                tags.clear();
+                for (&k, &v) in keys.iter().zip(&vals) {
+                    tags.push((self.string(k)?, self.string(v)?));
+                }
+                refs.clear();
+                let mut prev = 0;
+                for &d in &deltas {
+                    prev += zigzag(d);
+                    refs.push(prev);
+                }
+                f(kind, id, &tags, &refs, self.point(lat, lon));
+            }
+        }
+        Ok(())
+    }
+
+    fn visit_dense<F>(&self, mut msg: &[u8], f: &mut F) -> io::Result<()>
+    where
+        F: FnMut(Kind, i64, &[(&str, &str)], &[i64], Point),
+    {
+        let (mut ids, mut lats, mut lons, mut kvs) =
+            (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+        while let Some((tag, field)) = next_field(&mut msg)? {
+            match tag {
+                1 => field.repeated(&mut ids)?,
+                8 => field.repeated(&mut lats)?,
+                9 => field.repeated(&mut lons)?,
+                10 => field.repeated(&mut kvs)?,
+                _ => {}
+            }
+        }
+        if ids.len() != lats.len() || ids.len() != lons.len() {
+            return Err(invalid("mismatched dense node arrays"));
+        }
+        let mut kvs = kvs.into_iter();
+        let mut tags = Vec::new();
+        let (mut id, mut lat, mut lon) = (0, 0, 0);
+        for i in 0..ids.len() {
+            id += zigzag(ids[i]);
+            lat += zigzag(lats[i]);
+            lon += zigzag(lons[i]);
+            // pairs of string indexes, each node's terminated by zero
+            tags.clear();
+            while let Some(k) = kvs.next().filter(|&k| k != 0) {
+                let v = kvs.next().ok_or_else(|| invalid("truncated dense tags"))?;
+                tags.push((self.string(k)?, self.string(v)?));
+            }
+            f(Kind::Node, id, &tags, &[], self.point(lat, lon));
+        }
+        Ok(())
+    }
+}
+
+enum Field<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+impl Field<'_> {
+    fn varint(&self) -> io::Result<u64> {
+        match self {
+            Field::Varint(v) => Ok(*v),
+            _ => Err(invalid("expected a varint")),
+        }
+    }
+
+    /// Appends the values of a repeated varint field, packed or not.
+    fn repeated(&self, out: &mut Vec<u64>) -> io::Result<()> {
+        match self {
+            Field::Varint(v) => out.push(*v),
+            Field::Bytes(mut b) => {
+                while !b.is_empty() {
+                    out.push(varint(&mut b)?);
+                }
+            }
+            Field::Fixed => return Err(invalid("expected a varint")),
+        }
+        Ok(())
+    }
+}
+
+/// Reads the next field of a protobuf message.
+fn next_field<'a>(msg: &mut &'a [u8]) -> io::Result<Option<(u32, Field<'a>)>> {
+    if msg.is_empty() {
+        return Ok(None);
+    }
+    let key = varint(msg)?;
+    let field = match key & 7 {
+        0 => Field::Varint(varint(msg)?),
+        1 => {
+            skip(msg, 8)?;
+            Field::Fixed
+        }
+        2 => {
+            let len = varint(msg)? as usize;
+            Field::Bytes(skip(msg, len)?)
+        }
+        5 => {
+            skip(msg, 4)?;
+            Field::Fixed
+        }
+        _ => return Err(invalid("unsupported wire type")),
+    };
+    Ok(Some(((key >> 3) as u32, field)))
+}
+
+fn skip<'a>(msg: &mut &'a [u8], len: usize) -> io::Result<&'a [u8]> {
+    if msg.len() < len {
+        return Err(invalid("truncated message"));
+    }
+    let (bytes, rest) = msg.split_at(len);
+    *msg = rest;
+    Ok(bytes)
+}
+
+fn varint(msg: &mut &[u8]) -> io::Result<u64> {
+    let mut v = 0;
+    for shift in (0..64).step_by(7) {
+        let (&b, rest) = msg
+            .split_first()
+            .ok_or_else(|| invalid("truncated varint"))?;
+        *msg = rest;
+        v |= ((b & 0x7f) as u64) << shift;
+        if b & 0x80 == 0 {
+            return Ok(v);
+        }
+    }
+    Err(invalid("varint too long"))
+}
+
+fn zigzag(v: u64) -> i64 {
+    (v >> 1) as i64 ^ -((v & 1) as i64)
+}
+
+fn utf8(b: &[u8]) -> io::Result<&str> {
+    std::str::from_utf8(b).map_err(|_| invalid("invalid utf-8"))
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
@@ -0,0 +1,160 @@
+//! A two-tier index for mixing a few moving items with many static ones.
+//!
+//! Items that change often live in a small overlay tree, so that their
+//! updates don't degrade the much larger base tree of static items. The base
+//! tree is packed by [`Overlay::bulk_load`] and can be packed again with
+//! [`Overlay::repack_base`] once promotions and inserts have worn it down.
+
+use crate::{Alloc, Item, IterItem, NearbyIterator, Quality, RTree, Rect, SearchIterator};
+use std::iter::{Chain, Peekable};
+
+pub struct Overlay<T, A: Alloc<T>> {
+    base: RTree<T, A>,
+    hot: RTree<T, A>,
+}
+
+impl<T, A: Alloc<T> + Clone> Overlay<T, A> {
+    pub fn new(alloc: A) -> Self {
+        Overlay {
+            base: RTree::new(alloc.clone()),
+            hot: RTree::new(alloc),
+        }
+    }
+
+    /// Packs the static `items` into the base tree with
+    /// [`RTree::bulk_load`], leaving the overlay empty.
+    pub fn bulk_load<I>(alloc: A, items: I) -> Self
+    where
+        I: IntoIterator<Item = (Rect, T)>,
+    {
+        Overlay {
+            base: RTree::bulk_load(alloc.clone(), items),
+            hot: RTree::new(alloc),
+        }
+    }
+}
+
+impl<T, A: Alloc<T>> Overlay<T, A> {
+    pub fn len(&self) -> usize {
+        self.base.len() + self.hot.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The tree of static items.
+    pub fn base(&self) -> &RTree<T, A> {
+        &self.base
+    }
+
+    /// The tree of frequently updated items.
+    pub fn hot(&self) -> &RTree<T, A> {
+        &self.hot
+    }
+
+    /// Inserts a static item.
+    pub fn insert(&mut self, rect: Rect, data: T) {
+        self.base.insert(rect, data);
+    }
+
+    /// Inserts a frequently updated item.
+    pub fn insert_hot(&mut self, rect: Rect, data: T) {
+        self.hot.insert(rect, data);
+    }
+
+    /// Moves a frequently updated item from `old` to `new`, returning whether
+    /// it was found in the overlay. See [`RTree::update`].
+    pub fn update_hot(&mut self, old: Rect, new: Rect, data: &T) -> bool
+    where
+        T: PartialEq,
+    {
+        self.hot.update(old, new, data)
+    }
+
+    /// Packs the base tree again, see [`RTree::rebuild`], returning its
+    /// quality before and after.
+    pub fn repack_base(&mut self) -> (Quality, Quality) {
+        self.base.rebuild()
+    }
+
+    /// Removes an item from either tier, trying the overlay first.
+    pub fn remove(&mut self, rect: Rect, data: &T) -> Option<Item<T>>
+    where
+        T: PartialEq,
+    {
+        self.hot
+            .remove(rect, data)
+            .or_else(|| self.base.remove(rect, data))
+    }
+
+    /// Moves a static item to the overlay, returning whether it was found.
+    pub fn promote(&mut self, rect: Rect, data: &T) -> bool
+    where
+        T: PartialEq,
+    {
+        match self.base.remove(rect, data) {
+            Some(item) => {
+                self.hot.insert(item.rect, item.item);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Moves an item from the overlay to the base tree, returning whether it
+    /// was found.
+    pub fn demote(&mut self, rect: Rect, data: &T) -> bool
+    where
+        T: PartialEq,
+    {
+        match self.hot.remove(rect, data) {
+            Some(item) => {
+                self.base.insert(item.rect, item.item);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Searches both tiers, yielding the overlay's items first.
+    pub fn search(&self, rect: Rect) -> Chain<SearchIterator<'_, T, A>, SearchIterator<'_, T, A>> {
+        self.hot.search(rect).chain(self.base.search(rect))
+    }
+
+    /// Like [`RTree::nearby`], merging the items of both tiers by distance.
+    pub fn nearby<F>(&self, dist: F) -> OverlayNearbyIterator<'_, T, A, F>
+    where
+        F: FnMut(&Rect, Option<&T>) -> f32 + Clone,
+    {
+        OverlayNearbyIterator {
+            hot: self.hot.nearby(dist.clone()).peekable(),
+            base: self.base.nearby(dist).peekable(),
+        }
+    }
+}
+
+pub struct OverlayNearbyIterator<'a, T, A, F>
+where
+    A: Alloc<T>,
+    F: FnMut(&Rect, Option<&'a T>) -> f32,
+{
+    hot: Peekable<NearbyIterator<'a, T, A, F>>,
+    base: Peekable<NearbyIterator<'a, T, A, F>>,
+}
+
+impl<'a, T, A, F> Iterator for OverlayNearbyIterator<'a, T, A, F>
+where
+    A: Alloc<T>,
+    F: FnMut(&Rect, Option<&'a T>) -> f32,
+{
+    type Item = IterItem<'a, T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match (self.hot.peek(), self.base.peek()) {
+            (Some(a), Some(b)) if b.dist < a.dist => self.base.next(),
+            (Some(_), _) => self.hot.next(),
+            (None, _) => self.base.next(),
+        }
+    }
+}
@@ -0,0 +1,7 @@
+//! The types needed by most uses of the crate, to glob import with
+//! `use rtree::prelude::*`.
+
+pub use crate::{
+    Alloc, BoxAlloc, Chebyshev, Config, Euclidean, ItemKey, IterItem, Manhattan, Metric, Order,
+    Point, Polygon, RTree, Rect, Scalar,
+};
@@ -0,0 +1,1089 @@
+//! Searches, nearest neighbor queries and joins.
+
+use crate::geom::{hilbert, max, min, Ordered};
+use crate::node::{diff_nodes, join, self_join, Entry};
+use crate::{
+    Alloc, Cancelled, Euclidean, IterItem, Metric, NearbyItem, NearbyIterator, NearbyQueue, Node,
+    Order, OrderedSearchIterator, Point, Polygon, RTree, Rect, RefineIterator, Scalar,
+    SearchIterator,
+};
+use arrayvec::ArrayVec;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::hash::Hash;
+use std::ops::{Bound, RangeBounds};
+use std::sync::atomic::AtomicBool;
+
+/// A pair of moving items that collide during a tick.
+pub struct Collision<'a, 'b, T, U> {
+    pub a: IterItem<'a, T>,
+    pub b: IterItem<'b, U>,
+    /// Time of impact in `[0, 1]`.
+    pub toi: f32,
+}
+
+/// A pair of overlapping items.
+pub struct Overlap<'a, 'b, T, U> {
+    pub a: IterItem<'a, T>,
+    pub b: IterItem<'b, U>,
+    /// Shortest translation that separates `a` from `b`, see [`Rect::mtv`].
+    pub mtv: Point,
+}
+
+/// A pair of nodes of two trees queued by [`RTree::closest_pairs`].
+struct PendingPair<'a, 'b, T, A: Alloc<T>, U, B: Alloc<U>> {
+    dist: f32,
+    a: &'a Node<T, A>,
+    b: &'b Node<U, B>,
+    /// Order of queueing, breaking ties between pairs of items.
+    seq: usize,
+}
+
+impl<T, A: Alloc<T>, U, B: Alloc<U>> PendingPair<'_, '_, T, A, U, B> {
+    fn items(&self) -> bool {
+        matches!((self.a, self.b), (Node::Item(_), Node::Item(_)))
+    }
+}
+
+impl<T, A: Alloc<T>, U, B: Alloc<U>> PartialEq for PendingPair<'_, '_, T, A, U, B> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T, A: Alloc<T>, U, B: Alloc<U>> Eq for PendingPair<'_, '_, T, A, U, B> {}
+
+impl<T, A: Alloc<T>, U, B: Alloc<U>> PartialOrd for PendingPair<'_, '_, T, A, U, B> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T, A: Alloc<T>, U, B: Alloc<U>> Ord for PendingPair<'_, '_, T, A, U, B> {
+    /// Reversed, so that the heap pops the closest first, with pairs of
+    /// nodes before pairs of items at the same distance.
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.dist
+            .total_cmp(&other.dist)
+            .then_with(|| self.items().cmp(&other.items()))
+            .then_with(|| self.seq.cmp(&other.seq))
+            .reverse()
+    }
+}
+
+/// The differences between two trees, see [`RTree::diff`].
+pub struct Diff<'a, 'b, T> {
+    /// Items only in the other tree.
+    pub added: Vec<IterItem<'b, T>>,
+    /// Items only in this tree.
+    pub removed: Vec<IterItem<'a, T>>,
+    /// Items in both trees, but under different rects.
+    pub changed: Vec<(IterItem<'a, T>, IterItem<'b, T>)>,
+}
+
+/// The error of [`RTree::try_search`] and [`RTree::try_nearby`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InvalidRectError {
+    /// A coordinate of the rect is NaN.
+    NaN(Rect),
+    /// The min of the rect is greater than its max on some axis.
+    Inverted(Rect),
+}
+
+impl std::fmt::Display for InvalidRectError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            InvalidRectError::NaN(rect) => write!(f, "query rect {:?} has a NaN coordinate", rect),
+            InvalidRectError::Inverted(rect) => write!(f, "query rect {:?} is inverted", rect),
+        }
+    }
+}
+
+impl std::error::Error for InvalidRectError {}
+
+fn checked(rect: Rect) -> Result<Rect, InvalidRectError> {
+    let coords = [rect.min.x, rect.min.y, rect.max.x, rect.max.y];
+    if coords.iter().any(|v| v.is_nan()) {
+        Err(InvalidRectError::NaN(rect))
+    } else if rect.min.x > rect.max.x || rect.min.y > rect.max.y {
+        Err(InvalidRectError::Inverted(rect))
+    } else {
+        Ok(rect)
+    }
+}
+
+/// Results of a query that may have been cut short, see
+/// [`RTree::search_budgeted`] and [`RTree::knn_budgeted`].
+pub struct Budgeted<'a, T> {
+    pub items: Vec<IterItem<'a, T>>,
+    /// Whether the query visited every node it needed, so that `items` is
+    /// the complete answer.
+    pub exact: bool,
+}
+
+impl<T, A: Alloc<T, S>, S: Scalar> RTree<T, A, S> {
+    pub fn search(&self, rect: Rect<S>) -> SearchIterator<'_, T, A, S> {
+        SearchIterator::new(
+            &self.root,
+            &self.values,
+            self.height,
+            rect,
+            self.config.half_open,
+            self.config.profile,
+        )
+    }
+
+    /// Searches for the items intersecting `rect` a level of the tree at a
+    /// time, so that a view can paint the nodes found right away and refine
+    /// them while idle.
+    ///
+    /// Each step yields the whole result at the next level down: first the
+    /// nodes below the root with the number of items in each, and finally
+    /// the items themselves.
+    pub fn refine(&self, rect: Rect<S>) -> RefineIterator<'_, T, A, S> {
+        RefineIterator::new(&self.root, &self.values, rect, self.config.half_open)
+    }
+
+    /// Estimates the number of items intersecting `rect` for query planning,
+    /// by descending `levels` levels below the root and assuming the items
+    /// of the nodes reached are spread evenly over their rects. Nodes inside
+    /// `rect` are counted exactly, so the estimate is exact once `levels`
+    /// reaches the height of the tree.
+    pub fn estimate_count(&self, rect: Rect<S>, levels: usize) -> usize {
+        match &self.root {
+            Some(Node::Parent(root)) if root.rect.intersects(&rect) => {
+                root.estimate_count(&rect, self.height, levels).round() as usize
+            }
+            _ => 0,
+        }
+    }
+
+    /// Searches for the items intersecting `rect` inserted after the item
+    /// with sequence number `since`, see [`IterItem::seq`].
+    ///
+    /// Nodes track the newest item inserted below them, so subtrees with no
+    /// newer items are skipped. Removals don't update this, so areas where
+    /// recent items were removed are still visited until the tree is
+    /// [rebuilt](Self::rebuild).
+    pub fn search_recent(&self, rect: Rect<S>, since: u64) -> SearchIterator<'_, T, A, S> {
+        let mut iter = self.search(rect);
+        iter.since = Some(since);
+        iter
+    }
+
+    /// Determines whether any item intersects `rect`.
+    pub fn intersects_any(&self, rect: Rect<S>) -> bool {
+        self.search(rect).next().is_some()
+    }
+
+    /// Calls `f` on the items intersecting `rect`, like [`search`](Self::search)
+    /// without the state of an iterator.
+    pub fn for_each_in_rect<F>(&self, rect: Rect<S>, mut f: F)
+    where
+        F: FnMut(&Rect<S>, &T),
+    {
+        if let Some(Node::Parent(root)) = &self.root {
+            root.for_each_in_rect(&rect, self.config.half_open, &mut |entry| {
+                f(&entry.rect, &self.values[entry.index])
+            });
+        }
+    }
+
+    /// Searches for the items intersecting `rect` into `out`, without
+    /// allocating, for contexts where allocation isn't allowed. Returns
+    /// whether every item fit, the first `N` found are kept otherwise.
+    pub fn search_into<'a, const N: usize>(
+        &'a self,
+        rect: Rect<S>,
+        out: &mut ArrayVec<IterItem<'a, T, S>, N>,
+    ) -> bool {
+        out.clear();
+        let mut complete = true;
+        if let Some(Node::Parent(root)) = &self.root {
+            root.for_each_in_rect(&rect, self.config.half_open, &mut |entry| {
+                if out.try_push(entry.iter_item(&self.values, 0.0)).is_err() {
+                    complete = false;
+                }
+            });
+        }
+        complete
+    }
+
+    /// Searches for the items intersecting `rect` whose attribute is within
+    /// `range`, skipping the nodes with no such items.
+    ///
+    /// Panics unless an attribute was set with
+    /// [`set_attribute`](Self::set_attribute).
+    pub fn search_where<R>(&self, rect: Rect<S>, range: R) -> Vec<IterItem<'_, T, S>>
+    where
+        R: RangeBounds<f32>,
+    {
+        let attr = self.attr.expect("no attribute set");
+        let test = |[lo, hi]: [f32; 2]| {
+            let above = match range.start_bound() {
+                Bound::Included(start) => hi >= *start,
+                Bound::Excluded(start) => hi > *start,
+                Bound::Unbounded => true,
+            };
+            let below = match range.end_bound() {
+                Bound::Included(end) => lo <= *end,
+                Bound::Excluded(end) => lo < *end,
+                Bound::Unbounded => true,
+            };
+            above && below
+        };
+        let mut found = Vec::new();
+        if let Some(Node::Parent(root)) = &self.root {
+            root.for_each_where(&rect, self.config.half_open, &test, &mut |entry| {
+                if range.contains(&attr(&self.values[entry.index])) {
+                    found.push(entry.iter_item(&self.values, 0.0));
+                }
+            });
+        }
+        found
+    }
+
+    /// Searches for the items intersecting `rect`, returning copies of them
+    /// which don't borrow the tree, to pass across threads or FFI.
+    pub fn search_owned(&self, rect: Rect<S>) -> Vec<(Rect<S>, T)>
+    where
+        T: Clone,
+    {
+        let mut out = Vec::new();
+        self.for_each_in_rect(rect, |rect, data| out.push((*rect, data.clone())));
+        out
+    }
+
+    /// Like [`for_each_in_rect`](Self::for_each_in_rect), with mutable
+    /// access to the values.
+    pub fn for_each_in_rect_mut<F>(&mut self, rect: Rect<S>, mut f: F)
+    where
+        F: FnMut(&Rect<S>, &mut T),
+    {
+        let values = &mut self.values;
+        if let Some(Node::Parent(root)) = &self.root {
+            root.for_each_in_rect(&rect, self.config.half_open, &mut |entry| {
+                f(&entry.rect, &mut values[entry.index])
+            });
+        }
+    }
+
+    /// Like [`for_each_in_rect`](Self::for_each_in_rect), also passing the
+    /// rects of the nodes above each item, from the root down to its leaf.
+    ///
+    /// Meant for measuring the tree around items, such as the area of the
+    /// nodes covering an item beyond its own rect.
+    pub fn for_each_with_ancestors<F>(&self, rect: Rect<S>, mut f: F)
+    where
+        F: FnMut(&[Rect<S>], &Rect<S>, &T),
+    {
+        if let Some(Node::Parent(root)) = &self.root {
+            let mut path = Vec::with_capacity(self.height + 1);
+            root.for_each_with_path(
+                &rect,
+                self.config.half_open,
+                &mut path,
+                &mut |path, entry| f(path, &entry.rect, &self.values[entry.index]),
+            );
+        }
+    }
+}
+
+impl<T, A: Alloc<T>> RTree<T, A> {
+    /// Like [`search`](Self::search), returning the items in `order`.
+    ///
+    /// Nodes are visited best first, so results stream in order without
+    /// collecting them, except in [`Order::Insertion`].
+    pub fn search_ordered(&self, rect: Rect, order: Order) -> OrderedSearchIterator<'_, T, A> {
+        OrderedSearchIterator::new(&self.root, &self.values, rect, order, self.config.half_open)
+    }
+
+    /// Searches for items whose rects intersect `[lo, hi]` on the x axis,
+    /// whatever their extent on the y axis.
+    pub fn search_x_range(&self, lo: f32, hi: f32) -> SearchIterator<'_, T, A> {
+        self.search(Rect::new(
+            Point::new(lo, f32::NEG_INFINITY),
+            Point::new(hi, f32::INFINITY),
+        ))
+    }
+
+    /// Searches for items whose rects intersect `[lo, hi]` on the y axis,
+    /// whatever their extent on the x axis.
+    pub fn search_y_range(&self, lo: f32, hi: f32) -> SearchIterator<'_, T, A> {
+        self.search(Rect::new(
+            Point::new(f32::NEG_INFINITY, lo),
+            Point::new(f32::INFINITY, hi),
+        ))
+    }
+
+    /// Searches for items within their own margin of `rect`, that is whose
+    /// rects intersect `rect` grown by their margin on every side, skipping
+    /// the nodes whose largest margin can't reach `rect`.
+    ///
+    /// Without a margin set with [`set_margin`](Self::set_margin), it's the
+    /// same as [`search`](Self::search).
+    pub fn search_padded(&self, rect: Rect) -> Vec<IterItem<'_, T>> {
+        let margin = |entry: &Entry| self.margin.map_or(0.0, |m| m(&self.values[entry.index]));
+        let mut found = Vec::new();
+        if let Some(Node::Parent(root)) = &self.root {
+            root.for_each_padded(&rect, self.config.half_open, &margin, &mut |entry| {
+                found.push(entry.iter_item(&self.values, 0.0));
+            });
+        }
+        found
+    }
+
+    /// Like [`search`](Self::search), visiting at most `budget` nodes.
+    ///
+    /// Nodes are visited depth first, so a truncated result holds whole
+    /// subtrees rather than a sample of the whole area.
+    pub fn search_budgeted(&self, rect: Rect, budget: usize) -> Budgeted<'_, T> {
+        let mut out = Budgeted {
+            items: Vec::new(),
+            exact: true,
+        };
+        let Some(Node::Parent(root)) = &self.root else {
+            return out;
+        };
+        let mut stack = vec![root];
+        let mut visits = 0;
+        while let Some(parent) = stack.pop() {
+            if visits == budget {
+                out.exact = false;
+                break;
+            }
+            visits += 1;
+            for node in parent.nodes.iter() {
+                if !node.rect().intersects(&rect) {
+                    continue;
+                }
+                match node {
+                    Node::Item(entry) => {
+                        if self.config.half_open && !entry.rect.intersects_half_open(&rect) {
+                            continue;
+                        }
+                        out.items.push(entry.iter_item(&self.values, 0.0));
+                    }
+                    Node::Parent(child) => stack.push(child),
+                }
+            }
+        }
+        out
+    }
+
+    /// Like [`search`](Self::search), but fails on a `rect` with a NaN
+    /// coordinate or a min greater than its max, which would otherwise find
+    /// nothing.
+    pub fn try_search(&self, rect: Rect) -> Result<SearchIterator<'_, T, A>, InvalidRectError> {
+        Ok(self.search(checked(rect)?))
+    }
+
+    /// Returns the items in order of their distance to `rect`, failing on
+    /// the same rects as [`try_search`](Self::try_search).
+    #[allow(clippy::type_complexity)]
+    pub fn try_nearby(
+        &self,
+        rect: Rect,
+    ) -> Result<NearbyIterator<'_, T, A, impl FnMut(&Rect, Option<&T>) -> f32>, InvalidRectError>
+    {
+        let rect = checked(rect)?;
+        Ok(self.nearby(move |r, _| rect.box_dist(r)))
+    }
+
+    /// Returns the items in order of the distances computed by `dist`.
+    ///
+    /// Items at the same distance are ordered by the min and then the max of
+    /// their rects, and then by the order they were inserted in.
+    pub fn nearby<F>(&self, dist: F) -> NearbyIterator<'_, T, A, F>
+    where
+        F: FnMut(&Rect, Option<&'_ T>) -> f32,
+    {
+        NearbyIterator::new(&self.root, &self.values, dist, NearbyQueue::new())
+    }
+
+    /// Finds the item nearest to `point`, by [`Rect::box_dist`].
+    pub fn nearest(&self, point: Point) -> Option<IterItem<'_, T>> {
+        let target = Rect::point(point.x, point.y);
+        self.nearby(|rect, _| rect.box_dist(&target)).next()
+    }
+
+    /// Finds the `k` items nearest to `point`, ordered by [`Rect::box_dist`].
+    pub fn k_nearest(&self, point: Point, k: usize) -> Vec<IterItem<'_, T>> {
+        let target = Rect::point(point.x, point.y);
+        self.nearby(|rect, _| rect.box_dist(&target))
+            .take(k)
+            .collect()
+    }
+
+    /// Finds the `k` items nearest to `point` among those intersecting
+    /// `rect`, ordered by [`Rect::box_dist`] like
+    /// [`k_nearest`](Self::k_nearest).
+    ///
+    /// Nodes outside `rect` are never queued, so closer items outside it
+    /// cost nothing, and nodes farther than the `k`th item are never
+    /// visited.
+    pub fn knn_in_rect(&self, point: Point, k: usize, rect: Rect) -> Vec<IterItem<'_, T>> {
+        let mut out = Vec::new();
+        let Some(root) = &self.root else {
+            return out;
+        };
+        let target = Rect::point(point.x, point.y);
+        let half_open = self.config.half_open;
+        let mut heap = BinaryHeap::new();
+        heap.push(NearbyItem::new(0.0, root, &self.values));
+        while out.len() < k {
+            let Some(item) = heap.pop() else {
+                break;
+            };
+            let parent = match item.node {
+                Node::Item(entry) => {
+                    out.push(entry.iter_item(&self.values, item.dist));
+                    continue;
+                }
+                Node::Parent(parent) => parent,
+            };
+            #[cfg(feature = "stats")]
+            crate::stats::count(|c| c.visited += 1);
+            for node in parent.nodes.iter() {
+                let inside = match node {
+                    Node::Item(entry) if half_open => entry.rect.intersects_half_open(&rect),
+                    _ => node.rect().intersects(&rect),
+                };
+                if inside {
+                    let dist = node.rect().box_dist(&target);
+                    heap.push(NearbyItem::new(dist, node, &self.values));
+                }
+            }
+        }
+        out
+    }
+
+    /// Like [`nearby`](Self::nearby), but reuses a caller-owned `queue`.
+    pub fn nearby_with<'a, 'q, F>(
+        &'a self,
+        queue: &'q mut NearbyQueue<'a, T, A>,
+        dist: F,
+    ) -> NearbyIterator<'a, T, A, F, &'q mut NearbyQueue<'a, T, A>>
+    where
+        F: FnMut(&Rect, Option<&'a T>) -> f32,
+    {
+        NearbyIterator::new(&self.root, &self.values, dist, queue)
+    }
+
+    /// Buckets the items intersecting `rect` into square grid cells of `size`.
+    ///
+    /// An item belongs to the cell containing the center of its rect. Cells
+    /// are keyed by `(floor(x / size), floor(y / size))`.
+    pub fn group_by_cell(
+        &self,
+        rect: Rect,
+        size: f32,
+    ) -> HashMap<(i32, i32), Vec<IterItem<'_, T>>> {
+        let mut cells: HashMap<_, Vec<_>> = HashMap::new();
+        for item in self.search(rect) {
+            let c = item.rect.center();
+            let cell = ((c.x / size).floor() as i32, (c.y / size).floor() as i32);
+            cells.entry(cell).or_default().push(item);
+        }
+        cells
+    }
+
+    /// Finds the items of `self` and `other` that collide while moving.
+    ///
+    /// `da` and `db` return the displacement of an item over the tick. Each
+    /// pair whose relative motion brings the rects into contact is reported
+    /// once with its earliest time of impact, ordered by that time. The
+    /// displacement functions are called more than once per item and should
+    /// be cheap and deterministic.
+    pub fn collisions<'b, U, B, DA, DB>(
+        &self,
+        other: &'b RTree<U, B>,
+        mut da: DA,
+        mut db: DB,
+    ) -> Vec<Collision<'_, 'b, T, U>>
+    where
+        B: Alloc<U>,
+        DA: FnMut(&Rect, &T) -> Point,
+        DB: FnMut(&Rect, &U) -> Point,
+    {
+        let mut pairs = Vec::new();
+        let (Some(ra), Some(rb)) = (&self.root, &other.root) else {
+            return pairs;
+        };
+        // Every item sweeps through its rect grown by the largest
+        // displacement in its tree, so candidate pairs are those whose rects
+        // intersect when grown by both.
+        let mut pad = Point::default();
+        for item in self.iter() {
+            let d = da(&item.rect, item.data);
+            pad = Point::new(max(pad.x, d.x.abs()), max(pad.y, d.y.abs()));
+        }
+        for item in other.iter() {
+            let d = db(&item.rect, item.data);
+            pad = Point::new(max(pad.x, d.x.abs()), max(pad.y, d.y.abs()));
+        }
+        let pad = Point::new(pad.x * 2.0, pad.y * 2.0);
+        let mut test = |a: &Rect, b: &Rect| a.padded(pad).intersects(b);
+        let never = AtomicBool::new(false);
+        let _ = join(ra, rb, &mut test, Some(pad.x), &never, &mut |a, b| {
+            let ma = da(&a.rect, &self.values[a.index]);
+            let mb = db(&b.rect, &other.values[b.index]);
+            let motion = Point::new(ma.x - mb.x, ma.y - mb.y);
+            if let Some(toi) = a.rect.time_of_impact(motion, &b.rect) {
+                pairs.push(Collision {
+                    a: a.iter_item(&self.values, 0.0),
+                    b: b.iter_item(&other.values, 0.0),
+                    toi,
+                });
+            }
+        });
+        pairs.sort_by(|a, b| a.toi.total_cmp(&b.toi));
+        pairs
+    }
+
+    /// Finds an item whose polygon contains `point`.
+    ///
+    /// Candidates are found through their rects and then tested exactly, so
+    /// the item rects must enclose the polygons.
+    pub fn locate_polygon_containing(&self, point: Point) -> Option<IterItem<'_, T>>
+    where
+        T: Polygon,
+    {
+        self.search(Rect::point(point.x, point.y))
+            .find(|item| item.data.contains_point(point))
+    }
+
+    /// Finds all pairs of intersecting items between `self` and `other`,
+    /// along with the translation that separates them.
+    pub fn overlaps<'b, U, B>(&self, other: &'b RTree<U, B>) -> Vec<Overlap<'_, 'b, T, U>>
+    where
+        B: Alloc<U>,
+    {
+        let mut pairs = Vec::new();
+        if let (Some(ra), Some(rb)) = (&self.root, &other.root) {
+            let mut test = |a: &Rect, b: &Rect| a.intersects(b);
+            let never = AtomicBool::new(false);
+            let _ = join(ra, rb, &mut test, Some(0.0), &never, &mut |a, b| {
+                pairs.push(Overlap {
+                    a: a.iter_item(&self.values, 0.0),
+                    b: b.iter_item(&other.values, 0.0),
+                    mtv: a.rect.mtv(&b.rect).unwrap(),
+                });
+            });
+        }
+        pairs
+    }
+
+    /// Finds all pairs of distinct items of the tree whose rects intersect,
+    /// each pair once, in no particular order.
+    pub fn intersection_pairs(&self) -> Vec<(IterItem<'_, T>, IterItem<'_, T>)> {
+        let mut pairs = Vec::new();
+        if let Some(root) = &self.root {
+            let mut test = |a: &Rect, b: &Rect| a.intersects(b);
+            let never = AtomicBool::new(false);
+            let _ = self_join(root, &mut test, Some(0.0), &never, &mut |a, b| {
+                pairs.push((
+                    a.iter_item(&self.values, 0.0),
+                    b.iter_item(&self.values, 0.0),
+                ));
+            });
+        }
+        pairs
+    }
+
+    /// Finds the `k` closest pairs of an item of `self` and an item of
+    /// `other`, ordered by the [`Rect::box_dist`] between their rects, which
+    /// is set as the `dist` of both items.
+    ///
+    /// Pairs of nodes are queued best first by their distance, so the pairs
+    /// are found without a nearest neighbor query for every item.
+    pub fn closest_pairs<'a, 'b, U, B>(
+        &'a self,
+        other: &'b RTree<U, B>,
+        k: usize,
+    ) -> Vec<(IterItem<'a, T>, IterItem<'b, U>)>
+    where
+        B: Alloc<U>,
+    {
+        let mut pairs = Vec::new();
+        let (Some(ra), Some(rb)) = (&self.root, &other.root) else {
+            return pairs;
+        };
+        let mut seq = 0;
+        let mut heap = BinaryHeap::new();
+        let mut push = |heap: &mut BinaryHeap<_>, a: &'a Node<T, A>, b: &'b Node<U, B>| {
+            let dist = a.rect().box_dist(b.rect());
+            heap.push(PendingPair { dist, a, b, seq });
+            seq += 1;
+        };
+        push(&mut heap, ra, rb);
+        while pairs.len() < k {
+            let Some(PendingPair { dist, a, b, .. }) = heap.pop() else {
+                break;
+            };
+            match (a, b) {
+                (Node::Item(x), Node::Item(y)) => pairs.push((
+                    x.iter_item(&self.values, dist),
+                    y.iter_item(&other.values, dist),
+                )),
+                // split the larger of two parents, like joins do
+                (Node::Parent(x), Node::Parent(y)) if x.rect.area() >= y.rect.area() => {
+                    for n in x.nodes.iter() {
+                        push(&mut heap, n, b);
+                    }
+                }
+                (Node::Parent(x), Node::Item(_)) => {
+                    for n in x.nodes.iter() {
+                        push(&mut heap, n, b);
+                    }
+                }
+                (_, Node::Parent(y)) => {
+                    for n in y.nodes.iter() {
+                        push(&mut heap, a, n);
+                    }
+                }
+            }
+        }
+        pairs
+    }
+
+    /// Finds the pairs of items from `self` and `other` whose rects pass
+    /// `test`, such as being within a distance of each other.
+    ///
+    /// Pairs of nodes are tested as well and skipped when they fail, so
+    /// `test` must pass for two rects whenever it passes for any two rects
+    /// inside them. A distance threshold qualifies, as grown rects are only
+    /// closer, while a test for containment needs to be relaxed to
+    /// intersection for the nodes.
+    pub fn pairs_within<'b, U, B, P>(
+        &self,
+        other: &'b RTree<U, B>,
+        test: P,
+    ) -> Vec<(IterItem<'_, T>, IterItem<'b, U>)>
+    where
+        B: Alloc<U>,
+        P: FnMut(&Rect, &Rect) -> bool,
+    {
+        self.try_pairs_within(other, test, &AtomicBool::new(false))
+            .unwrap()
+    }
+
+    /// Like [`pairs_within`](Self::pairs_within), giving up once `cancel` is
+    /// set, which is checked for every pair of nodes compared.
+    #[allow(clippy::type_complexity)]
+    pub fn try_pairs_within<'b, U, B, P>(
+        &self,
+        other: &'b RTree<U, B>,
+        mut test: P,
+        cancel: &AtomicBool,
+    ) -> Result<Vec<(IterItem<'_, T>, IterItem<'b, U>)>, Cancelled>
+    where
+        B: Alloc<U>,
+        P: FnMut(&Rect, &Rect) -> bool,
+    {
+        let mut pairs = Vec::new();
+        if let (Some(ra), Some(rb)) = (&self.root, &other.root) {
+            join(ra, rb, &mut test, None, cancel, &mut |a, b| {
+                pairs.push((
+                    a.iter_item(&self.values, 0.0),
+                    b.iter_item(&other.values, 0.0),
+                ));
+            })?;
+        }
+        Ok(pairs)
+    }
+
+    /// Compares `self` to `other`, matching items by their values.
+    ///
+    /// Subtrees that are identical in both trees are skipped, which makes
+    /// comparing trees that share most of their history cheap.
+    pub fn diff<'b, B>(&self, other: &'b RTree<T, B>) -> Diff<'_, 'b, T>
+    where
+        T: Eq + Hash,
+        B: Alloc<T>,
+    {
+        let (ea, mut pending) = self.unmatched(other);
+        let mut diff = Diff {
+            added: Vec::new(),
+            removed: Vec::new(),
+            changed: Vec::new(),
+        };
+        for e in ea {
+            let a = e.iter_item(&self.values, 0.0);
+            match pending.get_mut(a.data).and_then(|rects| rects.pop()) {
+                Some(b) => diff.changed.push((a, b.iter_item(&other.values, 0.0))),
+                None => diff.removed.push(a),
+            }
+        }
+        let added = pending.into_values().flatten();
+        diff.added = added.map(|e| e.iter_item(&other.values, 0.0)).collect();
+        diff
+    }
+
+    /// Finds the items in exactly one of `self` and `other`, matching items
+    /// by both their rects and values. Returns the items only in `self` and
+    /// the items only in `other`.
+    ///
+    /// Like [`diff`](Self::diff), subtrees that are identical in both trees
+    /// are skipped, so validating a rebuilt tree against the tree it was
+    /// built from is cheap when little has changed.
+    pub fn symmetric_difference<'b, B>(
+        &self,
+        other: &'b RTree<T, B>,
+    ) -> (Vec<IterItem<'_, T>>, Vec<IterItem<'b, T>>)
+    where
+        T: Eq + Hash,
+        B: Alloc<T>,
+    {
+        let (ea, pending) = self.unmatched(other);
+        let a = ea.iter().map(|e| e.iter_item(&self.values, 0.0));
+        let b = pending.into_values().flatten();
+        (
+            a.collect(),
+            b.map(|e| e.iter_item(&other.values, 0.0)).collect(),
+        )
+    }
+
+    /// The entries of `self` and of `other`, by value, that have no item
+    /// with the same rect and value in the other tree.
+    fn unmatched<'b, B>(
+        &self,
+        other: &'b RTree<T, B>,
+    ) -> (Vec<&Entry>, HashMap<&'b T, Vec<&'b Entry>>)
+    where
+        T: Eq + Hash,
+        B: Alloc<T>,
+    {
+        let (mut ea, mut eb) = (Vec::new(), Vec::new());
+        match (&self.root, &other.root) {
+            (Some(Node::Parent(a)), Some(Node::Parent(b))) if self.height == other.height => {
+                diff_nodes(a, &self.values, b, &other.values, &mut ea, &mut eb);
+            }
+            (a, b) => {
+                a.iter().for_each(|n| n.entries(&mut ea));
+                b.iter().for_each(|n| n.entries(&mut eb));
+            }
+        }
+        let mut pending: HashMap<&T, Vec<&Entry>> = HashMap::new();
+        for e in eb {
+            pending.entry(&other.values[e.index]).or_default().push(e);
+        }
+        // Drop the exact matches first, these are items that only moved
+        // between nodes.
+        ea.retain(|e| {
+            let Some(rects) = pending.get_mut(&self.values[e.index]) else {
+                return true;
+            };
+            match rects.iter().position(|x| x.rect == e.rect) {
+                Some(i) => {
+                    rects.swap_remove(i);
+                    false
+                }
+                None => true,
+            }
+        });
+        (ea, pending)
+    }
+
+    /// Splits the items into `k` spatially coherent groups of nearly equal
+    /// size, returning the bounding rect and the items of each group.
+    ///
+    /// Items are ordered along a Hilbert curve by the center of their rects.
+    /// Fewer than `k` groups are returned when there are fewer than `k` items.
+    pub fn partition(&self, k: usize) -> Vec<(Rect, Vec<IterItem<'_, T>>)> {
+        let Some(extent) = self.rect() else {
+            return Vec::new();
+        };
+        let mut items: Vec<_> = self
+            .iter()
+            .map(|item| (hilbert(&extent, item.rect.center()), item))
+            .collect();
+        items.sort_by_key(|x| x.0);
+        let k = k.min(items.len());
+        let mut items = items.into_iter().map(|x| x.1);
+        let mut groups = Vec::with_capacity(k);
+        for i in 0..k {
+            // spread the remainder over the first groups
+            let len = self.length / k + (i < self.length % k) as usize;
+            let group: Vec<_> = items.by_ref().take(len).collect();
+            let mut rect = group[0].rect;
+            group.iter().for_each(|x| rect.expand(&x.rect));
+            groups.push((rect, group));
+        }
+        groups
+    }
+
+    /// Finds the `k` nearest items for each of `points`, ordered by distance.
+    ///
+    /// The points are visited in Hilbert order and each search is bounded by
+    /// the results of the previous, spatially close, search. The returned
+    /// vectors are in the same order as `points`.
+    pub fn knn_batch(&self, points: &[Point], k: usize) -> Vec<Vec<IterItem<'_, T>>> {
+        self.knn_batch_with(points, k, &Euclidean)
+    }
+
+    /// Like [`knn_batch`](Self::knn_batch), with distances in `metric`.
+    pub fn knn_batch_with<M: Metric>(
+        &self,
+        points: &[Point],
+        k: usize,
+        metric: &M,
+    ) -> Vec<Vec<IterItem<'_, T>>> {
+        let mut results: Vec<Vec<IterItem<T>>> = points.iter().map(|_| Vec::new()).collect();
+        let Some(root) = &self.root else {
+            return results;
+        };
+        if k == 0 || points.is_empty() {
+            return results;
+        }
+        let mut extent = Rect::point(points[0].x, points[0].y);
+        for p in points {
+            extent.expand(&Rect::point(p.x, p.y));
+        }
+        let mut order: Vec<usize> = (0..points.len()).collect();
+        order.sort_by_cached_key(|&i| hilbert(&extent, points[i]));
+
+        let mut queue = BinaryHeap::new();
+        let mut prev: Vec<Rect> = Vec::with_capacity(k);
+        for i in order {
+            let point = points[i];
+            // The previous k results are k candidates for this point as well,
+            // so nothing farther than the farthest of them can be in the result.
+            let mut bound = f32::INFINITY;
+            if prev.len() == k {
+                bound = prev.iter().fold(0.0, |d, r| max(d, metric.dist(point, r)));
+            }
+            let found = &mut results[i];
+            queue.clear();
+            queue.push(NearbyItem::new(Default::default(), root, &self.values));
+            while let Some(item) = queue.pop() {
+                match item.node {
+                    Node::Item(data) => {
+                        found.push(data.iter_item(&self.values, item.dist));
+                        if found.len() == k {
+                            break;
+                        }
+                    }
+                    Node::Parent(nodes) => {
+                        for node in nodes.nodes.iter() {
+                            let dist = node.metric_dist(point, metric, self.config.circles);
+                            if dist <= bound {
+                                queue.push(NearbyItem::new(dist, node, &self.values));
+                            }
+                        }
+                    }
+                }
+            }
+            prev.clear();
+            prev.extend(found.iter().map(|x| x.rect));
+        }
+        results
+    }
+
+    /// Samples the distance to the nearest item at the centers of the cells
+    /// of a `cols` by `rows` grid over `rect`, returned row by row from the
+    /// bottom left. Distances are Euclidean, not squared, and infinite for an
+    /// empty tree.
+    ///
+    /// Cells are processed in square tiles that share a single search: the
+    /// items nearest to any cell of a tile are no farther from it than the
+    /// item nearest to the tile's center plus half its diagonal.
+    pub fn distance_field(&self, rect: Rect, cols: usize, rows: usize) -> Vec<f32> {
+        const TILE: usize = 8;
+        let mut field = vec![f32::INFINITY; cols * rows];
+        if self.length == 0 {
+            return field;
+        }
+        let w = (rect.max.x - rect.min.x) / cols as f32;
+        let h = (rect.max.y - rect.min.y) / rows as f32;
+        let center = |col: usize, row: usize| {
+            Point::new(
+                rect.min.x + (col as f32 + 0.5) * w,
+                rect.min.y + (row as f32 + 0.5) * h,
+            )
+        };
+        let mut candidates = Vec::new();
+        for row0 in (0..rows).step_by(TILE) {
+            for col0 in (0..cols).step_by(TILE) {
+                let (row1, col1) = ((row0 + TILE).min(rows) - 1, (col0 + TILE).min(cols) - 1);
+                let (lo, hi) = (center(col0, row0), center(col1, row1));
+                let tile = Rect::new(lo, hi);
+                let mid = tile.center();
+                let nearest = self.knn_batch(&[mid], 1)[0][0].rect;
+                let half_diag = (hi.x - lo.x).hypot(hi.y - lo.y) / 2.0;
+                // with slack for the rounding of the square root, which could
+                // otherwise leave out the nearest item itself
+                let reach = (Rect::point(mid.x, mid.y).box_dist(&nearest).sqrt() + half_diag)
+                    * (1.0 + 4.0 * f32::EPSILON);
+                candidates.clear();
+                candidates.push(nearest);
+                candidates.extend(
+                    self.search(tile.padded(Point::new(reach, reach)))
+                        .map(|item| item.rect)
+                        .filter(|r| r.box_dist(&tile) <= reach * reach),
+                );
+                for row in row0..=row1 {
+                    for col in col0..=col1 {
+                        let p = Rect::point(center(col, row).x, center(col, row).y);
+                        let d = candidates
+                            .iter()
+                            .fold(f32::INFINITY, |d, r| d.min(p.box_dist(r)));
+                        field[row * cols + col] = d.sqrt();
+                    }
+                }
+            }
+        }
+        field
+    }
+
+    /// Finds the `k` nearest items to `point`, visiting at most `budget`
+    /// nodes.
+    ///
+    /// Once the budget is spent, the nearest of the items already reached
+    /// are returned, which may miss closer items in nodes never visited.
+    pub fn knn_budgeted(&self, point: Point, k: usize, budget: usize) -> Budgeted<'_, T> {
+        let mut out = Budgeted {
+            items: Vec::with_capacity(k),
+            exact: true,
+        };
+        let Some(root) = &self.root else {
+            return out;
+        };
+        let mut visits = 0;
+        let mut queue = BinaryHeap::new();
+        queue.push(NearbyItem::new(Default::default(), root, &self.values));
+        while out.items.len() < k {
+            let Some(item) = queue.pop() else {
+                break;
+            };
+            match item.node {
+                Node::Item(entry) => {
+                    out.items.push(entry.iter_item(&self.values, item.dist));
+                }
+                Node::Parent(nodes) => {
+                    if visits == budget {
+                        out.exact = false;
+                        continue;
+                    }
+                    visits += 1;
+                    for node in nodes.nodes.iter() {
+                        let dist = node.metric_dist(point, &Euclidean, self.config.circles);
+                        queue.push(NearbyItem::new(dist, node, &self.values));
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    /// Finds the `k` items whose rects are nearest to `rect`, ordered by
+    /// [`Rect::box_dist`].
+    ///
+    /// Besides the usual lower bounds, nodes are pruned by the upper bounds
+    /// of their sibling nodes: every edge of a node's rect touches an item,
+    /// so each node has an item no farther than its nearest edge's farther
+    /// corner.
+    pub fn knn_rect(&self, rect: Rect, k: usize) -> Vec<IterItem<'_, T>> {
+        let mut found = Vec::with_capacity(k);
+        let Some(root) = &self.root else {
+            return found;
+        };
+        if k == 0 {
+            return found;
+        }
+        let mut bound = f32::INFINITY;
+        let mut bounds = Vec::new();
+        let mut queue = BinaryHeap::new();
+        queue.push(NearbyItem::new(Default::default(), root, &self.values));
+        while let Some(item) = queue.pop() {
+            match item.node {
+                Node::Item(data) => {
+                    found.push(data.iter_item(&self.values, item.dist));
+                    if found.len() == k {
+                        break;
+                    }
+                }
+                Node::Parent(nodes) => {
+                    // The children hold distinct items, and those found so
+                    // far are no farther than any of them, so the k nearest
+                    // are within the bound of the child completing k.
+                    bounds.clear();
+                    bounds.extend(nodes.nodes.iter().map(|node| match node {
+                        Node::Item(entry) => entry.rect.box_dist(&rect),
+                        Node::Parent(n) => n.rect.minmax_dist(&rect),
+                    }));
+                    let need = k - found.len();
+                    if need <= bounds.len() {
+                        bounds.select_nth_unstable_by_key(need - 1, |&d| Ordered(d));
+                        bound = min(bound, bounds[need - 1]);
+                    }
+                    for node in nodes.nodes.iter() {
+                        let dist = node.rect().box_dist(&rect);
+                        if dist <= bound {
+                            queue.push(NearbyItem::new(dist, node, &self.values));
+                        }
+                    }
+                }
+            }
+        }
+        found
+    }
+
+    /// Finds the `k` nearest items to `point` in each of `categories`, in a
+    /// single traversal.
+    ///
+    /// `category` returns the category of an item, items in none of
+    /// `categories` are skipped. The returned vectors are in the same order
+    /// as `categories` and ordered by distance.
+    pub fn knn_per_category<K, F>(
+        &self,
+        point: Point,
+        k: usize,
+        categories: &[K],
+        mut category: F,
+    ) -> Vec<Vec<IterItem<'_, T>>>
+    where
+        K: PartialEq,
+        F: FnMut(&T) -> K,
+    {
+        let mut results: Vec<Vec<IterItem<T>>> = categories.iter().map(|_| Vec::new()).collect();
+        let mut open = if k == 0 { 0 } else { categories.len() };
+        let target = Rect::point(point.x, point.y);
+        let mut items = self.nearby(|rect, _| rect.box_dist(&target));
+        while open > 0 {
+            let Some(item) = items.next() else {
+                break;
+            };
+            let key = category(item.data);
+            let Some(i) = categories.iter().position(|c| *c == key) else {
+                continue;
+            };
+            if results[i].len() < k {
+                results[i].push(item);
+                if results[i].len() == k {
+                    open -= 1;
+                }
+            }
+        }
+        results
+    }
+
+    /// Finds the `k` nearest items to `point` with distinct keys, ordered by
+    /// distance.
+    ///
+    /// Only the nearest item of each key computed by `key` is returned, such
+    /// as one store per chain, and the search goes on until `k` keys are
+    /// found or the items run out.
+    pub fn knn_distinct<K, F>(&self, point: Point, k: usize, mut key: F) -> Vec<IterItem<'_, T>>
+    where
+        K: Eq + Hash,
+        F: FnMut(&T) -> K,
+    {
+        let mut seen = HashSet::new();
+        let target = Rect::point(point.x, point.y);
+        self.nearby(|rect, _| rect.box_dist(&target))
+            .filter(|item| seen.insert(key(item.data)))
+            .take(k)
+            .collect()
+    }
+}
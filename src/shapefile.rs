@@ -0,0 +1,174 @@
+//! Loading ESRI shapefiles.
+//!
+//! Features are indexed by the bounding box stored in the `.shp` file and
+//! paired with their attribute record from the `.dbf` file next to it.
+
+use crate::{Alloc, Point, RTree, Rect};
+use std::fs::File;
+use std::io::{self, BufReader, Error, ErrorKind, Read};
+use std::path::Path;
+
+/// The attributes of a single feature, as trimmed strings.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Record {
+    pub fields: Vec<(String, String)>,
+}
+
+impl Record {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// Reads `path` (the `.shp` file) and the `.dbf` file next to it.
+///
+/// Features with a null shape are skipped.
+pub fn read(path: impl AsRef<Path>) -> io::Result<Vec<(Rect, Record)>> {
+    let path = path.as_ref();
+    let shp = BufReader::new(File::open(path)?);
+    let dbf = BufReader::new(File::open(path.with_extension("dbf"))?);
+    read_from(shp, dbf)
+}
+
+/// Reads features from the contents of a `.shp` and a `.dbf` file.
+pub fn read_from(mut shp: impl Read, mut dbf: impl Read) -> io::Result<Vec<(Rect, Record)>> {
+    let mut header = [0; 100];
+    shp.read_exact(&mut header)?;
+    if be_u32(&header[0..]) != 9994 {
+        return Err(invalid("not a shapefile"));
+    }
+    let mut records = Records::new(&mut dbf)?;
+    let mut features = Vec::with_capacity(records.count);
+    let mut head = [0; 8];
+    let mut content = Vec::new();
+    loop {
+        match shp.read_exact(&mut head) {
+            Ok(()) => {}
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err),
+        }
+        // content length is in 16-bit words
+        content.resize(be_u32(&head[4..]) as usize * 2, 0);
+        shp.read_exact(&mut content)?;
+        let record = records.next(&mut dbf)?;
+        if let Some(rect) = envelope(&content)? {
+            features.push((rect, record));
+        }
+    }
+    Ok(features)
+}
+
+impl<T, A: Alloc<T>> RTree<T, A> {
+    /// Inserts every feature of the shapefile at `path`, converting attribute
+    /// records to items with `f`.
+    pub fn load_shapefile<F>(&mut self, path: impl AsRef<Path>, mut f: F) -> io::Result<()>
+    where
+        F: FnMut(Record) -> T,
+    {
+        for (rect, record) in read(path)? {
+            self.insert(rect, f(record));
+        }
+        Ok(())
+    }
+}
+
+fn envelope(content: &[u8]) -> io::Result<Option<Rect>> {
+    if content.len() < 4 {
+        return Err(invalid("truncated shape"));
+    }
+    let coord = |at: usize| {
+        content
+            .get(at..at + 8)
+            .map(|b| f64::from_le_bytes(b.try_into().unwrap()) as f32)
+            .ok_or_else(|| invalid("truncated shape"))
+    };
+    match le_u32(content) {
+        // null shape
+        0 => Ok(None),
+        // point, point z, point m
+        1 | 11 | 21 => {
+            let (x, y) = (coord(4)?, coord(12)?);
+            Ok(Some(Rect::point(x, y)))
+        }
+        // everything else starts with its bounding box
+        3 | 5 | 8 | 13 | 15 | 18 | 23 | 25 | 28 | 31 => Ok(Some(Rect::new(
+            Point::new(coord(4)?, coord(12)?),
+            Point::new(coord(20)?, coord(28)?),
+        ))),
+        _ => Err(invalid("unknown shape type")),
+    }
+}
+
+/// Sequential reader of `.dbf` records.
+struct Records {
+    count: usize,
+    fields: Vec<(String, usize)>,
+    buf: Vec<u8>,
+}
+
+impl Records {
+    fn new(dbf: &mut impl Read) -> io::Result<Self> {
+        let mut header = [0; 32];
+        dbf.read_exact(&mut header)?;
+        let count = le_u32(&header[4..]) as usize;
+        let header_len = le_u16(&header[8..]) as usize;
+        let record_len = le_u16(&header[10..]) as usize;
+        if header_len < 33 || record_len == 0 {
+            return Err(invalid("bad dbf header"));
+        }
+        let mut descriptors = vec![0; header_len - 32];
+        dbf.read_exact(&mut descriptors)?;
+        let mut fields = Vec::new();
+        for desc in descriptors.chunks_exact(32) {
+            if desc[0] == 0x0d {
+                break;
+            }
+            let name = desc[..11].split(|&b| b == 0).next().unwrap();
+            fields.push((decode(name), desc[16] as usize));
+        }
+        Ok(Records {
+            count,
+            fields,
+            buf: vec![0; record_len],
+        })
+    }
+
+    fn next(&mut self, dbf: &mut impl Read) -> io::Result<Record> {
+        dbf.read_exact(&mut self.buf)?;
+        // the first byte is the deletion flag
+        let mut at = 1;
+        let mut record = Record::default();
+        for (name, len) in &self.fields {
+            let value = self
+                .buf
+                .get(at..at + len)
+                .ok_or_else(|| invalid("truncated dbf record"))?;
+            record.fields.push((name.clone(), decode(value)));
+            at += len;
+        }
+        Ok(record)
+    }
+}
+
+fn decode(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).trim().to_string()
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+fn be_u32(b: &[u8]) -> u32 {
+    u32::from_be_bytes(b[..4].try_into().unwrap())
+}
+
+fn le_u32(b: &[u8]) -> u32 {
+    u32::from_le_bytes(b[..4].try_into().unwrap())
+}
+
+fn le_u16(b: &[u8]) -> u16 {
+    u16::from_le_bytes(b[..2].try_into().unwrap())
+}
@@ -0,0 +1,56 @@
+//! Counting the work done by the trees of the current thread, so that
+//! benchmarks can check how many nodes an operation touched rather than
+//! only how long it took.
+//!
+//! Counters are kept per thread, which makes them cheap to update and keeps
+//! tests running in parallel from counting each other's work. Nodes are
+//! counted as visited by [`RTree::search`], [`RTree::search_ordered`],
+//! [`RTree::for_each_in_rect`], [`RTree::search_where`] and the nearest
+//! neighbor queries built on [`RTree::nearby`].
+//!
+//! [`RTree::search`]: crate::RTree::search
+//! [`RTree::search_ordered`]: crate::RTree::search_ordered
+//! [`RTree::for_each_in_rect`]: crate::RTree::for_each_in_rect
+//! [`RTree::search_where`]: crate::RTree::search_where
+//! [`RTree::nearby`]: crate::RTree::nearby
+
+use std::cell::Cell;
+
+/// The work counted on one thread, see [`counters`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WorkCounters {
+    /// Nodes whose children were tested by queries.
+    pub visited: u64,
+    /// Nodes split by inserts, counting a split of the root once.
+    pub splits: u64,
+    /// Items inserted again after a removal left their node underfull.
+    pub reinserts: u64,
+}
+
+thread_local! {
+    static COUNTERS: Cell<WorkCounters> = const {
+        Cell::new(WorkCounters {
+            visited: 0,
+            splits: 0,
+            reinserts: 0,
+        })
+    };
+}
+
+pub(crate) fn count(f: impl FnOnce(&mut WorkCounters)) {
+    COUNTERS.with(|counters| {
+        let mut c = counters.get();
+        f(&mut c);
+        counters.set(c);
+    });
+}
+
+/// The work of the current thread since it started or the counters were
+/// reset.
+pub fn counters() -> WorkCounters {
+    COUNTERS.with(Cell::get)
+}
+
+pub fn reset_counters() {
+    COUNTERS.with(|counters| counters.set(WorkCounters::default()));
+}
@@ -0,0 +1,79 @@
+//! [proptest] strategies for rects and operation sequences.
+//!
+//! The generators are biased towards the inputs that exercise the tree's edge
+//! cases: zero-area rects, rects that share an edge with their neighbours and
+//! long runs of identical coordinates.
+
+use crate::geom::{max, min};
+use crate::{Point, Rect};
+use proptest::prelude::*;
+use std::ops::Range;
+
+/// A coordinate in `[-180, 180]`.
+///
+/// Half of the values are snapped to a coarse grid so that independently
+/// generated rects often touch or duplicate each other.
+pub fn coord() -> impl Strategy<Value = f32> {
+    prop_oneof![-180.0f32..=180.0, (-8i32..=8).prop_map(|v| v as f32 * 22.5)]
+}
+
+pub fn point() -> impl Strategy<Value = Point> {
+    (coord(), coord()).prop_map(|(x, y)| Point::new(x, y))
+}
+
+/// A zero-area rect.
+pub fn point_rect() -> impl Strategy<Value = Rect> {
+    point().prop_map(|p| Rect::new(p, p))
+}
+
+/// A rect with zero width or zero height.
+pub fn degenerate_rect() -> impl Strategy<Value = Rect> {
+    (point(), coord(), any::<bool>()).prop_map(|(p, v, vertical)| {
+        if vertical {
+            Rect::new(Point::new(p.x, min(p.y, v)), Point::new(p.x, max(p.y, v)))
+        } else {
+            Rect::new(Point::new(min(p.x, v), p.y), Point::new(max(p.x, v), p.y))
+        }
+    })
+}
+
+/// A rect with `min <= max` on both axes.
+pub fn proper_rect() -> impl Strategy<Value = Rect> {
+    (point(), point()).prop_map(|(a, b)| {
+        Rect::new(
+            Point::new(min(a.x, b.x), min(a.y, b.y)),
+            Point::new(max(a.x, b.x), max(a.y, b.y)),
+        )
+    })
+}
+
+/// Any of [`point_rect`], [`degenerate_rect`] or [`proper_rect`].
+pub fn rect() -> impl Strategy<Value = Rect> {
+    prop_oneof![point_rect(), degenerate_rect(), proper_rect()]
+}
+
+/// A single mutation or query against a tree.
+#[derive(Clone, Debug)]
+pub enum Op<T> {
+    Insert(Rect, T),
+    /// Remove a live item, chosen by index modulo the number of live items.
+    Remove(usize),
+    Search(Rect),
+}
+
+/// A sequence of operations whose inserted values are drawn from `value`.
+///
+/// Inserts are weighted to keep the tree growing so that splits and
+/// underflows on removal both get exercised.
+pub fn ops<S>(value: S, len: Range<usize>) -> impl Strategy<Value = Vec<Op<S::Value>>>
+where
+    S: Strategy,
+    S::Value: Clone,
+{
+    let op = prop_oneof![
+        3 => (rect(), value).prop_map(|(r, v)| Op::Insert(r, v)),
+        2 => any::<usize>().prop_map(Op::Remove),
+        1 => rect().prop_map(Op::Search),
+    ];
+    prop::collection::vec(op, len)
+}
@@ -0,0 +1,176 @@
+//! Queries streamed in pages of owned results, for async services.
+//!
+//! A [`PageStream`] holds the tree through an [`Arc`] and remembers where
+//! its query stopped as a path of child indices, so no borrow of the tree
+//! is held between pages. Each page is computed when it's polled, which lets
+//! a slow consumer hold back a large result set.
+
+use crate::geom::Ordered;
+use crate::{Alloc, Node, Parent, Point, RTree, Rect};
+use futures_core::Stream;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+enum Query {
+    /// Items intersecting the rect, found depth first. `path` holds the
+    /// index of the next child to visit at each level, and is empty once
+    /// the search is done.
+    Search { rect: Rect, path: Vec<u32> },
+    /// The `k` items nearest to the point, found best first from a queue
+    /// of paths to nodes and items.
+    Knn {
+        target: Rect,
+        k: usize,
+        queue: BinaryHeap<Reverse<(Ordered, Vec<u32>)>>,
+    },
+}
+
+/// A search or nearest neighbor query emitting pages of `(rect, value)`
+/// pairs, see the [module](self) docs.
+///
+/// Pages hold at most the page size given and are never empty. The stream
+/// is also an [`Iterator`] for use outside of async code.
+pub struct PageStream<T, A: Alloc<T> = crate::BoxAlloc> {
+    tree: Arc<RTree<T, A>>,
+    query: Query,
+    page: usize,
+}
+
+impl<T: Clone, A: Alloc<T>> PageStream<T, A> {
+    /// Streams the items intersecting `rect`, in the order of
+    /// [`RTree::search`].
+    pub fn search(tree: Arc<RTree<T, A>>, rect: Rect, page: usize) -> Self {
+        assert!(page > 0, "page must be positive");
+        let path = if tree.root.is_some() { vec![0] } else { vec![] };
+        PageStream {
+            tree,
+            query: Query::Search { rect, path },
+            page,
+        }
+    }
+
+    /// Streams the `k` items nearest to `point`, ordered by
+    /// [`Rect::box_dist`] like [`RTree::k_nearest`].
+    pub fn knn(tree: Arc<RTree<T, A>>, point: Point, k: usize, page: usize) -> Self {
+        assert!(page > 0, "page must be positive");
+        let mut queue = BinaryHeap::new();
+        if tree.root.is_some() && k > 0 {
+            queue.push(Reverse((Ordered(0.0), vec![])));
+        }
+        PageStream {
+            tree,
+            query: Query::Knn {
+                target: Rect::point(point.x, point.y),
+                k,
+                queue,
+            },
+            page,
+        }
+    }
+
+    fn next_page(&mut self) -> Vec<(Rect, T)> {
+        let tree = &*self.tree;
+        let Some(root) = &tree.root else {
+            return Vec::new();
+        };
+        let mut out = Vec::new();
+        match &mut self.query {
+            Query::Search { rect, path } => {
+                if path.is_empty() {
+                    return out;
+                }
+                let half_open = tree.config.half_open;
+                let mut stack: Vec<&Parent<T, A>> = vec![parent(root)];
+                for &i in &path[..path.len() - 1] {
+                    let node = &stack[stack.len() - 1].nodes[i as usize];
+                    stack.push(parent(node));
+                }
+                while let Some(&parent) = stack.last() {
+                    let i = path.last_mut().unwrap();
+                    let Some(node) = parent.nodes.get(*i as usize) else {
+                        stack.pop();
+                        path.pop();
+                        if let Some(i) = path.last_mut() {
+                            *i += 1;
+                        }
+                        continue;
+                    };
+                    if !node.rect().intersects(rect) {
+                        *i += 1;
+                        continue;
+                    }
+                    match node {
+                        Node::Item(entry) => {
+                            if out.len() == self.page {
+                                break;
+                            }
+                            *i += 1;
+                            if !half_open || entry.rect.intersects_half_open(rect) {
+                                out.push((entry.rect, tree.values[entry.index].clone()));
+                            }
+                        }
+                        Node::Parent(child) => {
+                            stack.push(child);
+                            path.push(0);
+                        }
+                    }
+                }
+            }
+            Query::Knn { target, k, queue } => {
+                while out.len() < self.page && *k > 0 {
+                    let Some(Reverse((_, path))) = queue.pop() else {
+                        break;
+                    };
+                    let mut node = root;
+                    for &i in &path {
+                        node = &parent(node).nodes[i as usize];
+                    }
+                    match node {
+                        Node::Item(entry) => {
+                            out.push((entry.rect, tree.values[entry.index].clone()));
+                            *k -= 1;
+                        }
+                        Node::Parent(parent) => {
+                            for (i, child) in parent.nodes.iter().enumerate() {
+                                let mut path = path.clone();
+                                path.push(i as u32);
+                                let dist = child.rect().box_dist(target);
+                                queue.push(Reverse((Ordered(dist), path)));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+fn parent<T, A: Alloc<T>>(node: &Node<T, A>) -> &Parent<T, A> {
+    match node {
+        Node::Parent(parent) => parent,
+        Node::Item(_) => unreachable!(),
+    }
+}
+
+impl<T: Clone, A: Alloc<T>> Iterator for PageStream<T, A> {
+    type Item = Vec<(Rect, T)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let page = self.next_page();
+        (!page.is_empty()).then_some(page)
+    }
+}
+
+/// Pages are computed synchronously when polled, so the stream is always
+/// ready.
+impl<T: Clone, A: Alloc<T>> Stream for PageStream<T, A> {
+    type Item = Vec<(Rect, T)>;
+
+    fn poll_next(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Poll::Ready(self.get_mut().next())
+    }
+}
@@ -3,6 +3,9 @@
 #![allow(unused_imports)]
 
 use super::*;
+use crate::geom::Circle;
+use arrayvec::ArrayVec;
+use blink_alloc::Blink;
 
 use std::fmt::Display;
 use std::fs::File;
@@ -25,26 +28,26 @@ fn test_rtree(count: usize, points_only: bool) {
         }
     }
     // insert each point
-    for i in 0..pts.len() {
-        tr.insert(pts[i], i);
+    for (i, pt) in pts.iter().enumerate() {
+        tr.insert(*pt, i);
         // check the length
         assert_eq!(tr.len(), i + 1);
         // search for this item
-        assert_eq!(tr.search(pts[i]).filter(|x| x.data == &i).count(), 1);
+        assert_eq!(tr.search(*pt).filter(|x| x.data == &i).count(), 1);
     }
     // scan all rects and compare
     let mut all: Vec<IterItem<usize>> = tr.iter().collect();
     all.sort_by(|a, b| a.data.cmp(b.data));
     assert_eq!(all.len(), pts.len());
-    for i in 0..pts.len() {
-        if pts[i] != all[i].rect {
+    for (pt, item) in pts.iter().zip(&all) {
+        if *pt != item.rect {
             panic!("not equal");
         }
     }
 
     // search for each point again
-    for i in 0..pts.len() {
-        assert_eq!(tr.search(pts[i]).filter(|x| x.data == &i).count(), 1);
+    for (i, pt) in pts.iter().enumerate() {
+        assert_eq!(tr.search(*pt).filter(|x| x.data == &i).count(), 1);
     }
 
     // scan kNN
@@ -254,3 +257,3313 @@ fn example() {
     // OUTPUT:
     // PHX
 }
+
+#[cfg(feature = "proptest")]
+mod prop {
+    use super::*;
+    use crate::strategies::{ops, Op};
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn ops_match_model(ops in ops(any::<u32>(), 0..300)) {
+            let blink = Blink::new();
+            let mut tr = RTree::new(&blink);
+            let mut model: Vec<(Rect, u32)> = Vec::new();
+            for op in ops {
+                match op {
+                    Op::Insert(rect, data) => {
+                        tr.insert(rect, data);
+                        model.push((rect, data));
+                    }
+                    Op::Remove(i) if !model.is_empty() => {
+                        let (rect, data) = model.swap_remove(i % model.len());
+                        prop_assert!(tr.remove(rect, &data).is_some());
+                    }
+                    Op::Remove(_) => {}
+                    Op::Search(rect) => {
+                        let mut found: Vec<u32> = tr.search(rect).map(|x| *x.data).collect();
+                        let mut expect: Vec<u32> = model
+                            .iter()
+                            .filter(|(r, _)| r.intersects(&rect))
+                            .map(|(_, d)| *d)
+                            .collect();
+                        found.sort_unstable();
+                        expect.sort_unstable();
+                        prop_assert_eq!(found, expect);
+                    }
+                }
+                prop_assert_eq!(tr.len(), model.len());
+            }
+        }
+    }
+}
+
+#[test]
+fn drain() {
+    let blink = Blink::new();
+    let mut tr = RTree::new(&blink);
+    for i in 0..1000 {
+        tr.insert(Rect::point(i as f32, -(i as f32)), i);
+    }
+    let mut items: Vec<_> = tr.drain_all().collect();
+    items.sort_by_key(|x| x.1);
+    assert_eq!(items.len(), 1000);
+    assert!(items.iter().enumerate().all(|(i, x)| x.1 == i));
+    assert_eq!(tr.len(), 0);
+    assert_eq!(tr.iter().count(), 0);
+
+    // the drained tree is still usable
+    tr.insert(Rect::point(1.0, 1.0), 1);
+    tr.insert(Rect::point(2.0, 2.0), 2);
+    let mut items = tr.into_vec();
+    items.sort_by_key(|x| x.1);
+    assert_eq!(
+        items,
+        vec![(Rect::point(1.0, 1.0), 1), (Rect::point(2.0, 2.0), 2)]
+    );
+}
+
+#[test]
+fn occupancy() {
+    let blink = Blink::new();
+    let mut tr = RTree::new(&blink);
+    for i in 0..10_000 {
+        tr.insert(Rect::point(fastrand::f32(), fastrand::f32()), i);
+    }
+    let occ = tr.occupancy();
+    let items: usize = occ.leaves.iter().enumerate().map(|(n, c)| n * c).sum();
+    assert_eq!(items, tr.len());
+    assert_eq!(occ.leaves[MAX_ITEMS], 0);
+    assert_eq!(occ.leaves[..MIN_ITEMS].iter().sum::<usize>(), 0);
+    let children: usize = occ.branches.iter().enumerate().map(|(n, c)| n * c).sum();
+    let nodes: usize = occ.leaves.iter().chain(&occ.branches).sum();
+    // every node but the root is somebody's child
+    assert_eq!(children, nodes - 1);
+}
+
+#[test]
+fn knn_batch() {
+    let blink = Blink::new();
+    let mut tr = RTree::new(&blink);
+    for i in 0..10_000 {
+        let x = fastrand::f32() * 360.0 - 180.0;
+        let y = fastrand::f32() * 180.0 - 90.0;
+        tr.insert(Rect::point(x, y), i);
+    }
+    let points: Vec<Point> = (0..500)
+        .map(|_| {
+            Point::new(
+                fastrand::f32() * 360.0 - 180.0,
+                fastrand::f32() * 180.0 - 90.0,
+            )
+        })
+        .collect();
+    let results = tr.knn_batch(&points, 10);
+    assert_eq!(results.len(), points.len());
+    for (p, found) in points.iter().zip(results) {
+        let target = Rect::point(p.x, p.y);
+        let expect: Vec<f32> = tr
+            .nearby(|rect, _| rect.box_dist(&target))
+            .take(10)
+            .map(|x| x.dist)
+            .collect();
+        let found: Vec<f32> = found.iter().map(|x| x.dist).collect();
+        assert_eq!(found, expect);
+    }
+}
+
+#[test]
+fn group_by_cell() {
+    let blink = Blink::new();
+    let mut tr = RTree::new(&blink);
+    for x in 0..10 {
+        for y in 0..10 {
+            tr.insert(Rect::point(x as f32 + 0.5, y as f32 + 0.5), (x, y));
+        }
+    }
+    let cells = tr.group_by_cell(Rect::INFINITY, 5.0);
+    assert_eq!(cells.len(), 4);
+    assert!(cells.values().all(|items| items.len() == 25));
+    assert!(cells[&(1, 0)].iter().all(|x| x.data.0 >= 5 && x.data.1 < 5));
+
+    let cells = tr.group_by_cell(Rect::new(Point::new(0.0, 0.0), Point::new(3.0, 3.0)), 2.0);
+    assert_eq!(cells.values().map(|x| x.len()).sum::<usize>(), 9);
+    assert_eq!(cells[&(1, 1)].len(), 1);
+}
+
+#[test]
+fn collisions() {
+    let blink = Blink::new();
+    let mut a = RTree::new(&blink);
+    let mut b = RTree::new(&blink);
+    let rand_rect = || {
+        let x = fastrand::f32() * 100.0;
+        let y = fastrand::f32() * 100.0;
+        Rect::new(Point::new(x, y), Point::new(x + 1.0, y + 1.0))
+    };
+    let motion = |i: usize| Point::new((i % 7) as f32 - 3.0, (i % 5) as f32 - 2.0);
+    let mut ra = vec![];
+    let mut rb = vec![];
+    for i in 0..500 {
+        ra.push(rand_rect());
+        a.insert(ra[i], i);
+        rb.push(rand_rect());
+        b.insert(rb[i], i + 1);
+    }
+    let pairs = a.collisions(&b, |_, &i| motion(i), |_, &i| motion(i));
+    let mut expect = 0;
+    for (i, x) in ra.iter().enumerate() {
+        for (j, y) in rb.iter().enumerate() {
+            let (mi, mj) = (motion(i), motion(j + 1));
+            let rel = Point::new(mi.x - mj.x, mi.y - mj.y);
+            if let Some(toi) = x.time_of_impact(rel, y) {
+                expect += 1;
+                let found = pairs.iter().find(|p| *p.a.data == i && *p.b.data == j + 1);
+                assert_eq!(found.map(|p| p.toi), Some(toi));
+            }
+        }
+    }
+    assert!(expect > 0);
+    assert_eq!(pairs.len(), expect);
+    assert!(pairs.windows(2).all(|w| w[0].toi <= w[1].toi));
+
+    let r = Rect::new(Point::new(0.0, 0.0), Point::new(1.0, 1.0));
+    let r2 = Rect::new(Point::new(3.0, 0.0), Point::new(4.0, 1.0));
+    assert_eq!(r.time_of_impact(Point::new(4.0, 0.0), &r2), Some(0.5));
+    assert_eq!(r.time_of_impact(Point::new(1.0, 0.0), &r2), None);
+    assert_eq!(r.time_of_impact(Point::new(4.0, 3.0), &r2), None);
+    assert_eq!(r.time_of_impact(Point::default(), &r), Some(0.0));
+}
+
+#[test]
+fn overlaps() {
+    let r = |x0, y0, x1, y1| Rect::new(Point::new(x0, y0), Point::new(x1, y1));
+    assert_eq!(
+        r(0.0, 0.0, 2.0, 2.0).mtv(&r(1.5, 0.0, 4.0, 2.0)),
+        Some(Point::new(-0.5, 0.0))
+    );
+    assert_eq!(
+        r(0.0, 0.0, 2.0, 2.0).mtv(&r(0.0, -1.0, 2.0, 0.5)),
+        Some(Point::new(0.0, 0.5))
+    );
+    assert_eq!(r(0.0, 0.0, 1.0, 1.0).mtv(&r(2.0, 2.0, 3.0, 3.0)), None);
+
+    let blink = Blink::new();
+    let mut a = RTree::new(&blink);
+    let mut b = RTree::new(&blink);
+    for i in 0..100 {
+        let x = i as f32 * 2.0;
+        a.insert(r(x, 0.0, x + 1.5, 1.0), i);
+        b.insert(r(x + 1.0, 0.75, x + 1.75, 2.0), i);
+    }
+    let pairs = a.overlaps(&b);
+    assert_eq!(pairs.len(), 100);
+    for p in pairs {
+        assert_eq!(p.a.data, p.b.data);
+        assert_eq!(p.mtv, Point::new(0.0, -0.25));
+    }
+}
+
+#[test]
+fn overlaps_dense() {
+    // heavily overlapping rects, where leaves are joined by plane sweep
+    let rect = || {
+        let (x, y) = (fastrand::f32(), fastrand::f32());
+        let (w, h) = (fastrand::f32() * 0.2, fastrand::f32() * 0.2);
+        Rect::new(Point::new(x, y), Point::new(x + w, y + h))
+    };
+    let mut a = RTree::default();
+    let mut b = RTree::default();
+    for i in 0..500 {
+        a.insert(rect(), i);
+        b.insert(rect(), i);
+    }
+    let mut found: Vec<_> = a
+        .overlaps(&b)
+        .iter()
+        .map(|p| (*p.a.data, *p.b.data))
+        .collect();
+    found.sort();
+    let mut expect = Vec::new();
+    for x in a.iter() {
+        for y in b.iter() {
+            if x.rect.intersects(&y.rect) {
+                expect.push((*x.data, *y.data));
+            }
+        }
+    }
+    expect.sort();
+    assert_eq!(found, expect);
+}
+
+#[test]
+fn intersection_pairs() {
+    let rect = || {
+        let (x, y) = (fastrand::f32(), fastrand::f32());
+        let (w, h) = (fastrand::f32() * 0.1, fastrand::f32() * 0.1);
+        Rect::new(Point::new(x, y), Point::new(x + w, y + h))
+    };
+    let mut tr = RTree::default();
+    for i in 0..1000 {
+        tr.insert(rect(), i);
+    }
+    let mut found: Vec<_> = tr
+        .intersection_pairs()
+        .iter()
+        .map(|(a, b)| (*a.data.min(b.data), *a.data.max(b.data)))
+        .collect();
+    found.sort();
+    let items: Vec<_> = tr.iter().collect();
+    let mut expect = Vec::new();
+    for (i, x) in items.iter().enumerate() {
+        for y in &items[i + 1..] {
+            if x.rect.intersects(&y.rect) {
+                expect.push((*x.data.min(y.data), *x.data.max(y.data)));
+            }
+        }
+    }
+    expect.sort();
+    assert_eq!(found, expect);
+    assert!(RTree::<u32>::default().intersection_pairs().is_empty());
+}
+
+#[test]
+fn polygon_containing() {
+    let blink = Blink::new();
+    let mut tr = RTree::new(&blink);
+    // two triangles splitting the unit square along its diagonal
+    let lower = vec![
+        Point::new(0.0, 0.0),
+        Point::new(1.0, 0.0),
+        Point::new(1.0, 1.0),
+    ];
+    let upper = vec![
+        Point::new(0.0, 0.0),
+        Point::new(1.0, 1.0),
+        Point::new(0.0, 1.0),
+    ];
+    let square = Rect::new(Point::new(0.0, 0.0), Point::new(1.0, 1.0));
+    tr.insert(square, lower.clone());
+    tr.insert(square, upper.clone());
+    let found = tr.locate_polygon_containing(Point::new(0.75, 0.25));
+    assert_eq!(found.map(|x| x.data), Some(&lower));
+    let found = tr.locate_polygon_containing(Point::new(0.25, 0.75));
+    assert_eq!(found.map(|x| x.data), Some(&upper));
+    assert!(tr.locate_polygon_containing(Point::new(1.5, 0.5)).is_none());
+}
+
+#[cfg(feature = "shapefile")]
+#[test]
+fn shapefile() {
+    use crate::shapefile::read_from;
+
+    let mut shp = vec![0; 100];
+    shp[..4].copy_from_slice(&9994u32.to_be_bytes());
+    let mut record = |content: Vec<u8>| {
+        shp.extend(1u32.to_be_bytes());
+        shp.extend((content.len() as u32 / 2).to_be_bytes());
+        shp.extend(content);
+    };
+    let f64s = |kind: u32, v: &[f64]| {
+        let mut c = kind.to_le_bytes().to_vec();
+        v.iter().for_each(|x| c.extend(x.to_le_bytes()));
+        c
+    };
+    record(f64s(1, &[1.5, 2.5]));
+    record(f64s(0, &[]));
+    record(f64s(5, &[-1.0, -2.0, 3.0, 4.0, 0.0]));
+
+    let mut dbf = vec![0; 32];
+    dbf[4..8].copy_from_slice(&3u32.to_le_bytes());
+    dbf[8..10].copy_from_slice(&(32u16 * 3 + 1).to_le_bytes());
+    dbf[10..12].copy_from_slice(&9u16.to_le_bytes());
+    for (name, len) in [("NAME", 5u8), ("CODE", 3)] {
+        let mut desc = [0; 32];
+        desc[..name.len()].copy_from_slice(name.as_bytes());
+        desc[11] = b'C';
+        desc[16] = len;
+        dbf.extend(desc);
+    }
+    dbf.push(0x0d);
+    dbf.extend(b" one  001 two  002 threeABC");
+
+    let features = read_from(&shp[..], &dbf[..]).unwrap();
+    assert_eq!(features.len(), 2);
+    assert_eq!(features[0].0, Rect::point(1.5, 2.5));
+    assert_eq!(features[0].1.get("NAME"), Some("one"));
+    assert_eq!(
+        features[1].0,
+        Rect::new(Point::new(-1.0, -2.0), Point::new(3.0, 4.0))
+    );
+    assert_eq!(features[1].1.get("CODE"), Some("ABC"));
+}
+
+#[cfg(feature = "wkb")]
+#[test]
+fn wkb() {
+    use crate::wkb::{envelope, to_wkb};
+
+    let rect = Rect::new(Point::new(-1.0, 2.0), Point::new(3.0, 4.5));
+    assert_eq!(envelope(&to_wkb(&rect)).unwrap(), Some(rect));
+    let point = Rect::point(7.0, -8.0);
+    assert_eq!(to_wkb(&point).len(), 21);
+    assert_eq!(envelope(&to_wkb(&point)).unwrap(), Some(point));
+
+    // big-endian EWKB LineString Z with SRID 4326
+    let mut ewkb = vec![0];
+    ewkb.extend((0x8000_0000u32 | 0x2000_0000 | 2).to_be_bytes());
+    ewkb.extend(4326u32.to_be_bytes());
+    ewkb.extend(2u32.to_be_bytes());
+    for v in [5.0f64, -1.0, 100.0, -2.0, 3.0, 200.0] {
+        ewkb.extend(v.to_be_bytes());
+    }
+    let expect = Rect::new(Point::new(-2.0, -1.0), Point::new(5.0, 3.0));
+    assert_eq!(envelope(&ewkb).unwrap(), Some(expect));
+
+    // empty multipolygon, truncated input
+    let empty = [1, 6, 0, 0, 0, 0, 0, 0, 0];
+    assert_eq!(envelope(&empty).unwrap(), None);
+    assert!(envelope(&ewkb[..20]).is_err());
+}
+
+#[cfg(feature = "stats")]
+#[test]
+fn work_counters() {
+    use crate::stats::{counters, reset_counters, WorkCounters};
+    reset_counters();
+    let mut tr = RTree::default();
+    for i in 0..1000 {
+        tr.insert(Rect::point(fastrand::f32(), fastrand::f32()), i);
+    }
+    let built = counters();
+    assert_eq!(built.visited, 0);
+    // every node but the root was made by a split, and two by a root split
+    assert_eq!(
+        built.splits as usize,
+        tr.quality().nodes - 1 - tr.quality().height
+    );
+    reset_counters();
+    assert_eq!(counters(), WorkCounters::default());
+    let area = Rect::new(Point::new(0.25, 0.25), Point::new(0.5, 0.5));
+    let found = tr.search(area).count();
+    let visited = counters().visited;
+    assert!(visited > 0 && (visited as usize) < tr.quality().nodes);
+    reset_counters();
+    tr.for_each_in_rect(area, |_, _| {});
+    assert_eq!(counters().visited, visited);
+    reset_counters();
+    tr.retain_in_rect(area, |_, _| false);
+    assert_eq!(counters().visited, 0);
+    assert_eq!(tr.len() + found, 1000);
+}
+
+#[cfg(feature = "stream")]
+#[test]
+fn page_stream() {
+    use crate::stream::PageStream;
+    use futures_core::Stream;
+    use std::pin::Pin;
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Waker};
+    let mut tr = RTree::default();
+    for i in 0..10000 {
+        tr.insert(Rect::point(fastrand::f32(), fastrand::f32()), i);
+    }
+    let tr = Arc::new(tr);
+    let area = Rect::new(Point::new(0.25, 0.25), Point::new(0.75, 0.75));
+    let mut stream = PageStream::search(tr.clone(), area, 100);
+    let mut cx = Context::from_waker(Waker::noop());
+    let mut found = Vec::new();
+    while let Poll::Ready(Some(page)) = Pin::new(&mut stream).poll_next(&mut cx) {
+        assert!(!page.is_empty() && page.len() <= 100);
+        found.extend(page.into_iter().map(|x| x.1));
+    }
+    let expect: Vec<_> = tr.search(area).map(|x| *x.data).collect();
+    assert_eq!(found, expect);
+    let point = Point::new(0.5, 0.5);
+    let pages: Vec<_> = PageStream::knn(tr.clone(), point, 250, 100).collect();
+    assert_eq!(
+        pages.iter().map(|p| p.len()).collect::<Vec<_>>(),
+        [100, 100, 50]
+    );
+    let dists: Vec<_> = pages
+        .iter()
+        .flatten()
+        .map(|x| x.0.box_dist(&Rect::point(0.5, 0.5)))
+        .collect();
+    let expect: Vec<_> = tr
+        .k_nearest(point, 250)
+        .iter()
+        .map(|x| x.rect.box_dist(&Rect::point(0.5, 0.5)))
+        .collect();
+    assert_eq!(dists, expect);
+    assert_eq!(
+        PageStream::search(Arc::new(RTree::<i32>::default()), area, 10).count(),
+        0
+    );
+}
+
+#[cfg(feature = "geoparquet")]
+#[test]
+fn geoparquet() {
+    use arrow_array::{ArrayRef, BinaryArray, Int32Array, RecordBatch};
+    use parquet::arrow::ArrowWriter;
+    use parquet::file::metadata::KeyValue;
+    use parquet::file::properties::WriterProperties;
+    use std::sync::Arc;
+
+    let rects = [
+        Rect::new(Point::new(0.0, 0.0), Point::new(1.0, 1.0)),
+        Rect::point(5.0, 6.0),
+    ];
+    let shapes: Vec<Option<Vec<u8>>> = vec![
+        Some(crate::wkb::to_wkb(&rects[0])),
+        None,
+        Some(crate::wkb::to_wkb(&rects[1])),
+    ];
+    let batch = RecordBatch::try_from_iter([
+        ("geom", Arc::new(BinaryArray::from_iter(shapes)) as ArrayRef),
+        (
+            "id",
+            Arc::new(Int32Array::from(vec![10, 20, 30])) as ArrayRef,
+        ),
+    ])
+    .unwrap();
+    let geo = r#"{"version": "1.0.0", "primary_column": "geom", "columns": {"geom": {"encoding": "WKB"}}}"#;
+    let props = WriterProperties::builder()
+        .set_key_value_metadata(Some(vec![KeyValue::new("geo".into(), geo.to_string())]))
+        .build();
+    let path = std::env::temp_dir().join(format!("rtree-{}.parquet", std::process::id()));
+    let file = File::create(&path).unwrap();
+    let mut writer = ArrowWriter::try_new(file, batch.schema(), Some(props)).unwrap();
+    writer.write(&batch).unwrap();
+    writer.close().unwrap();
+
+    let blink = Blink::new();
+    let mut tr = RTree::new(&blink);
+    tr.load_geoparquet(File::open(&path).unwrap(), |batch, row| {
+        let ids = batch.column_by_name("id").unwrap();
+        ids.as_any()
+            .downcast_ref::<Int32Array>()
+            .unwrap()
+            .value(row)
+    })
+    .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let mut items: Vec<_> = tr.iter().map(|x| (x.rect, *x.data)).collect();
+    items.sort_by_key(|x| x.1);
+    assert_eq!(items, vec![(rects[0], 10), (rects[1], 30)]);
+}
+
+#[cfg(all(feature = "csv", feature = "ndjson"))]
+#[test]
+fn loaders() {
+    use crate::loader::Columns;
+
+    let blink = Blink::new();
+    let mut tr = RTree::new(&blink);
+    let csv = "name,lat,lon\nPHX,33.4373,-112.0078\n\"LAX, CA\",33.9425,-118.4071\n";
+    tr.load_csv(csv.as_bytes(), Columns::default(), |r| r[0].to_string())
+        .unwrap();
+    let ndjson = "{\"x\": 1.5, \"y\": \"2\", \"name\": \"A\"}\n\n{\"x\": 3, \"y\": 4}\n";
+    let columns = Columns { x: "x", y: "y" };
+    tr.load_ndjson(ndjson.as_bytes(), columns, |o| {
+        o.get("name")
+            .map_or("?", |v| v.as_str().unwrap())
+            .to_string()
+    })
+    .unwrap();
+    let mut items: Vec<_> = tr.iter().map(|x| (x.data.as_str(), x.rect)).collect();
+    items.sort_by(|a, b| a.0.cmp(b.0));
+    assert_eq!(
+        items,
+        vec![
+            ("?", Rect::point(3.0, 4.0)),
+            ("A", Rect::point(1.5, 2.0)),
+            ("LAX, CA", Rect::point(-118.4071, 33.9425)),
+            ("PHX", Rect::point(-112.0078, 33.4373)),
+        ]
+    );
+    assert!(tr
+        .load_csv(
+            "a,b\n1,2\n".as_bytes(),
+            Columns::default(),
+            |_| String::new()
+        )
+        .is_err());
+    assert!(tr
+        .load_ndjson("{\"lon\": 1}".as_bytes(), Columns::default(), |_| {
+            String::new()
+        })
+        .is_err());
+}
+
+#[test]
+fn partition() {
+    let blink = Blink::new();
+    let mut tr = RTree::new(&blink);
+    for i in 0..1003 {
+        tr.insert(Rect::point(fastrand::f32(), fastrand::f32()), i);
+    }
+    let groups = tr.partition(4);
+    assert_eq!(groups.len(), 4);
+    let sizes: Vec<_> = groups.iter().map(|g| g.1.len()).collect();
+    assert_eq!(sizes, vec![251, 251, 251, 250]);
+    let mut seen: Vec<usize> = groups
+        .iter()
+        .flat_map(|g| g.1.iter().map(|x| *x.data))
+        .collect();
+    seen.sort_unstable();
+    assert!(seen.iter().copied().eq(0..1003));
+    // Hilbert groups of uniform points are compact: each covers far less
+    // than the whole unit square
+    for (rect, _) in &groups {
+        assert!(rect.area() < 0.6);
+    }
+    assert_eq!(tr.partition(2000).len(), 1003);
+}
+
+#[test]
+fn nearby_with_queue() {
+    let blink = Blink::new();
+    let mut tr = RTree::new(&blink);
+    for i in 0..1000 {
+        tr.insert(Rect::point(fastrand::f32(), fastrand::f32()), i);
+    }
+    let mut queue = NearbyQueue::new();
+    for _ in 0..100 {
+        let target = Rect::point(fastrand::f32(), fastrand::f32());
+        let dist = |rect: &Rect, _: Option<&i32>| rect.box_dist(&target);
+        let found: Vec<_> = tr
+            .nearby_with(&mut queue, dist)
+            .take(5)
+            .map(|x| *x.data)
+            .collect();
+        let expect: Vec<_> = tr.nearby(dist).take(5).map(|x| *x.data).collect();
+        assert_eq!(found, expect);
+    }
+}
+
+#[test]
+fn scan_len() {
+    let blink = Blink::new();
+    let mut tr = RTree::new(&blink);
+    assert_eq!(tr.iter().len(), 0);
+    for i in 0..500 {
+        tr.insert(Rect::point(fastrand::f32(), fastrand::f32()), i);
+    }
+    let mut iter = tr.iter();
+    assert_eq!(iter.len(), 500);
+    iter.by_ref().take(123).for_each(drop);
+    assert_eq!(iter.size_hint(), (377, Some(377)));
+    assert_eq!(iter.count(), 377);
+}
+
+#[test]
+fn clear() {
+    let mut tr = RTree::new(ArenaAlloc::new());
+    for i in 0..1000 {
+        tr.insert(Rect::point(i as f32, i as f32), i);
+    }
+    let keys: Vec<_> = tr.iter().map(|x| x.key).collect();
+    // the copy shares the arena, which clearing the tree mustn't reset
+    let copy = tr.clone();
+    tr.clear();
+    assert!(std::rc::Rc::strong_count(&tr.alloc.blink) > 1);
+    assert_eq!(tr.len(), 0);
+    assert_eq!(tr.iter().count(), 0);
+    assert!(keys.iter().all(|&key| tr.get(key).is_none()));
+    assert_eq!(copy.search(Rect::point(5.0, 5.0)).next().unwrap().data, &5);
+    drop(copy);
+    // only the allocator holds the arena once the nodes are gone
+    assert_eq!(std::rc::Rc::strong_count(&tr.alloc.blink), 1);
+
+    for i in 0..1000 {
+        tr.insert(Rect::point(i as f32, i as f32), i);
+    }
+    tr.clear();
+    assert_eq!(std::rc::Rc::strong_count(&tr.alloc.blink), 1);
+    for i in 0..10 {
+        tr.insert(Rect::point(i as f32, i as f32), i);
+    }
+    assert_eq!(tr.len(), 10);
+    assert_eq!(tr.search(Rect::point(3.0, 3.0)).next().unwrap().data, &3);
+    assert!(keys.iter().all(|&key| tr.get(key).is_none()));
+}
+
+#[test]
+fn compact() {
+    let blink = Blink::new();
+    let mut tr = RTree::new(&blink);
+    for i in 0..1000 {
+        tr.insert(Rect::point(i as f32, i as f32), i);
+    }
+    let keys: Vec<_> = tr.iter().map(|x| (x.key, *x.data)).collect();
+    for i in (0..1000).step_by(2) {
+        tr.remove(Rect::point(i as f32, i as f32), &i).unwrap();
+    }
+    for &(key, i) in &keys {
+        assert_eq!(tr.get(key), (i % 2 == 1).then_some(&i));
+    }
+    // freed slots are reused, stale keys don't see the new values
+    tr.insert(Rect::point(0.0, 0.0), 5000);
+    assert_eq!(tr.values.len(), 1000);
+    assert!(keys.iter().all(|&(key, _)| tr.get(key) != Some(&5000)));
+
+    tr.compact();
+    assert_eq!(tr.values.len(), 501);
+    assert!(keys.iter().all(|&(key, _)| tr.get(key).is_none()));
+    for item in tr.iter() {
+        assert_eq!(tr.get(item.key), Some(item.data));
+    }
+    let key = tr.search(Rect::point(1.0, 1.0)).next().unwrap().key;
+    *tr.get_mut(key).unwrap() = 1001;
+    assert_eq!(tr.remove(Rect::point(1.0, 1.0), &1001).unwrap().item, 1001);
+    assert_eq!(tr.len(), 500);
+}
+
+#[test]
+fn retain_in_rect() {
+    let blink = Blink::new();
+    let mut tr = RTree::new(&blink);
+    for x in 0..100 {
+        for y in 0..100 {
+            tr.insert(Rect::point(x as f32, y as f32), x * 100 + y);
+        }
+    }
+    let area = Rect::new(Point::new(10.0, 10.0), Point::new(59.0, 59.0));
+    tr.retain_in_rect(area, |rect, v| {
+        assert!(rect.intersects(&area));
+        *v += 1_000_000;
+        rect.min.x >= 30.0
+    });
+    assert_eq!(tr.len(), 10_000 - 20 * 50);
+    let mut found = 0;
+    for item in tr.iter() {
+        let inside = item.rect.intersects(&area);
+        assert!(!inside || item.rect.min.x >= 30.0);
+        assert_eq!(*item.data >= 1_000_000, inside);
+        found += 1;
+    }
+    assert_eq!(found, tr.len());
+    assert_eq!(tr.search(area).count(), 30 * 50);
+
+    tr.retain_in_rect(Rect::INFINITY, |_, _| false);
+    assert_eq!(tr.len(), 0);
+    assert!(tr.rect().is_none());
+    tr.insert(Rect::point(1.0, 1.0), 1);
+    assert_eq!(tr.iter().count(), 1);
+}
+
+/// Checks that every node encloses its children, that all leaves are at the
+/// same depth and that only the root may hold fewer than `MIN_ITEMS` nodes.
+fn check_nodes<T, A: Alloc<T>>(parent: &Parent<T, A>, height: usize, root: bool) -> usize {
+    assert!(root || parent.len() >= MIN_ITEMS);
+    assert!(parent.len() < MAX_ITEMS);
+    let mut count = 0;
+    for node in parent.nodes.iter() {
+        let r = node.rect();
+        assert!(r.min.x >= parent.rect.min.x && r.max.x <= parent.rect.max.x);
+        assert!(r.min.y >= parent.rect.min.y && r.max.y <= parent.rect.max.y);
+        count += match node {
+            Node::Item(_) => {
+                assert_eq!(height, 0);
+                1
+            }
+            Node::Parent(child) => check_nodes(child, height - 1, false),
+        };
+    }
+    assert_eq!(parent.nodes.count, count);
+    count
+}
+
+#[test]
+fn remove_rebalance() {
+    let blink = Blink::new();
+    let mut tr = RTree::new(&blink);
+    let mut pts: Vec<_> = (0..5000)
+        .map(|i| (Rect::point(fastrand::f32(), fastrand::f32()), i))
+        .collect();
+    for &(rect, i) in &pts {
+        tr.insert(rect, i);
+    }
+    fastrand::shuffle(&mut pts);
+    while let Some((rect, i)) = pts.pop() {
+        assert_eq!(tr.remove(rect, &i).unwrap().item, i);
+        if let Some(Node::Parent(root)) = &tr.root {
+            assert_eq!(check_nodes(root, tr.height, true), tr.len());
+        }
+        if pts.len() % 500 == 0 {
+            for &(rect, i) in &pts {
+                assert_eq!(tr.search(rect).filter(|x| *x.data == i).count(), 1);
+            }
+        }
+    }
+    assert!(tr.root.is_none());
+}
+
+#[test]
+fn diff() {
+    let blink = Blink::new();
+    let mut a = RTree::new(&blink);
+    let mut b = RTree::new(&blink);
+    let pts: Vec<_> = (0..2000)
+        .map(|i| (Rect::point(fastrand::f32(), fastrand::f32()), i))
+        .collect();
+    for &(rect, i) in &pts {
+        a.insert(rect, i);
+        b.insert(rect, i);
+    }
+    let d = a.diff(&b);
+    assert!(d.added.is_empty() && d.removed.is_empty() && d.changed.is_empty());
+
+    b.remove(pts[10].0, &10).unwrap();
+    b.remove(pts[20].0, &20).unwrap();
+    b.insert(Rect::point(2.0, 2.0), 20);
+    b.insert(Rect::point(0.5, 0.5), 5000);
+    let d = a.diff(&b);
+    let removed: Vec<_> = d.removed.iter().map(|x| (x.rect, *x.data)).collect();
+    assert_eq!(removed, vec![pts[10]]);
+    let added: Vec<_> = d.added.iter().map(|x| (x.rect, *x.data)).collect();
+    assert_eq!(added, vec![(Rect::point(0.5, 0.5), 5000)]);
+    assert_eq!(d.changed.len(), 1);
+    assert_eq!(d.changed[0].0.rect, pts[20].0);
+    assert_eq!(d.changed[0].1.rect, Rect::point(2.0, 2.0));
+    assert_eq!(*d.changed[0].1.data, 20);
+
+    // the reverse diff swaps added and removed
+    let d = b.diff(&a);
+    assert_eq!(d.added.len(), 1);
+    assert_eq!(*d.added[0].data, 10);
+    assert_eq!(d.removed.len(), 1);
+    assert_eq!(*d.removed[0].data, 5000);
+}
+
+#[test]
+fn node_size() {
+    use std::mem::size_of;
+
+    // values live in the slab, so large ones don't grow the nodes
+    let small = size_of::<Node<u8, BoxAlloc>>();
+    assert_eq!(size_of::<Node<[u8; 1024], BoxAlloc>>(), small);
+    assert_eq!(size_of::<Node<String, &Blink>>(), small);
+    // and what nodes keep about their items lives with their children, so
+    // that it doesn't grow the slots of the items either
+    assert!(small <= 32, "{small}");
+    assert!(size_of::<Parent<u8, BoxAlloc>>() <= 24);
+}
+
+#[test]
+fn removed_item() {
+    let mut tr = RTree::default();
+    tr.insert(Rect::point(1.0, 2.0), "one".to_string());
+    let removed = tr
+        .remove(Rect::point(1.0, 2.0), &"one".to_string())
+        .unwrap();
+    assert_eq!(removed.rect(), Rect::point(1.0, 2.0));
+    assert_eq!(removed.data(), "one");
+    let (rect, data) = removed.into_inner();
+    assert_eq!(rect, Rect::point(1.0, 2.0));
+    assert_eq!(data, "one");
+}
+
+#[test]
+fn remove_where() {
+    struct Handle(u32);
+
+    let mut tr = RTree::default();
+    for i in 0..1000 {
+        tr.insert(Rect::point((i % 10) as f32, 0.0), Handle(i));
+    }
+    let removed = tr
+        .remove_where(Rect::point(3.0, 0.0), |h| h.0 > 500)
+        .unwrap();
+    assert_eq!(removed.rect, Rect::point(3.0, 0.0));
+    assert!(removed.item.0 > 500 && removed.item.0 % 10 == 3);
+    assert_eq!(tr.len(), 999);
+    // only items intersecting the rect are candidates
+    assert!(tr.remove_where(Rect::point(3.5, 0.0), |_| true).is_none());
+    assert_eq!(tr.len(), 999);
+}
+
+#[test]
+fn update() {
+    let mut tr = RTree::default();
+    let mut pts: Vec<_> = (0..5000)
+        .map(|_| Rect::point(fastrand::f32(), fastrand::f32()))
+        .collect();
+    for (i, p) in pts.iter().enumerate() {
+        tr.insert(*p, i);
+    }
+    let keys: Vec<_> = {
+        let mut items: Vec<_> = tr.iter().map(|x| (*x.data, x.key)).collect();
+        items.sort_by_key(|x| x.0);
+        items.into_iter().map(|x| x.1).collect()
+    };
+    // small moves mostly stay in their leaves, large ones don't
+    for step in [0.0001, 0.5] {
+        for (i, p) in pts.iter_mut().enumerate() {
+            let moved = Rect::point(p.min.x + step, p.min.y);
+            assert!(tr.update(*p, moved, &i));
+            *p = moved;
+        }
+    }
+    assert_eq!(tr.len(), pts.len());
+    for (i, p) in pts.iter().enumerate() {
+        let found: Vec<_> = tr.search(*p).filter(|x| *x.data == i).collect();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].key, keys[i]);
+    }
+    assert_eq!(
+        tr.search(Rect::new(Point::new(0.0, 0.0), Point::new(0.5, 1.0)))
+            .count(),
+        0
+    );
+    assert!(!tr.update(Rect::point(5.0, 5.0), Rect::point(0.0, 0.0), &0));
+
+    // only an item at exactly the old rect is moved, whether or not the
+    // new rect fits in its leaf
+    let mut tr = RTree::default();
+    let big = Rect::new(Point::new(0.0, 0.0), Point::new(2.0, 2.0));
+    tr.insert(big, 7);
+    tr.insert(Rect::point(1.0, 1.0), 7);
+    assert!(!tr.update(Rect::point(0.5, 0.5), Rect::point(9.0, 9.0), &7));
+    assert!(!tr.update(Rect::point(0.5, 0.5), Rect::point(1.5, 1.5), &7));
+    assert!(tr.update(Rect::point(1.0, 1.0), Rect::point(9.0, 9.0), &7));
+    assert_eq!(tr.search(big).map(|x| x.rect).collect::<Vec<_>>(), [big]);
+}
+
+#[test]
+fn retain() {
+    let mut tr = RTree::default();
+    for i in 0..10000 {
+        tr.insert(Rect::point(fastrand::f32(), fastrand::f32()), i);
+    }
+    tr.retain(|_, v| {
+        *v *= 2;
+        *v % 3 == 0
+    });
+    assert_eq!(tr.len(), 3334);
+    assert!(tr.iter().all(|x| *x.data % 6 == 0));
+    tr.retain(|_, _| false);
+    assert_eq!(tr.len(), 0);
+    assert!(tr.rect().is_none());
+}
+
+#[test]
+fn drain_in_rect() {
+    let mut tr = RTree::default();
+    let pts: Vec<_> = (0..10000)
+        .map(|i| (Rect::point(fastrand::f32(), fastrand::f32()), i))
+        .collect();
+    for &(rect, i) in &pts {
+        tr.insert(rect, i);
+    }
+    let area = Rect::new(Point::new(0.25, 0.25), Point::new(0.75, 0.75));
+    let mut drained: Vec<_> = tr.drain_in_rect(area).collect();
+    drained.sort_by_key(|x| x.1);
+    let expect: Vec<_> = pts
+        .iter()
+        .copied()
+        .filter(|x| x.0.intersects(&area))
+        .collect();
+    assert_eq!(drained, expect);
+    assert_eq!(tr.len(), pts.len() - expect.len());
+    assert_eq!(tr.search(area).count(), 0);
+    assert_eq!(tr.iter().count(), tr.len());
+    assert_eq!(tr.drain_in_rect(area).len(), 0);
+}
+
+#[test]
+fn remove_in_rect_incremental() {
+    let mut tr = RTree::default();
+    for i in 0..10000 {
+        tr.insert(Rect::point(fastrand::f32(), fastrand::f32()), i);
+    }
+    let area = Rect::new(Point::new(0.25, 0.25), Point::new(0.75, 0.75));
+    let inside = tr.search(area).count();
+    let mut removal = tr.remove_in_rect_incremental(area, 100);
+    assert_eq!(removal.removed(), 100);
+    assert_eq!(tr.len(), 10000 - 100);
+    let mut calls = 1;
+    while !removal.is_done() {
+        assert!(tr.resume_removal(&mut removal, 100) <= 100);
+        calls += 1;
+    }
+    assert_eq!(removal.removed(), inside);
+    assert_eq!(calls, inside / 100 + 1);
+    assert_eq!(tr.search(area).count(), 0);
+    assert_eq!(tr.len(), 10000 - inside);
+    assert_eq!(tr.resume_removal(&mut removal, 100), 0);
+}
+
+#[test]
+fn pairs_within() {
+    let mut a = RTree::default();
+    let mut b = RTree::default();
+    for i in 0..2000 {
+        a.insert(Rect::point(fastrand::f32(), fastrand::f32()), i);
+        b.insert(Rect::point(fastrand::f32(), fastrand::f32()), i);
+    }
+    let within = |ra: &Rect, rb: &Rect| ra.box_dist(rb) <= 0.01 * 0.01;
+    let mut found: Vec<_> = a
+        .pairs_within(&b, within)
+        .iter()
+        .map(|(x, y)| (*x.data, *y.data))
+        .collect();
+    found.sort();
+    let mut expect = Vec::new();
+    for x in a.iter() {
+        for y in b.iter() {
+            if within(&x.rect, &y.rect) {
+                expect.push((*x.data, *y.data));
+            }
+        }
+    }
+    expect.sort();
+    assert!(!expect.is_empty());
+    assert_eq!(found, expect);
+}
+
+#[test]
+fn knn_in_rect() {
+    let mut tr = RTree::default();
+    for i in 0..5000 {
+        tr.insert(Rect::point(fastrand::f32(), fastrand::f32()), i);
+    }
+    let window = Rect::new(Point::new(0.6, 0.1), Point::new(0.9, 0.4));
+    // the point is outside the window, with many closer items around it
+    let point = Point::new(0.2, 0.2);
+    let target = Rect::point(point.x, point.y);
+    let found: Vec<_> = tr
+        .knn_in_rect(point, 20, window)
+        .iter()
+        .map(|x| x.dist)
+        .collect();
+    let mut expect: Vec<_> = tr
+        .search(window)
+        .map(|x| x.rect.box_dist(&target))
+        .collect();
+    expect.sort_by(f32::total_cmp);
+    assert_eq!(found, expect[..20]);
+    assert_eq!(tr.knn_in_rect(point, 10_000, window).len(), expect.len());
+    let outside = Rect::new(Point::new(2.0, 2.0), Point::new(3.0, 3.0));
+    assert!(tr.knn_in_rect(point, 5, outside).is_empty());
+}
+
+#[test]
+fn closest_pairs() {
+    let mut a = RTree::default();
+    let mut b = RTree::default();
+    for i in 0..1000 {
+        a.insert(Rect::point(fastrand::f32(), fastrand::f32()), i);
+        b.insert(Rect::point(fastrand::f32(), fastrand::f32()), i);
+    }
+    let pairs = a.closest_pairs(&b, 50);
+    let mut expect = Vec::new();
+    for x in a.iter() {
+        for y in b.iter() {
+            expect.push(x.rect.box_dist(&y.rect));
+        }
+    }
+    expect.sort_by(f32::total_cmp);
+    let dists: Vec<_> = pairs
+        .iter()
+        .map(|(x, y)| x.rect.box_dist(&y.rect))
+        .collect();
+    assert_eq!(dists, expect[..50]);
+    assert!(pairs.iter().all(|(x, y)| x.dist == y.dist));
+    assert_eq!(a.closest_pairs(&b, 2_000_000).len(), 1_000_000);
+    assert!(a.closest_pairs(&RTree::<u32>::default(), 5).is_empty());
+}
+
+#[test]
+fn symmetric_difference() {
+    let mut a = RTree::default();
+    let pts: Vec<_> = (0..2000)
+        .map(|i| (Rect::point(fastrand::f32(), fastrand::f32()), i))
+        .collect();
+    for &(rect, i) in &pts {
+        a.insert(rect, i);
+    }
+    let mut b = RTree::bulk_load(BoxAlloc, pts.iter().copied());
+    let (x, y) = a.symmetric_difference(&b);
+    assert!(x.is_empty() && y.is_empty());
+
+    // a moved item is in both halves
+    b.remove(pts[20].0, &20).unwrap();
+    b.insert(Rect::point(2.0, 2.0), 20);
+    b.insert(Rect::point(0.5, 0.5), 5000);
+    let (x, y) = a.symmetric_difference(&b);
+    let x: Vec<_> = x.iter().map(|x| (x.rect, *x.data)).collect();
+    let mut y: Vec<_> = y.iter().map(|x| (x.rect, *x.data)).collect();
+    y.sort_by_key(|x| x.1);
+    assert_eq!(x, vec![pts[20]]);
+    assert_eq!(
+        y,
+        vec![(Rect::point(2.0, 2.0), 20), (Rect::point(0.5, 0.5), 5000)]
+    );
+}
+
+#[test]
+fn journal() {
+    let blink = Blink::new();
+    let mut primary = RTree::new(&blink);
+    let mut replica = RTree::new(&blink);
+    primary.enable_journal();
+    for i in 0..1000u32 {
+        primary.insert(Rect::point(i as f32, 0.0), i);
+    }
+    replica.apply_journal(&primary.take_journal()).unwrap();
+    assert!(primary.take_journal().is_empty());
+
+    primary.remove(Rect::point(5.0, 0.0), &5).unwrap();
+    let area = Rect::new(Point::new(100.0, 0.0), Point::new(199.0, 0.0));
+    primary.retain_in_rect(area, |_, v| {
+        if *v % 3 == 0 {
+            *v += 10_000;
+        }
+        *v % 2 == 0
+    });
+    let journal = primary.take_journal();
+    replica.apply_journal(&journal).unwrap();
+
+    let sorted = |tr: &RTree<u32, &Blink>| {
+        let mut items: Vec<_> = tr.iter().map(|x| (x.rect.min.x as u32, *x.data)).collect();
+        items.sort();
+        items
+    };
+    assert_eq!(replica.len(), 1000 - 1 - 50);
+    assert_eq!(sorted(&primary), sorted(&replica));
+
+    // replaying a removal that already happened fails
+    assert!(replica.apply_journal(&journal).is_err());
+    assert!(replica.apply_journal(&[9]).is_err());
+
+    primary.drain_all();
+    replica.apply_journal(&primary.take_journal()).unwrap();
+    assert_eq!(replica.len(), 0);
+}
+
+#[test]
+fn freeze() {
+    let mut tr = RTree::new(BoxAlloc);
+    for i in 0..2000 {
+        tr.insert(Rect::point(fastrand::f32(), fastrand::f32()), i);
+    }
+    let view = tr.freeze();
+    let area = Rect::new(Point::new(0.25, 0.25), Point::new(0.5, 0.75));
+    let mut expect: Vec<_> = tr.search(area).map(|x| (*x.data, x.key)).collect();
+    expect.sort_by_key(|x| x.0);
+    let all: Vec<_> = tr.iter().map(|x| (x.rect, x.key, x.seq, *x.data)).collect();
+    assert!(view.to_bytes() == tr.freeze_with::<f32>().to_bytes());
+
+    // the tree keeps changing while another thread queries the view
+    let handle = {
+        let view = view.clone();
+        std::thread::spawn(move || {
+            let mut found: Vec<_> = view.search(area).map(|x| (*x.data, x.key)).collect();
+            found.sort_by_key(|x| x.0);
+            found
+        })
+    };
+    let key = all[0].1;
+    *tr.get_mut(key).unwrap() = -1;
+    tr.insert(Rect::point(0.3, 0.3), -2);
+    tr.retain_in_rect(Rect::INFINITY, |_, i| *i % 2 == 0);
+    tr.compact();
+    assert_eq!(handle.join().unwrap(), expect);
+    assert_eq!(view.len(), 2000);
+    let seen: Vec<_> = view
+        .iter()
+        .map(|x| (x.rect, x.key, x.seq, *x.data))
+        .collect();
+    assert_eq!(seen.len(), 2000);
+    assert_eq!(seen, all);
+    tr.retain_in_rect(Rect::INFINITY, |_, _| false);
+    assert_eq!(view.search(Rect::INFINITY).count(), 2000);
+    assert!(tr.freeze().is_empty());
+    assert_eq!(tr.freeze().iter().len(), 0);
+}
+
+#[test]
+fn freeze_shares() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    static CLONES: AtomicUsize = AtomicUsize::new(0);
+    struct Counted(u32);
+    impl Clone for Counted {
+        fn clone(&self) -> Self {
+            CLONES.fetch_add(1, Ordering::Relaxed);
+            Counted(self.0)
+        }
+    }
+    let mut tr = RTree::new(BoxAlloc);
+    for i in 0..5000 {
+        tr.insert(Rect::point(fastrand::f32(), fastrand::f32()), Counted(i));
+    }
+    let mut keys: Vec<_> = tr.iter().map(|x| (x.data.0, x.key)).collect();
+    keys.sort_by_key(|x| x.0);
+    let keys: Vec<_> = keys.into_iter().map(|x| x.1).collect();
+    let view = tr.freeze();
+    assert_eq!(CLONES.load(Ordering::Relaxed), 0);
+    // copies the chunk of values it writes to, and only once
+    tr.get_mut(keys[100]).unwrap().0 = 9999;
+    tr.get_mut(keys[101]).unwrap().0 = 9999;
+    assert!(CLONES.load(Ordering::Relaxed) <= CHUNK);
+    let rect = view.iter().find(|x| x.key == keys[100]).unwrap().rect;
+    assert_eq!(
+        view.search(rect)
+            .find(|x| x.key == keys[100])
+            .unwrap()
+            .data
+            .0,
+        100
+    );
+    assert_eq!(tr.get(keys[100]).unwrap().0, 9999);
+    // values nobody holds on to any more are left alone
+    drop(view);
+    tr.get_mut(keys[102]).unwrap().0 = 9999;
+    assert!(CLONES.load(Ordering::Relaxed) <= CHUNK);
+}
+
+#[test]
+fn bulk_load() {
+    let blink = Blink::new();
+    let points: Vec<_> = (0..20000)
+        .map(|_| Rect::point(fastrand::f32(), fastrand::f32()))
+        .collect();
+    let mut tr = RTree::bulk_load(&blink, points.iter().copied().zip(0..));
+    assert_eq!(tr.len(), points.len());
+    let area = Rect::new(Point::new(0.25, 0.25), Point::new(0.5, 0.75));
+    let mut found: Vec<_> = tr.search(area).map(|x| *x.data).collect();
+    found.sort();
+    let expect: Vec<_> = (0..)
+        .zip(&points)
+        .filter(|(_, p)| p.intersects(&area))
+        .map(|(i, _)| i)
+        .collect();
+    assert_eq!(found, expect);
+    // packs tighter than inserting
+    let mut inserted = RTree::new(&blink);
+    for (i, p) in points.iter().enumerate() {
+        inserted.insert(*p, i as i32);
+    }
+    assert!(tr.quality().nodes < inserted.quality().nodes);
+    // still usable as a dynamic tree
+    tr.insert(Rect::point(0.3, 0.3), -1);
+    assert!(tr.remove(points[0], &0).is_some());
+    assert_eq!(tr.len(), points.len());
+    assert_eq!(RTree::<i32, _>::bulk_load(&blink, []).len(), 0);
+}
+
+#[test]
+fn everything_and_empty() {
+    let all = Rect::EVERYTHING;
+    let none = Rect::<f32>::EMPTY;
+    let r = Rect::new(Point::new(1.0, 2.0), Point::new(3.0, 5.0));
+    assert!(all.intersects(&r) && r.intersects(&all));
+    assert!(!none.intersects(&r) && !none.intersects(&all) && !all.intersects(&none));
+    assert!(none.is_empty() && !all.is_empty() && !r.is_empty());
+    assert_eq!(all.area(), f32::INFINITY);
+    assert_eq!(none.area(), 0.0);
+    // an infinite line has no area rather than NaN
+    let line = Rect::new(
+        Point::new(f32::NEG_INFINITY, 1.0),
+        Point::new(f32::INFINITY, 1.0),
+    );
+    assert_eq!(line.area(), 0.0);
+    assert_eq!(r.unioned_area(&none), r.area());
+    let mut union = Rect::EMPTY;
+    union.expand(&r);
+    assert_eq!(union, r);
+    assert_eq!(Rect::<i32>::EVERYTHING.area(), i64::MAX);
+    assert_eq!(Rect::<i32>::EMPTY.area(), 0);
+
+    let mut tr = RTree::default();
+    for i in 0..1000 {
+        tr.insert(Rect::point(fastrand::f32(), fastrand::f32()), i);
+    }
+    tr.insert(all, -1);
+    tr.insert(line, -2);
+    for i in 1000..2000 {
+        tr.insert(Rect::point(fastrand::f32(), fastrand::f32()), i);
+    }
+    assert_eq!(tr.search(all).count(), 2002);
+    assert_eq!(tr.search(none).count(), 0);
+    let mut found: Vec<_> = tr.search(Rect::point(5.0, 5.0)).map(|x| *x.data).collect();
+    found.sort();
+    assert_eq!(found, [-1]);
+    assert!(tr.remove(all, &-1).is_some());
+    assert_eq!(
+        tr.search(Rect::point(5.0, 1.0))
+            .map(|x| *x.data)
+            .collect::<Vec<_>>(),
+        [-2]
+    );
+
+    let mut tr = RTree::<_, _, i32>::with_scalar(BoxAlloc);
+    for i in 0..1000 {
+        tr.insert(Rect::point(fastrand::i32(..1000), fastrand::i32(..1000)), i);
+        tr.insert(Rect::EVERYTHING, -i);
+    }
+    assert_eq!(tr.search(Rect::EVERYTHING).count(), 2000);
+    assert_eq!(tr.search(Rect::EMPTY).count(), 0);
+}
+
+#[test]
+fn stats() {
+    let empty = RTree::<i32>::default().stats();
+    assert_eq!((empty.nodes, empty.leaves, empty.mean_fill), (0, 0, 0.0));
+    let points: Vec<_> = (0..10000)
+        .map(|i| (Rect::point(fastrand::f32(), fastrand::f32()), i))
+        .collect();
+    let mut inserted = RTree::default();
+    inserted.extend(points.iter().copied());
+    let loaded: RTree<_> = points.iter().copied().collect();
+    for tr in [&inserted, &loaded] {
+        let stats = tr.stats();
+        assert_eq!(stats.height, tr.quality().height);
+        assert_eq!(stats.nodes, tr.quality().nodes);
+        assert_eq!(stats.leaves, tr.occupancy().leaves.iter().sum::<usize>());
+        assert!(stats.min_fill <= stats.mean_fill && stats.mean_fill <= stats.max_fill);
+        assert!(stats.max_fill <= 1.0);
+        assert!(stats.area > 0.0 && stats.area < 2.0);
+    }
+    assert!(loaded.stats().mean_fill > inserted.stats().mean_fill);
+    assert!(loaded.stats().leaves < inserted.stats().leaves);
+}
+
+#[test]
+fn format_tree() {
+    let mut tr = RTree::default();
+    assert_eq!(tr.format_tree(), "");
+    assert_eq!(
+        format!("{:?}", tr),
+        "RTree { len: 0, height: 0, rect: None, .. }"
+    );
+    for i in 0..1000 {
+        tr.insert(Rect::point(fastrand::f32(), fastrand::f32()), i);
+    }
+    let dump = tr.format_tree();
+    let lines: Vec<_> = dump.lines().collect();
+    assert_eq!(lines.len(), tr.quality().nodes);
+    assert!(lines[0].starts_with('['));
+    assert!(lines[0].ends_with(", 1000 items"));
+    let leaves = lines.iter().filter(|l| l.contains("leaf of")).count();
+    assert_eq!(leaves, tr.occupancy().leaves.iter().sum::<usize>());
+    let deepest = lines.iter().map(|l| l.len() - l.trim_start().len()).max();
+    assert_eq!(deepest, Some(tr.quality().height * 2));
+    assert!(format!("{:?}", tr).starts_with("RTree { len: 1000, height: "));
+}
+
+#[test]
+fn clone() {
+    let blink = Blink::new();
+    let mut tr = RTree::new(&blink);
+    for i in 0..1000 {
+        tr.insert(Rect::point(fastrand::f32(), fastrand::f32()), i);
+    }
+    let key = tr.iter().next().map(|x| x.key).unwrap();
+    let value = *tr.get(key).unwrap();
+    let mut fork = tr.clone();
+    assert_eq!(fork.get(key), Some(&value));
+    assert_eq!(fork.quality(), tr.quality());
+    let area = Rect::new(Point::new(0.25, 0.25), Point::new(0.75, 0.75));
+    let inside = tr.search(area).count();
+    *fork.get_mut(key).unwrap() = -1;
+    assert_eq!(fork.drain_in_rect(area).len(), inside);
+    fork.insert(Rect::point(0.5, 0.5), 1000);
+    // the original is untouched
+    assert_eq!(tr.len(), 1000);
+    assert_eq!(tr.search(area).count(), inside);
+    assert_eq!(tr.get(key), Some(&value));
+    assert_eq!(fork.len(), 1001 - inside);
+}
+
+#[test]
+fn chunked() {
+    use crate::chunked::Packed;
+    let points: Vec<_> = (0..20000)
+        .map(|i| (Rect::point(fastrand::f32(), fastrand::f32()), i))
+        .collect();
+    let mut job = RTree::bulk_load_chunked(BoxAlloc, points.iter().copied());
+    let chunks = job.take_chunks();
+    assert!(chunks.len() > 1);
+    let packed: Vec<Packed<_, _>> = std::thread::scope(|s| {
+        let handles: Vec<_> = chunks
+            .into_iter()
+            .map(|c| s.spawn(move || c.run()))
+            .collect();
+        handles
+            .into_iter()
+            .rev()
+            .map(|h| h.join().unwrap())
+            .collect()
+    });
+    let tr = job.finish(packed);
+    let expect = RTree::bulk_load(BoxAlloc, points.iter().copied());
+    assert_eq!(tr.len(), expect.len());
+    assert_eq!(tr.quality(), expect.quality());
+    let area = Rect::new(Point::new(0.25, 0.25), Point::new(0.5, 0.75));
+    assert_eq!(tr.search(area).count(), expect.search(area).count());
+    // rebuilding runs the same way, chunks may also run inline
+    let mut job = tr.rebuild_chunked();
+    let packed: Vec<_> = job.take_chunks().into_iter().map(|c| c.run()).collect();
+    let mut tr = job.finish(packed);
+    assert_eq!(tr.quality(), expect.quality());
+    tr.insert(Rect::point(0.3, 0.3), -1);
+    assert_eq!(tr.len(), points.len() + 1);
+    // joins
+    let near = |a: &Rect, b: &Rect| a.padded(Point::new(0.001, 0.001)).intersects(b);
+    let mut expect: Vec<_> = tr
+        .pairs_within(&tr, near)
+        .into_iter()
+        .map(|(a, b)| (*a.data, *b.data))
+        .collect();
+    expect.sort();
+    let chunks = tr.pairs_within_chunked(&tr, 8, near);
+    assert!(chunks.len() >= 8);
+    let mut pairs: Vec<_> = std::thread::scope(|s| {
+        let handles: Vec<_> = chunks
+            .iter()
+            .map(|c| s.spawn(move || c.run(near)))
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap())
+            .map(|(a, b)| (*a.data, *b.data))
+            .collect()
+    });
+    pairs.sort();
+    assert_eq!(pairs, expect);
+    assert_eq!(
+        RTree::<i32>::bulk_load_chunked(BoxAlloc, [])
+            .finish([])
+            .len(),
+        0
+    );
+}
+
+#[test]
+fn cancel() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    let cancel = AtomicBool::new(false);
+    let points = (0..10000).map(|i| {
+        if i == 5000 {
+            cancel.store(true, Ordering::Relaxed);
+        }
+        (Rect::point(fastrand::f32(), fastrand::f32()), i)
+    });
+    assert_eq!(
+        RTree::try_bulk_load(BoxAlloc, points, &cancel).err(),
+        Some(Cancelled)
+    );
+    let cancel = AtomicBool::new(false);
+    let points = (0..10000).map(|i| (Rect::point(fastrand::f32(), fastrand::f32()), i));
+    let mut tr = RTree::try_bulk_load(BoxAlloc, points, &cancel).unwrap();
+    let area = Rect::new(Point::new(0.25, 0.25), Point::new(0.75, 0.75));
+    let inside = tr.search(area).count();
+    // stops partway through a join
+    let mut tests = 0;
+    let pairs = tr.try_pairs_within(
+        &tr,
+        |a, b| {
+            tests += 1;
+            if tests == 100 {
+                cancel.store(true, Ordering::Relaxed);
+            }
+            a.intersects(b)
+        },
+        &cancel,
+    );
+    assert_eq!(pairs.err(), Some(Cancelled));
+    assert!(tests < 1000);
+    // leaves the tree as it was
+    let before = tr.quality();
+    assert_eq!(tr.try_rebuild(&cancel), Err(Cancelled));
+    assert_eq!(tr.quality(), before);
+    assert_eq!(tr.try_drain_in_rect(area, &cancel).err(), Some(Cancelled));
+    assert_eq!(tr.len(), 10000);
+    assert_eq!(tr.search(area).count(), inside);
+    cancel.store(false, Ordering::Relaxed);
+    assert!(tr.try_rebuild(&cancel).is_ok());
+    assert_eq!(tr.try_drain_in_rect(area, &cancel).unwrap().len(), inside);
+    assert_eq!(tr.len(), 10000 - inside);
+}
+
+#[test]
+fn search_where() {
+    struct Poi {
+        rating: f32,
+    }
+
+    let mut tr = RTree::default();
+    for _ in 0..2000 {
+        let rating = fastrand::f32() * 5.0;
+        tr.insert(
+            Rect::point(fastrand::f32(), fastrand::f32()),
+            Poi { rating },
+        );
+    }
+    // set with items in the tree, and kept up by later inserts
+    tr.set_attribute(|poi| poi.rating);
+    for _ in 0..2000 {
+        let rating = fastrand::f32() * 5.0;
+        tr.insert(
+            Rect::point(fastrand::f32(), fastrand::f32()),
+            Poi { rating },
+        );
+    }
+    let check = |tr: &RTree<Poi>| {
+        let area = Rect::new(Point::new(0.25, 0.25), Point::new(0.75, 0.75));
+        let found = tr.search_where(area, 4.5..).len();
+        let expect = tr.search(area).filter(|x| x.data.rating >= 4.5).count();
+        assert!(expect > 0);
+        assert_eq!(found, expect);
+        let found = tr.search_where(area, 1.0..=2.0).len();
+        let expect = tr
+            .search(area)
+            .filter(|x| (1.0..=2.0).contains(&x.data.rating))
+            .count();
+        assert_eq!(found, expect);
+    };
+    check(&tr);
+    tr.retain(|_, poi| poi.rating < 4.9);
+    check(&tr);
+    tr.rebuild();
+    check(&tr);
+}
+
+#[test]
+fn search_owned() {
+    let mut tr = RTree::new(BoxAlloc);
+    for i in 0..100 {
+        tr.insert(Rect::point(i as f32, 0.0), i.to_string());
+    }
+    let area = Rect::new(Point::new(10.0, 0.0), Point::new(19.0, 0.0));
+    let mut found = tr.search_owned(area);
+    // the results outlive the tree
+    drop(tr);
+    found.sort_by_key(|x| x.0.min.x as u32);
+    assert_eq!(found.len(), 10);
+    assert_eq!(found[0], (Rect::point(10.0, 0.0), "10".to_string()));
+}
+
+#[test]
+fn freeze_deterministic() {
+    // a grid with many equal centers, to exercise the tie breaking
+    let rects: Vec<Rect> = (0..3000)
+        .map(|_| {
+            let (x, y) = (fastrand::u32(0..20) as f32, fastrand::u32(0..20) as f32);
+            Rect::new(Point::new(x, y), Point::new(x + 1.0, y + 1.0))
+        })
+        .collect();
+    let build = |reorder: bool| {
+        let mut tr = RTree::new(BoxAlloc);
+        for (i, rect) in rects.iter().enumerate() {
+            tr.insert(*rect, i as u32);
+        }
+        if reorder {
+            // changes the shape but not the inserts
+            tr.reorder();
+        }
+        let pr = tr.freeze_pr().to_bytes();
+        tr.rebuild();
+        (tr.freeze().to_bytes(), pr)
+    };
+    let (a, b) = (build(false), build(true));
+    assert!(!a.0.is_empty());
+    assert!(a == b);
+}
+
+#[test]
+fn envelope_index() {
+    use crate::frozen::EnvelopeIndex;
+
+    let items: Vec<(Rect, u32)> = (0..20000)
+        .map(|i| (Rect::point(fastrand::f32(), fastrand::f32()), i))
+        .collect();
+    let index = EnvelopeIndex::<f32>::build(items.clone(), 16);
+    assert_eq!(index.len(), items.len());
+    let area = Rect::new(Point::new(0.25, 0.25), Point::new(0.5, 0.75));
+    let mut found: Vec<_> = index.search(area).map(|x| x.1).collect();
+    found.sort();
+    let expect: Vec<_> = items
+        .iter()
+        .filter(|x| x.0.intersects(&area))
+        .map(|x| x.1)
+        .collect();
+    assert_eq!(found, expect);
+    // payloads come from another store
+    let names: Vec<String> = (0..20000).map(|i| format!("item {i}")).collect();
+    let name = index
+        .search_with(items[7].0, |id| &names[id as usize])
+        .find(|x| x.0 == items[7].0)
+        .unwrap()
+        .1;
+    assert_eq!(name, "item 7");
+    assert!(EnvelopeIndex::<f32>::build(Vec::new(), 16).is_empty());
+}
+
+#[cfg(feature = "f16")]
+#[test]
+fn freeze_f16() {
+    use crate::frozen::f16;
+
+    let blink = Blink::new();
+    let mut tr = RTree::new(&blink);
+    for i in 0..2000 {
+        let (x, y) = (
+            fastrand::f32() * 360.0 - 180.0,
+            fastrand::f32() * 180.0 - 90.0,
+        );
+        tr.insert(Rect::point(x, y), i);
+    }
+    let view = tr.freeze_with::<f16>();
+    for item in tr.iter() {
+        // rounded rects cover the originals and stay close to them
+        let found = view
+            .search(item.rect)
+            .find(|x| x.data == item.data)
+            .unwrap();
+        assert!(found.rect.min.x <= item.rect.min.x && found.rect.max.x >= item.rect.max.x);
+        assert!(found.rect.min.y <= item.rect.min.y && found.rect.max.y >= item.rect.max.y);
+        assert!(found.rect.max.x - found.rect.min.x < 0.25);
+    }
+}
+
+#[test]
+fn robust_duplicates() {
+    let blink = Blink::new();
+    let smallest_leaf = |robust| {
+        let mut tr = RTree::new(&blink);
+        tr.set_robust(robust);
+        for i in 0..10_000 {
+            // a cluster of duplicates with some points off by rounding
+            let d = if i % 100 == 0 { 1e-8 } else { 0.0 };
+            tr.insert(Rect::point(0.1 + d, 0.1), i);
+        }
+        if let Some(Node::Parent(root)) = &tr.root {
+            assert_eq!(check_nodes(root, tr.height, true), 10_000);
+        }
+        tr.occupancy().leaves.iter().position(|&n| n > 0).unwrap()
+    };
+    // duplicates used to all move to one side of a split
+    assert_eq!(smallest_leaf(false), MIN_ITEMS);
+    assert!(
+        smallest_leaf(true) >= MAX_ITEMS / 2,
+        "{}",
+        smallest_leaf(true)
+    );
+}
+
+#[test]
+fn nearby_ties() {
+    let blink = Blink::new();
+    let mut tr = RTree::new(&blink);
+    // points on the corners of squares around the origin, so that many
+    // share a distance, with every point inserted three times
+    let mut pts = Vec::new();
+    for r in 1..50 {
+        let r = r as f32;
+        for (x, y) in [(-r, -r), (-r, r), (r, -r), (r, r)] {
+            pts.extend([(x, y); 3]);
+        }
+    }
+    fastrand::shuffle(&mut pts);
+    for (i, &(x, y)) in pts.iter().enumerate() {
+        tr.insert(Rect::point(x, y), i);
+    }
+    let target = Rect::point(0.0, 0.0);
+    let found: Vec<_> = tr
+        .nearby(|rect, _| rect.box_dist(&target))
+        .map(|x| *x.data)
+        .collect();
+    let mut expect: Vec<_> = (0..pts.len()).collect();
+    expect.sort_by(|&a, &b| {
+        let (pa, pb) = (pts[a], pts[b]);
+        let (da, db) = (pa.0 * pa.0 + pa.1 * pa.1, pb.0 * pb.0 + pb.1 * pb.1);
+        da.total_cmp(&db)
+            .then(pa.0.total_cmp(&pb.0))
+            .then(pa.1.total_cmp(&pb.1))
+            .then(a.cmp(&b))
+    });
+    assert_eq!(found, expect);
+}
+
+#[test]
+fn knn_per_category() {
+    let blink = Blink::new();
+    let mut tr = RTree::new(&blink);
+    for i in 0..5000 {
+        tr.insert(Rect::point(fastrand::f32(), fastrand::f32()), i);
+    }
+    let point = Point::new(0.5, 0.5);
+    let target = Rect::point(0.5, 0.5);
+    // categories by remainder, 3 is never asked for and 7 doesn't exist
+    let categories = [0, 1, 2, 7];
+    let results = tr.knn_per_category(point, 5, &categories, |v| v % 4);
+    assert_eq!(results.len(), 4);
+    for (c, found) in categories.iter().zip(&results) {
+        let expect: Vec<_> = tr
+            .nearby(|rect, _| rect.box_dist(&target))
+            .filter(|x| x.data % 4 == *c)
+            .take(5)
+            .map(|x| *x.data)
+            .collect();
+        let found: Vec<_> = found.iter().map(|x| *x.data).collect();
+        assert_eq!(found, expect);
+    }
+    assert!(results[3].is_empty());
+}
+
+#[test]
+fn refine() {
+    let mut tr = RTree::default();
+    for i in 0..5000 {
+        tr.insert(Rect::point(fastrand::f32(), fastrand::f32()), i);
+    }
+    let area = Rect::new(Point::new(0.25, 0.25), Point::new(0.5, 0.75));
+    let levels: Vec<_> = tr.refine(area).collect();
+    assert_eq!(levels.len(), tr.quality().height + 1);
+    // every level covers the result, with fewer parts than the next
+    let mut expect: Vec<_> = tr.search(area).map(|x| *x.data).collect();
+    for pair in levels.windows(2) {
+        assert!(pair[0].len() <= pair[1].len());
+    }
+    for level in &levels[..levels.len() - 1] {
+        let total: usize = level
+            .iter()
+            .map(|part| match part {
+                Refinement::Node { rect, count } => {
+                    assert!(rect.intersects(&area));
+                    *count
+                }
+                Refinement::Item(_) => panic!("item above the leaves"),
+            })
+            .sum();
+        assert!(total >= expect.len());
+    }
+    let mut found: Vec<_> = levels
+        .last()
+        .unwrap()
+        .iter()
+        .map(|part| match part {
+            Refinement::Item(item) => *item.data,
+            Refinement::Node { .. } => panic!("node in the last level"),
+        })
+        .collect();
+    found.sort();
+    expect.sort();
+    assert_eq!(found, expect);
+}
+
+#[test]
+fn collect() {
+    let pts = |range: std::ops::Range<i32>| range.map(|i| (Rect::point(i as f32, i as f32), i));
+    let mut tr: RTree<i32> = pts(0..1000).collect();
+    assert_eq!(tr.len(), 1000);
+    tr.extend(pts(1000..1500));
+    assert_eq!(tr.len(), 1500);
+    let area = Rect::new(Point::new(998.0, 998.0), Point::new(1001.0, 1001.0));
+    let mut found: Vec<_> = tr.search(area).map(|x| *x.data).collect();
+    found.sort();
+    assert_eq!(found, vec![998, 999, 1000, 1001]);
+
+    // and into a tree with an arena
+    let blink = Blink::new();
+    let mut tr = RTree::new(&blink);
+    tr.extend(pts(0..100));
+    assert_eq!(tr.len(), 100);
+}
+
+#[test]
+fn owned() {
+    struct Index {
+        tree: RTree<String>,
+    }
+    fn build() -> Index {
+        let mut tree = RTree::default();
+        for i in 0..1000 {
+            tree.insert(Rect::point(i as f32, i as f32), i.to_string());
+        }
+        Index { tree }
+    }
+    let index = build();
+    let area = Rect::new(Point::new(10.0, 10.0), Point::new(12.0, 12.0));
+    assert_eq!(index.tree.search(area).count(), 3);
+    let moved = std::thread::spawn(move || index.tree.len()).join().unwrap();
+    assert_eq!(moved, 1000);
+}
+
+#[test]
+fn nearest() {
+    let mut tr = RTree::default();
+    assert!(tr.nearest(Point::new(0.0, 0.0)).is_none());
+    for i in 0..1000 {
+        tr.insert(Rect::point(i as f32, 0.0), i);
+    }
+    let found = tr.nearest(Point::new(10.2, 5.0)).unwrap();
+    assert_eq!(*found.data, 10);
+    let found: Vec<_> = tr
+        .k_nearest(Point::new(10.2, 0.0), 3)
+        .iter()
+        .map(|x| *x.data)
+        .collect();
+    assert_eq!(found, vec![10, 11, 9]);
+    assert_eq!(tr.k_nearest(Point::new(0.0, 0.0), 5000).len(), 1000);
+}
+
+#[test]
+fn knn_distinct() {
+    let blink = Blink::new();
+    let mut tr = RTree::new(&blink);
+    for i in 0..5000 {
+        tr.insert(Rect::point(fastrand::f32(), fastrand::f32()), i);
+    }
+    let target = Rect::point(0.5, 0.5);
+    let found: Vec<_> = tr
+        .knn_distinct(Point::new(0.5, 0.5), 10, |v| v % 7)
+        .iter()
+        .map(|x| *x.data)
+        .collect();
+    // the nearest of each of the 7 keys, as there are fewer than 10
+    let mut expect: Vec<i32> = Vec::new();
+    for item in tr.nearby(|rect, _| rect.box_dist(&target)) {
+        if !expect.iter().any(|v| v % 7 == item.data % 7) {
+            expect.push(*item.data);
+            if expect.len() == 7 {
+                break;
+            }
+        }
+    }
+    assert_eq!(found, expect);
+    assert_eq!(found.len(), 7);
+    assert_eq!(tr.knn_distinct(Point::new(0.5, 0.5), 3, |v| v % 7).len(), 3);
+}
+
+#[test]
+fn tune() {
+    let blink = Blink::new();
+    let mut tr = RTree::new(&blink);
+    for i in 0..5000 {
+        tr.insert(Rect::point(fastrand::f32(), fastrand::f32()), i);
+    }
+    let queries: Vec<_> = (0..100)
+        .map(|_| {
+            let (x, y) = (fastrand::f32(), fastrand::f32());
+            Rect::new(Point::new(x, y), Point::new(x + 0.01, y + 0.01))
+        })
+        .collect();
+    let expect: Vec<Vec<_>> = queries
+        .iter()
+        .map(|q| {
+            let mut found: Vec<_> = tr.search(*q).map(|x| *x.data).collect();
+            found.sort();
+            found
+        })
+        .collect();
+    let report = tr.tune(&queries, true);
+    assert_eq!(report.len(), 8);
+    assert!(report.windows(2).all(|w| w[0].1 <= w[1].1));
+    assert_eq!(tr.config(), report[0].0);
+    assert_eq!(tr.len(), 5000);
+    if let Some(Node::Parent(root)) = &tr.root {
+        assert_eq!(check_nodes(root, tr.height, true), 5000);
+    }
+    for (q, expect) in queries.iter().zip(expect) {
+        let mut found: Vec<_> = tr.search(*q).map(|x| *x.data).collect();
+        found.sort();
+        assert_eq!(found, expect);
+    }
+}
+
+#[test]
+fn overlay() {
+    use crate::overlay::Overlay;
+
+    let blink = Blink::new();
+    let mut ov = Overlay::new(&blink);
+    for i in 0..1000 {
+        ov.insert(Rect::point(i as f32, 0.0), i);
+    }
+    for i in 1000..1010 {
+        ov.insert_hot(Rect::point((i - 1000) as f32 + 0.5, 0.0), i);
+    }
+    assert!(ov.promote(Rect::point(3.0, 0.0), &3));
+    assert!(!ov.promote(Rect::point(3.0, 0.0), &3));
+    assert_eq!((ov.base().len(), ov.hot().len(), ov.len()), (999, 11, 1010));
+
+    let area = Rect::new(Point::new(2.0, 0.0), Point::new(4.0, 0.0));
+    let mut found: Vec<_> = ov.search(area).map(|x| *x.data).collect();
+    found.sort();
+    assert_eq!(found, vec![2, 3, 4, 1002, 1003]);
+
+    let target = Rect::point(0.0, 0.0);
+    let found: Vec<_> = ov
+        .nearby(|rect, _| rect.box_dist(&target))
+        .take(8)
+        .map(|x| *x.data)
+        .collect();
+    assert_eq!(found, vec![0, 1000, 1, 1001, 2, 1002, 3, 1003]);
+
+    assert!(ov.demote(Rect::point(3.0, 0.0), &3));
+    assert_eq!(ov.remove(Rect::point(3.0, 0.0), &3).unwrap().item, 3);
+    assert_eq!(ov.len(), 1009);
+
+    let pts: Vec<_> = (0..5000)
+        .map(|i| (Rect::point(fastrand::f32(), fastrand::f32()), i))
+        .collect();
+    let mut ov = Overlay::bulk_load(BoxAlloc, pts.iter().copied());
+    let mut hot = Rect::point(0.5, 0.5);
+    ov.insert_hot(hot, -1);
+    for _ in 0..100 {
+        let next = Rect::point(fastrand::f32(), fastrand::f32());
+        assert!(ov.update_hot(hot, next, &-1));
+        hot = next;
+    }
+    assert!(!ov.update_hot(pts[0].0, hot, &pts[0].1));
+    assert_eq!(ov.search(hot).filter(|x| *x.data == -1).count(), 1);
+    for &(rect, i) in &pts[..2500] {
+        assert!(ov.promote(rect, &i));
+    }
+    for i in 0..2500 {
+        ov.insert(Rect::point(fastrand::f32(), fastrand::f32()), 5000 + i);
+    }
+    let (before, after) = ov.repack_base();
+    assert!(after.nodes < before.nodes);
+    assert_eq!(ov.base().len(), 5000);
+}
+
+#[test]
+fn lsm() {
+    use crate::lsm::Lsm;
+
+    let blink = Blink::new();
+    let mut lsm = Lsm::new(&blink, 64);
+    let mut model = Vec::new();
+    let mut merging: Option<std::thread::JoinHandle<_>> = None;
+    for i in 0..5000 {
+        let rect = Rect::point(fastrand::f32(), fastrand::f32());
+        lsm.insert(rect, i);
+        model.push((rect, i));
+        if i % 3 == 0 {
+            let (rect, v) = model.swap_remove(fastrand::usize(..model.len()));
+            assert!(lsm.remove(rect, &v));
+            assert!(!lsm.remove(rect, &v));
+        }
+        if i % 1000 == 999 {
+            // inserts leave the merging to the caller
+            assert!(lsm.levels() > 4, "{}", lsm.levels());
+            // which merges on another thread while the index keeps changing
+            if let Some(handle) = merging.take() {
+                assert!(lsm.finish_merge(handle.join().unwrap()));
+            }
+            merging = lsm
+                .merge_job()
+                .map(|job| std::thread::spawn(move || job.run()));
+        }
+    }
+    if let Some(handle) = merging {
+        assert!(lsm.finish_merge(handle.join().unwrap()));
+    }
+    assert_eq!(lsm.len(), model.len());
+    let area = Rect::new(Point::new(0.2, 0.2), Point::new(0.6, 0.5));
+    let mut found: Vec<_> = lsm.search(area).map(|x| *x.data).collect();
+    found.sort();
+    let mut expect: Vec<_> = model
+        .iter()
+        .filter(|x| x.0.intersects(&area))
+        .map(|x| x.1)
+        .collect();
+    expect.sort();
+    assert_eq!(found, expect);
+
+    lsm.flush();
+    assert!(lsm.levels() <= 4, "{}", lsm.levels());
+    assert_eq!(lsm.len(), model.len());
+    assert_eq!(lsm.search(Rect::INFINITY).count(), model.len());
+
+    // a merge of levels merged in the meantime is dropped
+    for i in 0..64 {
+        lsm.insert(Rect::point(2.0, 2.0), 5000 + i);
+    }
+    let job = lsm.merge_job().unwrap();
+    lsm.flush();
+    assert!(!lsm.finish_merge(job.run()));
+    assert_eq!(lsm.len(), model.len() + 64);
+    assert_eq!(lsm.search(Rect::INFINITY).count(), model.len() + 64);
+}
+
+#[test]
+fn filter() {
+    let blink = Blink::new();
+    let mut tr = RTree::new(&blink);
+    for i in 0..1000 {
+        tr.insert(
+            Rect::point((i * 7 % 1000) as f32, (i * 13 % 1000) as f32),
+            i,
+        );
+    }
+    tr.enable_filter(1.0, 1 << 16);
+    tr.insert(
+        Rect::new(Point::new(2000.0, 0.0), Point::new(2004.5, 3.0)),
+        1000,
+    );
+    let mut skipped = 0;
+    for _ in 0..1000 {
+        let p = Point::new(fastrand::f32() * 3000.0, fastrand::f32() * 1000.0);
+        let expect = tr.search(Rect::point(p.x, p.y)).next().is_some();
+        assert_eq!(tr.contains_point(p), expect);
+        skipped += !tr.filter.as_ref().unwrap().may_contain(p) as usize;
+    }
+    assert!(skipped > 900, "{skipped}");
+    assert!(tr.contains_point(Point::new(7.0, 13.0)));
+    assert!(tr.contains_point(Point::new(2003.0, 1.0)));
+
+    // a huge rect saturates the filter, answers stay correct
+    tr.insert(
+        Rect::new(Point::new(-1e6, -1e6), Point::new(1e6, 1e6)),
+        1001,
+    );
+    assert!(tr.contains_point(Point::new(0.5, 0.5)));
+}
+
+fn check_circles<T, A: Alloc<T>>(parent: &Parent<T, A>, circles: &mut Vec<Circle>) {
+    circles.push(parent.nodes.circle.unwrap());
+    for node in parent.nodes.iter() {
+        match node {
+            Node::Item(entry) => {
+                let r = entry.rect;
+                for c in &circles[..] {
+                    for p in [r.min, r.max, Point::new(r.min.x, r.max.y)] {
+                        assert_eq!(c.dist(p), 0.0);
+                    }
+                }
+            }
+            Node::Parent(child) => check_circles(child, circles),
+        }
+    }
+    circles.pop();
+}
+
+#[test]
+fn knn_circles() {
+    let blink = Blink::new();
+    let mut tr = RTree::new(&blink);
+    let mut rects = Vec::new();
+    for i in 0..5000 {
+        // items along the diagonal
+        let t = fastrand::f32() * 100.0;
+        let x = t + fastrand::f32();
+        let y = t + fastrand::f32();
+        let rect = Rect::new(Point::new(x, y), Point::new(x + 0.1, y + 0.1));
+        tr.insert(rect, i);
+        rects.push(rect);
+    }
+    for i in (0..5000).step_by(5) {
+        assert!(tr.remove(rects[i], &i).is_some());
+    }
+    // circles aren't kept until they're used
+    if let Some(Node::Parent(root)) = &tr.root {
+        assert!(root.nodes.circle.is_none());
+    }
+    let points: Vec<Point> = (0..200)
+        .map(|_| Point::new(fastrand::f32() * 120.0, fastrand::f32() * 120.0))
+        .collect();
+    let dists = |tr: &RTree<usize, &Blink>| -> Vec<Vec<f32>> {
+        let found = tr.knn_batch(&points, 5);
+        found
+            .iter()
+            .map(|items| items.iter().map(|x| x.dist).collect())
+            .collect()
+    };
+    let expect = dists(&tr);
+    tr.set_config(Config {
+        circles: true,
+        ..tr.config()
+    });
+    assert_eq!(dists(&tr), expect);
+    if let Some(Node::Parent(root)) = &tr.root {
+        check_circles(root, &mut Vec::new());
+    }
+    // and are then kept up to date
+    for i in (1..5000).step_by(5) {
+        assert!(tr.remove(rects[i], &i).is_some());
+    }
+    for i in 5000..6000 {
+        let t = fastrand::f32() * 100.0;
+        tr.insert(Rect::new(Point::new(t, t), Point::new(t + 0.1, t + 0.1)), i);
+    }
+    tr.rebuild_in_rect(Rect::new(Point::new(0.0, 0.0), Point::new(10.0, 10.0)));
+    if let Some(Node::Parent(root)) = &tr.root {
+        check_circles(root, &mut Vec::new());
+    }
+    tr.set_config(Config {
+        circles: false,
+        ..tr.config()
+    });
+    if let Some(Node::Parent(root)) = &tr.root {
+        assert!(root.nodes.circle.is_none());
+    }
+}
+
+#[test]
+fn axis_range() {
+    let blink = Blink::new();
+    let mut tr = RTree::new(&blink);
+    for i in 0..2000 {
+        let x = fastrand::f32() * 360.0 - 180.0;
+        let y = fastrand::f32() * 180.0 - 90.0;
+        tr.insert(Rect::new(Point::new(x, y), Point::new(x + 1.0, y + 1.0)), i);
+    }
+    let collect = |it: SearchIterator<usize, &Blink>| {
+        let mut found: Vec<usize> = it.map(|x| *x.data).collect();
+        found.sort();
+        found
+    };
+    let expect: Vec<usize> = tr
+        .iter()
+        .filter(|x| x.rect.max.x >= -10.0 && x.rect.min.x <= 20.0)
+        .map(|x| *x.data)
+        .collect();
+    assert!(!expect.is_empty());
+    assert_eq!(collect(tr.search_x_range(-10.0, 20.0)), sorted(expect));
+    let expect: Vec<usize> = tr
+        .iter()
+        .filter(|x| x.rect.max.y >= 45.0 && x.rect.min.y <= 45.0)
+        .map(|x| *x.data)
+        .collect();
+    assert!(!expect.is_empty());
+    assert_eq!(collect(tr.search_y_range(45.0, 45.0)), sorted(expect));
+}
+
+fn sorted(mut v: Vec<usize>) -> Vec<usize> {
+    v.sort();
+    v
+}
+
+#[test]
+fn knn_rect() {
+    let a = Rect::new(Point::new(0.0, 0.0), Point::new(2.0, 2.0));
+    let b = Rect::new(Point::new(1.0, 1.0), Point::new(3.0, 3.0));
+    assert_eq!(a.box_dist(&b), 0.0);
+    assert_eq!(a.box_dist(&Rect::point(5.0, 6.0)), 25.0);
+
+    let blink = Blink::new();
+    let mut tr = RTree::new(&blink);
+    for i in 0..5000 {
+        let x = fastrand::f32() * 100.0;
+        let y = fastrand::f32() * 100.0;
+        let (w, h) = (fastrand::f32() * 2.0, fastrand::f32() * 2.0);
+        tr.insert(Rect::new(Point::new(x, y), Point::new(x + w, y + h)), i);
+    }
+    for _ in 0..100 {
+        let x = fastrand::f32() * 120.0 - 10.0;
+        let y = fastrand::f32() * 120.0 - 10.0;
+        let query = Rect::new(Point::new(x, y), Point::new(x + 5.0, y + 1.0));
+        let found: Vec<f32> = tr.knn_rect(query, 10).iter().map(|x| x.dist).collect();
+        let expect: Vec<f32> = tr
+            .nearby(|rect, _| rect.box_dist(&query))
+            .take(10)
+            .map(|x| x.dist)
+            .collect();
+        assert_eq!(found, expect);
+    }
+}
+
+#[test]
+fn knn_metric() {
+    let blink = Blink::new();
+    let mut tr = RTree::new(&blink);
+    for i in 0..3000 {
+        let x = fastrand::f32() * 100.0;
+        let y = fastrand::f32() * 100.0;
+        tr.insert(Rect::new(Point::new(x, y), Point::new(x + 0.5, y + 0.2)), i);
+    }
+    let points: Vec<Point> = (0..100)
+        .map(|_| Point::new(fastrand::f32() * 100.0, fastrand::f32() * 100.0))
+        .collect();
+    fn check<M: Metric>(tr: &RTree<usize, &Blink>, points: &[Point], metric: M) {
+        let results = tr.knn_batch_with(points, 8, &metric);
+        for (p, found) in points.iter().zip(results) {
+            let mut expect: Vec<f32> = tr.iter().map(|x| metric.dist(*p, &x.rect)).collect();
+            expect.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            expect.truncate(8);
+            let found: Vec<f32> = found.iter().map(|x| x.dist).collect();
+            assert_eq!(found, expect);
+        }
+    }
+    for circles in [false, true] {
+        tr.set_config(Config {
+            circles,
+            ..tr.config()
+        });
+        check(&tr, &points, Euclidean);
+        check(&tr, &points, Manhattan);
+        check(&tr, &points, Chebyshev);
+    }
+}
+
+#[test]
+fn half_open() {
+    let blink = Blink::new();
+    let mut tr = RTree::new(&blink);
+    for x in 0..10 {
+        for y in 0..10 {
+            let min = Point::new(x as f32, y as f32);
+            let max = Point::new(x as f32 + 1.0, y as f32 + 1.0);
+            tr.insert(Rect::new(min, max), (x, y));
+        }
+    }
+    let at = |tr: &RTree<(i32, i32), &Blink>, x, y| -> Vec<(i32, i32)> {
+        let mut found: Vec<_> = tr.search(Rect::point(x, y)).map(|x| *x.data).collect();
+        found.sort();
+        found
+    };
+    assert_eq!(at(&tr, 3.0, 4.0).len(), 4);
+    tr.set_config(Config {
+        half_open: true,
+        ..tr.config()
+    });
+    assert_eq!(at(&tr, 3.0, 4.0), vec![(3, 4)]);
+    assert_eq!(at(&tr, 3.5, 4.0), vec![(3, 4)]);
+    assert_eq!(at(&tr, 0.0, 0.0), vec![(0, 0)]);
+    assert_eq!(at(&tr, 10.0, 5.0), vec![]);
+    let found = tr.search(Rect::new(Point::new(2.0, 2.0), Point::new(4.0, 4.0)));
+    assert_eq!(found.count(), 9);
+}
+
+#[cfg(feature = "bevy")]
+#[test]
+fn bevy_index() {
+    use crate::bevy::{SpatialIndex, SpatialIndexPlugin};
+    use ::bevy::app::App;
+    use ::bevy::math::Vec3A;
+    use ::bevy::render::primitives::Aabb;
+    use ::bevy::transform::components::Transform;
+    use ::bevy::transform::TransformPlugin;
+
+    let mut app = App::new();
+    app.add_plugins((TransformPlugin, SpatialIndexPlugin));
+    let aabb = Aabb {
+        center: Vec3A::ZERO,
+        half_extents: Vec3A::new(1.0, 2.0, 0.0),
+    };
+    let a = app
+        .world_mut()
+        .spawn((Transform::from_xyz(10.0, 10.0, 0.0), aabb))
+        .id();
+    let b = app
+        .world_mut()
+        .spawn((Transform::from_xyz(-5.0, 0.0, 0.0), aabb))
+        .id();
+    app.update();
+    let index = app.world().resource::<SpatialIndex>();
+    assert_eq!(index.len(), 2);
+    let rect = Rect::new(Point::new(9.0, 8.0), Point::new(11.0, 12.0));
+    assert_eq!(index.get(a), Some(rect));
+    assert_eq!(index.nearest(Point::new(0.0, 0.0), 1), vec![b]);
+
+    app.world_mut()
+        .entity_mut(a)
+        .get_mut::<Transform>()
+        .unwrap()
+        .translation
+        .x = -20.0;
+    app.world_mut().despawn(b);
+    app.update();
+    let index = app.world().resource::<SpatialIndex>();
+    assert_eq!(index.len(), 1);
+    assert_eq!(index.nearest(Point::new(0.0, 0.0), 5), vec![a]);
+    assert_eq!(index.search(rect).count(), 0);
+}
+
+#[test]
+fn budgeted() {
+    let blink = Blink::new();
+    let mut tr = RTree::new(&blink);
+    for i in 0..5000 {
+        tr.insert(Rect::point(fastrand::f32(), fastrand::f32()), i);
+    }
+    let rect = Rect::new(Point::new(0.2, 0.2), Point::new(0.6, 0.6));
+    let all = tr.search(rect).count();
+    let found = tr.search_budgeted(rect, usize::MAX);
+    assert!(found.exact);
+    assert_eq!(found.items.len(), all);
+    let found = tr.search_budgeted(rect, 3);
+    assert!(!found.exact);
+    assert!(found.items.len() < all);
+    assert!(found.items.iter().all(|x| x.rect.intersects(&rect)));
+
+    let point = Point::new(0.5, 0.5);
+    let expect: Vec<f32> = tr.knn_batch(&[point], 10)[0]
+        .iter()
+        .map(|x| x.dist)
+        .collect();
+    let found = tr.knn_budgeted(point, 10, usize::MAX);
+    assert!(found.exact);
+    assert_eq!(
+        found.items.iter().map(|x| x.dist).collect::<Vec<_>>(),
+        expect
+    );
+    let found = tr.knn_budgeted(point, 10, 2);
+    assert!(!found.exact);
+    assert!(found.items.windows(2).all(|w| w[0].dist <= w[1].dist));
+}
+
+#[test]
+fn for_each_in_rect() {
+    let blink = Blink::new();
+    let mut tr = RTree::new(&blink);
+    for i in 0..3000 {
+        tr.insert(Rect::point(fastrand::f32(), fastrand::f32()), i);
+    }
+    let rect = Rect::new(Point::new(0.1, 0.3), Point::new(0.4, 0.9));
+    let mut expect: Vec<usize> = tr.search(rect).map(|x| *x.data).collect();
+    expect.sort();
+    let mut found = Vec::new();
+    tr.for_each_in_rect(rect, |r, &i| {
+        assert!(r.intersects(&rect));
+        found.push(i);
+    });
+    found.sort();
+    assert_eq!(found, expect);
+    tr.for_each_in_rect_mut(rect, |_, i| *i += 10_000);
+    let mut found: Vec<usize> = tr.search(rect).map(|x| *x.data - 10_000).collect();
+    found.sort();
+    assert_eq!(found, expect);
+}
+
+#[test]
+fn for_each_with_ancestors() {
+    let mut tr = RTree::new(BoxAlloc);
+    for i in 0..3000 {
+        tr.insert(Rect::point(fastrand::f32(), fastrand::f32()), i);
+    }
+    let rect = Rect::new(Point::new(0.1, 0.3), Point::new(0.4, 0.9));
+    let mut expect: Vec<usize> = tr.search(rect).map(|x| *x.data).collect();
+    expect.sort();
+    let mut found = Vec::new();
+    tr.for_each_with_ancestors(rect, |path, r, &i| {
+        assert_eq!(path.len(), tr.height + 1);
+        assert_eq!(path[0], tr.rect().unwrap());
+        // every node covers the ones below it and the item
+        for w in path.windows(2) {
+            assert!(w[0].contains(&w[1]));
+        }
+        assert!(path[path.len() - 1].contains(r));
+        found.push(i);
+    });
+    found.sort();
+    assert_eq!(found, expect);
+}
+
+#[test]
+fn scan_rev() {
+    let blink = Blink::new();
+    let mut tr = RTree::new(&blink);
+    for i in 0..2000 {
+        tr.insert(Rect::point(fastrand::f32(), fastrand::f32()), i);
+    }
+    let fwd: Vec<usize> = tr.iter().map(|x| *x.data).collect();
+    let mut rev: Vec<usize> = tr.iter().rev().map(|x| *x.data).collect();
+    rev.reverse();
+    assert_eq!(fwd, rev);
+    // meeting in the middle
+    let mut it = tr.iter();
+    let mut front = Vec::new();
+    let mut back = Vec::new();
+    loop {
+        match (it.next(), it.next_back()) {
+            (Some(a), Some(b)) => {
+                front.push(*a.data);
+                back.push(*b.data);
+            }
+            (Some(a), None) => front.push(*a.data),
+            (None, _) => break,
+        }
+        assert_eq!(it.len(), 2000 - front.len() - back.len());
+    }
+    back.reverse();
+    front.extend(back);
+    assert_eq!(front, fwd);
+}
+
+#[cfg(feature = "osmpbf")]
+#[test]
+fn osm_pbf() {
+    use crate::osmpbf::Kind;
+    use flate2::write::ZlibEncoder;
+
+    fn varint(out: &mut Vec<u8>, mut v: u64) {
+        while v >= 0x80 {
+            out.push(v as u8 | 0x80);
+            v >>= 7;
+        }
+        out.push(v as u8);
+    }
+    fn bytes(out: &mut Vec<u8>, tag: u64, b: &[u8]) {
+        varint(out, tag << 3 | 2);
+        varint(out, b.len() as u64);
+        out.extend(b);
+    }
+    fn packed(out: &mut Vec<u8>, tag: u64, vals: &[u64]) {
+        let mut b = Vec::new();
+        for &v in vals {
+            varint(&mut b, v);
+        }
+        bytes(out, tag, &b);
+    }
+    fn zz(v: i64) -> u64 {
+        ((v << 1) ^ (v >> 63)) as u64
+    }
+    fn blob(out: &mut Vec<u8>, kind: &str, data: &[u8], zlib: bool) {
+        let mut blob = Vec::new();
+        if zlib {
+            let mut enc = ZlibEncoder::new(Vec::new(), Default::default());
+            enc.write_all(data).unwrap();
+            bytes(&mut blob, 3, &enc.finish().unwrap());
+        } else {
+            bytes(&mut blob, 1, data);
+        }
+        let mut header = Vec::new();
+        bytes(&mut header, 1, kind.as_bytes());
+        varint(&mut header, 3 << 3);
+        varint(&mut header, blob.len() as u64);
+        out.extend((header.len() as u32).to_be_bytes());
+        out.extend(header);
+        out.extend(blob);
+    }
+
+    let mut table = Vec::new();
+    for s in ["", "amenity", "cafe", "highway", "residential"] {
+        bytes(&mut table, 1, s.as_bytes());
+    }
+    // three dense nodes, the second one a cafe
+    let mut dense = Vec::new();
+    packed(&mut dense, 1, &[zz(1), zz(1), zz(1)]);
+    packed(&mut dense, 8, &[zz(100_000), zz(10_000), zz(10_000)]);
+    packed(&mut dense, 9, &[zz(200_000), zz(-10_000), zz(-10_000)]);
+    packed(&mut dense, 10, &[0, 1, 2, 0, 0]);
+    let mut nodes = Vec::new();
+    bytes(&mut nodes, 2, &dense);
+    // a residential street through nodes 1 and 3
+    let mut way = Vec::new();
+    varint(&mut way, 1 << 3);
+    varint(&mut way, 7);
+    packed(&mut way, 2, &[3]);
+    packed(&mut way, 3, &[4]);
+    packed(&mut way, 8, &[zz(1), zz(2)]);
+    let mut ways = Vec::new();
+    bytes(&mut ways, 3, &way);
+    let mut block = Vec::new();
+    bytes(&mut block, 1, &table);
+    bytes(&mut block, 2, &nodes);
+    bytes(&mut block, 2, &ways);
+
+    let mut file = Vec::new();
+    blob(&mut file, "OSMHeader", &[], false);
+    blob(&mut file, "OSMData", &block, true);
+
+    let mut tr = RTree::new(BoxAlloc);
+    tr.load_osm_pbf(&file[..], true, |e| {
+        let tagged = e.tag("amenity") == Some("cafe") || e.tag("highway").is_some();
+        tagged.then_some((e.kind, e.id))
+    })
+    .unwrap();
+    assert_eq!(tr.len(), 2);
+    let mut items: Vec<_> = tr.iter().map(|x| (*x.data, x.rect)).collect();
+    items.sort_by_key(|x| x.0 .1);
+    assert_eq!(items[0].0, (Kind::Node, 2));
+    assert!((items[0].1.min.x - 0.019).abs() < 1e-6);
+    assert!((items[0].1.min.y - 0.011).abs() < 1e-6);
+    assert_eq!(items[1].0, (Kind::Way, 7));
+    assert!((items[1].1.min.x - 0.018).abs() < 1e-6);
+    assert!((items[1].1.max.x - 0.02).abs() < 1e-6);
+
+    let mut tr = RTree::new(BoxAlloc);
+    tr.load_osm_pbf(&file[..], false, |e| Some(e.id)).unwrap();
+    assert_eq!(tr.len(), 3);
+    assert!(tr
+        .load_osm_pbf(&file[..file.len() - 1], false, |e| Some(e.id))
+        .is_err());
+}
+
+#[cfg(feature = "s2")]
+#[test]
+fn s2_cells() {
+    use crate::cells::s2_cell_rects;
+
+    let near = |a: f32, b: f32| (a - b).abs() < 0.01;
+    // face 0 is centered on (0, 0)
+    let face = s2_cell_rects(1 << 60).unwrap();
+    assert_eq!(face.len(), 1);
+    assert!(near(face[0].min.x, -45.0) && near(face[0].max.x, 45.0));
+    assert!(near(face[0].min.y, -45.0) && near(face[0].max.y, 45.0));
+    // face 2 holds the north pole
+    let face = s2_cell_rects(2 << 61 | 1 << 60).unwrap();
+    assert_eq!(face[0].min.x, -180.0);
+    assert_eq!(face[0].max.y, 90.0);
+    assert!(near(face[0].min.y, 35.26));
+    // face 3 is centered on the antimeridian
+    let face = s2_cell_rects(3 << 61 | 1 << 60).unwrap();
+    assert_eq!(face.len(), 2);
+    // a level 10 cell over Manhattan
+    let rects = s2_cell_rects(0x89c2590000000000).unwrap();
+    assert_eq!(rects.len(), 1);
+    assert!(rects[0].intersects(&Rect::point(-73.99, 40.73)));
+    assert!(rects[0].max.x - rects[0].min.x < 0.2);
+    assert!(s2_cell_rects(0).is_err());
+    assert!(s2_cell_rects(7 << 61 | 1 << 60).is_err());
+
+    let mut tr = RTree::new(BoxAlloc);
+    for i in 0..1000 {
+        let x = fastrand::f32() * 360.0 - 180.0;
+        let y = fastrand::f32() * 180.0 - 90.0;
+        tr.insert(Rect::point(x, y), i);
+    }
+    // the four children of face 0 cover it exactly once
+    let children: Vec<u64> = (0..4).map(|pos| pos << 59 | 1 << 58).collect();
+    let found = tr.search_s2_cells(&children).unwrap();
+    let expect = tr.search_s2_cells(&[1 << 60]).unwrap();
+    assert_eq!(found.len(), expect.len());
+}
+
+#[cfg(feature = "h3")]
+#[test]
+fn h3_cells() {
+    use crate::cells::h3_cell_rects;
+
+    let rects = h3_cell_rects(0x8a1fb46622dffff).unwrap();
+    assert_eq!(rects.len(), 1);
+    assert!(rects[0].intersects(&Rect::point(2.3733, 48.8541)));
+    assert!(rects[0].max.x - rects[0].min.x < 0.01);
+    assert!(h3_cell_rects(0).is_err());
+
+    let mut tr = RTree::new(BoxAlloc);
+    tr.insert(Rect::point(2.3733, 48.8541), 1);
+    tr.insert(Rect::point(2.0, 48.0), 2);
+    let found = tr
+        .search_h3_cells(&[0x8a1fb46622dffff, 0x8a1fb46622dffff])
+        .unwrap();
+    assert_eq!(found.len(), 1);
+    assert_eq!(*found[0].data, 1);
+}
+
+#[test]
+fn geohash() {
+    use crate::geohash::{decode, encode};
+
+    assert_eq!(encode(Point::new(-5.6, 42.6), 5), "ezs42");
+    assert_eq!(encode(Point::new(10.40744, 57.64911), 9), "u4pruydqq");
+    let cell = decode("ezs42").unwrap();
+    assert!(cell.intersects(&Rect::point(-5.6, 42.6)));
+    assert!(cell.max.x - cell.min.x < 0.05);
+    assert!(decode("ezs4a").is_err());
+
+    let mut tr = RTree::new(BoxAlloc);
+    for i in 0..2000 {
+        let x = fastrand::f32() * 360.0 - 180.0;
+        let y = fastrand::f32() * 180.0 - 90.0;
+        tr.insert(Rect::point(x, y), i);
+    }
+    let expect: Vec<usize> = tr
+        .geohashes(2)
+        .filter(|(hash, _)| hash.starts_with('u'))
+        .map(|(_, item)| *item.data)
+        .collect();
+    let mut found: Vec<usize> = tr.search_geohash("U").unwrap().map(|x| *x.data).collect();
+    found.sort();
+    assert_eq!(found, sorted(expect));
+}
+
+#[test]
+fn reorder() {
+    let blink = Blink::new();
+    let mut tr = RTree::new(&blink);
+    for i in 0..5000 {
+        tr.insert(Rect::point(fastrand::f32(), fastrand::f32()), i);
+    }
+    tr.set_config(Config {
+        profile: true,
+        ..tr.config()
+    });
+    let hot = Rect::new(Point::new(0.9, 0.9), Point::new(1.0, 1.0));
+    for _ in 0..10 {
+        assert!(tr.intersects_any(hot));
+    }
+    let before: Vec<usize> = tr.iter().map(|x| *x.data).collect();
+    tr.reorder();
+    let after: Vec<usize> = tr.iter().map(|x| *x.data).collect();
+    assert_eq!(sorted(before), sorted(after));
+    // the first leaf now holds the hot corner
+    let first = tr.iter().next().unwrap();
+    assert!(first.rect.intersects(&hot.padded(Point::new(0.2, 0.2))));
+    if let Some(Node::Parent(root)) = &tr.root {
+        assert_eq!(check_nodes(root, tr.height, true), 5000);
+    }
+}
+
+#[test]
+fn memory_limit() {
+    let mut tr = RTree::new(BoxAlloc);
+    let count = |tr: &RTree<usize, BoxAlloc>| {
+        let occ = tr.occupancy();
+        occ.leaves.iter().chain(&occ.branches).sum::<usize>()
+    };
+    for i in 0..3000 {
+        tr.insert(Rect::point(fastrand::f32(), fastrand::f32()), i);
+    }
+    assert_eq!(tr.nodes, count(&tr));
+    let items: Vec<(Rect, usize)> = tr.iter().map(|x| (x.rect, *x.data)).collect();
+    for (rect, i) in &items[..2000] {
+        tr.remove(*rect, i).unwrap();
+    }
+    assert_eq!(tr.nodes, count(&tr));
+    tr.retain_in_rect(Rect::INFINITY, |_, i| *i % 3 != 0);
+    assert_eq!(tr.nodes, count(&tr));
+
+    let limit = tr.memory_usage() + 20_000;
+    tr.set_memory_limit(Some(limit));
+    let err = loop {
+        match tr.try_insert(Rect::point(0.5, 0.5), 9999) {
+            Ok(()) => assert!(tr.memory_usage() <= limit),
+            Err(err) => break err,
+        }
+    };
+    assert_eq!(err.data, 9999);
+    assert_eq!(err.limit, limit);
+    assert!(tr.memory_usage() <= limit);
+    tr.set_memory_limit(None);
+    tr.insert(err.rect, err.data);
+}
+
+#[cfg(feature = "alloc-counters")]
+#[test]
+fn alloc_counters() {
+    let mut tr = RTree::new(BoxAlloc);
+    let mut items = Vec::new();
+    for i in 0..2000 {
+        let rect = Rect::point(fastrand::f32(), fastrand::f32());
+        tr.insert(rect, i);
+        items.push((rect, i));
+    }
+    let counters = tr.alloc_counters();
+    assert_eq!(counters.insert.ops, 2000);
+    // every node but the first root came from a split or a root split
+    assert_eq!(counters.insert.nodes as usize, tr.nodes);
+    assert_eq!(counters.split.nodes as usize, tr.nodes - tr.height - 1);
+    assert_eq!(counters.insert.bytes as usize, tr.memory_usage());
+    tr.reset_alloc_counters();
+    for (rect, i) in &items[..1500] {
+        tr.remove(*rect, i).unwrap();
+    }
+    let counters = tr.alloc_counters();
+    assert_eq!(counters.remove.ops, 1500);
+    assert_eq!(counters.insert.ops, 0);
+    assert_eq!(counters.remove.nodes, counters.split.nodes);
+}
+
+#[test]
+fn insert_unique() {
+    let mut tr = RTree::new(BoxAlloc);
+    let mut rects = Vec::new();
+    for i in 0..1000 {
+        let rect = Rect::point(fastrand::f32(), fastrand::f32());
+        tr.insert_unique(rect, i).unwrap();
+        rects.push(rect);
+    }
+    for (i, rect) in rects.iter().enumerate() {
+        let err = tr.insert_unique(*rect, i).unwrap_err();
+        assert_eq!((err.rect, err.data), (*rect, i));
+    }
+    // same rect, other value
+    tr.insert_unique(rects[0], 1000).unwrap();
+    assert_eq!(tr.len(), 1001);
+}
+
+#[test]
+fn grouped() {
+    use crate::grouped::Grouped;
+    let mut gr = Grouped::new(BoxAlloc);
+    let cells: Vec<Rect> = (0..100)
+        .map(|i| {
+            let (x, y) = ((i % 10) as f32, (i / 10) as f32);
+            Rect::new(Point::new(x, y), Point::new(x + 1.0, y + 1.0))
+        })
+        .collect();
+    for i in 0..5000 {
+        gr.insert(cells[i % 100], i);
+    }
+    assert_eq!((gr.len(), gr.rects()), (5000, 100));
+    assert_eq!(gr.get(cells[7]).len(), 50);
+    let found: Vec<(Rect, &[usize])> = gr
+        .search(Rect::new(Point::new(0.5, 0.5), Point::new(0.5, 0.5)))
+        .collect();
+    assert_eq!(found.len(), 1);
+    assert!(found[0].1.iter().all(|i| i % 100 == 0));
+    for i in (0..5000).filter(|i| i % 100 == 7) {
+        assert_eq!(gr.remove(cells[7], &i), Some(i));
+    }
+    assert_eq!(gr.remove(cells[7], &7), None);
+    assert_eq!((gr.len(), gr.rects()), (4950, 99));
+    assert!(gr.get(cells[7]).is_empty());
+    let total: usize = gr.iter().map(|(_, values)| values.len()).sum();
+    assert_eq!(total, 4950);
+}
+
+#[test]
+fn grouped_sorted() {
+    use crate::grouped::Grouped;
+    let mut gr = Grouped::new(BoxAlloc);
+    let at = Rect::point(1.0, 2.0);
+    let mut ids: Vec<u32> = (0..5000).collect();
+    fastrand::shuffle(&mut ids);
+    for &id in &ids {
+        gr.insert_sorted(at, id);
+    }
+    gr.insert_sorted(Rect::point(3.0, 4.0), 7);
+    assert_eq!((gr.len(), gr.rects()), (5001, 2));
+    assert!(gr.get(at).windows(2).all(|w| w[0] < w[1]));
+    fastrand::shuffle(&mut ids);
+    for &id in &ids[..4000] {
+        assert_eq!(gr.remove_sorted(at, &id), Some(id));
+        assert_eq!(gr.remove_sorted(at, &id), None);
+    }
+    // plain removal keeps the order too
+    assert_eq!(gr.remove(at, &ids[4000]), Some(ids[4000]));
+    let mut rest = ids[4001..].to_vec();
+    rest.sort();
+    assert_eq!(gr.get(at), rest);
+    for id in rest {
+        assert_eq!(gr.remove_sorted(at, &id), Some(id));
+    }
+    assert_eq!((gr.len(), gr.rects()), (1, 1));
+}
+
+#[test]
+fn snap() {
+    let mut tr = RTree::new(BoxAlloc);
+    tr.set_config(Config {
+        snap: Some(0.25),
+        ..tr.config()
+    });
+    let rect = |x: f32, y: f32| Rect::new(Point::new(x, y), Point::new(x + 0.1 + 0.2, y + 0.3));
+    tr.insert(rect(0.1 + 0.2, 1.0), 1);
+    let item = tr.iter().next().unwrap();
+    assert_eq!(
+        item.rect,
+        Rect::new(Point::new(0.25, 1.0), Point::new(0.5, 1.25))
+    );
+    // a recomputed rect with different rounding errors
+    assert!(tr.remove(rect(0.3, 1.0), &1).is_some());
+    assert_eq!(tr.len(), 0);
+    tr.insert(rect(0.3, 1.0), 2);
+    assert!(tr.insert_unique(rect(0.29, 1.01), 2).is_err());
+
+    // integer coordinates round to the nearest multiple too
+    let mut tr = RTree::<_, _, i32>::with_scalar(BoxAlloc);
+    tr.set_config(Config {
+        snap: Some(2.5),
+        ..tr.config()
+    });
+    tr.insert(Rect::new(Point::new(7, -7), Point::new(9, 2)), 1);
+    assert_eq!(
+        tr.iter().next().unwrap().rect,
+        Rect::new(Point::new(8, -8), Point::new(10, 3))
+    );
+}
+
+#[test]
+fn rebuild() {
+    let mut tr = RTree::new(BoxAlloc);
+    let mut pts: Vec<_> = (0..10000)
+        .map(|i| (Rect::point(fastrand::f32(), fastrand::f32()), i))
+        .collect();
+    for &(rect, i) in &pts {
+        tr.insert(rect, i);
+    }
+    fastrand::shuffle(&mut pts);
+    for (rect, i) in pts.drain(5000..) {
+        tr.remove(rect, &i).unwrap();
+    }
+    let (before, after) = tr.rebuild();
+    assert_eq!(after.nodes, tr.nodes);
+    assert!(after.nodes < before.nodes);
+    assert!(after.overlap < before.overlap);
+    if let Some(Node::Parent(root)) = &tr.root {
+        assert_eq!(check_nodes(root, tr.height, true), 5000);
+    }
+    for &(rect, i) in &pts {
+        assert_eq!(tr.search(rect).filter(|x| *x.data == i).count(), 1);
+    }
+    // still balanced after more changes
+    for (rect, i) in pts.drain(..2500) {
+        tr.remove(rect, &i).unwrap();
+    }
+    for i in 0..2500 {
+        tr.insert(Rect::point(fastrand::f32(), fastrand::f32()), i);
+    }
+    if let Some(Node::Parent(root)) = &tr.root {
+        assert_eq!(check_nodes(root, tr.height, true), 5000);
+    }
+    let mut empty: RTree<usize, _> = RTree::new(BoxAlloc);
+    assert_eq!(empty.rebuild().1.nodes, 0);
+}
+
+#[test]
+fn rebuild_in_rect() {
+    let mut tr = RTree::new(BoxAlloc);
+    let mut pts: Vec<_> = (0..20000)
+        .map(|i| (Rect::point(fastrand::f32(), fastrand::f32()), i))
+        .collect();
+    tr.extend(pts.iter().copied());
+    tr.rebuild();
+    // churn in one corner degrades the nodes there
+    let region = Rect::new(Point::new(0.0, 0.0), Point::new(0.3, 0.3));
+    let mut next = pts.len();
+    for _ in 0..3 {
+        let (churned, kept): (Vec<_>, Vec<_>) = pts
+            .into_iter()
+            .partition(|(rect, i)| region.intersects(rect) && i % 2 == 0);
+        for (rect, i) in churned {
+            tr.remove(rect, &i).unwrap();
+        }
+        pts = kept;
+        for _ in 0..1000 {
+            let rect = Rect::point(fastrand::f32() * 0.3, fastrand::f32() * 0.3);
+            tr.insert(rect, next);
+            pts.push((rect, next));
+            next += 1;
+        }
+    }
+    let before = tr.quality();
+    let repacked = tr.rebuild_in_rect(region);
+    let after = tr.quality();
+    assert!(repacked > 0 && repacked < tr.len());
+    assert!(after.nodes < before.nodes);
+    assert_eq!(after.nodes, tr.nodes);
+    if let Some(Node::Parent(root)) = &tr.root {
+        assert_eq!(check_nodes(root, tr.height, true), pts.len());
+    }
+    for &(rect, i) in &pts {
+        assert_eq!(tr.search(rect).filter(|x| *x.data == i).count(), 1);
+    }
+    assert_eq!(tr.rebuild_in_rect(Rect::point(-1.0, -1.0)), 0);
+
+    let mut small = RTree::new(BoxAlloc);
+    small.insert(Rect::point(0.0, 0.0), 0);
+    assert_eq!(small.rebuild_in_rect(region), 1);
+    assert_eq!(small.len(), 1);
+}
+
+#[test]
+fn topology() {
+    let mut tr = RTree::new(BoxAlloc);
+    for i in 0..500 {
+        let (x, y) = (fastrand::f32(), fastrand::f32());
+        tr.insert(Rect::new(Point::new(x, y), Point::new(x + 0.01, y)), i);
+    }
+    for i in 0..200 {
+        let rect = tr.iter().find(|x| *x.data == i).unwrap().rect;
+        tr.remove(rect, &i).unwrap();
+    }
+    let dump = tr.dump_topology();
+    let loaded = RTree::load_topology(BoxAlloc, &dump).unwrap();
+    assert_eq!(loaded.dump_topology(), dump);
+    assert_eq!(loaded.len(), tr.len());
+    assert_eq!(loaded.quality(), tr.quality());
+    let mut found: Vec<_> = loaded.iter().map(|x| *x.data).collect();
+    found.sort();
+    assert_eq!(found, (0..300).collect::<Vec<_>>());
+
+    let empty: RTree<usize> =
+        RTree::load_topology(BoxAlloc, "rtree height 0 max_items 16\n").unwrap();
+    assert_eq!(empty.len(), 0);
+    for bad in [
+        "",
+        "rtree height 0 max_items 99\n",
+        "rtree height 0 max_items 16\nN 0.0 0.0 1.0 1.0 2\nI 0.0 0.0 1.0 1.0\n",
+        "rtree height 0 max_items 16\nN 0.0 0.0 1.0 1.0 1\nN 0.0 0.0 1.0 1.0 1\n",
+        "rtree height 0 max_items 16\nN 0.0 0.0 1.0 1.0 1\nI 0.0 x 1.0 1.0\n",
+    ] {
+        assert!(RTree::load_topology(BoxAlloc, bad).is_err(), "{bad:?}");
+    }
+    // nodes at least as full as max_items would overflow on insert
+    for len in [16, 32] {
+        let item = "I 0.0 0.0 1.0 1.0\n";
+        let full = format!(
+            "rtree height 0 max_items 16\nN 0.0 0.0 1.0 1.0 {len}\n{}",
+            item.repeat(len)
+        );
+        assert!(RTree::load_topology(BoxAlloc, &full).is_err());
+    }
+}
+
+#[test]
+fn freeze_pr() {
+    for n in [0, 1, 32, 33, 5000] {
+        let mut tr = RTree::new(BoxAlloc);
+        for i in 0..n {
+            // long skinny rects in both directions
+            let (x, y) = (fastrand::f32(), fastrand::f32());
+            let (w, h) = if i % 2 == 0 {
+                (0.5, 0.001)
+            } else {
+                (0.001, 0.5)
+            };
+            tr.insert(Rect::new(Point::new(x, y), Point::new(x + w, y + h)), i);
+        }
+        let view = tr.freeze_pr();
+        assert_eq!(view.len(), n);
+        for _ in 0..20 {
+            let (x, y) = (fastrand::f32(), fastrand::f32());
+            let area = Rect::new(Point::new(x, y), Point::new(x + 0.05, y + 0.05));
+            let expect = sorted(tr.search(area).map(|x| *x.data).collect());
+            let found = sorted(view.search(area).map(|x| *x.data).collect());
+            assert_eq!(found, expect);
+        }
+        let all = sorted(view.search(Rect::INFINITY).map(|x| *x.data).collect());
+        assert_eq!(all, (0..n).collect::<Vec<_>>());
+    }
+}
+
+#[test]
+fn search_padded() {
+    let mut tr = RTree::new(BoxAlloc);
+    tr.set_margin(|(radius, _)| *radius);
+    let mut agents = Vec::new();
+    for i in 0..2000 {
+        let p = Point::new(fastrand::f32(), fastrand::f32());
+        // a few agents reach much further than the rest
+        let radius = if i % 100 == 0 {
+            0.3
+        } else {
+            fastrand::f32() * 0.05
+        };
+        tr.insert(Rect::point(p.x, p.y), (radius, i));
+        agents.push((p, radius, i));
+    }
+    for _ in 0..500 {
+        let (p, radius, i) = agents.swap_remove(fastrand::usize(..agents.len()));
+        assert!(tr.remove(Rect::point(p.x, p.y), &(radius, i)).is_some());
+    }
+    let expect = |area: Rect| {
+        let near = agents.iter().filter(|(p, r, _)| {
+            area.padded(Point::new(*r, *r))
+                .intersects(&Rect::point(p.x, p.y))
+        });
+        sorted(near.map(|(.., i)| *i).collect())
+    };
+    let found = |tr: &RTree<(f32, usize), BoxAlloc>, area: Rect| {
+        sorted(
+            tr.search_padded(area)
+                .iter()
+                .map(|item| item.data.1)
+                .collect(),
+        )
+    };
+    let area = Rect::new(Point::new(0.4, 0.4), Point::new(0.5, 0.5));
+    assert_eq!(found(&tr, area), expect(area));
+    assert!(found(&tr, area).len() > tr.search(area).count());
+    let far = Rect::new(Point::new(1.2, 1.2), Point::new(1.3, 1.3));
+    tr.rebuild();
+    assert_eq!(found(&tr, far), expect(far));
+    // margins are also right for trees built before the margin was set
+    let mut other = RTree::new(BoxAlloc);
+    for (p, radius, i) in &agents {
+        other.insert(Rect::point(p.x, p.y), (*radius, *i));
+    }
+    assert_eq!(found(&other, area).len(), other.search(area).count());
+    other.set_margin(|(radius, _)| *radius);
+    assert_eq!(found(&other, area), expect(area));
+    assert_eq!(found(&other, far), expect(far));
+}
+
+#[test]
+fn estimate_count() {
+    let mut tr = RTree::new(BoxAlloc);
+    for i in 0..20000 {
+        tr.insert(Rect::point(fastrand::f32(), fastrand::f32()), i);
+    }
+    for _ in 0..20 {
+        let (x, y) = (fastrand::f32() * 0.5, fastrand::f32() * 0.5);
+        let area = Rect::new(Point::new(x, y), Point::new(x + 0.5, y + 0.5));
+        let exact = tr.search(area).count();
+        assert_eq!(tr.estimate_count(area, tr.height), exact);
+        let rough = tr.estimate_count(area, 1) as f64;
+        assert!((rough - exact as f64).abs() < exact as f64 * 0.3);
+    }
+    assert_eq!(tr.estimate_count(Rect::INFINITY, 0), 20000);
+}
+
+#[test]
+fn distance_field() {
+    let mut tr = RTree::new(BoxAlloc);
+    for i in 0..500 {
+        let (x, y) = (fastrand::f32(), fastrand::f32());
+        tr.insert(
+            Rect::new(Point::new(x, y), Point::new(x + 0.01, y + 0.02)),
+            i,
+        );
+    }
+    let area = Rect::new(Point::new(-0.2, -0.2), Point::new(1.2, 1.2));
+    let (cols, rows) = (37, 21);
+    let field = tr.distance_field(area, cols, rows);
+    assert_eq!(field.len(), cols * rows);
+    let (w, h) = (1.4 / cols as f32, 1.4 / rows as f32);
+    for row in 0..rows {
+        for col in 0..cols {
+            let p = Point::new(-0.2 + (col as f32 + 0.5) * w, -0.2 + (row as f32 + 0.5) * h);
+            let expect = tr.knn_batch(&[p], 1)[0][0].dist.sqrt();
+            assert!((field[row * cols + col] - expect).abs() < 1e-5);
+        }
+    }
+
+    // single cell tiles, where only the nearest item is in reach
+    let mut tr = RTree::new(BoxAlloc);
+    tr.insert(Rect::point(1.0, 1.0), 0);
+    let square = Rect::new(Point::new(-1.0, -1.0), Point::new(1.0, 1.0));
+    assert_eq!(tr.distance_field(square, 1, 1), [2f32.sqrt()]);
+    for i in 1..20 {
+        tr.insert(Rect::point(fastrand::f32() * 3.0, fastrand::f32() * 3.0), i);
+    }
+    for (cols, rows) in [(1, 1), (1, 7), (9, 1), (2, 3), (11, 17)] {
+        let (x, y) = (fastrand::f32() * 4.0 - 1.0, fastrand::f32() * 4.0 - 1.0);
+        let area = Rect::new(Point::new(x, y), Point::new(x + 0.7, y + 1.3));
+        let field = tr.distance_field(area, cols, rows);
+        let (w, h) = (0.7 / cols as f32, 1.3 / rows as f32);
+        for row in 0..rows {
+            for col in 0..cols {
+                let p = Point::new(x + (col as f32 + 0.5) * w, y + (row as f32 + 0.5) * h);
+                let expect = tr.nearest(p).unwrap().dist.sqrt();
+                assert!((field[row * cols + col] - expect).abs() < 1e-5);
+            }
+        }
+    }
+
+    let empty: RTree<(), _> = RTree::new(BoxAlloc);
+    assert!(empty
+        .distance_field(area, 2, 2)
+        .iter()
+        .all(|d| d.is_infinite()));
+}
+
+#[test]
+fn search_ordered() {
+    let mut tr = RTree::new(BoxAlloc);
+    for i in 0..3000 {
+        let (x, y) = (fastrand::f32(), fastrand::f32());
+        tr.insert(
+            Rect::new(Point::new(x, y), Point::new(x + 0.01, y + 0.01)),
+            i,
+        );
+    }
+    let area = Rect::new(Point::new(0.2, 0.3), Point::new(0.6, 0.5));
+    let count = tr.search(area).count();
+
+    let xs: Vec<f32> = tr
+        .search_ordered(area, Order::MinX)
+        .map(|x| x.rect.min.x)
+        .collect();
+    assert_eq!(xs.len(), count);
+    assert!(xs.windows(2).all(|w| w[0] <= w[1]));
+
+    let focus = Point::new(0.5, 0.4);
+    let near: Vec<f32> = tr
+        .search_ordered(area, Order::Near(focus))
+        .map(|x| x.dist)
+        .collect();
+    assert_eq!(near.len(), count);
+    assert!(near.windows(2).all(|w| w[0] <= w[1]));
+
+    let seqs: Vec<usize> = tr
+        .search_ordered(area, Order::Insertion)
+        .map(|x| *x.data)
+        .collect();
+    assert_eq!(seqs, sorted(tr.search(area).map(|x| *x.data).collect()));
+}
+
+#[test]
+fn seq() {
+    let mut tr = RTree::new(BoxAlloc);
+    for i in 0..100 {
+        tr.insert(Rect::point(i as f32, 0.0), i);
+    }
+    tr.remove(Rect::point(5.0, 0.0), &5).unwrap();
+    // reuses the slot of the removed item
+    tr.insert(Rect::point(5.0, 0.0), 100);
+    let mut items: Vec<_> = tr.iter().map(|x| (x.seq, *x.data)).collect();
+    items.sort();
+    assert_eq!(items.last(), Some(&(100, 100)));
+    assert!(items.iter().all(|(seq, i)| *seq == *i as u64));
+    tr.compact();
+    let view = tr.freeze();
+    let newest = view
+        .search(Rect::new(Point::new(0.0, 0.0), Point::new(10.0, 0.0)))
+        .max_by_key(|x| x.seq)
+        .unwrap();
+    assert_eq!(*newest.data, 100);
+}
+
+#[test]
+fn search_recent() {
+    fn check_newest<T, A: Alloc<T>>(parent: &Parent<T, A>, values: &Slab<T>) -> u64 {
+        let mut newest = 0;
+        for node in parent.nodes.iter() {
+            newest = newest.max(match node {
+                Node::Item(entry) => values.seq(entry.index),
+                Node::Parent(child) => check_newest(child, values),
+            });
+        }
+        assert!(parent.nodes.newest >= newest);
+        newest
+    }
+    let mut tr = RTree::new(BoxAlloc);
+    let mut pts: Vec<_> = (0..5000)
+        .map(|i| (Rect::point(fastrand::f32(), fastrand::f32()), i))
+        .collect();
+    for &(rect, i) in &pts {
+        tr.insert(rect, i);
+    }
+    fastrand::shuffle(&mut pts);
+    for (rect, i) in pts.drain(..1000) {
+        tr.remove(rect, &i).unwrap();
+    }
+    for i in 5000..5500 {
+        tr.insert(Rect::point(fastrand::f32(), fastrand::f32()), i);
+    }
+    let area = Rect::new(Point::new(0.1, 0.1), Point::new(0.7, 0.9));
+    for rebuilt in [false, true] {
+        if rebuilt {
+            tr.rebuild();
+        }
+        if let Some(Node::Parent(root)) = &tr.root {
+            check_newest(root, &tr.values);
+        }
+        let since = tr.iter().find(|x| *x.data == 5000).unwrap().seq - 1;
+        let found = sorted(tr.search_recent(area, since).map(|x| *x.data).collect());
+        let expect = sorted(
+            tr.search(area)
+                .map(|x| *x.data)
+                .filter(|i| *i >= 5000)
+                .collect(),
+        );
+        assert_eq!(found, expect);
+    }
+}
+
+#[test]
+fn search_into() {
+    let mut tr = RTree::new(BoxAlloc);
+    for i in 0..1000 {
+        tr.insert(Rect::point(fastrand::f32(), fastrand::f32()), i);
+    }
+    let area = Rect::new(Point::new(0.2, 0.2), Point::new(0.35, 0.35));
+    let expect = sorted(tr.search(area).map(|x| *x.data).collect());
+    let mut out = ArrayVec::<_, 64>::new();
+    assert!(tr.search_into(area, &mut out));
+    assert_eq!(sorted(out.iter().map(|x| *x.data).collect()), expect);
+    let mut small = ArrayVec::<_, 2>::new();
+    assert!(!tr.search_into(area, &mut small));
+    assert_eq!(small.len(), 2);
+}
+
+#[test]
+fn merge_ordered() {
+    let trees: Vec<_> = (0..4)
+        .map(|_| {
+            let mut tr = RTree::new(BoxAlloc);
+            for i in 0..1000 {
+                tr.insert(Rect::point(fastrand::f32(), fastrand::f32()), i);
+            }
+            tr
+        })
+        .collect();
+    let area = Rect::new(Point::new(0.2, 0.2), Point::new(0.8, 0.6));
+    let count: usize = trees.iter().map(|tr| tr.search(area).count()).sum();
+    for order in [Order::MinX, Order::Near(Point::new(0.3, 0.5))] {
+        let iters = trees.iter().map(|tr| tr.search_ordered(area, order));
+        let keys: Vec<f32> = crate::merge_ordered(iters, order)
+            .map(|x| order.key(&x.rect))
+            .collect();
+        assert_eq!(keys.len(), count);
+        assert!(keys.windows(2).all(|w| w[0] <= w[1]));
+    }
+}
+
+#[test]
+fn cursor() {
+    fn walk<T, A: Alloc<T>>(cursor: &mut Cursor<T, A>, keys: &mut Vec<ItemKey>) -> usize {
+        if let Some(item) = cursor.item() {
+            assert!(cursor.is_empty());
+            assert_eq!(item.rect, cursor.rect());
+            keys.push(item.key);
+            return 1;
+        }
+        let mut count = 0;
+        for i in 0..cursor.len() {
+            let rect = cursor.child_rect(i).unwrap();
+            assert!(cursor.rect().contains(&rect));
+            let depth = cursor.depth();
+            assert!(cursor.down(i));
+            assert_eq!(cursor.depth(), depth + 1);
+            count += walk(cursor, keys);
+            assert!(cursor.up());
+        }
+        assert!(!cursor.down(cursor.len()));
+        assert_eq!(cursor.count(), count);
+        count
+    }
+
+    let mut tr = RTree::new(BoxAlloc);
+    assert!(tr.cursor().is_none());
+    for i in 0..2000 {
+        tr.insert(Rect::point(fastrand::f32(), fastrand::f32()), i);
+    }
+    let mut cursor = tr.cursor().unwrap();
+    let mut keys = Vec::new();
+    assert_eq!(walk(&mut cursor, &mut keys), tr.len());
+    assert!(!cursor.up());
+    keys.sort_by_key(|k| (k.index, k.gen));
+    keys.dedup();
+    assert_eq!(keys.len(), tr.len());
+}
+
+#[test]
+fn try_search() {
+    let mut tr = RTree::new(BoxAlloc);
+    for i in 0..100 {
+        tr.insert(Rect::point(i as f32, i as f32), i);
+    }
+    let rect = Rect::new(Point::new(10.0, 10.0), Point::new(20.0, 20.0));
+    assert_eq!(tr.try_search(rect).unwrap().count(), 11);
+    let nan = Rect::new(Point::new(f32::NAN, 10.0), Point::new(20.0, 20.0));
+    assert!(matches!(tr.try_search(nan), Err(InvalidRectError::NaN(_))));
+    let inverted = Rect::new(Point::new(20.0, 10.0), Point::new(10.0, 20.0));
+    assert_eq!(
+        tr.try_search(inverted).err(),
+        Some(InvalidRectError::Inverted(inverted))
+    );
+    assert!(tr.try_nearby(inverted).is_err());
+    let near: Vec<_> = tr
+        .try_nearby(rect)
+        .unwrap()
+        .take(13)
+        .map(|x| *x.data)
+        .collect();
+    assert_eq!(&near[..11], &(10..=20).collect::<Vec<_>>()[..]);
+    assert!(near[11..].contains(&9) && near[11..].contains(&21));
+}
+
+#[test]
+fn scalar() {
+    // apart in f64, but the same in f32
+    let mut tr = RTree::<usize, _, f64>::with_scalar(BoxAlloc);
+    let base = 1e9;
+    for i in 0..1000 {
+        let x = base + i as f64 * 0.25;
+        tr.insert(Rect::point(x, x), i);
+    }
+    let rect = Rect::new(
+        Point::new(base + 10.0, base),
+        Point::new(base + 20.0, base + 30.0),
+    );
+    let mut found: Vec<_> = tr.search(rect).map(|x| *x.data).collect();
+    found.sort();
+    assert_eq!(found, (40..=80).collect::<Vec<_>>());
+    for i in 0..500 {
+        let x = base + i as f64 * 0.25;
+        assert_eq!(tr.remove(Rect::point(x, x), &i).unwrap().item, i);
+    }
+    assert_eq!(tr.len(), 500);
+    assert_eq!(tr.search(rect).count(), 0);
+
+    let mut tiles = RTree::<(i64, i64), _, i64>::with_scalar(BoxAlloc);
+    for x in 0..100 {
+        for y in 0..100 {
+            let (x, y) = (x << 40, y << 40);
+            tiles.insert(
+                Rect::new(Point::new(x, y), Point::new(x + 1, y + 1)),
+                (x, y),
+            );
+        }
+    }
+    let rect = Rect::new(Point::new(10 << 40, 0), Point::new((10 << 40) + 1, 1));
+    let found: Vec<_> = tiles.search(rect).map(|x| *x.data).collect();
+    assert_eq!(found, [(10 << 40, 0)]);
+    tiles.retain_in_rect(
+        Rect::new(Point::new(0, 0), Point::new(50 << 40, i64::MAX)),
+        |_, _| false,
+    );
+    assert_eq!(tiles.len(), 4900);
+    assert_eq!(tiles.iter().count(), 4900);
+}
+
+#[test]
+fn nd() {
+    use crate::nd::{BoxN, RTreeNd};
+
+    let mut tr = RTreeNd::new(BoxAlloc);
+    let mut boxes = Vec::new();
+    for i in 0..2000 {
+        let p = [fastrand::f32(), fastrand::f32(), fastrand::f32()];
+        boxes.push(BoxN::new(p, p.map(|v| v + 0.01)));
+        tr.insert(boxes[i], i);
+    }
+    let query = BoxN::new([0.2, 0.2, 0.4], [0.6, 0.5, 0.5]);
+    let mut found: Vec<_> = tr.search(query).map(|(_, i)| *i).collect();
+    found.sort();
+    let expect: Vec<_> = (0..boxes.len())
+        .filter(|&i| boxes[i].intersects(&query))
+        .collect();
+    assert_eq!(found, expect);
+    for (i, bbox) in boxes.iter().enumerate().take(1000) {
+        assert_eq!(tr.remove(*bbox, &i), Some(i));
+    }
+    assert_eq!(tr.remove(boxes[0], &0), None);
+    assert_eq!(tr.len(), 1000);
+    assert_eq!(tr.iter().count(), 1000);
+
+    // nearest items by the distance on all axes
+    let point = [0.5, 0.5, 0.9];
+    let mut expect: Vec<_> = (1000..boxes.len())
+        .map(|i| (boxes[i].point_dist(&point), i))
+        .collect();
+    expect.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let found: Vec<_> = tr
+        .k_nearest(point, 20)
+        .into_iter()
+        .map(|(b, i)| (b.point_dist(&point), *i))
+        .collect();
+    assert_eq!(
+        found.iter().map(|x| x.0).collect::<Vec<_>>(),
+        expect[..20].iter().map(|x| x.0).collect::<Vec<_>>()
+    );
+    assert_eq!(tr.nearest(point).map(|x| *x.1), Some(found[0].1));
+
+    // indexed by the axes the queries are selective on
+    let mut tr = RTreeNd::with_axes(BoxAlloc, [2, 0]);
+    for (i, bbox) in boxes.iter().enumerate() {
+        tr.insert(*bbox, i);
+    }
+    assert_eq!(tr.axes(), [2, 0]);
+    let floor = BoxN::new([0.0, 0.0, 0.3], [1.0, 1.0, 0.31]);
+    let mut found: Vec<_> = tr.search(floor).map(|(_, i)| *i).collect();
+    found.sort();
+    let expect: Vec<_> = (0..boxes.len())
+        .filter(|&i| boxes[i].intersects(&floor))
+        .collect();
+    assert_eq!(found, expect);
+    let point = [0.1, 0.2, 0.3];
+    let best = boxes
+        .iter()
+        .map(|b| b.point_dist(&point))
+        .fold(f32::INFINITY, f32::min);
+    assert_eq!(
+        tr.nearest(point).map(|(b, _)| b.point_dist(&point)),
+        Some(best)
+    );
+}
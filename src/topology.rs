@@ -0,0 +1,165 @@
+//! A textual dump of the arrangement of the nodes of a tree, for bug
+//! reports about trees that search slowly or misbehave.
+//!
+//! The dump holds the rects of the nodes and items but no values, so it can
+//! be shared without the data it was built from. It starts with a header
+//! line, followed by a line per node and item in depth first order:
+//!
+//! ```text
+//! rtree height 1 max_items 16
+//! N 0.0 0.0 3.0 2.0 2
+//!   N 0.0 0.0 1.0 1.0 2
+//!     I 0.0 0.0 0.5 0.5
+//!     I 0.5 0.5 1.0 1.0
+//!   N 2.0 1.0 3.0 2.0 1
+//!     I 2.0 1.0 3.0 2.0
+//! ```
+//!
+//! Nodes give their rect and number of children, items their rect.
+//! Indentation is only for reading, and coordinates are written so that
+//! they're read back exactly.
+
+use crate::node::Entry;
+use crate::{Alloc, Node, Parent, Point, RTree, Rect, MAX_ITEMS, MIN_ITEMS};
+use std::fmt::Write;
+use std::io::{self, Error, ErrorKind};
+
+impl<T, A: Alloc<T>> RTree<T, A> {
+    /// Writes the nodes of the tree as described in the [module](self)
+    /// docs.
+    pub fn dump_topology(&self) -> String {
+        let mut out = format!(
+            "rtree height {} max_items {}\n",
+            self.height, self.config.max_items
+        );
+        if let Some(Node::Parent(root)) = &self.root {
+            dump(root, 0, &mut out);
+        }
+        out
+    }
+}
+
+fn dump<T, A: Alloc<T>>(parent: &Parent<T, A>, depth: usize, out: &mut String) {
+    let _ = writeln!(
+        out,
+        "{:indent$}N {} {}",
+        "",
+        format_rect(&parent.rect),
+        parent.len(),
+        indent = depth * 2
+    );
+    for node in parent.nodes.iter() {
+        match node {
+            Node::Item(entry) => {
+                let _ = writeln!(
+                    out,
+                    "{:indent$}I {}",
+                    "",
+                    format_rect(&entry.rect),
+                    indent = depth * 2 + 2
+                );
+            }
+            Node::Parent(child) => dump(child, depth + 1, out),
+        }
+    }
+}
+
+fn format_rect(r: &Rect) -> String {
+    format!("{:?} {:?} {:?} {:?}", r.min.x, r.min.y, r.max.x, r.max.y)
+}
+
+impl<A: Alloc<usize>> RTree<usize, A> {
+    /// Reads a tree written by [`dump_topology`](Self::dump_topology), with
+    /// the nodes arranged exactly as they were. Items are numbered in the
+    /// order they appear.
+    ///
+    /// Node rects are taken as written, even if they don't cover their
+    /// children, so that broken trees can be reproduced.
+    pub fn load_topology(alloc: A, dump: &str) -> io::Result<Self> {
+        let mut lines = dump.lines().map(str::trim).filter(|l| !l.is_empty());
+        let header = lines.next().ok_or_else(|| invalid("missing header"))?;
+        let (height, max_items) = match *header.split(' ').collect::<Vec<_>>() {
+            ["rtree", "height", height, "max_items", max_items] => {
+                (parse(height)?, parse::<usize>(max_items)?)
+            }
+            _ => return Err(invalid("bad header")),
+        };
+        if !(2 * MIN_ITEMS..=MAX_ITEMS).contains(&max_items) {
+            return Err(invalid("max_items out of range"));
+        }
+        // nodes have at least two children, so no tree is any taller
+        if height >= usize::BITS as usize {
+            return Err(invalid("height out of range"));
+        }
+        let mut tree = RTree::new(alloc);
+        tree.config.max_items = max_items;
+        if let Some(line) = lines.next() {
+            let mut root = load(&mut tree, line, &mut lines, height)?;
+            root.renew(&tree.values);
+            tree.root = Some(Node::Parent(root));
+            tree.height = height;
+        }
+        if lines.next().is_some() {
+            return Err(invalid("lines after the root"));
+        }
+        Ok(tree)
+    }
+}
+
+/// Reads the node of `height` on `line` and its children from `lines`.
+fn load<'a, A: Alloc<usize>>(
+    tree: &mut RTree<usize, A>,
+    line: &str,
+    lines: &mut impl Iterator<Item = &'a str>,
+    height: usize,
+) -> io::Result<Parent<usize, A>> {
+    let (rect, len) = match line.split_once(' ') {
+        Some(("N", rest)) => match rest.rsplit_once(' ') {
+            Some((rect, len)) => (parse_rect(rect)?, parse::<usize>(len)?),
+            None => return Err(invalid("bad node")),
+        },
+        _ => return Err(invalid("expected a node")),
+    };
+    // nodes are split once they're full, so inserts could overflow one
+    // that already is
+    if !(1..tree.config.max_items).contains(&len) {
+        return Err(invalid("bad number of children"));
+    }
+    let mut children = Vec::with_capacity(len);
+    for _ in 0..len {
+        let line = lines.next().ok_or_else(|| invalid("truncated dump"))?;
+        if height > 0 {
+            children.push(Node::Parent(load(tree, line, lines, height - 1)?));
+            continue;
+        }
+        let rect = match line.split_once(' ') {
+            Some(("I", rect)) => parse_rect(rect)?,
+            _ => return Err(invalid("expected an item")),
+        };
+        let index = tree.values.insert(tree.length);
+        tree.length += 1;
+        children.push(Node::Item(Entry { rect, index }));
+    }
+    let mut parent = Parent::of(children, &tree.alloc);
+    parent.rect = rect;
+    tree.nodes += 1;
+    Ok(parent)
+}
+
+fn parse_rect(s: &str) -> io::Result<Rect> {
+    match *s.split(' ').collect::<Vec<_>>() {
+        [x0, y0, x1, y1] => Ok(Rect::new(
+            Point::new(parse(x0)?, parse(y0)?),
+            Point::new(parse(x1)?, parse(y1)?),
+        )),
+        _ => Err(invalid("bad rect")),
+    }
+}
+
+fn parse<V: std::str::FromStr>(s: &str) -> io::Result<V> {
+    s.parse().map_err(|_| invalid("bad number"))
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
@@ -0,0 +1,150 @@
+//! Converting between Well-Known Binary geometries and rects.
+//!
+//! Both ISO WKB and PostGIS extended WKB (with SRID, Z and M flags) are
+//! accepted. Only the envelope of a geometry is kept.
+
+use crate::{Point, Rect};
+use std::io::{self, Error, ErrorKind};
+
+/// Returns the envelope of a WKB geometry, or `None` if it is empty.
+pub fn envelope(wkb: &[u8]) -> io::Result<Option<Rect>> {
+    let mut reader = Reader { buf: wkb, pos: 0 };
+    let mut env = None;
+    reader.geometry(&mut env)?;
+    Ok(env)
+}
+
+/// Encodes `rect` as a little-endian WKB polygon, or as a point if it has no
+/// extent.
+pub fn to_wkb(rect: &Rect) -> Vec<u8> {
+    let mut out = vec![1];
+    let coords = |out: &mut Vec<u8>, p: Point| {
+        out.extend((p.x as f64).to_le_bytes());
+        out.extend((p.y as f64).to_le_bytes());
+    };
+    if rect.min == rect.max {
+        out.extend(1u32.to_le_bytes());
+        coords(&mut out, rect.min);
+        return out;
+    }
+    out.extend(3u32.to_le_bytes());
+    out.extend(1u32.to_le_bytes());
+    out.extend(5u32.to_le_bytes());
+    for p in [
+        rect.min,
+        Point::new(rect.max.x, rect.min.y),
+        rect.max,
+        Point::new(rect.min.x, rect.max.y),
+        rect.min,
+    ] {
+        coords(&mut out, p);
+    }
+    out
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn bytes<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        let bytes = self
+            .buf
+            .get(self.pos..self.pos + N)
+            .ok_or_else(|| invalid("truncated geometry"))?;
+        self.pos += N;
+        Ok(bytes.try_into().unwrap())
+    }
+
+    fn u32(&mut self, le: bool) -> io::Result<u32> {
+        let b = self.bytes()?;
+        Ok(if le {
+            u32::from_le_bytes(b)
+        } else {
+            u32::from_be_bytes(b)
+        })
+    }
+
+    fn f64(&mut self, le: bool) -> io::Result<f64> {
+        let b = self.bytes()?;
+        Ok(if le {
+            f64::from_le_bytes(b)
+        } else {
+            f64::from_be_bytes(b)
+        })
+    }
+
+    fn geometry(&mut self, env: &mut Option<Rect>) -> io::Result<()> {
+        let le = match self.bytes::<1>()? {
+            [0] => false,
+            [1] => true,
+            _ => return Err(invalid("bad byte order")),
+        };
+        let mut kind = self.u32(le)?;
+        let mut dims = 2;
+        // PostGIS extended flags
+        if kind & 0x8000_0000 != 0 {
+            dims += 1;
+        }
+        if kind & 0x4000_0000 != 0 {
+            dims += 1;
+        }
+        if kind & 0x2000_0000 != 0 {
+            self.u32(le)?;
+        }
+        kind &= 0x0fff_ffff;
+        // ISO Z, M and ZM variants
+        dims += match kind / 1000 {
+            0 => 0,
+            1 | 2 => 1,
+            3 => 2,
+            _ => return Err(invalid("unknown geometry type")),
+        };
+        match kind % 1000 {
+            1 => self.points(le, dims, 1, env),
+            2 => {
+                let n = self.u32(le)?;
+                self.points(le, dims, n, env)
+            }
+            3 => {
+                for _ in 0..self.u32(le)? {
+                    let n = self.u32(le)?;
+                    self.points(le, dims, n, env)?;
+                }
+                Ok(())
+            }
+            4..=7 => {
+                for _ in 0..self.u32(le)? {
+                    self.geometry(env)?;
+                }
+                Ok(())
+            }
+            _ => Err(invalid("unknown geometry type")),
+        }
+    }
+
+    fn points(&mut self, le: bool, dims: usize, n: u32, env: &mut Option<Rect>) -> io::Result<()> {
+        for _ in 0..n {
+            let x = self.f64(le)? as f32;
+            let y = self.f64(le)? as f32;
+            for _ in 2..dims {
+                self.f64(le)?;
+            }
+            // empty points are encoded as NaN coordinates
+            if x.is_nan() || y.is_nan() {
+                continue;
+            }
+            let rect = Rect::point(x, y);
+            match env {
+                Some(env) => env.expand(&rect),
+                None => *env = Some(rect),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}